@@ -161,7 +161,7 @@ impl<'a> FullCodegenBuilder<'a> {
             GcPointerBase::typeid_offsetof() as i32,
         );
         let cmp_result = self.builder.ins().icmp_imm(IntCC::Equal, type_id, unsafe {
-            transmute::<_, i64>(TypeId::of::<JsObject>())
+            transmute::<_, u128>(TypeId::of::<JsObject>()) as i64
         });
         let promoted = self.builder.ins().bint(types::I64, cmp_result);
         self.builder.ins().jump(merge, &[promoted]);
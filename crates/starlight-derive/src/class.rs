@@ -0,0 +1,587 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+//! Implementation of `#[js_class]` and `#[js_impl]`.
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{
+    spanned::Spanned, AttributeArgs, Error, FnArg, ImplItem, ImplItemMethod, ItemImpl, ItemStruct,
+    Lit, Meta, NestedMeta, ReturnType, Type,
+};
+
+/// Options accepted by `#[js_class(...)]`.
+struct ClassOptions {
+    name: Option<String>,
+    snapshot: bool,
+}
+
+fn parse_class_options(args: AttributeArgs) -> Result<ClassOptions, Error> {
+    let mut options = ClassOptions {
+        name: None,
+        snapshot: false,
+    };
+    for arg in args {
+        match arg {
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("name") => match nv.lit {
+                Lit::Str(s) => options.name = Some(s.value()),
+                lit => return Err(Error::new(lit.span(), "expected string literal")),
+            },
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("snapshot") => {
+                options.snapshot = true
+            }
+            arg => {
+                return Err(Error::new(
+                    arg.span(),
+                    "unknown js_class option, expected `name = \"...\"` or `snapshot`",
+                ))
+            }
+        }
+    }
+    Ok(options)
+}
+
+pub fn expand_class(args: AttributeArgs, item: ItemStruct) -> Result<TokenStream, Error> {
+    let options = parse_class_options(args)?;
+    if !item.generics.params.is_empty() {
+        return Err(Error::new(
+            item.generics.span(),
+            "js_class can't be used on generic types",
+        ));
+    }
+    let ident = &item.ident;
+    let name = options.name.unwrap_or_else(|| ident.to_string());
+    let (deserialize, serialize) = if options.snapshot {
+        (
+            quote! {
+                extern "C" fn deserialize(
+                    object: &mut ::starlight::vm::object::JsObject,
+                    deser: &mut ::starlight::gc::snapshot::deserializer::Deserializer,
+                ) {
+                    let data = unsafe {
+                        <#ident as ::starlight::gc::snapshot::deserializer::Deserializable>::deserialize_inplace(deser)
+                    };
+                    *object.data::<#ident>() = ::std::mem::ManuallyDrop::new(data);
+                }
+            },
+            quote! {
+                extern "C" fn serialize(
+                    object: &::starlight::vm::object::JsObject,
+                    ser: &mut ::starlight::gc::snapshot::serializer::SnapshotSerializer,
+                ) {
+                    ::starlight::gc::snapshot::serializer::Serializable::serialize(&**object.data::<#ident>(), ser);
+                }
+            },
+        )
+    } else {
+        (quote! {}, quote! {})
+    };
+    let (deserialize_field, serialize_field) = if options.snapshot {
+        (quote!(Some(deserialize)), quote!(Some(serialize)))
+    } else {
+        (quote!(None), quote!(None))
+    };
+
+    Ok(quote! {
+        #item
+
+        impl ::starlight::vm::class::JsClass for #ident {
+            fn class() -> &'static ::starlight::vm::class::Class {
+                use ::starlight::vm::method_table::MethodTable;
+
+                #[allow(improper_ctypes_definitions)]
+                extern "C" fn trace(
+                    tracer: &mut dyn ::starlight::gc::cell::Tracer,
+                    object: &mut ::starlight::vm::object::JsObject,
+                ) {
+                    ::starlight::gc::cell::Trace::trace(&mut **object.data::<#ident>(), tracer);
+                }
+
+                extern "C" fn finalize(
+                    object: ::starlight::gc::cell::GcPointer<::starlight::vm::object::JsObject>,
+                ) {
+                    unsafe { ::std::mem::ManuallyDrop::drop(object.data::<#ident>()) }
+                }
+
+                extern "C" fn additional_size() -> usize {
+                    ::std::mem::size_of::<#ident>()
+                }
+
+                #deserialize
+                #serialize
+
+                static CLASS: ::starlight::vm::class::Class = ::starlight::vm::class::Class {
+                    name: #name,
+                    method_table: ::starlight::js_method_table!(#ident),
+                    drop: Some(finalize),
+                    trace: Some(trace),
+                    deserialize: #deserialize_field,
+                    serialize: #serialize_field,
+                    additional_size: Some(additional_size),
+                };
+                ::starlight::register_class_reference!(#ident, CLASS);
+                &CLASS
+            }
+        }
+    })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MemberKind {
+    Constructor,
+    Method,
+    Getter,
+    Setter,
+}
+
+/// Method of `#[js_impl]` block exposed to JS.
+struct Member {
+    kind: MemberKind,
+    js_name: String,
+    rust_name: syn::Ident,
+    wrapper: syn::Ident,
+    is_static: bool,
+    arity: u32,
+}
+
+/// Converts `snake_case` Rust name into `camelCase` JS name.
+fn camel_case(name: &str) -> String {
+    let mut result = String::with_capacity(name.len());
+    let mut upper = false;
+    for c in name.trim_start_matches('_').chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            result.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// Removes `#[js_*]` attribute from `method` and returns its kind and explicit JS name.
+fn take_member_attr(
+    method: &mut ImplItemMethod,
+) -> Result<Option<(MemberKind, Option<String>)>, Error> {
+    let mut found = None;
+    let mut error = None;
+    method.attrs.retain(|attr| {
+        let kind = if attr.path.is_ident("js_constructor") {
+            MemberKind::Constructor
+        } else if attr.path.is_ident("js_method") {
+            MemberKind::Method
+        } else if attr.path.is_ident("js_getter") {
+            MemberKind::Getter
+        } else if attr.path.is_ident("js_setter") {
+            MemberKind::Setter
+        } else {
+            return true;
+        };
+        if found.is_some() {
+            error = Some(Error::new(attr.span(), "duplicate js attribute"));
+            return false;
+        }
+        let mut name = None;
+        if !attr.tokens.is_empty() {
+            match attr.parse_meta() {
+                Ok(Meta::List(list)) => {
+                    for nested in list.nested {
+                        match nested {
+                            NestedMeta::Meta(Meta::NameValue(nv))
+                                if nv.path.is_ident("name") && kind != MemberKind::Constructor =>
+                            {
+                                if let Lit::Str(s) = &nv.lit {
+                                    name = Some(s.value());
+                                    continue;
+                                }
+                                error = Some(Error::new(nv.span(), "expected string literal"));
+                            }
+                            nested => {
+                                error = Some(Error::new(nested.span(), "unknown option"));
+                            }
+                        }
+                    }
+                }
+                Ok(meta) => error = Some(Error::new(meta.span(), "expected `name = \"...\"`")),
+                Err(e) => error = Some(e),
+            }
+        }
+        found = Some((kind, name));
+        false
+    });
+    match error {
+        Some(e) => Err(e),
+        None => Ok(found),
+    }
+}
+
+fn is_last_segment(ty: &Type, name: &str) -> bool {
+    match ty {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .map(|segment| segment.ident == name)
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+/// `GcPointer<Context>` parameters receive the calling context.
+fn is_context(ty: &Type) -> bool {
+    if let Type::Path(path) = ty {
+        if let Some(segment) = path.path.segments.last() {
+            if segment.ident != "GcPointer" {
+                return false;
+            }
+            if let syn::PathArguments::AngleBracketed(args) = &segment.arguments {
+                return args.args.iter().any(|arg| match arg {
+                    syn::GenericArgument::Type(ty) => is_last_segment(ty, "Context"),
+                    _ => false,
+                });
+            }
+        }
+    }
+    false
+}
+
+/// `&Arguments` parameters receive raw call arguments.
+fn is_arguments(ty: &Type) -> bool {
+    match ty {
+        Type::Reference(reference) => is_last_segment(&reference.elem, "Arguments"),
+        _ => false,
+    }
+}
+
+/// Generates `JsAPI` wrapper converting JS arguments for `method` and returns the exposed member.
+fn wrap_member(
+    self_ty: &Type,
+    method: &ImplItemMethod,
+    kind: MemberKind,
+    name: Option<String>,
+) -> Result<(Member, TokenStream), Error> {
+    let sig = &method.sig;
+    let rust_name = sig.ident.clone();
+    if !sig.generics.params.is_empty() || sig.asyncness.is_some() {
+        return Err(Error::new(
+            sig.span(),
+            "js methods can't be generic or async",
+        ));
+    }
+    let mut receiver = None;
+    let mut bindings = vec![];
+    let mut call_args = vec![];
+    let mut arity = 0u32;
+    for (i, input) in sig.inputs.iter().enumerate() {
+        match input {
+            FnArg::Receiver(r) => {
+                if r.reference.is_none() {
+                    return Err(Error::new(
+                        r.span(),
+                        "js methods must take `self` by reference",
+                    ));
+                }
+                receiver = Some(r.mutability.is_some());
+            }
+            FnArg::Typed(typed) => {
+                let ty = &typed.ty;
+                let arg = format_ident!("arg{}", i);
+                if is_context(ty) {
+                    call_args.push(quote!(ctx));
+                } else if is_arguments(ty) {
+                    call_args.push(quote!(args));
+                } else {
+                    let index = arity as usize;
+                    let index = syn::Index::from(index);
+                    bindings.push(quote! {
+                        let #arg = <#ty as ::starlight::vm::convert::FromJs>::from_js(ctx, args.at(#index))?;
+                    });
+                    call_args.push(quote!(#arg));
+                    arity += 1;
+                }
+            }
+        }
+    }
+
+    let is_static = receiver.is_none();
+    let wrapper = format_ident!("__js_{}", rust_name);
+    let js_name = match name {
+        Some(name) => name,
+        None => {
+            let base = rust_name.to_string();
+            let base = match kind {
+                MemberKind::Getter => base.strip_prefix("get_").unwrap_or(&base).to_owned(),
+                MemberKind::Setter => base.strip_prefix("set_").unwrap_or(&base).to_owned(),
+                _ => base,
+            };
+            camel_case(&base)
+        }
+    };
+
+    match kind {
+        MemberKind::Constructor => {
+            if !is_static {
+                return Err(Error::new(sig.span(), "js constructor can't take `self`"));
+            }
+            let returns_result = match &sig.output {
+                ReturnType::Type(_, ty) => is_last_segment(ty, "Result"),
+                ReturnType::Default => {
+                    return Err(Error::new(
+                        sig.span(),
+                        "js constructor must return `Self` or `Result<Self, JsValue>`",
+                    ))
+                }
+            };
+            let call = quote!(<#self_ty>::#rust_name(#(#call_args),*));
+            let call = if returns_result {
+                call
+            } else {
+                quote!(Ok(#call))
+            };
+            let body = quote! {
+                #[doc(hidden)]
+                #[allow(unused_variables)]
+                fn #wrapper(
+                    ctx: ::starlight::gc::cell::GcPointer<::starlight::vm::context::Context>,
+                    args: &::starlight::vm::arguments::Arguments,
+                ) -> Result<Self, ::starlight::vm::value::JsValue> {
+                    #(#bindings)*
+                    #call
+                }
+            };
+            return Ok((
+                Member {
+                    kind,
+                    js_name,
+                    rust_name,
+                    wrapper,
+                    is_static,
+                    arity,
+                },
+                body,
+            ));
+        }
+        MemberKind::Getter if arity != 0 => {
+            return Err(Error::new(sig.span(), "js getter can't take arguments"));
+        }
+        MemberKind::Setter if arity != 1 => {
+            return Err(Error::new(
+                sig.span(),
+                "js setter must take exactly one argument",
+            ));
+        }
+        _ => {}
+    }
+
+    let call = match receiver {
+        None => quote!(<#self_ty>::#rust_name(#(#call_args),*)),
+        Some(mutable) => {
+            let this = if mutable {
+                quote!(&mut *this)
+            } else {
+                quote!(&*this)
+            };
+            quote! {{
+                let mut this = <::starlight::vm::object::TypedJsObject<#self_ty> as ::starlight::JsTryFrom<::starlight::vm::value::JsValue>>::try_from(ctx, args.this)?;
+                <#self_ty>::#rust_name(#this, #(#call_args),*)
+            }}
+        }
+    };
+    let body = quote! {
+        #[doc(hidden)]
+        #[allow(unused_variables, unused_mut)]
+        fn #wrapper(
+            ctx: ::starlight::gc::cell::GcPointer<::starlight::vm::context::Context>,
+            args: &::starlight::vm::arguments::Arguments,
+        ) -> Result<::starlight::vm::value::JsValue, ::starlight::vm::value::JsValue> {
+            #(#bindings)*
+            let result = #call;
+            ::starlight::vm::convert::IntoJs::into_js(result, ctx)
+        }
+    };
+    Ok((
+        Member {
+            kind,
+            js_name,
+            rust_name,
+            wrapper,
+            is_static,
+            arity,
+        },
+        body,
+    ))
+}
+
+pub fn expand_impl(mut item: ItemImpl) -> Result<TokenStream, Error> {
+    if item.trait_.is_some() {
+        return Err(Error::new(
+            item.span(),
+            "js_impl must be used on inherent impl block",
+        ));
+    }
+    let self_ty = (*item.self_ty).clone();
+    let mut members = vec![];
+    let mut wrappers = vec![];
+    for impl_item in item.items.iter_mut() {
+        if let ImplItem::Method(method) = impl_item {
+            if let Some((kind, name)) = take_member_attr(method)? {
+                let (member, wrapper) = wrap_member(&self_ty, method, kind, name)?;
+                members.push(member);
+                wrappers.push(wrapper);
+            }
+        }
+    }
+
+    let constructors = members
+        .iter()
+        .filter(|member| member.kind == MemberKind::Constructor)
+        .collect::<Vec<_>>();
+    if constructors.len() > 1 {
+        return Err(Error::new(
+            constructors[1].rust_name.span(),
+            "js class can have only one constructor",
+        ));
+    }
+    let constructor = match constructors.first() {
+        Some(member) => {
+            let wrapper = &member.wrapper;
+            quote!(Self::#wrapper(ctx, args))
+        }
+        None => quote! {
+            Err(::starlight::vm::value::JsValue::new(ctx.new_type_error(format!(
+                "{} is not a constructor",
+                <Self as ::starlight::vm::class::JsClass>::class().name
+            ))))
+        },
+    };
+
+    let type_name = quote!(#self_ty).to_string().replace(' ', "");
+    let constructor_name = format!("{}::constructor", type_name);
+    let mut registrations = vec![];
+    let mut references = vec![];
+    let mut accessors: Vec<(String, bool, Option<&Member>, Option<&Member>)> = vec![];
+    for member in members.iter() {
+        let wrapper = &member.wrapper;
+        match member.kind {
+            MemberKind::Constructor => continue,
+            MemberKind::Method => {
+                let js_name = &member.js_name;
+                let arity = member.arity;
+                let define = if member.is_static {
+                    quote!(static_method)
+                } else {
+                    quote!(method)
+                };
+                registrations.push(quote! {
+                    builder.#define(#js_name, Self::#wrapper, #arity)?;
+                });
+            }
+            MemberKind::Getter | MemberKind::Setter => {
+                let index = match accessors.iter().position(|(name, is_static, _, _)| {
+                    *name == member.js_name && *is_static == member.is_static
+                }) {
+                    Some(index) => index,
+                    None => {
+                        accessors.push((member.js_name.clone(), member.is_static, None, None));
+                        accessors.len() - 1
+                    }
+                };
+                let slot = if member.kind == MemberKind::Getter {
+                    &mut accessors[index].2
+                } else {
+                    &mut accessors[index].3
+                };
+                if slot.is_some() {
+                    return Err(Error::new(
+                        member.rust_name.span(),
+                        format!("duplicate accessor for `{}`", member.js_name),
+                    ));
+                }
+                *slot = Some(member);
+            }
+        }
+        let name = format!("{}::{}", type_name, member.rust_name);
+        references.push(quote! {
+            ::starlight::gc::snapshot::references::register_native_reference(
+                concat!(module_path!(), "::", #name),
+                <#self_ty>::#wrapper as ::starlight::vm::function::JsAPI as usize,
+            );
+        });
+    }
+    for (js_name, is_static, getter, setter) in accessors.iter() {
+        let function = |member: &Option<&Member>| match member {
+            Some(member) => {
+                let wrapper = &member.wrapper;
+                let arity = member.arity;
+                quote! {
+                    ::starlight::vm::value::JsValue::new(::starlight::vm::function::JsNativeFunction::new(
+                        builder.context,
+                        ::starlight::vm::symbol_table::Internable::intern(#js_name),
+                        Self::#wrapper,
+                        #arity,
+                    ))
+                }
+            }
+            None => quote!(::starlight::vm::value::JsValue::encode_undefined_value()),
+        };
+        let getter = function(getter);
+        let setter = function(setter);
+        let define = if *is_static {
+            quote!(static_accessor)
+        } else {
+            quote!(accessor)
+        };
+        registrations.push(quote! {
+            let getter = #getter;
+            let setter = #setter;
+            builder.#define(#js_name, getter, setter, ::starlight::vm::attributes::CONFIGURABLE)?;
+        });
+    }
+
+    let (impl_generics, _, where_clause) = item.generics.split_for_impl();
+    Ok(quote! {
+        #item
+
+        impl #impl_generics #self_ty #where_clause {
+            #(#wrappers)*
+        }
+
+        impl #impl_generics ::starlight::vm::builder::ClassConstructor for #self_ty #where_clause {
+            fn constructor(
+                ctx: ::starlight::gc::cell::GcPointer<::starlight::vm::context::Context>,
+                args: &::starlight::vm::arguments::Arguments,
+            ) -> Result<Self, ::starlight::vm::value::JsValue> {
+                #constructor
+            }
+
+            fn raw_constructor(
+                ctx: ::starlight::gc::cell::GcPointer<::starlight::vm::context::Context>,
+                args: &::starlight::vm::arguments::Arguments,
+            ) -> Result<::starlight::vm::value::JsValue, ::starlight::vm::value::JsValue> {
+                let data = <Self as ::starlight::vm::builder::ClassConstructor>::constructor(ctx, args)?;
+                Ok(::starlight::vm::value::JsValue::new(
+                    ::starlight::vm::builder::new_instance(ctx, data),
+                ))
+            }
+
+            fn init(
+                builder: &mut ::starlight::vm::builder::ClassBuilder,
+            ) -> Result<(), ::starlight::vm::value::JsValue> {
+                #(#registrations)*
+                Ok(())
+            }
+        }
+
+        const _: () = {
+            #[::starlight::__ctor::ctor]
+            fn register_native_references() {
+                ::starlight::gc::snapshot::references::register_native_reference(
+                    concat!(module_path!(), "::", #constructor_name),
+                    <#self_ty as ::starlight::vm::builder::ClassConstructor>::raw_constructor as usize,
+                );
+                #(#references)*
+            }
+        };
+    })
+}
@@ -1,9 +1,13 @@
 /* This Source Code Form is subject to the terms of the Mozilla Public
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+use proc_macro::TokenStream;
 use quote::quote;
+use syn::{parse_macro_input, AttributeArgs, ItemImpl, ItemStruct};
 use synstructure::{decl_derive, BindStyle, Structure};
 
+mod class;
+
 decl_derive!([GcTrace, attributes(unsafe_ignore_trace)] => derive_trace);
 
 fn derive_trace(mut s: Structure<'_>) -> proc_macro2::TokenStream {
@@ -35,3 +39,43 @@ fn derive_trace(mut s: Structure<'_>) -> proc_macro2::TokenStream {
         #trace_impl
     }
 }
+
+/// Implements `JsClass` for a struct so it can be registered with `Context::register_class`. The struct must
+/// implement `Trace` (usually through `#[derive(GcTrace)]`), its data is dropped when object is collected.
+///
+/// Options:
+/// - `name = "..."`: JS class name, defaults to the struct name.
+/// - `snapshot`: store struct data in snapshots, requires `Serializable` and `Deserializable` implementations.
+#[proc_macro_attribute]
+pub fn js_class(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as AttributeArgs);
+    let item = parse_macro_input!(item as ItemStruct);
+    class::expand_class(args, item)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+/// Implements `ClassConstructor` from methods of an impl block marked with:
+/// - `#[js_constructor]`: called by `new`, returns `Self` or `Result<Self, JsValue>`.
+/// - `#[js_method]`: method on prototype, or on constructor if it has no `self` receiver.
+/// - `#[js_getter]`, `#[js_setter]`: accessor, `get_`/`set_` prefix is removed from the name.
+///
+/// JS names are camelCase versions of Rust names unless `name = "..."` is given. Arguments are converted with
+/// `FromJs` and results with `IntoJs`, `GcPointer<Context>` and `&Arguments` parameters receive the current context
+/// and raw arguments. Generated functions are registered as native references at startup so classes can be stored
+/// in snapshots.
+#[proc_macro_attribute]
+pub fn js_impl(args: TokenStream, item: TokenStream) -> TokenStream {
+    if !args.is_empty() {
+        return syn::Error::new(
+            proc_macro2::Span::call_site(),
+            "js_impl doesn't take arguments",
+        )
+        .to_compile_error()
+        .into();
+    }
+    let item = parse_macro_input!(item as ItemImpl);
+    class::expand_impl(item)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
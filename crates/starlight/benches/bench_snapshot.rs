@@ -25,7 +25,7 @@ fn criterion_benchmark(c: &mut Criterion) {
         b.iter_with_large_drop(|| {
             let opts = Options::default();
             let heap = default_heap(&opts);
-            Deserializer::deserialize(false, &snapshot, opts, heap, None, |_, _| {})
+            Deserializer::deserialize(false, &snapshot, opts, heap, None, |_, _| {}).unwrap()
         });
     });
 }
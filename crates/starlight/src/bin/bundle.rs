@@ -1,9 +1,19 @@
 use starlight::{
-    gc::snapshot::Snapshot,
-    vm::{context::Context, VirtualMachine},
+    bundle::{appended_len, compile_entry, Bundle},
+    gc::{
+        default_heap,
+        snapshot::{
+            base::SnapshotData, deserializer::Deserializer, serializer::SnapshotSerializer,
+            Snapshot,
+        },
+    },
+    vm::{context::Context, VirtualMachine, VirtualMachineRef},
     Platform,
 };
-use std::path::PathBuf;
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+};
 use structopt::*;
 
 #[derive(Debug, StructOpt)]
@@ -16,18 +26,198 @@ pub struct Options {
     use_musl: bool,
     #[structopt(long = "output-c", help = "Output bundle as raw C file")]
     output_c: bool,
+    #[structopt(
+        long = "strip-source",
+        help = "Do not store source text of functions in the bundle"
+    )]
+    strip_source: bool,
+    #[structopt(
+        long = "base",
+        parse(from_os_str),
+        help = "Store only the difference to this base snapshot, bundle loads the base from this path at runtime. \
+                Base is written if it doesn't exist or is stale"
+    )]
+    base: Option<PathBuf>,
+    #[structopt(
+        long = "standalone",
+        conflicts_with = "base",
+        help = "Append bundle to a copy of the sl runner instead of linking it with C compiler"
+    )]
+    standalone: bool,
+    #[structopt(
+        long = "runner",
+        parse(from_os_str),
+        help = "Runner executable for standalone bundle, sl next to this executable by default"
+    )]
+    runner: Option<PathBuf>,
+    #[structopt(
+        long = "include",
+        parse(from_os_str),
+        number_of_values = 1,
+        help = "File or directory to include in standalone bundle, modules and files read with std.File"
+    )]
+    include: Vec<PathBuf>,
+    #[structopt(
+        last = true,
+        help = "Runtime flags of standalone bundle, same as flags of sl"
+    )]
+    runtime_flags: Vec<String>,
+}
+
+fn fail(message: &str, error: impl std::fmt::Display) -> ! {
+    eprintln!("{}: {}", message, error);
+    std::process::exit(1);
+}
+
+/// Add file at `path` or all files under directory at `path` to `files`.
+fn collect_files(path: &Path, files: &mut Vec<(PathBuf, Cow<'static, [u8]>)>) {
+    let path = std::fs::canonicalize(path)
+        .unwrap_or_else(|error| fail(&format!("Failed to include {}", path.display()), error));
+    if path.is_dir() {
+        let entries = std::fs::read_dir(&path)
+            .unwrap_or_else(|error| fail(&format!("Failed to read {}", path.display()), error));
+        for entry in entries {
+            let entry = entry
+                .unwrap_or_else(|error| fail(&format!("Failed to read {}", path.display()), error));
+            collect_files(&entry.path(), files);
+        }
+    } else if !files.iter().any(|(file, _)| *file == path) {
+        let data = std::fs::read(&path)
+            .unwrap_or_else(|error| fail(&format!("Failed to read {}", path.display()), error));
+        files.push((path, data.into()));
+    }
+}
+
+/// Write executable that runs `opts.input` without C toolchain: snapshot, included files and runtime flags are
+/// appended to a copy of the runner.
+fn write_standalone(opts: &Options) {
+    let entry = std::fs::canonicalize(&opts.input)
+        .unwrap_or_else(|error| fail("Failed to read JS file", error));
+    let root = entry.parent().map(Path::to_path_buf).unwrap_or_default();
+    let runtime_flags = std::iter::once("sl".to_owned())
+        .chain(opts.runtime_flags.iter().cloned())
+        .chain(std::iter::once(entry.to_string_lossy().into_owned()));
+    let mut options = starlight::options::Options::from_iter_safe(runtime_flags)
+        .unwrap_or_else(|error| error.exit());
+    // `--stripSource` may also be given as a runtime flag.
+    if opts.strip_source {
+        options = options.with_strip_source(true);
+    }
+
+    let mut files = vec![];
+    collect_files(&entry, &mut files);
+    for path in opts.include.iter() {
+        collect_files(path, &mut files);
+    }
+    let source = String::from_utf8_lossy(&files[0].1).into_owned();
+
+    let mut vm = VirtualMachine::new(options, None);
+    let ctx = Context::new(&mut vm);
+    vm.heap().defer();
+    let function = compile_entry(ctx, &entry, &source).unwrap_or_else(|error| {
+        let error = error.to_string(ctx).unwrap_or_default();
+        fail("Failed to compile JS file", error)
+    });
+    let snapshot = Snapshot::take(false, &mut vm, |ser, _| ser.write_gcpointer(function));
+    vm.heap().undefer();
+    unsafe {
+        vm.dispose();
+    }
+
+    let bundle = Bundle {
+        root,
+        entry,
+        args: opts.runtime_flags.clone(),
+        files,
+        snapshot: snapshot.buffer.into_vec().into(),
+    };
+    let runner = opts.runner.clone().unwrap_or_else(|| {
+        let exe = std::env::current_exe().unwrap_or_default();
+        exe.with_file_name(format!("sl{}", std::env::consts::EXE_SUFFIX))
+    });
+    let mut executable = std::fs::read(&runner).unwrap_or_else(|error| {
+        fail(
+            &format!("Failed to read runner {}", runner.display()),
+            error,
+        )
+    });
+    // runner may be a bundle itself.
+    if let Some(len) = appended_len(&executable) {
+        executable.truncate(executable.len() - len);
+    }
+    executable.extend_from_slice(&bundle.encode());
+    std::fs::write(&opts.output, &executable)
+        .unwrap_or_else(|error| fail("Failed to write bundle", error));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&opts.output, std::fs::Permissions::from_mode(0o755))
+            .unwrap_or_else(|error| fail("Failed to write bundle", error));
+    }
+}
+
+/// Load VM from base snapshot at `path`, base is written first if it is missing or can't be loaded.
+fn load_base(path: &Path, options: &starlight::options::Options) -> VirtualMachineRef {
+    let load = || {
+        let data = SnapshotData::map(path).map_err(|error| error.to_string())?;
+        let heap = default_heap(options);
+        Deserializer::deserialize_base(false, data, options.clone(), heap, None, |_, _| {})
+            .map_err(|error| error.to_string())
+    };
+    load().unwrap_or_else(|error| {
+        eprintln!("Writing base snapshot {}: {}", path.display(), error);
+        let mut vm = VirtualMachine::new(options.clone(), None);
+        Context::new(&mut vm);
+        let snapshot = Snapshot::take(false, &mut vm, |_, _| {});
+        unsafe {
+            vm.dispose();
+        }
+        // bundles may have the base mapped, so it is replaced instead of being written in place.
+        let temp = path.with_extension(format!("{}.tmp", std::process::id()));
+        std::fs::write(&temp, &snapshot.buffer)
+            .and_then(|_| std::fs::rename(&temp, path))
+            .unwrap_or_else(|error| {
+                eprintln!("Failed to write base snapshot: {}", error);
+                std::process::exit(1);
+            });
+        load().unwrap_or_else(|error| {
+            eprintln!("Failed to load base snapshot: {}", error);
+            std::process::exit(1);
+        })
+    })
 }
 
 fn main() {
     let opts = Options::from_args();
     Platform::initialize();
+    if opts.standalone {
+        write_standalone(&opts);
+        return;
+    }
     let string = std::fs::read_to_string(&opts.input).unwrap_or_else(|error| {
         eprintln!("Failed to read JS file: {}", error);
         std::process::exit(1);
     });
 
-    let mut vm = VirtualMachine::new(starlight::options::Options::default(), None);
-    let ctx = Context::new(&mut vm);
+    let options = starlight::options::Options::default().with_strip_source(opts.strip_source);
+    let base = opts.base.as_ref().map(|base| {
+        let dir = base.parent().filter(|dir| !dir.as_os_str().is_empty());
+        std::fs::canonicalize(dir.unwrap_or_else(|| Path::new(".")))
+            .map(|dir| dir.join(base.file_name().unwrap_or_default()))
+            .unwrap_or_else(|_| base.clone())
+    });
+    let (mut vm, ctx) = match &base {
+        Some(base) => {
+            let mut vm = load_base(base, &options);
+            let ctx = vm.context(0);
+            (vm, ctx)
+        }
+        None => {
+            let mut vm = VirtualMachine::new(options, None);
+            let ctx = Context::new(&mut vm);
+            (vm, ctx)
+        }
+    };
     vm.heap().defer();
     let func = ctx
         .compile(
@@ -40,9 +230,22 @@ fn main() {
             eprintln!("Failed to compile JS file: {:?}", error);
             std::process::exit(1);
         });
-    let snapshot = Snapshot::take(false, &mut vm, |ser, _rt| {
+    let write_function = |ser: &mut SnapshotSerializer, _: &mut VirtualMachine| {
         ser.write_gcpointer(func.get_object())
-    });
+    };
+    let snapshot = if base.is_some() {
+        Snapshot::take_delta(false, &mut vm, write_function)
+    } else {
+        Snapshot::take(false, &mut vm, write_function)
+    };
+    let execute = match &base {
+        Some(base) => format!(
+            "__execute_layered_bundle({:?},snapshot,{})",
+            base.display().to_string(),
+            snapshot.buffer.len()
+        ),
+        None => format!("__execute_bundle(snapshot,{})", snapshot.buffer.len()),
+    };
     vm.heap().undefer();
     let mut c_src = String::with_capacity(snapshot.buffer.len() + 128);
     c_src.push_str(&format!(
@@ -67,13 +270,14 @@ static const uint8_t snapshot[{}] = {{
  
 
     void __execute_bundle(const uint8_t*,size_t);
+    void __execute_layered_bundle(const char*,const uint8_t*,size_t);
     void platform_initialize();
     int main() {{
         platform_initialize();
-        __execute_bundle(snapshot,{});
+        {};
     }}
     "#,
-        snapshot.buffer.len()
+        execute
     ));
     unsafe {
         vm.dispose();
@@ -1,48 +1,48 @@
 /* This Source Code Form is subject to the terms of the Mozilla Public
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+use starlight::bundle::Bundle;
 use starlight::gc::default_heap;
+use starlight::gc::snapshot::{base::SnapshotData, code_cache::FileCodeCache};
 use starlight::prelude::*;
 use starlight::vm::context::Context;
-use std::path::Path;
+use starlight::vm::JobQueue;
 use structopt::*;
 
 #[cfg(not(debug_assertions))]
 #[global_allocator]
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
-use const_random::const_random;
-const BIN_ID: u64 = const_random!(u64);
 const SNAPSHOT_FILENAME: &str = ".startup-snapshot";
 fn main() {
     Platform::initialize();
+    // executables made with `starlight-bundle --standalone` are copies of sl with bundle appended.
+    if let Some(bundle) = Bundle::current() {
+        std::process::exit(bundle.run());
+    }
     let options = Options::from_args();
 
     let mut deserialized = false;
-    let mut vm = if Path::new(SNAPSHOT_FILENAME).exists() {
-        let mut src = std::fs::read(SNAPSHOT_FILENAME);
-        match src {
-            Ok(ref mut src) => {
-                let mut bytes: [u8; 8] = [0; 8];
-                bytes.copy_from_slice(&src[0..8]);
-                let heap = default_heap(&options);
-                if u64::from_ne_bytes(bytes) != BIN_ID {
-                    VirtualMachine::with_heap(heap, options, None)
-                } else {
-                    let snapshot = &src[8..];
-                    deserialized = true;
-                    let heap = default_heap(&options);
-                    Deserializer::deserialize(false, snapshot, options, heap, None, |_, _| {})
-                }
-            }
-            Err(_) => {
-                let heap = default_heap(&options);
-                VirtualMachine::with_heap(heap, options, None)
-            }
+    // snapshot that fails validation was written by another build or is damaged, it is regenerated below.
+    let snapshot = SnapshotData::map(SNAPSHOT_FILENAME)
+        .ok()
+        .and_then(|snapshot| {
+            let heap = default_heap(&options);
+            Deserializer::deserialize(false, &snapshot, options.clone(), heap, None, |_, _| {})
+                .map_err(|error| {
+                    eprintln!("Regenerating {}: {}", SNAPSHOT_FILENAME, error);
+                })
+                .ok()
+        });
+    let mut vm = match snapshot {
+        Some(vm) => {
+            deserialized = true;
+            vm
+        }
+        None => {
+            let heap = default_heap(&options);
+            VirtualMachine::with_heap(heap, options, None)
         }
-    } else {
-        let heap = default_heap(&options);
-        VirtualMachine::with_heap(heap, options, None)
     };
 
     #[cfg(all(target_pointer_width = "64", feature = "ffi"))]
@@ -50,6 +50,13 @@ fn main() {
         vm.add_ffi();
     }
 
+    if vm.options().code_cache {
+        vm.set_code_cache(Some(Box::new(FileCodeCache)));
+    }
+
+    let jobs = JobQueue::default();
+    vm = vm.with_async_scheduler(jobs.scheduler());
+
     let mut ctx = if !deserialized {
         Context::new(&mut vm)
     } else {
@@ -58,10 +65,16 @@ fn main() {
 
     if !deserialized {
         let snapshot = Snapshot::take(false, &mut vm, |_, _| {});
-        let mut buf = Vec::<u8>::with_capacity(8 + snapshot.buffer.len());
-        buf.extend(&BIN_ID.to_ne_bytes());
-        buf.extend(snapshot.buffer.iter());
-        std::fs::write(SNAPSHOT_FILENAME, &buf).unwrap();
+        // other processes may have the snapshot mapped, so it is replaced instead of being written in place.
+        let temp = format!("{}.{}", SNAPSHOT_FILENAME, std::process::id());
+        std::fs::write(&temp, &snapshot.buffer).unwrap();
+        std::fs::rename(&temp, SNAPSHOT_FILENAME).unwrap();
+    }
+
+    // module loader isn't part of the snapshot, so CommonJS is enabled after it is taken.
+    if vm.options().commonjs {
+        ctx.enable_commonjs()
+            .unwrap_or_else(|_| panic!("Failed to enable CommonJS"));
     }
 
     let gcstack = vm.shadowstack();
@@ -89,24 +102,13 @@ fn main() {
                     }
                 }
             );
-            letroot!(funcc = gcstack, *function);
-            let global = ctx.global_object();
-            letroot!(module_object = gcstack, JsObject::new_empty(ctx));
-            let exports = JsObject::new_empty(ctx);
-            module_object
-                .put(ctx, "@exports".intern(), JsValue::new(exports), false)
-                .unwrap_or_else(|_| unreachable!());
-            let mut args = [JsValue::new(*module_object)];
-            letroot!(
-                args = gcstack,
-                Arguments::new(JsValue::encode_object_value(global), &mut args)
-            );
-
             let start = std::time::Instant::now();
-            match function
-                .as_function_mut()
-                .call(ctx, &mut args, JsValue::new(*funcc))
-            {
+            let result = ctx.evaluate_module(&name, *function).and_then(|module| {
+                // evaluation of module with top level await completes in jobs.
+                jobs.run(ctx);
+                module.error().map_or(Ok(()), Err)
+            });
+            match result {
                 Ok(_) => {
                     let elapsed = start.elapsed();
                     eprintln!("Executed in {}ms", elapsed.as_nanos() as f64 / 1000000f64);
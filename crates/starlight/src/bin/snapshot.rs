@@ -21,7 +21,7 @@ fn main() {
     runtime.heap().undefer();
 
     let start = Instant::now();
-    let mut ctx = Deserializer::deserialize_context(&mut runtime, false, &buffer);
+    let mut ctx = Deserializer::deserialize_context(&mut runtime, false, &buffer).unwrap();
     println!("Deserialize context cost: {:?}", start.elapsed());
     ctx.eval("print('hello,world');print=null;").unwrap();
 
@@ -0,0 +1,367 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+//! Self-contained bundles.
+//!
+//! `starlight-bundle --standalone` appends a bundle to a copy of the `sl` executable, `sl` checks its own executable
+//! for a bundle at startup and runs it instead of parsing command line. Bundle consists of the snapshot with compiled
+//! entry module, files that are available to the bundle at runtime (modules it imports and assets read with
+//! `std.File`) and runtime flags. It is followed by a trailer of payload length and [BUNDLE_MAGIC].
+//!
+//! Bundled files are keyed by their absolute path when the bundle was created. Module loader and `std.File` look
+//! files up in the bundle before the file system, relative paths are resolved against the directory of the entry
+//! module.
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    convert::TryInto,
+    path::{Component, Path, PathBuf},
+};
+
+use memmap2::Mmap;
+use once_cell::sync::OnceCell;
+use structopt::StructOpt;
+
+use crate::{
+    gc::{cell::GcPointer, default_heap, snapshot::deserializer::Deserializable},
+    options::Options,
+    prelude::*,
+    vm::{context::Context, object::JsObject, JobQueue},
+};
+
+pub const BUNDLE_MAGIC: [u8; 8] = *b"SLBUNDLE";
+const BUNDLE_VERSION: u32 = 1;
+const TRAILER_SIZE: usize = 8 + BUNDLE_MAGIC.len();
+
+/// Contents of a bundle.
+pub struct Bundle<'a> {
+    /// Directory of the entry module when the bundle was created, relative paths are resolved against it.
+    pub root: PathBuf,
+    /// Path of the entry module.
+    pub entry: PathBuf,
+    /// Runtime flags passed to [Options](crate::options::Options) before the entry path.
+    pub args: Vec<String>,
+    /// Bundled files keyed by their absolute path.
+    pub files: Vec<(PathBuf, Cow<'a, [u8]>)>,
+    /// Snapshot of the VM with compiled entry module written by the snapshot callback. Entry module is compiled
+    /// from the bundled source when snapshot can't be loaded, e.g. when runner is another engine build.
+    pub snapshot: Cow<'a, [u8]>,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pc: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pc..self.pc.checked_add(len)?)?;
+        self.pc += len;
+        Some(bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes(8)?.try_into().ok()?))
+    }
+
+    fn blob(&mut self) -> Option<&'a [u8]> {
+        let len = self.u64()?;
+        self.bytes(len as usize)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        String::from_utf8(self.blob()?.to_vec()).ok()
+    }
+}
+
+fn write_blob(output: &mut Vec<u8>, blob: &[u8]) {
+    output.extend_from_slice(&(blob.len() as u64).to_le_bytes());
+    output.extend_from_slice(blob);
+}
+
+fn write_path(output: &mut Vec<u8>, path: &Path) {
+    write_blob(output, path.to_string_lossy().as_bytes());
+}
+
+/// Length of the bundle appended to `executable` including its trailer.
+pub fn appended_len(executable: &[u8]) -> Option<usize> {
+    let trailer = executable.len().checked_sub(TRAILER_SIZE)?;
+    if executable[trailer + 8..] != BUNDLE_MAGIC {
+        return None;
+    }
+    let len = u64::from_le_bytes(executable[trailer..trailer + 8].try_into().ok()?) as usize;
+    len.checked_add(TRAILER_SIZE)
+        .filter(|len| *len <= executable.len())
+}
+
+impl<'a> Bundle<'a> {
+    /// Encode bundle followed by its trailer, result is appended to the runner executable.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut output = vec![];
+        output.extend_from_slice(&BUNDLE_VERSION.to_le_bytes());
+        write_path(&mut output, &self.root);
+        write_path(&mut output, &self.entry);
+        output.extend_from_slice(&(self.args.len() as u32).to_le_bytes());
+        for arg in self.args.iter() {
+            write_blob(&mut output, arg.as_bytes());
+        }
+        output.extend_from_slice(&(self.files.len() as u32).to_le_bytes());
+        for (path, data) in self.files.iter() {
+            write_path(&mut output, path);
+            write_blob(&mut output, data);
+        }
+        write_blob(&mut output, &self.snapshot);
+        let len = output.len() as u64;
+        output.extend_from_slice(&len.to_le_bytes());
+        output.extend_from_slice(&BUNDLE_MAGIC);
+        output
+    }
+
+    /// Decode bundle appended to `executable`. Returns `None` if there's no bundle or it is malformed.
+    pub fn decode(executable: &'a [u8]) -> Option<Self> {
+        let len = appended_len(executable)?;
+        let start = executable.len() - len;
+        let mut reader = Reader {
+            data: &executable[start..executable.len() - TRAILER_SIZE],
+            pc: 0,
+        };
+        if reader.u32()? != BUNDLE_VERSION {
+            return None;
+        }
+        let root = PathBuf::from(reader.string()?);
+        let entry = PathBuf::from(reader.string()?);
+        let args = (0..reader.u32()?)
+            .map(|_| reader.string())
+            .collect::<Option<Vec<_>>>()?;
+        let files = (0..reader.u32()?)
+            .map(|_| Some((PathBuf::from(reader.string()?), reader.blob()?.into())))
+            .collect::<Option<Vec<_>>>()?;
+        let snapshot = reader.blob()?.into();
+        Some(Self {
+            root,
+            entry,
+            args,
+            files,
+            snapshot,
+        })
+    }
+}
+
+impl Bundle<'static> {
+    /// Bundle appended to the running executable.
+    pub fn current() -> Option<Self> {
+        let file = std::fs::File::open(std::env::current_exe().ok()?).ok()?;
+        // SAFETY: executable of the running process can't be modified while it runs.
+        let map = unsafe { Mmap::map(&file).ok()? };
+        appended_len(&map)?;
+        // bundled files are used for the whole lifetime of the process.
+        let executable: &'static [u8] = Box::leak(Box::new(map));
+        Self::decode(executable)
+    }
+
+    /// Make bundled files available to module loader and `std.File`. Only the first installed bundle is used.
+    pub fn install_files(&self) {
+        let files = self
+            .files
+            .iter()
+            .map(|(path, data)| {
+                let data: &'static [u8] = match data {
+                    Cow::Borrowed(data) => data,
+                    Cow::Owned(data) => Box::leak(data.clone().into_boxed_slice()),
+                };
+                (normalize(path), data)
+            })
+            .collect();
+        let _ = FILES.set(BundledFiles {
+            root: self.root.clone(),
+            files,
+        });
+    }
+
+    /// Run the bundle and return exit code. Entry module is loaded from the snapshot or compiled from the bundled
+    /// source if snapshot can't be loaded.
+    pub fn run(self) -> i32 {
+        self.install_files();
+        let args = std::iter::once("sl".to_owned())
+            .chain(self.args.iter().cloned())
+            .chain(std::iter::once(self.entry.to_string_lossy().into_owned()));
+        let options = Options::from_iter(args);
+
+        let mut function = None;
+        let heap = default_heap(&options);
+        let loaded = Deserializer::deserialize(
+            false,
+            &self.snapshot,
+            options.clone(),
+            heap,
+            None,
+            |deser, _| unsafe {
+                function = Some(GcPointer::<JsObject>::deserialize_inplace(deser));
+            },
+        );
+        let (mut vm, mut ctx) = match loaded {
+            Ok(mut vm) => {
+                let ctx = vm.context(0);
+                (vm, ctx)
+            }
+            Err(_) => {
+                let mut vm = VirtualMachine::with_heap(default_heap(&options), options, None);
+                let mut ctx = Context::new(&mut vm);
+                let source = bundled_file(&self.entry).unwrap_or_default();
+                match compile_entry(ctx, &self.entry, &String::from_utf8_lossy(source)) {
+                    Ok(entry) => function = Some(entry),
+                    Err(error) => {
+                        let error = error.to_string(ctx).unwrap_or_default();
+                        eprintln!("Compilation failed: {}", error);
+                        return 1;
+                    }
+                }
+                (vm, ctx)
+            }
+        };
+
+        #[cfg(all(target_pointer_width = "64", feature = "ffi"))]
+        if vm.options().enable_ffi {
+            vm.add_ffi();
+        }
+        if vm.options().commonjs {
+            ctx.enable_commonjs()
+                .unwrap_or_else(|_| panic!("Failed to enable CommonJS"));
+        }
+        let jobs = JobQueue::default();
+        vm = vm.with_async_scheduler(jobs.scheduler());
+
+        let stack = vm.shadowstack();
+        letroot!(
+            function = stack,
+            function.expect("bundle has no entry module")
+        );
+        let mut code = 0;
+        let result = ctx
+            .evaluate_module(&self.entry.to_string_lossy(), *function)
+            .and_then(|module| {
+                jobs.run(ctx);
+                module.error().map_or(Ok(()), Err)
+            });
+        if let Err(error) = result {
+            let error = error
+                .to_string(ctx)
+                .unwrap_or_else(|_| "<unknown error>".to_owned());
+            eprintln!("Uncaught exception: {}", error);
+            eprintln!("Stacktrace: \n{}", ctx.take_stacktrace());
+            code = 1;
+        }
+        unsafe {
+            vm.dispose();
+        }
+        code
+    }
+}
+
+/// Compile `entry` module of a bundle from `source`.
+pub fn compile_entry(
+    mut ctx: GcPointer<Context>,
+    entry: &Path,
+    source: &str,
+) -> Result<GcPointer<JsObject>, JsValue> {
+    let name = entry
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    ctx.compile_module(&entry.to_string_lossy(), &name, source)
+        .map(|function| function.get_jsobject())
+}
+
+struct BundledFiles {
+    root: PathBuf,
+    files: HashMap<PathBuf, &'static [u8]>,
+}
+
+static FILES: OnceCell<BundledFiles> = OnceCell::new();
+
+/// Resolve `.` and `..` components of `path` without touching the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Contents of bundled file at `path`. Relative paths are resolved against the directory of the entry module.
+pub fn bundled_file(path: impl AsRef<Path>) -> Option<&'static [u8]> {
+    let bundle = FILES.get()?;
+    bundle.files.get(&bundled_path(path)?).copied()
+}
+
+/// Normalized path of bundled file at `path`, `None` if the file is not bundled.
+pub(crate) fn bundled_path(path: impl AsRef<Path>) -> Option<PathBuf> {
+    let bundle = FILES.get()?;
+    let path = path.as_ref();
+    let path = if path.is_relative() {
+        normalize(&bundle.root.join(path))
+    } else {
+        normalize(path)
+    };
+    if bundle.files.contains_key(&path) {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let bundle = Bundle {
+            root: PathBuf::from("/app"),
+            entry: PathBuf::from("/app/main.js"),
+            args: vec!["--verboseGC".to_owned()],
+            files: vec![(PathBuf::from("/app/lib.js"), Cow::Borrowed(&b"lib"[..]))],
+            snapshot: Cow::Borrowed(&b"snapshot"[..]),
+        };
+        let mut executable = b"runner".to_vec();
+        assert_eq!(appended_len(&executable), None);
+        executable.extend_from_slice(&bundle.encode());
+        assert_eq!(appended_len(&executable), Some(executable.len() - 6));
+
+        let decoded = Bundle::decode(&executable).unwrap();
+        assert_eq!(decoded.root, bundle.root);
+        assert_eq!(decoded.entry, bundle.entry);
+        assert_eq!(decoded.args, bundle.args);
+        assert_eq!(decoded.files, bundle.files);
+        assert_eq!(decoded.snapshot, bundle.snapshot);
+
+        // payload length pointing before the start of the executable.
+        let mut broken = executable.clone();
+        let trailer = broken.len() - TRAILER_SIZE;
+        broken[trailer..trailer + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(Bundle::decode(&broken).is_none());
+        // payload cut in the middle of a file.
+        let mut truncated = executable[..executable.len() - TRAILER_SIZE - 12].to_vec();
+        truncated.extend_from_slice(&((truncated.len() - 6) as u64).to_le_bytes());
+        truncated.extend_from_slice(&BUNDLE_MAGIC);
+        assert!(Bundle::decode(&truncated).is_none());
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            normalize(Path::new("/app/./lib/../data/text.txt")),
+            PathBuf::from("/app/data/text.txt")
+        );
+    }
+}
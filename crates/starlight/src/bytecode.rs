@@ -3,17 +3,69 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 use crate::{
     gc::cell::{GcPointer, Trace, Tracer},
-    vm::{object::JsObject, structure::Structure, structure_chain::StructureChain},
+    vm::{
+        code_block::CodeBlock,
+        function::{FuncType, JsAPI, JsFunction}, object::JsObject, structure::Structure,
+        structure_chain::StructureChain,
+    },
 };
 
+pub mod inline_cache;
 pub mod opcodes;
 pub mod profile;
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum GetByIdMode {
     Default,
-    ProtoLoad(GcPointer<JsObject> /*cached slot */),
+    /// Property was found on `holder` somewhere in the prototype chain. Chain stores structures of all prototypes
+    /// starting from direct prototype of the receiver and is used to validate that chain did not change.
+    ProtoLoad(
+        GcPointer<JsObject>, /* holder */
+        GcPointer<StructureChain>,
+    ),
     ArrayLength,
 }
+
+/// Single entry of monomorphic or polymorphic load cache.
+#[derive(Clone, Copy)]
+pub struct PropertyCacheEntry {
+    pub structure: GcPointer<Structure>,
+    pub offset: u32,
+    pub mode: GetByIdMode,
+}
+
+/// Single entry of polymorphic store cache. Only stores to existing properties are cached.
+#[derive(Clone, Copy)]
+pub struct PutByIdCacheEntry {
+    pub structure: GcPointer<Structure>,
+    pub offset: u32,
+}
+
+/// Callee recorded by monomorphic `call`/`new` site.
+#[derive(Clone, Copy)]
+pub enum CallTarget {
+    /// Function compiled to bytecode, all closures created from the same code share single target.
+    Code(GcPointer<CodeBlock>),
+    Native(JsAPI),
+}
+
+impl CallTarget {
+    pub fn matches(&self, func: &JsFunction) -> bool {
+        match (self, &func.ty) {
+            (CallTarget::Code(code), FuncType::User(vm_fn)) => GcPointer::ptr_eq(code, &vm_fn.code),
+            (CallTarget::Native(f), FuncType::Native(native)) => *f as usize == native.func as usize,
+            _ => false,
+        }
+    }
+
+    pub fn of(func: &JsFunction) -> Option<Self> {
+        match &func.ty {
+            FuncType::User(vm_fn) => Some(CallTarget::Code(vm_fn.code)),
+            FuncType::Native(native) => Some(CallTarget::Native(native.func)),
+            _ => None,
+        }
+    }
+}
+
 pub enum TypeFeedBack {
     StructureCache {
         structure: GcPointer<Structure>,
@@ -23,15 +75,48 @@ pub enum TypeFeedBack {
         offset: u32,
         mode: GetByIdMode,
     },
+    /// Up to [inline_cache::POLYMORPHIC_CACHE_SIZE] entries ordered from most to least recently used.
+    PolyPropertyCache {
+        entries: Vec<PropertyCacheEntry>,
+        evictions: u32,
+    },
     PutByIdFeedBack {
         new_structure: Option<GcPointer<Structure>>,
         old_structure: Option<GcPointer<Structure>>,
         offset: u32,
         structure_chain: Option<GcPointer<StructureChain>>,
     },
+    /// Up to [inline_cache::POLYMORPHIC_CACHE_SIZE] entries ordered from most to least recently used.
+    PolyPutByIdFeedBack {
+        entries: Vec<PutByIdCacheEntry>,
+        evictions: u32,
+    },
+    /// Monomorphic `call` or `new` site.
+    CallFeedBack {
+        target: CallTarget,
+        /// Structure used to allocate `this` at `new` site. It is valid for as long as it is
+        /// the cached construct structure of the callee.
+        construct_structure: Option<GcPointer<Structure>>,
+    },
+    /// Site saw too many structures, lookups go through global [inline_cache::MegamorphicCache].
+    ///
+    /// For `call`/`new` sites this means that more than one callee was seen and generic call path is used.
+    Megamorphic,
     None,
 }
 
+unsafe impl Trace for GetByIdMode {
+    fn trace(&mut self, visitor: &mut dyn Tracer) {
+        match self {
+            GetByIdMode::ProtoLoad(holder, chain) => {
+                holder.trace(visitor);
+                chain.trace(visitor);
+            }
+            _ => (),
+        }
+    }
+}
+
 unsafe impl Trace for TypeFeedBack {
     fn trace(&mut self, visitor: &mut dyn Tracer) {
         match self {
@@ -39,9 +124,12 @@ unsafe impl Trace for TypeFeedBack {
                 structure, mode, ..
             } => {
                 structure.trace(visitor);
-                match mode {
-                    GetByIdMode::ProtoLoad(slot) => slot.trace(visitor),
-                    _ => (),
+                mode.trace(visitor);
+            }
+            Self::PolyPropertyCache { entries, .. } => {
+                for entry in entries.iter_mut() {
+                    entry.structure.trace(visitor);
+                    entry.mode.trace(visitor);
                 }
             }
             Self::StructureCache { structure } => structure.trace(visitor),
@@ -55,6 +143,20 @@ unsafe impl Trace for TypeFeedBack {
                 old_structure.trace(visitor);
                 structure_chain.trace(visitor);
             }
+            Self::PolyPutByIdFeedBack { entries, .. } => {
+                for entry in entries.iter_mut() {
+                    entry.structure.trace(visitor);
+                }
+            }
+            Self::CallFeedBack {
+                target,
+                construct_structure,
+            } => {
+                if let CallTarget::Code(code) = target {
+                    code.trace(visitor);
+                }
+                construct_structure.trace(visitor);
+            }
             _ => (),
         }
     }
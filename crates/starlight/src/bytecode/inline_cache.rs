@@ -0,0 +1,609 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+//! Polymorphic and megamorphic inline caches.
+//!
+//! Every property access site starts in uninitialized state (`TypeFeedBack::None`). After first cacheable access
+//! it becomes monomorphic, when another structure is seen site becomes polymorphic and keeps up to
+//! [POLYMORPHIC_CACHE_SIZE] entries in LRU order. When polymorphic site keeps evicting entries it goes megamorphic and
+//! from then on all lookups go through global [MegamorphicCache] that is shared by all sites in the VM.
+use super::{GetByIdMode, PropertyCacheEntry, PutByIdCacheEntry, TypeFeedBack};
+use crate::{
+    gc::cell::GcPointer,
+    vm::{
+        array::JsArray, class::JsClass, context::Context, object::JsObject, slot::Slot,
+        structure::Structure, structure_chain::StructureChain, symbol_table::Symbol,
+        value::JsValue,
+    },
+};
+use std::collections::{HashMap, HashSet};
+
+/// Maximal number of structures that polymorphic site can hold.
+pub const POLYMORPHIC_CACHE_SIZE: usize = 4;
+/// Number of evictions from full polymorphic site after which site becomes megamorphic.
+pub const MAX_POLYMORPHIC_EVICTIONS: u32 = 4;
+/// Global cache is flushed when it grows larger than this.
+pub const MEGAMORPHIC_CACHE_CAPACITY: usize = 8192;
+
+/// Global `(Structure, Symbol) -> offset` cache used by megamorphic sites.
+///
+/// Entries are not traced, instead cache is flushed at the start of every GC cycle and whenever unique
+/// structure is mutated in place. `in` results are kept apart from loads since `in` only looks at own properties.
+#[derive(Default)]
+pub struct MegamorphicCache {
+    entries: HashMap<(usize, Symbol), PropertyCacheEntry>,
+    stores: HashMap<(usize, Symbol), PutByIdCacheEntry>,
+    ins: HashSet<(usize, Symbol)>,
+}
+
+impl MegamorphicCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, structure: GcPointer<Structure>, name: Symbol) -> Option<PropertyCacheEntry> {
+        self.entries
+            .get(&(structure.base.as_ptr() as usize, name))
+            .copied()
+    }
+
+    pub fn insert(&mut self, name: Symbol, entry: PropertyCacheEntry) {
+        if self.entries.len() >= MEGAMORPHIC_CACHE_CAPACITY {
+            self.entries.clear();
+        }
+        self.entries
+            .insert((entry.structure.base.as_ptr() as usize, name), entry);
+    }
+
+    pub fn get_put(&self, structure: GcPointer<Structure>, name: Symbol) -> Option<u32> {
+        self.stores
+            .get(&(structure.base.as_ptr() as usize, name))
+            .map(|entry| entry.offset)
+    }
+
+    pub fn insert_put(&mut self, name: Symbol, entry: PutByIdCacheEntry) {
+        if self.stores.len() >= MEGAMORPHIC_CACHE_CAPACITY {
+            self.stores.clear();
+        }
+        self.stores
+            .insert((entry.structure.base.as_ptr() as usize, name), entry);
+    }
+
+    /// Returns true if objects with `structure` are known to have own property `name`.
+    pub fn get_in(&self, structure: GcPointer<Structure>, name: Symbol) -> bool {
+        self.ins
+            .contains(&(structure.base.as_ptr() as usize, name))
+    }
+
+    pub fn insert_in(&mut self, structure: GcPointer<Structure>, name: Symbol) {
+        if self.ins.len() >= MEGAMORPHIC_CACHE_CAPACITY {
+            self.ins.clear();
+        }
+        self.ins.insert((structure.base.as_ptr() as usize, name));
+    }
+
+    /// Remove all entries from cache.
+    pub fn invalidate(&mut self) {
+        self.entries.clear();
+        self.stores.clear();
+        self.ins.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len() + self.stores.len() + self.ins.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty() && self.stores.is_empty() && self.ins.is_empty()
+    }
+}
+
+/// Inline cache counters printed with `--dumpStats`.
+#[derive(Default, Debug, Clone)]
+pub struct InlineCacheStats {
+    pub get_by_id_hits: u64,
+    pub get_by_id_misses: u64,
+    pub proto_load_hits: u64,
+    pub put_by_id_hits: u64,
+    pub put_by_id_misses: u64,
+    pub in_hits: u64,
+    pub in_misses: u64,
+    pub instanceof_hits: u64,
+    pub instanceof_misses: u64,
+    pub call_hits: u64,
+    pub call_misses: u64,
+    pub megamorphic_hits: u64,
+    pub megamorphic_misses: u64,
+    pub to_monomorphic: u64,
+    pub to_polymorphic: u64,
+    pub to_megamorphic: u64,
+    pub lru_evictions: u64,
+    pub invalidations: u64,
+}
+
+impl InlineCacheStats {
+    pub fn print_stats(&self) {
+        fn ratio(hits: u64, misses: u64) -> f64 {
+            if hits + misses == 0 {
+                0.0
+            } else {
+                hits as f64 * 100.0 / (hits + misses) as f64
+            }
+        }
+        eprintln!("+-------------------------------------------+");
+        eprintln!("| Inline cache stats:                       |");
+        eprintln!(
+            "| {:<20} {:>10} {:>10} {:>6} |",
+            "Site", "hits", "misses", "%hit"
+        );
+        eprintln!("+-------------------------------------------+");
+        for (name, hits, misses) in [
+            ("get_by_id", self.get_by_id_hits, self.get_by_id_misses),
+            ("put_by_id", self.put_by_id_hits, self.put_by_id_misses),
+            ("in", self.in_hits, self.in_misses),
+            ("instanceof", self.instanceof_hits, self.instanceof_misses),
+            ("call/new", self.call_hits, self.call_misses),
+            ("megamorphic", self.megamorphic_hits, self.megamorphic_misses),
+        ]
+        .iter()
+        {
+            eprintln!(
+                "  {:<20} {:>10} {:>10} {:>6.2}",
+                name,
+                hits,
+                misses,
+                ratio(*hits, *misses)
+            );
+        }
+        eprintln!("  prototype chain hits: {}", self.proto_load_hits);
+        eprintln!(
+            "  states: ->mono {} ->poly {} ->mega {}",
+            self.to_monomorphic, self.to_polymorphic, self.to_megamorphic
+        );
+        eprintln!(
+            "  LRU evictions: {}, global cache flushes: {}",
+            self.lru_evictions, self.invalidations
+        );
+    }
+}
+
+/// Check that prototype chain of `obj` still matches `chain` up to `holder`, that no unique (dictionary)
+/// structure in between got `name` added in place and that unique `holder` still has `name` at `offset`.
+pub fn is_chain_valid(
+    ctx: GcPointer<Context>,
+    obj: &GcPointer<JsObject>,
+    holder: GcPointer<JsObject>,
+    chain: GcPointer<StructureChain>,
+    name: Symbol,
+    offset: u32,
+) -> bool {
+    let mut current = obj.prototype().copied();
+    for cached in chain.vector.iter() {
+        let proto = match current {
+            Some(proto) => proto,
+            None => return false,
+        };
+        let mut structure = proto.structure();
+        if !GcPointer::ptr_eq(&structure, cached) {
+            return false;
+        }
+        if GcPointer::ptr_eq(&proto, &holder) {
+            if structure.is_unique() {
+                let entry = structure.get(ctx, name);
+                return !entry.is_not_found() && entry.offset == offset;
+            }
+            return true;
+        }
+        if structure.is_unique() && !structure.get(ctx, name).is_not_found() {
+            return false;
+        }
+        current = proto.prototype().copied();
+    }
+    false
+}
+
+/// Try to load property from `obj` using cache entry. `None` is returned if entry does not apply to `obj`.
+pub fn try_cached_load(
+    ctx: GcPointer<Context>,
+    obj: &GcPointer<JsObject>,
+    name: Symbol,
+    entry: &PropertyCacheEntry,
+) -> Option<JsValue> {
+    match entry.mode {
+        GetByIdMode::Default => {
+            if GcPointer::ptr_eq(&entry.structure, &obj.structure()) {
+                return Some(*obj.direct(entry.offset as _));
+            }
+        }
+        GetByIdMode::ProtoLoad(holder, chain) => {
+            if GcPointer::ptr_eq(&entry.structure, &obj.structure())
+                && is_chain_valid(ctx, obj, holder, chain, name, entry.offset)
+            {
+                ctx.vm().ic_stats.proto_load_hits += 1;
+                return Some(*holder.direct(entry.offset as _));
+            }
+        }
+        GetByIdMode::ArrayLength => {
+            if obj.is_class(JsArray::class()) {
+                return Some(JsValue::new(obj.indexed.length()));
+            }
+        }
+    }
+    None
+}
+
+/// Build cache entry for load that was just performed through `slot`. Returns `None` if load is not cacheable.
+pub fn load_cache_entry(
+    ctx: GcPointer<Context>,
+    obj: &GcPointer<JsObject>,
+    slot: &Slot,
+) -> Option<PropertyCacheEntry> {
+    if !slot.is_load_cacheable() {
+        return None;
+    }
+    let base = unsafe { slot.base().unwrap().downcast_unchecked::<JsObject>() };
+    let mut structure = obj.structure();
+    if GcPointer::ptr_eq(&base, obj) {
+        return Some(PropertyCacheEntry {
+            structure,
+            offset: slot.offset(),
+            mode: GetByIdMode::Default,
+        });
+    }
+    // Property might be added to unique structure of receiver in place so we can't prove that
+    // lookup still ends up in the prototype chain.
+    if structure.is_unique() {
+        return None;
+    }
+    let chain = structure.prototype_chain(ctx, *obj);
+    Some(PropertyCacheEntry {
+        structure,
+        offset: slot.offset(),
+        mode: GetByIdMode::ProtoLoad(base, chain),
+    })
+}
+
+impl TypeFeedBack {
+    pub fn is_megamorphic(&self) -> bool {
+        matches!(self, Self::Megamorphic)
+    }
+
+    /// Lookup load entry for `obj`. Polymorphic hit moves entry to the front of the cache.
+    pub fn lookup_load(
+        &mut self,
+        ctx: GcPointer<Context>,
+        obj: &GcPointer<JsObject>,
+        name: Symbol,
+    ) -> Option<JsValue> {
+        match self {
+            Self::PropertyCache {
+                structure,
+                offset,
+                mode,
+            } => try_cached_load(
+                ctx,
+                obj,
+                name,
+                &PropertyCacheEntry {
+                    structure: *structure,
+                    offset: *offset,
+                    mode: *mode,
+                },
+            ),
+            Self::PolyPropertyCache { entries, .. } => {
+                for i in 0..entries.len() {
+                    if let Some(value) = try_cached_load(ctx, obj, name, &entries[i]) {
+                        entries[..=i].rotate_right(1);
+                        return Some(value);
+                    }
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Record new load entry. Site moves from uninitialized to monomorphic, then to polymorphic and
+    /// finally to megamorphic state.
+    pub fn record_load(&mut self, mut ctx: GcPointer<Context>, entry: PropertyCacheEntry) {
+        let stats = &mut ctx.vm.ic_stats;
+        match self {
+            Self::None | Self::StructureCache { .. } => {
+                stats.to_monomorphic += 1;
+                *self = Self::PropertyCache {
+                    structure: entry.structure,
+                    offset: entry.offset,
+                    mode: entry.mode,
+                };
+            }
+            Self::PropertyCache {
+                structure,
+                offset,
+                mode,
+            } => {
+                stats.to_polymorphic += 1;
+                let old = PropertyCacheEntry {
+                    structure: *structure,
+                    offset: *offset,
+                    mode: *mode,
+                };
+                *self = Self::PolyPropertyCache {
+                    entries: vec![entry, old],
+                    evictions: 0,
+                };
+            }
+            Self::PolyPropertyCache { entries, evictions } => {
+                if entries.len() < POLYMORPHIC_CACHE_SIZE {
+                    entries.insert(0, entry);
+                    return;
+                }
+                *evictions += 1;
+                stats.lru_evictions += 1;
+                if *evictions > MAX_POLYMORPHIC_EVICTIONS {
+                    stats.to_megamorphic += 1;
+                    *self = Self::Megamorphic;
+                    return;
+                }
+                entries.pop();
+                entries.insert(0, entry);
+            }
+            _ => (),
+        }
+    }
+
+    /// Lookup store entry for `structure`. Returns offset of property to overwrite.
+    pub fn lookup_put(&mut self, structure: GcPointer<Structure>) -> Option<u32> {
+        match self {
+            Self::PutByIdFeedBack {
+                new_structure: None,
+                old_structure: Some(old_structure),
+                offset,
+                ..
+            } => {
+                if GcPointer::ptr_eq(old_structure, &structure) {
+                    Some(*offset)
+                } else {
+                    None
+                }
+            }
+            Self::PolyPutByIdFeedBack { entries, .. } => {
+                for i in 0..entries.len() {
+                    if GcPointer::ptr_eq(&entries[i].structure, &structure) {
+                        let offset = entries[i].offset;
+                        entries[..=i].rotate_right(1);
+                        return Some(offset);
+                    }
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Record new store entry. Only stores to existing properties are recorded.
+    pub fn record_put(&mut self, mut ctx: GcPointer<Context>, entry: PutByIdCacheEntry) {
+        let stats = &mut ctx.vm.ic_stats;
+        match self {
+            Self::PutByIdFeedBack {
+                new_structure: None,
+                old_structure: Some(old_structure),
+                offset,
+                ..
+            } => {
+                stats.to_polymorphic += 1;
+                let old = PutByIdCacheEntry {
+                    structure: *old_structure,
+                    offset: *offset,
+                };
+                *self = Self::PolyPutByIdFeedBack {
+                    entries: vec![entry, old],
+                    evictions: 0,
+                };
+            }
+            Self::PolyPutByIdFeedBack { entries, evictions } => {
+                if entries.len() < POLYMORPHIC_CACHE_SIZE {
+                    entries.insert(0, entry);
+                    return;
+                }
+                *evictions += 1;
+                stats.lru_evictions += 1;
+                if *evictions > MAX_POLYMORPHIC_EVICTIONS {
+                    stats.to_megamorphic += 1;
+                    *self = Self::Megamorphic;
+                    return;
+                }
+                entries.pop();
+                entries.insert(0, entry);
+            }
+            Self::Megamorphic => (),
+            _ => {
+                stats.to_monomorphic += 1;
+                *self = Self::PutByIdFeedBack {
+                    new_structure: None,
+                    old_structure: Some(entry.structure),
+                    offset: entry.offset,
+                    structure_chain: None,
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gc::cell::GcPointer;
+    use crate::gc::snapshot::{deserializer::Deserializer, Snapshot};
+    use crate::vm::context::Context;
+    use crate::vm::tests::{eval_with_jobs, test_runtime};
+    use crate::vm::value::JsValue;
+
+    const SHAPES: &str = "function get(o) { return o.x; } \
+         let objs = []; \
+         for (let i = 0; i < 20; i++) { let o = { x: i }; o['k' + i] = i; objs.push(o); } \
+         let sum = 0; \
+         for (let round = 0; round < 3; round++) { for (let i = 0; i < objs.length; i++) { sum += get(objs[i]); } } ";
+
+    #[test]
+    fn test_polymorphic_to_megamorphic() {
+        let (mut runtime, jobs) = test_runtime();
+        let result = eval_with_jobs(&mut runtime, &jobs, &[SHAPES, "return sum;"].concat());
+        assert_eq!(result, "570");
+        let stats = runtime.ic_stats();
+        assert!(stats.to_polymorphic >= 1);
+        assert!(stats.lru_evictions >= 1);
+        assert!(stats.to_megamorphic >= 1);
+        assert!(stats.megamorphic_hits >= 1);
+    }
+
+    #[test]
+    fn test_megamorphic_invalidation() {
+        let (mut runtime, jobs) = test_runtime();
+        let result = eval_with_jobs(
+            &mut runtime,
+            &jobs,
+            &[
+                SHAPES,
+                "let d = objs[0]; delete d.k0; \
+                 let before = [get(d), get(d), 'x' in d, 'x' in d]; \
+                 d.y = 1; \
+                 delete d.x; \
+                 let after = [get(d), 'x' in d]; \
+                 d.x = 7; \
+                 after.push(get(d)); \
+                 let proto = { x: 1, z: 0 }; delete proto.z; \
+                 let child = Object.create(proto); \
+                 let inherited = [get(child), get(child)]; \
+                 delete proto.x; \
+                 inherited.push(get(child)); \
+                 return [before.join(), after.join(), inherited.join()].join(';');",
+            ]
+            .concat(),
+        );
+        assert_eq!(result, "0,0,true,true;,false,7;1,1,");
+        assert!(runtime.ic_stats().invalidations >= 1);
+    }
+
+    #[test]
+    fn test_snapshot_after_prototype_load() {
+        let (mut runtime, _) = test_runtime();
+        let mut ctx = Context::new(&mut runtime);
+        // loads through prototype cache prototype chains of receiver structures.
+        let result = ctx
+            .eval(
+                "var a = [1]; a.push(2); a.push(3); \
+                 function P() {} P.prototype.hello = function () { return 'hi'; }; \
+                 var p = new P(); p.hello(); \
+                 return p.hello() + a.length;",
+            )
+            .unwrap_or_else(|_| panic!());
+        assert_eq!(result.to_string(ctx).unwrap_or_default(), "hi3");
+        runtime.heap().gc();
+        let snapshot = Snapshot::take_context(false, &mut runtime, ctx, |_, _| {}).buffer;
+        let mut ctx = Deserializer::deserialize_context(&mut runtime, false, &snapshot)
+            .unwrap_or_else(|_| panic!());
+        let result = ctx
+            .eval("return p.hello() + a.push(4);")
+            .unwrap_or_else(|error| panic!("{}", error.to_string(ctx).unwrap_or_default()));
+        assert_eq!(result.to_string(ctx).unwrap_or_default(), "hi4");
+    }
+
+    #[test]
+    fn test_call_and_new_feedback() {
+        let (mut runtime, jobs) = test_runtime();
+        let result = eval_with_jobs(
+            &mut runtime,
+            &jobs,
+            "function add(a, b) { return a + b; } \
+             function P(x) { this.x = x; } \
+             let sum = 0, abs = 0, xs = 0, lengths = 0; \
+             for (let i = 0; i < 10; i++) { \
+                 sum += add(i, 1); abs += Math.abs(-i); xs += new P(i).x; lengths += new Array(i).length; \
+             } \
+             return [sum, abs, xs, lengths].join();",
+        );
+        assert_eq!(result, "55,45,45,45");
+        let stats = runtime.ic_stats();
+        assert!(stats.to_monomorphic >= 4);
+        assert!(stats.call_hits >= 4 * 9);
+        assert_eq!(stats.to_megamorphic, 0);
+    }
+
+    #[test]
+    fn test_new_site_structure() {
+        let (mut runtime, jobs) = test_runtime();
+        let result = eval_with_jobs(
+            &mut runtime,
+            &jobs,
+            "function P() { this.x = 1; } \
+             function make() { return new P(); } \
+             let a = make(), b = make(), c = new P(); \
+             P.prototype = { y: 2 }; \
+             let d = make(); \
+             return [ \
+                 Object.getPrototypeOf(a) === Object.getPrototypeOf(b), \
+                 Object.getPrototypeOf(a) === Object.getPrototypeOf(c), \
+                 Object.getPrototypeOf(d) === P.prototype, \
+                 a.y, d.y, d.x, d instanceof P, a instanceof P \
+             ].join();",
+        );
+        assert_eq!(result, "true,true,true,,2,1,true,false");
+    }
+
+    #[test]
+    fn test_call_megamorphic_fallback() {
+        let (mut runtime, jobs) = test_runtime();
+        let result = eval_with_jobs(
+            &mut runtime,
+            &jobs,
+            "function one(x) { return x + 1; } \
+             function two(x) { return x * 2; } \
+             function P(x) { this.x = x; } \
+             function Q(x) { this.x = -x; } \
+             function call(f, x) { return f(x); } \
+             function construct(F, x) { return new F(x).x; } \
+             let fs = [one, two, Math.abs, String], ctors = [P, Q, Number], out = []; \
+             for (let i = 0; i < 8; i++) { out.push(call(fs[i % 4], -i), construct(ctors[i % 3], i)); } \
+             return out.join();",
+        );
+        assert_eq!(result, "1,0,-2,-1,2,,-3,3,-3,-4,-10,,6,6,-7,-7");
+        let stats = runtime.ic_stats();
+        assert!(stats.to_megamorphic >= 2);
+        assert!(stats.call_misses >= 16);
+    }
+
+    #[test]
+    fn test_setup_for_vm_call_fast() {
+        let (mut runtime, _) = test_runtime();
+        let mut ctx = Context::new(&mut runtime);
+        let mut get = |source: &str| ctx.eval(source).unwrap_or_else(|_| panic!()).get_jsobject();
+        // calls compile lazy functions
+        let sloppy = get("function sloppy(a, b) { var c = 3; return c; } sloppy(); return sloppy;");
+        let strict =
+            get("function strict(a) { 'use strict'; return this; } strict(); return strict;");
+
+        let args = [JsValue::new(1), JsValue::new(2), JsValue::new(3)];
+        let func = sloppy.as_function().as_vm();
+        let (this, env) =
+            ctx.setup_for_vm_call_fast(func, JsValue::encode_undefined_value(), &args);
+        assert!(GcPointer::ptr_eq(
+            &this.get_jsobject(),
+            &ctx.global_object()
+        ));
+        let values = env.as_slice();
+        assert_eq!(
+            values.len(),
+            (func.code.param_count + func.code.var_count) as usize
+        );
+        assert_eq!(values[0].value.get_int32(), 1);
+        assert_eq!(values[1].value.get_int32(), 2);
+        assert!(values[2..].iter().all(|var| var.value.is_undefined()));
+        assert!(GcPointer::ptr_eq(&env.parent.unwrap(), &func.scope));
+
+        let func = strict.as_function().as_vm();
+        let (this, env) = ctx.setup_for_vm_call_fast(func, JsValue::encode_undefined_value(), &[]);
+        assert!(this.is_undefined());
+        assert!(env.as_slice()[0].value.is_undefined());
+        let (this, _) = ctx.setup_for_vm_call_fast(func, JsValue::new(4), &[]);
+        assert!(this.is_undefined());
+    }
+}
@@ -83,6 +83,14 @@
 //!  - **call_builtin**: call to builtin function.
 //!
 //!     Has 1 operand, index of builtin. Stack is manipulated in builtins.
+//!  - **call_eval**: same as **call** but performs direct eval when callee is `eval` of the current realm.
+//!
+//!     Second operand is index of the call site scopes in code block.
+//!  - **resolve_var**: Pushes object holding variable declared by direct eval or the global object.
+//!
+//!
+//!     `(-- obj)`
+//!  - **decl_var**: Declares variable of sloppy mode eval code in the environment of the caller.
 //!  - **newarray**: Takes the number of arguments from the stack and creates new array instance.
 //!
 //!
@@ -281,4 +289,13 @@ pub enum Opcode {
     OP_AWAIT,
     OP_NEWGENERATOR,
     OP_IS_OBJECT,
+    OP_CALL_EVAL,
+    OP_RESOLVE_VAR,
+    OP_DECL_VAR,
+    /// Read import of module record on top of the stack.
+    OP_GET_IMPORT,
+    /// Assignment to import, always throws.
+    OP_SET_IMPORT,
+    /// `import(specifier)`, replaces specifier on top of the stack with promise of module namespace.
+    OP_DYNAMIC_IMPORT,
 }
@@ -1,7 +1,11 @@
 /* This Source Code Form is subject to the terms of the Mozilla Public
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
-use crate::vm::{code_block::FileLocation, *};
+use crate::vm::{
+    code_block::{FileLocation, FunctionSource, LazyFunction, LazyKind, LazyScope},
+    module::{ExportEntry, ImportEntry, ImportName, ModuleInfo},
+    *,
+};
 use crate::{
     bytecode::{opcodes::Opcode, TypeFeedBack},
     prelude::*,
@@ -11,7 +15,7 @@ use std::convert::TryInto;
 use std::u16;
 use std::{cell::RefCell, collections::HashMap, ops::Range, rc::Rc};
 use swc_common::{errors::Handler, sync::Lrc};
-use swc_common::{FileName, SourceMap};
+use swc_common::{BytePos, FileName, SourceFile, SourceMap, Span, Spanned};
 use swc_ecmascript::parser::*;
 pub struct LoopControlInfo {
     breaks: Vec<Box<dyn FnOnce(&mut ByteCompiler)>>,
@@ -32,6 +36,9 @@ pub struct Scope {
     pub variables: HashMap<Symbol, Variable>,
 
     pub depth: u32,
+    /// Scope of function that calls direct `eval`. Names not found in scopes are resolved at runtime then since
+    /// `eval` may declare new variables.
+    pub dynamic: bool,
 }
 impl Scope {
     pub fn add_var(&mut self, name: Symbol, ix: u16) -> u16 {
@@ -71,6 +78,20 @@ impl Scope {
         );
         ix
     }
+
+    /// Declare import `name` of module, `ix` is index of the import in [ModuleInfo::imports].
+    pub fn add_import(&mut self, name: Symbol, ix: u16) -> u16 {
+        self.variables.insert(
+            name,
+            Variable {
+                kind: VariableKind::Import,
+                name,
+                index: ix,
+                dont_free: true,
+            },
+        );
+        ix
+    }
 }
 
 pub struct Variable {
@@ -85,12 +106,18 @@ pub enum VariableKind {
     Const,
     Var,
     Global,
+    /// Import of module, index of the variable is index of the import.
+    Import,
 }
 #[derive(Clone, Debug)]
 pub enum Access {
     Variable(u16, u32),
     Global(Symbol),
     ById(Symbol),
+    /// Name that may be declared by direct `eval` at runtime, global otherwise.
+    Dynamic(Symbol),
+    /// Import of module with index and depth of module scope.
+    Import(u16, u32),
     ArrayPat(Vec<(usize, Access)>),
     ByVal,
     This,
@@ -116,6 +143,7 @@ pub enum Val {
 pub enum CompileError {
     NotYetImpl(String),
     Val(JsValue),
+    Syntax(String),
 }
 
 pub struct ByteCompiler {
@@ -132,8 +160,107 @@ pub struct ByteCompiler {
     pub variable_freelist: Vec<u32>,
 
     pub info: Option<Vec<(Range<usize>, FileLocation)>>,
+    /// Line table of the script being compiled. When present bytecode locations are recorded in `info`.
+    pub lines: Option<Rc<LineTable>>,
 
     pub is_try: bool,
+    /// Source of the script being compiled. Functions keep their source text from it and are compiled lazily
+    /// when enabled.
+    pub source: Option<ScriptSource>,
+    /// Compiling code of direct `eval`. `arguments` and sloppy mode `var` declarations belong to the caller.
+    pub eval: bool,
+    /// Compiling module body, `await` is allowed outside of functions.
+    pub top_level_await: bool,
+}
+
+/// Source text of a script together with position of its first byte in the parser source map.
+#[derive(Clone, Copy)]
+pub struct ScriptSource {
+    pub text: GcPointer<JsString>,
+    /// Value subtracted from parser byte positions to get offsets into `text`.
+    pub base: i64,
+    /// Compile function bodies on first call.
+    pub lazy: bool,
+}
+
+impl ScriptSource {
+    pub fn new(ctx: GcPointer<Context>, file: Option<&SourceFile>) -> Option<Self> {
+        if ctx.vm.options.strip_source {
+            return None;
+        }
+        file.map(|file| Self {
+            text: JsString::new(ctx, &*file.src),
+            base: file.start_pos.0 as i64,
+            lazy: !ctx.vm.options.eager_compilation,
+        })
+    }
+
+    /// Returns source text of the function at `span`.
+    pub fn function_source(&self, span: Span) -> Option<FunctionSource> {
+        let start = span.lo.0 as i64 - self.base;
+        let end = span.hi.0 as i64 - self.base;
+        if start < 0 || end as usize > self.text.as_str().len() || start >= end {
+            return None;
+        }
+        Some(FunctionSource {
+            text: self.text,
+            span: start as u32..end as u32,
+        })
+    }
+}
+
+/// Maps parser positions in a source file to script lines and columns.
+pub struct LineTable {
+    src: Lrc<String>,
+    start: u32,
+    /// Byte offsets of line starts relative to `start`.
+    lines: Vec<u32>,
+    /// Line and column of the first byte of `src` in the script.
+    origin: (u32, i64),
+}
+
+impl LineTable {
+    pub fn new(file: &SourceFile) -> Self {
+        Self::with_origin(file, 0, 0)
+    }
+
+    /// Line table for `file` that is a part of larger script starting at `line` and `col`.
+    pub fn with_origin(file: &SourceFile, line: u32, col: i64) -> Self {
+        Self {
+            src: file.src.clone(),
+            start: file.start_pos.0,
+            lines: file
+                .lines
+                .iter()
+                .map(|pos| pos.0 - file.start_pos.0)
+                .collect(),
+            origin: (line, col),
+        }
+    }
+
+    pub fn location(&self, pos: BytePos) -> Option<FileLocation> {
+        let offset = pos.0.checked_sub(self.start)? as usize;
+        let line = self
+            .lines
+            .partition_point(|&start| start as usize <= offset)
+            .checked_sub(1)?;
+        let col = self
+            .src
+            .get(self.lines[line] as usize..offset)?
+            .encode_utf16()
+            .count() as i64;
+        Some(if line == 0 {
+            FileLocation {
+                line: self.origin.0,
+                col: (self.origin.1 + col).max(0) as u32,
+            }
+        } else {
+            FileLocation {
+                line: self.origin.0 + line as u32,
+                col: col as u32,
+            }
+        })
+    }
 }
 
 impl ByteCompiler {
@@ -186,11 +313,36 @@ impl ByteCompiler {
         if let Some((ix, scope)) = self.lookup_scope(var) {
             let cur_depth = self.scope.borrow().depth;
             let depth = cur_depth - scope.borrow().depth;
-            Access::Variable(ix, depth)
+            let import = matches!(
+                scope.borrow().variables.get(&var),
+                Some(Variable {
+                    kind: VariableKind::Import,
+                    ..
+                })
+            );
+            if import {
+                Access::Import(ix, depth)
+            } else {
+                Access::Variable(ix, depth)
+            }
+        } else if self.is_dynamic() {
+            Access::Dynamic(var)
         } else {
             Access::Global(var)
         }
     }
+
+    /// Returns true if any of enclosing functions calls direct `eval`.
+    fn is_dynamic(&self) -> bool {
+        let mut scope = Some(self.scope.clone());
+        while let Some(current) = scope {
+            if current.borrow().dynamic {
+                return true;
+            }
+            scope = current.borrow().parent.clone();
+        }
+        false
+    }
     pub fn emit_get_local(&mut self, depth: u32, index: u32) {
         if depth == 0 {
             self.emit(Opcode::OP_GE0GL, &[index], false);
@@ -258,15 +410,25 @@ impl ByteCompiler {
         &mut self,
         ctx: GcPointer<Context>,
         var: &VarDecl,
-        export: bool,
     ) -> Result<Vec<Symbol>, CompileError> {
         let mut names = vec![];
         for decl in var.decls.iter() {
             match &decl.name {
                 Pat::Ident(name) => {
                     let name_ = Self::ident_to_sym(&name.id);
+                    let declared = match self.scope.borrow().variables.get(&name_) {
+                        Some(Variable {
+                            kind: VariableKind::Let,
+                            index,
+                            ..
+                        }) => Some(*index),
+                        _ => None,
+                    };
                     let ix = if VarDeclKind::Var == var.kind || VarDeclKind::Const == var.kind {
                         None
+                    } else if declared.is_some() {
+                        // `let` of module scope is declared before module code runs.
+                        declared
                     } else {
                         Some(if let Some(ix) = self.variable_freelist.pop() {
                             self.scope.borrow_mut().add_let_var(name_, ix as _);
@@ -301,17 +463,6 @@ impl ByteCompiler {
                             self.access_set(acc)?;
                         }
                     }
-
-                    if export {
-                        let var = self.access_var(name_);
-                        self.access_get(var)?;
-                        let module = self.access_var("@module".intern());
-                        self.access_get(module)?;
-                        let exports = self.get_sym("@exports".intern());
-                        self.emit(Opcode::OP_GET_BY_ID, &[exports], true);
-                        let sym = self.get_sym(name_);
-                        self.emit(Opcode::OP_PUT_BY_ID, &[sym], true);
-                    }
                 }
 
                 x => {
@@ -328,6 +479,11 @@ impl ByteCompiler {
                 self.emit(Opcode::OP_GLOBALTHIS, &[], false);
                 self.emit(Opcode::OP_DELETE_BY_ID, &[id], false);
             }
+            Access::Dynamic(x) => {
+                let id = self.get_sym(x);
+                self.emit(Opcode::OP_RESOLVE_VAR, &[id], false);
+                self.emit(Opcode::OP_DELETE_BY_ID, &[id], false);
+            }
             Access::ByVal => {
                 self.emit(Opcode::OP_DELETE_BY_VAL, &[], false);
             }
@@ -335,7 +491,7 @@ impl ByteCompiler {
                 let id = self.get_sym(x);
                 self.emit(Opcode::OP_DELETE_BY_ID, &[id], false);
             }
-            Access::Variable(_ix, _depth) => {
+            Access::Variable(_ix, _depth) | Access::Import(_ix, _depth) => {
                 self.emit(Opcode::OP_PUSH_TRUE, &[], false);
                 // self.access_set()
             }
@@ -353,11 +509,20 @@ impl ByteCompiler {
                 self.emit_set_local(depth as _, index as _);
                 //self.emit_u16(index);
             }
+            Access::Import(index, depth) => {
+                self.emit_get_local(depth, 0);
+                self.emit(Opcode::OP_SET_IMPORT, &[index as _], false);
+            }
             Access::Global(x) => {
                 let name = self.get_sym(x);
                 self.emit(Opcode::OP_GLOBALTHIS, &[], false);
                 self.emit(Opcode::OP_PUT_BY_ID, &[name], true);
             }
+            Access::Dynamic(x) => {
+                let name = self.get_sym(x);
+                self.emit(Opcode::OP_RESOLVE_VAR, &[name], false);
+                self.emit(Opcode::OP_PUT_BY_ID, &[name], true);
+            }
             Access::ById(name) => {
                 let name = self.get_sym(name);
                 self.emit(Opcode::OP_PUT_BY_ID, &[name], true);
@@ -378,6 +543,10 @@ impl ByteCompiler {
             Access::Variable(index, depth) => {
                 self.emit_get_local(depth as _, index as _);
             }
+            Access::Import(index, depth) => {
+                self.emit_get_local(depth, 0);
+                self.emit(Opcode::OP_GET_IMPORT, &[index as _], false);
+            }
             Access::Global(x) => {
                 let name = self.get_sym(x);
                 self.emit(Opcode::OP_GLOBALTHIS, &[], false);
@@ -387,6 +556,15 @@ impl ByteCompiler {
                     self.emit(Opcode::OP_GET_BY_ID, &[name], true);
                 }
             }
+            Access::Dynamic(x) => {
+                let name = self.get_sym(x);
+                self.emit(Opcode::OP_RESOLVE_VAR, &[name], false);
+                if self.is_try {
+                    self.emit(Opcode::OP_TRY_GET_BY_ID, &[name], true);
+                } else {
+                    self.emit(Opcode::OP_GET_BY_ID, &[name], true);
+                }
+            }
             Access::ById(name) => {
                 let name = self.get_sym(name);
                 self.emit(Opcode::OP_GET_BY_ID, &[name], true);
@@ -454,6 +632,12 @@ impl ByteCompiler {
         }
         // self.code.compute_stack_size(ctx)?;
         self.code.literals_ptr = self.code.literals.as_ptr();
+        if let Some(mut info) = self.info.take() {
+            if let Some(last) = info.last_mut() {
+                last.0.end = self.code.code.len();
+            }
+            self.code.loc = info;
+        }
 
         Ok(self.code)
     }
@@ -497,6 +681,7 @@ impl ByteCompiler {
         mut ctx: GcPointer<Context>,
         params_: &[String],
         rel_path: &str,
+        file: &str,
         body: String,
         builtins: bool,
     ) -> Result<JsValue, CompileError> {
@@ -506,8 +691,10 @@ impl ByteCompiler {
             variables: HashMap::new(),
             parent: None,
             depth: 0,
+            dynamic: false,
         }));
         let mut code = CodeBlock::new(ctx, "<anonymous>".intern(), false, rel_path.into());
+        code.file_name = file.to_string();
         let mut compiler = ByteCompiler {
             lci: Vec::new(),
             builtins,
@@ -521,6 +708,10 @@ impl ByteCompiler {
             top_level: false,
             scope,
             is_try: true,
+            eval: false,
+            top_level_await: false,
+            source: None,
+            lines: None,
         };
         let mut p = 0;
         for x in params_.iter() {
@@ -537,6 +728,7 @@ impl ByteCompiler {
         let handler = Handler::with_emitter(true, false, Box::new(MyEmiter::default()));
 
         let fm = cm.new_source_file(FileName::Custom("<anonymous>".into()), body);
+        compiler.lines = Some(Rc::new(LineTable::new(&fm)));
 
         let mut parser = Parser::new(Syntax::Es(init_es_config()), StringInput::from(&*fm), None);
 
@@ -569,6 +761,82 @@ impl ByteCompiler {
         let fun = JsVMFunction::new(ctx, code, env);
         Ok(JsValue::new(fun))
     }
+
+    /// Compile function created by `Function`, `GeneratorFunction`, `AsyncFunction` or `AsyncGeneratorFunction`
+    /// constructor. `prefix` is the keyword part of the function expression e.g `function*`. Parameters and body
+    /// are parsed on their own first so that neither of them can close the other one. Function is created in
+    /// global scope of `ctx`. Async functions are not compiled yet, valid async source throws `SyntaxError` too.
+    pub fn compile_dynamic_function(
+        ctx: GcPointer<Context>,
+        prefix: &str,
+        params: &str,
+        body: &str,
+    ) -> Result<JsValue, JsValue> {
+        let syntax_error = |msg: String| JsValue::new(ctx.new_syntax_error(msg));
+        Self::parse_function_expr(&format!("({} anonymous({}\n) {{\n}})", prefix, params))
+            .map_err(syntax_error)?;
+        Self::parse_function_expr(&format!("({} anonymous(\n) {{\n{}\n}})", prefix, body))
+            .map_err(syntax_error)?;
+        let (fm, function) = Self::parse_function_expr(&format!(
+            "({} anonymous({}\n) {{\n{}\n}})",
+            prefix, params, body
+        ))
+        .map_err(syntax_error)?;
+        if function.is_async {
+            return Err(syntax_error("NYI: async functions are not supported yet".to_string()));
+        }
+        let source = ScriptSource::new(ctx, Some(&fm));
+        let mut code = CodeBlock::new(ctx, "anonymous".intern(), false, ".".into());
+        code.is_generator = function.is_generator;
+        code.length = Self::function_length(function.params.iter().map(|param| &param.pat));
+        code.source = source.and_then(|source| source.function_source(function.span));
+        let scope = Rc::new(RefCell::new(Scope {
+            variables: HashMap::new(),
+            parent: None,
+            depth: 0,
+            dynamic: false,
+        }));
+        Self::function_body(
+            ctx,
+            code,
+            scope,
+            false,
+            source,
+            Some(Rc::new(LineTable::new(&fm))),
+            &function,
+        )
+        .map_err(|e| syntax_error(format!("Compile Error {:?}", e)))?;
+        let env = crate::vm::environment::Environment::new(ctx, 0);
+        let fun = JsVMFunction::new(ctx, code, env);
+        if code.is_generator {
+            return Ok(JsValue::new(JsGeneratorFunction::new(ctx, fun)));
+        }
+        Ok(JsValue::new(fun))
+    }
+
+    /// Parse `source` that must consist of a single parenthesized function expression.
+    fn parse_function_expr(source: &str) -> Result<(Lrc<SourceFile>, Function), String> {
+        let cm: Lrc<SourceMap> = Default::default();
+        let handler = Handler::with_emitter(true, false, Box::new(MyEmiter::default()));
+        let fm = cm.new_source_file(FileName::Custom("anonymous".into()), source.into());
+        let mut parser = Parser::new(Syntax::Es(init_es_config()), StringInput::from(&*fm), None);
+        for e in parser.take_errors() {
+            e.into_diagnostic(&handler).emit();
+        }
+        let mut script = parser
+            .parse_script()
+            .map_err(|e| e.kind().msg().to_string())?;
+        if script.body.len() == 1 {
+            if let Some(Stmt::Expr(stmt)) = script.body.pop() {
+                if let Expr::Paren(paren) = *stmt.expr {
+                    if let Expr::Fn(fun) = *paren.expr {
+                        return Ok((fm, fun.function));
+                    }
+                }
+            }
+        }
+        Err("Unexpected token in function source".to_string())
+    }
     pub fn function(
         &mut self,
         ctx: GcPointer<Context>,
@@ -576,9 +844,19 @@ impl ByteCompiler {
         name: Symbol,
         expr: bool,
     ) -> Result<(), CompileError> {
-        let mut _rest = None;
-        let mut params = vec![];
-        let mut rat = None;
+        self.function_of_kind(ctx, function, name, expr, LazyKind::Function)
+    }
+
+    /// Compile `function` and push it on the stack. `kind` is the syntax function is written in, source of
+    /// lazily compiled functions is parsed again according to it.
+    fn function_of_kind(
+        &mut self,
+        ctx: GcPointer<Context>,
+        function: &Function,
+        name: Symbol,
+        expr: bool,
+        kind: LazyKind,
+    ) -> Result<(), CompileError> {
         let (mut code, ix) = if !expr {
             (
                 self.code.codes[self.fmap.get(&name).copied().unwrap() as usize],
@@ -587,6 +865,7 @@ impl ByteCompiler {
         } else {
             let p = self.code.path.clone();
             let mut code = CodeBlock::new(ctx, name, false, p);
+            code.file_name = self.code.file_name.clone();
             self.code.codes.push(code);
             (code, self.code.codes.len() - 1)
         };
@@ -594,15 +873,56 @@ impl ByteCompiler {
             return Err(CompileError::NotYetImpl("NYI: async".to_string()));
         }
         code.is_generator = function.is_generator;
+        code.length = Self::function_length(function.params.iter().map(|param| &param.pat));
+        code.source = self
+            .source
+            .and_then(|source| source.function_source(function.span));
+        match self.lazy_function(&code, function.span, kind) {
+            Some(lazy) => code.lazy = Some(Box::new(lazy)),
+            None => Self::function_body(
+                ctx,
+                code,
+                self.scope.clone(),
+                self.builtins,
+                self.source,
+                self.lines.clone(),
+                function,
+            )?,
+        }
+        let ix = if expr {
+            ix as u32
+        } else {
+            *self.fmap.get(&name).unwrap()
+        };
+        self.emit(Opcode::OP_GET_FUNCTION, &[ix], false);
+        Ok(())
+    }
+
+    /// Compile parameters and body of `function` into `code`.
+    fn function_body(
+        ctx: GcPointer<Context>,
+        code: GcPointer<CodeBlock>,
+        parent: ScopeRef,
+        builtins: bool,
+        source: Option<ScriptSource>,
+        lines: Option<Rc<LineTable>>,
+        function: &Function,
+    ) -> Result<(), CompileError> {
+        let depth = parent.borrow().depth + 1;
         let scope = Rc::new(RefCell::new(Scope {
             variables: HashMap::new(),
-            parent: Some(self.scope.clone()),
-            depth: self.scope.borrow().depth + 1,
+            depth,
+            parent: Some(parent),
+            dynamic: function
+                .body
+                .as_ref()
+                .map(|body| contains_direct_eval(body))
+                .unwrap_or(false),
         }));
 
         let mut compiler = ByteCompiler {
             lci: Vec::new(),
-            builtins: self.builtins,
+            builtins,
             variable_freelist: Vec::with_capacity(4),
             code,
             info: None,
@@ -613,54 +933,638 @@ impl ByteCompiler {
             top_level: false,
             scope,
             is_try: true,
+            eval: false,
+            top_level_await: false,
+            source,
+            lines,
+        };
+        let patterns = compiler.declare_params(function.params.iter().map(|param| &param.pat))?;
+        compiler.bind_params(ctx, &patterns)?;
+        if code.is_generator {
+            compiler.emit(Opcode::OP_INITIAL_YIELD, &[], false);
+        }
+        compiler.compile_fn(ctx, function)?;
+        compiler.finish(ctx).map_err(|x| CompileError::Val(x))?;
+        Ok(())
+    }
+
+    /// Value of `length` of function with parameters `pats`, parameters before the first one with default value or
+    /// rest parameter.
+    fn function_length<'a>(pats: impl Iterator<Item = &'a Pat>) -> u32 {
+        pats.take_while(|pat| !matches!(pat, Pat::Assign(_) | Pat::Rest(_)))
+            .count() as u32
+    }
+
+    /// Compile parameters and body of arrow function `arrow` into `code`.
+    fn arrow_body(
+        ctx: GcPointer<Context>,
+        mut code: GcPointer<CodeBlock>,
+        parent: ScopeRef,
+        builtins: bool,
+        source: Option<ScriptSource>,
+        lines: Option<Rc<LineTable>>,
+        arrow: &ArrowExpr,
+    ) -> Result<(), CompileError> {
+        code.strict = match &arrow.body {
+            BlockStmtOrExpr::BlockStmt(block) => block
+                .stmts
+                .first()
+                .map(|stmt| stmt.is_use_strict())
+                .unwrap_or(false),
+            _ => false,
+        };
+        let depth = parent.borrow().depth + 1;
+        let scope = Rc::new(RefCell::new(Scope {
+            variables: HashMap::new(),
+            depth,
+            parent: Some(parent),
+            dynamic: contains_direct_eval(&arrow.body),
+        }));
+        let mut compiler = ByteCompiler {
+            lci: Vec::new(),
+            builtins,
+            variable_freelist: vec![],
+            code,
+            info: None,
+            tail_pos: false,
+            fmap: HashMap::new(),
+            val_map: HashMap::new(),
+            name_map: HashMap::new(),
+            top_level: false,
+            scope,
+            is_try: true,
+            eval: false,
+            top_level_await: false,
+            source,
+            lines,
         };
+        let patterns = compiler.declare_params(arrow.params.iter())?;
+        compiler.bind_params(ctx, &patterns)?;
+        match &arrow.body {
+            BlockStmtOrExpr::BlockStmt(block) => {
+                compiler.compile(ctx, &block.stmts, false)?;
+                compiler.emit(Opcode::OP_PUSH_UNDEF, &[], false);
+                compiler.emit(Opcode::OP_RET, &[], false);
+            }
+            BlockStmtOrExpr::Expr(expr) => {
+                compiler.expr(ctx, expr, true, true)?;
+                compiler.emit(Opcode::OP_RET, &[], false);
+            }
+        }
+        compiler.finish(ctx).map_err(|x| CompileError::Val(x))?;
+        Ok(())
+    }
+
+    /// Declare parameters `pats` of the function being compiled. Parameters that are not plain identifiers
+    /// are stored in hidden slots and returned together with the slot so that [ByteCompiler::bind_params]
+    /// can destructure them on function entry.
+    fn declare_params<'a>(
+        &mut self,
+        pats: impl Iterator<Item = &'a Pat>,
+    ) -> Result<Vec<(u16, &'a Pat)>, CompileError> {
+        let mut param_count = 0;
+        let mut rest_at = None;
         let mut p = 0;
-        for x in function.params.iter() {
-            match x.pat {
+        let mut patterns = vec![];
+        for (i, pat) in pats.enumerate() {
+            match pat {
                 Pat::Ident(ref x) => {
-                    params.push(Self::ident_to_sym(&x.id));
+                    param_count += 1;
                     p += 1;
-                    compiler
-                        .scope
+                    self.scope
                         .borrow_mut()
                         .add_var(Self::ident_to_sym(&x.id), p - 1);
                 }
-                Pat::Rest(ref r) => match &*r.arg {
-                    Pat::Ident(ref id) => {
-                        p += 1;
-                        _rest = Some(Self::ident_to_sym(&id.id));
-                        rat = Some(
-                            compiler
-                                .scope
-                                .borrow_mut()
-                                .add_var(Self::ident_to_sym(&id.id), p - 1)
-                                as u32,
-                        );
+                Pat::Rest(ref r) => {
+                    p += 1;
+                    let name = match &*r.arg {
+                        Pat::Ident(ref id) => Self::ident_to_sym(&id.id),
+                        pat => {
+                            patterns.push((p - 1, pat));
+                            format!("@param{}", i).intern()
+                        }
+                    };
+                    rest_at = Some(self.scope.borrow_mut().add_var(name, p - 1) as u32);
+                }
+                pat => {
+                    param_count += 1;
+                    p += 1;
+                    self.scope
+                        .borrow_mut()
+                        .add_var(format!("@param{}", i).intern(), p - 1);
+                    patterns.push((p - 1, pat));
+                }
+            }
+        }
+        for (_, pat) in patterns.iter() {
+            let mut names = vec![];
+            Self::pat_names(pat, &mut names)?;
+            for name in names {
+                p += 1;
+                self.scope.borrow_mut().add_var(name, p - 1);
+            }
+        }
+        self.code.param_count = param_count;
+        self.code.var_count = p as _;
+        self.code.rest_at = rest_at;
+        Ok(patterns)
+    }
+
+    /// Bind parameters returned by [ByteCompiler::declare_params] from their hidden slots.
+    fn bind_params(
+        &mut self,
+        ctx: GcPointer<Context>,
+        patterns: &[(u16, &Pat)],
+    ) -> Result<(), CompileError> {
+        for (index, pat) in patterns.iter() {
+            self.emit_get_local(0, *index as _);
+            self.bind_pat(ctx, pat)?;
+        }
+        Ok(())
+    }
+
+    /// Collect names bound by `pat`.
+    fn pat_names(pat: &Pat, names: &mut Vec<Symbol>) -> Result<(), CompileError> {
+        match pat {
+            Pat::Ident(id) => names.push(Self::ident_to_sym(&id.id)),
+            Pat::Array(array) => {
+                for pat in array.elems.iter().flatten() {
+                    Self::pat_names(pat, names)?;
+                }
+            }
+            Pat::Object(object) => {
+                for prop in object.props.iter() {
+                    match prop {
+                        ObjectPatProp::KeyValue(keyvalue) => Self::pat_names(&keyvalue.value, names)?,
+                        ObjectPatProp::Assign(assign) => names.push(Self::ident_to_sym(&assign.key)),
+                        ObjectPatProp::Rest(rest) => Self::pat_names(&rest.arg, names)?,
+                    }
+                }
+            }
+            Pat::Rest(rest) => Self::pat_names(&rest.arg, names)?,
+            Pat::Assign(assign) => Self::pat_names(&assign.left, names)?,
+            x => return Err(CompileError::NotYetImpl(format!("NYI: {:?}", x))),
+        }
+        Ok(())
+    }
+
+    /// Replace value on top of the stack with `default` if it is `undefined`.
+    fn pat_default(&mut self, ctx: GcPointer<Context>, default: &Expr) -> Result<(), CompileError> {
+        self.emit(Opcode::OP_DUP, &[], false);
+        self.emit(Opcode::OP_PUSH_UNDEF, &[], false);
+        self.emit(Opcode::OP_STRICTEQ, &[], false);
+        let jdefined = self.cjmp(false);
+        self.emit(Opcode::OP_POP, &[], false);
+        self.expr(ctx, default, true, false)?;
+        jdefined(self);
+        Ok(())
+    }
+
+    /// Allocate hidden local slot in the function being compiled.
+    fn temp_local(&mut self) -> u32 {
+        self.code.var_count += 1;
+        self.code.var_count - 1
+    }
+
+    /// Push next value of iterator stored in local `iterator`, or `undefined` once it is done. Local is set to
+    /// `undefined` when iterator is done so `next` is not called again.
+    fn iterator_step(&mut self, iterator: u32) {
+        let next = self.get_sym("next".intern());
+        let done = self.get_sym("done".intern());
+        let value = self.get_sym("value".intern());
+        self.emit_get_local(0, iterator);
+        self.emit(Opcode::OP_DUP, &[], false);
+        self.emit(Opcode::OP_PUSH_UNDEF, &[], false);
+        self.emit(Opcode::OP_STRICTEQ, &[], false);
+        let jexhausted = self.cjmp(true);
+        self.emit(Opcode::OP_DUP, &[], false);
+        self.emit(Opcode::OP_GET_BY_ID, &[next], true);
+        self.emit(Opcode::OP_CALL, &[0], true);
+        self.emit(Opcode::OP_DUP, &[], false);
+        self.emit(Opcode::OP_GET_BY_ID, &[done], true);
+        let jdone = self.cjmp(true);
+        self.emit(Opcode::OP_GET_BY_ID, &[value], true);
+        let jend = self.jmp();
+        jdone(self);
+        self.emit(Opcode::OP_POP, &[], false);
+        self.emit(Opcode::OP_PUSH_UNDEF, &[], false);
+        self.emit_set_local(0, iterator);
+        self.emit(Opcode::OP_PUSH_UNDEF, &[], false);
+        // exhausted iterator is `undefined` already
+        jexhausted(self);
+        jend(self);
+    }
+
+    /// Push array of values left in iterator stored in local `iterator`.
+    fn iterator_rest(&mut self, iterator: u32) {
+        let next = self.get_sym("next".intern());
+        let done = self.get_sym("done".intern());
+        let value = self.get_sym("value".intern());
+        let length = self.get_sym("length".intern());
+        let array = self.temp_local();
+        self.emit(Opcode::OP_NEWARRAY, &[0], false);
+        self.emit_set_local(0, array);
+        let head = self.code.code.len();
+        self.emit_get_local(0, iterator);
+        self.emit(Opcode::OP_DUP, &[], false);
+        self.emit(Opcode::OP_PUSH_UNDEF, &[], false);
+        self.emit(Opcode::OP_STRICTEQ, &[], false);
+        let jexhausted = self.cjmp(true);
+        self.emit(Opcode::OP_DUP, &[], false);
+        self.emit(Opcode::OP_GET_BY_ID, &[next], true);
+        self.emit(Opcode::OP_CALL, &[0], true);
+        self.emit(Opcode::OP_DUP, &[], false);
+        self.emit(Opcode::OP_GET_BY_ID, &[done], true);
+        let jdone = self.cjmp(true);
+        self.emit(Opcode::OP_GET_BY_ID, &[value], true);
+        self.emit_get_local(0, array);
+        self.emit(Opcode::OP_DUP, &[], false);
+        self.emit(Opcode::OP_GET_BY_ID, &[length], true);
+        self.emit(Opcode::OP_SWAP, &[], false);
+        self.emit(Opcode::OP_PUT_BY_VAL, &[0], false);
+        self.goto(head);
+        jdone(self);
+        self.emit(Opcode::OP_PUSH_UNDEF, &[], false);
+        self.emit_set_local(0, iterator);
+        // exhausted iterator or `next` result is left on the stack
+        jexhausted(self);
+        self.emit(Opcode::OP_POP, &[], false);
+        self.emit_get_local(0, array);
+    }
+
+    /// Call `return` of iterator stored in local `iterator` unless it is done.
+    fn iterator_close(&mut self, iterator: u32) {
+        let return_ = self.get_sym("return".intern());
+        self.emit_get_local(0, iterator);
+        self.emit(Opcode::OP_DUP, &[], false);
+        self.emit(Opcode::OP_PUSH_UNDEF, &[], false);
+        self.emit(Opcode::OP_STRICTEQ, &[], false);
+        let jdone = self.cjmp(true);
+        self.emit(Opcode::OP_DUP, &[], false);
+        self.emit(Opcode::OP_GET_BY_ID, &[return_], true);
+        self.emit(Opcode::OP_DUP, &[], false);
+        self.emit(Opcode::OP_PUSH_UNDEF, &[], false);
+        self.emit(Opcode::OP_EQ, &[], false);
+        let jno_return = self.cjmp(true);
+        self.emit(Opcode::OP_CALL, &[0], true);
+        self.emit(Opcode::OP_POP, &[], false);
+        let jend = self.jmp();
+        jno_return(self);
+        self.emit(Opcode::OP_POP, &[], false);
+        jdone(self);
+        self.emit(Opcode::OP_POP, &[], false);
+        jend(self);
+    }
+
+    /// Bind value on top of the stack to variables of `pat`. Value is popped from the stack.
+    fn bind_pat(&mut self, ctx: GcPointer<Context>, pat: &Pat) -> Result<(), CompileError> {
+        match pat {
+            Pat::Ident(id) => {
+                let var = self.access_var(Self::ident_to_sym(&id.id));
+                self.access_set(var)?;
+            }
+            Pat::Assign(assign) => {
+                self.pat_default(ctx, &assign.right)?;
+                self.bind_pat(ctx, &assign.left)?;
+            }
+            Pat::Array(array) => {
+                let iterator_id = self.get_sym("Symbol.iterator".intern().private());
+                let iterator = self.temp_local();
+                self.emit(Opcode::OP_DUP, &[], false);
+                self.emit(Opcode::OP_GET_BY_ID, &[iterator_id], true);
+                self.emit(Opcode::OP_CALL, &[0], true);
+                self.emit_set_local(0, iterator);
+                for pat in array.elems.iter() {
+                    match pat {
+                        Some(Pat::Rest(rest)) => {
+                            self.iterator_rest(iterator);
+                            self.bind_pat(ctx, &rest.arg)?;
+                        }
+                        Some(pat) => {
+                            self.iterator_step(iterator);
+                            self.bind_pat(ctx, pat)?;
+                        }
+                        None => {
+                            self.iterator_step(iterator);
+                            self.emit(Opcode::OP_POP, &[], false);
+                        }
+                    }
+                }
+                self.iterator_close(iterator);
+            }
+            Pat::Object(object) => {
+                let has_rest = object
+                    .props
+                    .iter()
+                    .any(|prop| matches!(prop, ObjectPatProp::Rest(_)));
+                // keys that object rest excludes, names or locals with computed keys
+                let mut excluded = vec![];
+                for prop in object.props.iter() {
+                    self.emit(Opcode::OP_DUP, &[], false);
+                    match prop {
+                        ObjectPatProp::KeyValue(keyvalue) => {
+                            match keyvalue.key {
+                                PropName::Ident(ref id) => {
+                                    excluded.push(Err(id.sym.to_string()));
+                                    let name = self.get_sym(Self::ident_to_sym(id));
+                                    self.emit(Opcode::OP_GET_BY_ID, &[name], true);
+                                }
+                                PropName::Str(ref x) => {
+                                    excluded.push(Err(x.value.to_string()));
+                                    let name = self.get_sym(x.value.intern());
+                                    self.emit(Opcode::OP_GET_BY_ID, &[name], true);
+                                }
+                                PropName::Computed(ref computed) => {
+                                    self.expr(ctx, &computed.expr, true, false)?;
+                                    if has_rest {
+                                        let key = self.temp_local();
+                                        self.emit(Opcode::OP_DUP, &[], false);
+                                        self.emit_set_local(0, key);
+                                        excluded.push(Ok(key));
+                                    }
+                                    self.emit(Opcode::OP_SWAP, &[], false);
+                                    self.emit(Opcode::OP_GET_BY_VAL, &[0], false);
+                                }
+                                ref x => {
+                                    return Err(CompileError::NotYetImpl(format!("NYI: {:?}", x)))
+                                }
+                            }
+                            self.bind_pat(ctx, &keyvalue.value)?;
+                        }
+                        ObjectPatProp::Assign(assign) => {
+                            excluded.push(Err(assign.key.sym.to_string()));
+                            let name = Self::ident_to_sym(&assign.key);
+                            let id = self.get_sym(name);
+                            self.emit(Opcode::OP_GET_BY_ID, &[id], true);
+                            if let Some(ref value) = assign.value {
+                                self.pat_default(ctx, value)?;
+                            }
+                            let var = self.access_var(name);
+                            self.access_set(var)?;
+                        }
+                        ObjectPatProp::Rest(rest) => {
+                            for key in excluded.iter() {
+                                match key {
+                                    Ok(local) => self.emit_get_local(0, *local),
+                                    Err(name) => {
+                                        let name = self.get_val(ctx, Val::Str(name.clone()));
+                                        self.emit(Opcode::OP_PUSH_LITERAL, &[name], false);
+                                    }
+                                }
+                            }
+                            self.emit(Opcode::OP_NEWARRAY, &[excluded.len() as u32], false);
+                            self.emit(Opcode::OP_CALL_BUILTIN, &[2, 1, 0], false);
+                            self.bind_pat(ctx, &rest.arg)?;
+                        }
                     }
-                    ref x => return Err(CompileError::NotYetImpl(format!("NYI: {:?}", x))),
-                },
-                ref x => {
-                    return Err(CompileError::NotYetImpl(format!("NYI: {:?}", x)));
                 }
+                self.emit(Opcode::OP_POP, &[], false);
             }
+            x => return Err(CompileError::NotYetImpl(format!("NYI: {:?}", x))),
+        }
+        Ok(())
+    }
+
+    /// Returns lazy function description if function of `kind` at `span` can be compiled on first call.
+    fn lazy_function(&self, code: &CodeBlock, span: Span, kind: LazyKind) -> Option<LazyFunction> {
+        if !self.source?.lazy || code.source.is_none() {
+            return None;
         }
+        Some(LazyFunction {
+            scopes: self.capture_scopes(),
+            builtins: self.builtins,
+            kind,
+            position: self
+                .lines
+                .as_ref()
+                .and_then(|lines| lines.location(span.lo))
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Scopes visible from the current position, innermost first.
+    fn capture_scopes(&self) -> Vec<LazyScope> {
+        let mut scopes = vec![];
+        let mut scope = Some(self.scope.clone());
+        while let Some(current) = scope {
+            let current = current.borrow();
+            let (imports, variables) = current
+                .variables
+                .values()
+                .partition::<Vec<_>, _>(|var| matches!(var.kind, VariableKind::Import));
+            scopes.push(LazyScope {
+                depth: current.depth,
+                variables: variables.iter().map(|var| (var.name, var.index)).collect(),
+                imports: imports.iter().map(|var| (var.name, var.index)).collect(),
+                dynamic: current.dynamic,
+            });
+            scope = current.parent.clone();
+        }
+        scopes
+    }
+
+    /// Rebuild scope chain captured by [ByteCompiler::capture_scopes]. Returns innermost scope.
+    fn restore_scopes(scopes: &[LazyScope]) -> Option<ScopeRef> {
+        let mut scope: Option<ScopeRef> = None;
+        for lazy_scope in scopes.iter().rev() {
+            let mut variables = HashMap::new();
+            for &(name, index) in lazy_scope.variables.iter() {
+                variables.insert(
+                    name,
+                    Variable {
+                        kind: VariableKind::Var,
+                        name,
+                        index,
+                        dont_free: true,
+                    },
+                );
+            }
+            for &(name, index) in lazy_scope.imports.iter() {
+                variables.insert(
+                    name,
+                    Variable {
+                        kind: VariableKind::Import,
+                        name,
+                        index,
+                        dont_free: true,
+                    },
+                );
+            }
+            scope = Some(Rc::new(RefCell::new(Scope {
+                parent: scope,
+                variables,
+                depth: lazy_scope.depth,
+                dynamic: lazy_scope.dynamic,
+            })));
+        }
+        scope
+    }
+
+    /// Compile body of lazy function `code`. Does nothing if `code` is already compiled.
+    pub fn compile_lazy(ctx: GcPointer<Context>, mut code: GcPointer<CodeBlock>) -> Result<(), JsValue> {
+        let lazy = match code.lazy.take() {
+            Some(lazy) => lazy,
+            None => return Ok(()),
+        };
+        let cm: Lrc<SourceMap> = Default::default();
+        let handler = Handler::with_emitter(true, false, Box::new(MyEmiter::default()));
+        let text = code
+            .source
+            .clone()
+            .expect("lazy function without source");
+        // methods are parsed as the only property of object literal
+        let (prefix, suffix) = match lazy.kind {
+            LazyKind::Function | LazyKind::Arrow => ("(", ")"),
+            LazyKind::Method => ("({", "})"),
+        };
+        let fm = cm.new_source_file(
+            FileName::Custom(code.file_name.clone()),
+            format!("{}{}{}", prefix, text.as_str(), suffix),
+        );
+        let mut parser = Parser::new(Syntax::Es(init_es_config()), StringInput::from(&*fm), None);
+        for e in parser.take_errors() {
+            e.into_diagnostic(&handler).emit();
+        }
+        let expr = parser
+            .parse_expr()
+            .map_err(|e| JsValue::new(ctx.new_syntax_error(e.kind().msg())))?;
+        let expr = match &*expr {
+            Expr::Paren(paren) => &*paren.expr,
+            _ => unreachable!("lazy function source is not a function"),
+        };
+
+        // positions in the new source file are shifted by the prefix.
+        let source = ScriptSource {
+            text: text.text,
+            base: fm.start_pos.0 as i64 + prefix.len() as i64 - text.span.start as i64,
+            lazy: true,
+        };
+        let scope = Self::restore_scopes(&lazy.scopes).expect("lazy function without scopes");
+        let lines = Some(Rc::new(LineTable::with_origin(
+            &fm,
+            lazy.position.line,
+            lazy.position.col as i64 - prefix.len() as i64,
+        )));
+        match expr {
+            Expr::Fn(fun) => Self::function_body(
+                ctx,
+                code,
+                scope,
+                lazy.builtins,
+                Some(source),
+                lines,
+                &fun.function,
+            ),
+            Expr::Arrow(arrow) => Self::arrow_body(
+                ctx,
+                code,
+                scope,
+                lazy.builtins,
+                Some(source),
+                lines,
+                arrow,
+            ),
+            Expr::Object(object) => match object.props.first() {
+                Some(PropOrSpread::Prop(prop)) => match &**prop {
+                    Prop::Method(method) => Self::function_body(
+                        ctx,
+                        code,
+                        scope,
+                        lazy.builtins,
+                        Some(source),
+                        lines,
+                        &method.function,
+                    ),
+                    _ => unreachable!("lazy function source is not a method"),
+                },
+                _ => unreachable!("lazy function source is not a method"),
+            },
+            _ => unreachable!("lazy function source is not a function"),
+        }
+        .map_err(|e| {
+            // function stays lazy and every call reports the error instead of running a partial body
+            code.discard_body();
+            code.lazy = Some(lazy);
+            JsValue::new(ctx.new_syntax_error(format!("Compile Error {:?}", e)))
+        })
+    }
+
+    /// Compile `source` passed to direct `eval` at call site `site` of `caller`. Returned code has to be run in
+    /// a new environment whose parent is the environment of the caller.
+    pub fn compile_direct_eval(
+        ctx: GcPointer<Context>,
+        caller: GcPointer<CodeBlock>,
+        site: u32,
+        source: &str,
+    ) -> Result<GcPointer<CodeBlock>, JsValue> {
+        let cm: Lrc<SourceMap> = Default::default();
+        let handler = Handler::with_emitter(true, false, Box::new(MyEmiter::default()));
+        let fm = cm.new_source_file(FileName::Custom("<eval>".into()), source.into());
+        let mut parser = Parser::new(Syntax::Es(init_es_config()), StringInput::from(&*fm), None);
+        for e in parser.take_errors() {
+            e.into_diagnostic(&handler).emit();
+        }
+        let script = parser
+            .parse_script()
+            .map_err(|e| JsValue::new(ctx.new_syntax_error(e.kind().msg())))?;
+        let strict = caller.strict
+            || match script.body.get(0) {
+                Some(stmt) => stmt.is_use_strict(),
+                None => false,
+            };
+        let parent = Self::restore_scopes(&caller.evals[site as usize])
+            .expect("eval call site without scopes");
+        let mut code = CodeBlock::new(ctx, "<eval>".intern(), strict, caller.path.clone());
+        code.file_name = caller.file_name.clone();
+        // variables of sloppy eval called from global code are global too.
+        code.top_level = caller.top_level && !strict;
+        let depth = parent.borrow().depth + 1;
+        let mut compiler = ByteCompiler {
+            lci: Vec::new(),
+            top_level: code.top_level,
+            info: None,
+            tail_pos: false,
+            builtins: false,
+            scope: Rc::new(RefCell::new(Scope {
+                depth,
+                parent: Some(parent),
+                variables: Default::default(),
+                dynamic: script
+                    .body
+                    .iter()
+                    .any(|stmt| contains_direct_eval(stmt)),
+            })),
+            variable_freelist: vec![],
+            code,
+            val_map: Default::default(),
+            name_map: Default::default(),
+            fmap: Default::default(),
+            is_try: true,
+            eval: true,
+            top_level_await: false,
+            source: ScriptSource::new(ctx, Some(&fm)),
+            lines: Some(Rc::new(LineTable::new(&fm))),
+        };
+        compiler
+            .compile(ctx, &script.body, true)
+            .map_err(|e| JsValue::new(ctx.new_syntax_error(format!("Compile Error {:?}", e))))?;
+        compiler.emit(Opcode::OP_PUSH_UNDEF, &[], false);
+        compiler.emit(Opcode::OP_RET, &[], false);
+        compiler.finish(ctx)
+    }
 
-        code.param_count = params.len() as _;
-        code.var_count = p as _;
-        code.rest_at = rat;
-        if code.is_generator {
-            compiler.emit(Opcode::OP_INITIAL_YIELD, &[], false);
+    /// Declare `var` of sloppy mode eval code in the caller unless the caller already has variable `name`.
+    fn decl_caller_var(&mut self, name: Symbol) {
+        let caller_depth = self.scope.borrow().depth - 1;
+        match self.lookup_scope(name) {
+            Some((_, scope)) if scope.borrow().depth == caller_depth => {}
+            _ => {
+                let name = self.get_sym(name);
+                self.emit(Opcode::OP_DECL_VAR, &[name], false);
+            }
         }
-        compiler.compile_fn(ctx, function)?;
-        compiler.finish(ctx).map_err(|x| CompileError::Val(x))?;
-        let ix = if expr {
-            ix as u32
-        } else {
-            *self.fmap.get(&name).unwrap()
-        };
-        self.emit(Opcode::OP_GET_FUNCTION, &[ix], false);
-        Ok(())
     }
+
     pub fn fn_expr(
         &mut self,
         ctx: GcPointer<Context>,
@@ -708,37 +1612,227 @@ impl ByteCompiler {
                     self.scope.borrow_mut().add_let_var(name, c as _);
                     self.code.var_count += 1;
                 }
+                // module variables are declared upfront so that hoisted functions and importers can see them.
+                BindingKind::Let if var.1.path().is_empty() => {
+                    let s: &str = &(var.0).0;
+                    let name = s.intern();
+                    let c = self.code.var_count;
+                    self.scope.borrow_mut().add_let_var(name, c as _);
+                    self.code.var_count += 1;
+                }
+                BindingKind::Var => {
+                    let s: &str = &(var.0).0;
+                    let name = s.intern();
+                    let c = self.code.var_count;
+                    self.scope.borrow_mut().add_var(name, c as _);
+                    self.code.var_count += 1;
+                }
                 _ => (),
             }
         }
+        let has_default = body.iter().any(|item| match item {
+            ModuleItem::ModuleDecl(ModuleDecl::ExportDefaultExpr(_)) => true,
+            ModuleItem::ModuleDecl(ModuleDecl::ExportDefaultDecl(ExportDefaultDecl {
+                decl: DefaultDecl::Fn(FnExpr { ident: None, .. }),
+                ..
+            })) => true,
+            _ => false,
+        });
+        if has_default {
+            let c = self.code.var_count;
+            self.scope
+                .borrow_mut()
+                .add_let_var("*default*".intern(), c as _);
+            self.code.var_count += 1;
+        }
 
         let mut res = Ok(());
         VisitFnDecl::visit_module(body, &mut |decl| {
-            let name = Self::ident_to_sym(&decl.ident);
-            let p = self.code.path.clone();
-            let mut code = CodeBlock::new(ctx, name, false, p);
-            code.file_name = self.code.file_name.clone();
-            let ix = self.code.codes.len();
-            self.code.codes.push(code);
-            self.fmap.insert(name, ix as _);
-            let name = Self::ident_to_sym(&decl.ident);
-            match self.function(ctx, &decl.function, Self::ident_to_sym(&decl.ident), false) {
-                Ok(()) => {}
-                Err(e) => res = Err(e),
+            if let Err(e) = self.hoist_fn_decl(ctx, decl) {
+                res = Err(e);
             }
-            // self.emit(Opcode::OP_GET_FUNCTION, &[ix as _], false);
-            let var = self.access_var(name);
-            self.access_set(var).unwrap_or_else(|_| panic!("wtf"));
         });
-        res
+        res?;
+        for item in body {
+            if let ModuleItem::ModuleDecl(ModuleDecl::ExportDefaultDecl(ExportDefaultDecl {
+                decl: DefaultDecl::Fn(fun),
+                ..
+            })) = item
+            {
+                match fun.ident {
+                    Some(ref ident) => self.hoist_fn_decl(
+                        ctx,
+                        &FnDecl {
+                            ident: ident.clone(),
+                            declare: false,
+                            function: fun.function.clone(),
+                        },
+                    )?,
+                    None => {
+                        self.function(ctx, &fun.function, "default".intern(), true)?;
+                        let var = self.access_var("*default*".intern());
+                        self.access_set(var)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Compile function declaration `decl` and store the function in its variable.
+    fn hoist_fn_decl(
+        &mut self,
+        ctx: GcPointer<Context>,
+        decl: &FnDecl,
+    ) -> Result<(), CompileError> {
+        let name = Self::ident_to_sym(&decl.ident);
+        let p = self.code.path.clone();
+        let mut code = CodeBlock::new(ctx, name, false, p);
+        code.file_name = self.code.file_name.clone();
+        let ix = self.code.codes.len();
+        self.code.codes.push(code);
+        self.fmap.insert(name, ix as _);
+        self.function(ctx, &decl.function, name, false)?;
+        let var = self.access_var(name);
+        self.access_set(var)
+    }
+
+    /// Add modules requested by module `body` to `info` in source order and declare imports in module scope.
+    fn declare_imports(&mut self, body: &[ModuleItem], info: &mut ModuleInfo) {
+        for item in body {
+            let import = match item {
+                ModuleItem::ModuleDecl(ModuleDecl::Import(import)) => import,
+                ModuleItem::ModuleDecl(ModuleDecl::ExportNamed(NamedExport {
+                    src: Some(src),
+                    ..
+                }))
+                | ModuleItem::ModuleDecl(ModuleDecl::ExportAll(ExportAll { src, .. })) => {
+                    info.request(&src.value);
+                    continue;
+                }
+                _ => continue,
+            };
+            let request = info.request(&import.src.value);
+            for specifier in import.specifiers.iter() {
+                let (local, name) = match specifier {
+                    ImportSpecifier::Named(named) => (
+                        Self::ident_to_sym(&named.local),
+                        ImportName::Name(Self::ident_to_sym(
+                            named.imported.as_ref().unwrap_or(&named.local),
+                        )),
+                    ),
+                    ImportSpecifier::Default(default) => (
+                        Self::ident_to_sym(&default.local),
+                        ImportName::Name("default".intern()),
+                    ),
+                    ImportSpecifier::Namespace(namespace) => {
+                        (Self::ident_to_sym(&namespace.local), ImportName::Namespace)
+                    }
+                };
+                let ix = info.imports.len() as u16;
+                info.imports.push(ImportEntry {
+                    local,
+                    request,
+                    name,
+                });
+                self.scope.borrow_mut().add_import(local, ix);
+            }
+        }
+    }
+
+    /// Compile module declaration `decl`. Exported names together with names of module variables they export are
+    /// added to `exports`, exports of other modules are added to `info`.
+    fn module_decl(
+        &mut self,
+        ctx: GcPointer<Context>,
+        decl: &ModuleDecl,
+        info: &mut ModuleInfo,
+        exports: &mut Vec<(Symbol, Symbol)>,
+    ) -> Result<(), CompileError> {
+        match decl {
+            // imports are declared by `declare_imports`
+            ModuleDecl::Import(_) => {}
+            ModuleDecl::ExportDecl(export) => match export.decl {
+                Decl::Var(ref var) => {
+                    for name in self.var_decl(ctx, var)? {
+                        exports.push((name, name));
+                    }
+                }
+                // functions are hoisted
+                Decl::Fn(ref fun) => {
+                    let name = Self::ident_to_sym(&fun.ident);
+                    exports.push((name, name));
+                }
+                ref x => return Err(CompileError::NotYetImpl(format!("NYI: {:?}", x))),
+            },
+            ModuleDecl::ExportDefaultDecl(export) => match export.decl {
+                // default function is hoisted by `analyze_module`
+                DefaultDecl::Fn(ref fun) => {
+                    let local = match fun.ident {
+                        Some(ref ident) => Self::ident_to_sym(ident),
+                        None => "*default*".intern(),
+                    };
+                    exports.push(("default".intern(), local));
+                }
+                ref x => return Err(CompileError::NotYetImpl(format!("NYI: {:?}", x))),
+            },
+            ModuleDecl::ExportDefaultExpr(export) => {
+                self.expr(ctx, &export.expr, true, false)?;
+                let var = self.access_var("*default*".intern());
+                self.access_set(var)?;
+                exports.push(("default".intern(), "*default*".intern()));
+            }
+            ModuleDecl::ExportNamed(named_export) => {
+                let request = named_export
+                    .src
+                    .as_ref()
+                    .map(|src| info.request(&src.value));
+                for specifier in named_export.specifiers.iter() {
+                    match (specifier, request) {
+                        (ExportSpecifier::Named(named), request) => {
+                            let orig = Self::ident_to_sym(&named.orig);
+                            let export_as = match named.exported {
+                                Some(ref exported) => Self::ident_to_sym(exported),
+                                None => orig,
+                            };
+                            match request {
+                                Some(request) => info.exports.push(ExportEntry::Indirect {
+                                    name: export_as,
+                                    request,
+                                    import: ImportName::Name(orig),
+                                }),
+                                None => exports.push((export_as, orig)),
+                            }
+                        }
+                        (ExportSpecifier::Namespace(namespace), Some(request)) => {
+                            info.exports.push(ExportEntry::Indirect {
+                                name: Self::ident_to_sym(&namespace.name),
+                                request,
+                                import: ImportName::Namespace,
+                            })
+                        }
+                        _ => {
+                            return Err(CompileError::NotYetImpl(format!("NYI: {:?}", specifier)));
+                        }
+                    }
+                }
+            }
+            ModuleDecl::ExportAll(export_all) => {
+                let request = info.request(&export_all.src.value);
+                info.star_exports.push(request);
+            }
+            x => return Err(CompileError::NotYetImpl(format!("NYI: {:?}", x))),
+        }
+        Ok(())
     }
 
     pub fn compile_module(
-        mut ctx: GcPointer<Context>,
+        ctx: GcPointer<Context>,
         file: &str,
         path: &str,
         name: &str,
         module: &Module,
+        source: Option<&SourceFile>,
     ) -> Result<GcPointer<CodeBlock>, CompileError> {
         let name = name.intern();
 
@@ -754,6 +1848,7 @@ impl ByteCompiler {
                 parent: None,
                 variables: Default::default(),
                 depth: 0,
+                dynamic: false,
             })),
             variable_freelist: vec![],
             code,
@@ -761,140 +1856,69 @@ impl ByteCompiler {
             name_map: Default::default(),
             fmap: Default::default(),
             is_try: true,
+            eval: false,
+            top_level_await: true,
+            source: ScriptSource::new(ctx, source),
+            lines: source.map(|file| Rc::new(LineTable::new(file))),
         };
         code.var_count = 1;
         code.param_count = 1;
+        // slot 0 holds module record
         compiler.scope.borrow_mut().add_var("@module".intern(), 0);
 
-        let loader = JsValue::new(ctx.module_loader().unwrap());
-
-        let loader_val = compiler.get_val2(loader);
+        let mut info = ModuleInfo::default();
+        compiler.declare_imports(&module.body, &mut info);
         compiler.analyze_module(ctx, &module.body)?;
 
-        if let Some(item) = module.body.get(0) {
-            match item {
-                ModuleItem::Stmt(stmt) => match stmt {
-                    Stmt::Expr(e) => match &*e.expr {
-                        Expr::Lit(x) => match x {
-                            Lit::Str(x) => {
-                                code.strict = x.value.to_string() == "use strict";
-                            }
-                            _ => (),
-                        },
-                        _ => (),
-                    },
-                    _ => (),
-                },
-                _ => (),
-            }
-        }
+        // module code is always strict code.
+        code.strict = true;
+        // end of prologue, module body runs only when module is evaluated.
+        compiler.emit(Opcode::OP_PUSH_UNDEF, &[], false);
+        compiler.emit(Opcode::OP_RET, &[], false);
+        info.body_start = compiler.code.code.len() as u32;
+
+        let mut exports = vec![];
         for item in &module.body {
             match item {
                 ModuleItem::Stmt(stmt) => {
                     compiler.stmt(ctx, stmt)?;
                 }
-                ModuleItem::ModuleDecl(module_decl) => match module_decl {
-                    ModuleDecl::Import(import) => {
-                        let src = compiler.get_val(ctx, Val::Str(import.src.value.to_string()));
-                        compiler.emit(Opcode::OP_PUSH_UNDEF, &[], false);
-                        compiler.emit(Opcode::OP_PUSH_LITERAL, &[loader_val], false);
-                        compiler.emit(Opcode::OP_PUSH_LITERAL, &[src], false);
-                        compiler.emit(Opcode::OP_CALL, &[1], false);
-                        for specifier in import.specifiers.iter() {
-                            match specifier {
-                                ImportSpecifier::Default(default) => {
-                                    compiler.emit(Opcode::OP_DUP, &[], false);
-                                    let default = Self::ident_to_sym(&default.local);
-                                    let sym = compiler.get_sym("@default".intern());
-                                    compiler.emit(Opcode::OP_TRY_GET_BY_ID, &[sym], true);
-                                    compiler.create_const(default);
-                                }
-                                ImportSpecifier::Namespace(namespace) => {
-                                    compiler.emit(Opcode::OP_DUP, &[], false);
-                                    let default = Self::ident_to_sym(&namespace.local);
-                                    compiler.create_const(default);
-                                }
-                                ImportSpecifier::Named(named) => {
-                                    compiler.emit(Opcode::OP_DUP, &[], false);
-                                    let import_as = match named.imported {
-                                        Some(ref name) => Self::ident_to_sym(name),
-                                        None => Self::ident_to_sym(&named.local),
-                                    };
-                                    let name = Self::ident_to_sym(&named.local);
-                                    let sym = compiler.get_sym("@exports".intern());
-                                    compiler.emit(Opcode::OP_GET_BY_ID, &[sym], true);
-                                    let sym = compiler.get_sym(name);
-                                    compiler.emit(Opcode::OP_GET_BY_ID, &[sym], true);
-                                    compiler.create_const(import_as);
-                                }
-                            }
-                        }
-                        compiler.emit(Opcode::OP_POP, &[], false);
-                    }
-                    ModuleDecl::ExportDecl(decl) => {
-                        compiler.decl(ctx, &decl.decl, true)?;
-                    }
-                    ModuleDecl::ExportDefaultDecl(decl) => {
-                        match decl.decl {
-                            DefaultDecl::Fn(ref fun) => {
-                                compiler.fn_expr(ctx, fun, true)?;
-                            }
-                            ref x => {
-                                return Err(CompileError::NotYetImpl(format!("NYI: {:?}", x)));
-                            }
-                        }
-
-                        let module = compiler.access_var("@module".intern());
-                        compiler.access_get(module)?;
-                        let default = compiler.get_sym("@default".intern());
-                        compiler.emit(Opcode::OP_PUT_BY_ID, &[default], true);
-                    }
-                    ModuleDecl::ExportDefaultExpr(expr) => {
-                        compiler.expr(ctx, &expr.expr, true, false)?;
-                        let module = compiler.access_var("@module".intern());
-                        compiler.access_get(module)?;
-                        let default = compiler.get_sym("@default".intern());
-                        compiler.emit(Opcode::OP_PUT_BY_ID, &[default], true);
-                    }
-                    ModuleDecl::ExportNamed(named_export) => {
-                        if named_export.src.is_some() {
-                            return Err(CompileError::NotYetImpl(
-                                "NYI: export * from mod".to_string(),
-                            ));
-                        }
-
-                        for specifier in named_export.specifiers.iter() {
-                            match specifier {
-                                ExportSpecifier::Named(named) => {
-                                    let export_as = match named.exported {
-                                        Some(ref exported) => Self::ident_to_sym(exported),
-                                        None => Self::ident_to_sym(&named.orig),
-                                    };
-                                    let orig = compiler.access_var(Self::ident_to_sym(&named.orig));
-                                    compiler.access_get(orig)?;
-                                    let module = compiler.access_var("@module".intern());
-                                    compiler.access_get(module)?;
-                                    let exports = compiler.get_sym("@exports".intern());
-                                    compiler.emit(Opcode::OP_GET_BY_ID, &[exports], true);
-                                    let sym = compiler.get_sym(export_as);
-                                    compiler.emit(Opcode::OP_PUT_BY_ID, &[sym], true);
-                                }
-                                _ => {
-                                    return Err(CompileError::NotYetImpl(format!(
-                                        "NYI: {:?}",
-                                        specifier
-                                    )));
-                                }
-                            }
-                        }
-                    }
-                    x => return Err(CompileError::NotYetImpl(format!("NYI: {:?}", x))),
-                },
+                ModuleItem::ModuleDecl(module_decl) => {
+                    compiler.module_decl(ctx, module_decl, &mut info, &mut exports)?;
+                }
             }
         }
         compiler.emit(Opcode::OP_PUSH_UNDEF, &[], false);
         compiler.emit(Opcode::OP_RET, &[], false);
-        let mut result = compiler.finish(ctx).map_err(|x| CompileError::Val(x))?;
+        for (name, local) in exports {
+            let export = match compiler.scope.borrow().variables.get(&local) {
+                Some(Variable {
+                    kind: VariableKind::Import,
+                    index,
+                    ..
+                }) => {
+                    let import = &info.imports[*index as usize];
+                    ExportEntry::Indirect {
+                        name,
+                        request: import.request,
+                        import: import.name,
+                    }
+                }
+                Some(var) => ExportEntry::Local {
+                    name,
+                    slot: var.index as _,
+                },
+                None => {
+                    return Err(CompileError::Syntax(format!(
+                        "Export '{}' is not defined",
+                        ctx.description(local)
+                    )))
+                }
+            };
+            info.exports.push(export);
+        }
+        code.module = Some(Box::new(info));
+        let result = compiler.finish(ctx).map_err(|x| CompileError::Val(x))?;
         Ok(result)
     }
     pub fn compile_script(
@@ -903,6 +1927,7 @@ impl ByteCompiler {
         path: &str,
         fname: String,
         builtins: bool,
+        source: Option<&SourceFile>,
     ) -> Result<GcPointer<CodeBlock>, CompileError> {
         let name = "<script>".intern();
         let mut code = CodeBlock::new(ctx, name, false, path.into());
@@ -917,6 +1942,7 @@ impl ByteCompiler {
                 parent: None,
                 variables: Default::default(),
                 depth: 0,
+                dynamic: false,
             })),
             variable_freelist: vec![],
             code,
@@ -924,6 +1950,10 @@ impl ByteCompiler {
             name_map: Default::default(),
             fmap: Default::default(),
             is_try: true,
+            eval: false,
+            top_level_await: false,
+            source: ScriptSource::new(ctx, source),
+            lines: source.map(|file| Rc::new(LineTable::new(file))),
         };
 
         let is_strict = match p.body.get(0) {
@@ -942,18 +1972,46 @@ impl ByteCompiler {
     }
 
     pub fn compile_eval(
+        ctx: GcPointer<Context>,
+        p: &Script,
+        path: &str,
+        fname: String,
+        builtins: bool,
+        source: Option<&SourceFile>,
+    ) -> Result<GcPointer<CodeBlock>, CompileError> {
+        Self::compile_global_code(ctx, p, path, fname, builtins, source, false)
+    }
+
+    /// Compile source of indirect `eval` as global eval code. Unlike scripts, variables and functions declared by
+    /// strict eval code are local to it.
+    pub fn compile_indirect_eval(
+        ctx: GcPointer<Context>,
+        p: &Script,
+        source: Option<&SourceFile>,
+    ) -> Result<GcPointer<CodeBlock>, CompileError> {
+        Self::compile_global_code(ctx, p, "", String::new(), false, source, true)
+    }
+
+    fn compile_global_code(
         mut ctx: GcPointer<Context>,
         p: &Script,
         path: &str,
         fname: String,
         builtins: bool,
+        source: Option<&SourceFile>,
+        eval_code: bool,
     ) -> Result<GcPointer<CodeBlock>, CompileError> {
-        let name = "<script>".intern();
+        let name = if eval_code { "<eval>" } else { "<script>" }.intern();
         let mut code = CodeBlock::new(ctx, name, false, path.into());
         code.file_name = fname;
+        let is_strict = match p.body.get(0) {
+            Some(body) => body.is_use_strict(),
+            None => false,
+        };
+        let top_level = !(eval_code && is_strict);
         let mut compiler = ByteCompiler {
             lci: Vec::new(),
-            top_level: true,
+            top_level,
             info: None,
             tail_pos: false,
             builtins,
@@ -961,6 +2019,7 @@ impl ByteCompiler {
                 parent: None,
                 variables: Default::default(),
                 depth: 0,
+                dynamic: false,
             })),
             variable_freelist: vec![],
             code,
@@ -968,13 +2027,13 @@ impl ByteCompiler {
             name_map: Default::default(),
             fmap: Default::default(),
             is_try: true,
+            eval: false,
+            top_level_await: false,
+            source: ScriptSource::new(ctx, source),
+            lines: source.map(|file| Rc::new(LineTable::new(file))),
         };
 
-        let is_strict = match p.body.get(0) {
-            Some(body) => body.is_use_strict(),
-            None => false,
-        };
-        code.top_level = true;
+        code.top_level = top_level;
         code.strict = is_strict;
         compiler.push_scope();
         compiler.compile(ctx, &p.body, true)?;
@@ -990,6 +2049,12 @@ impl ByteCompiler {
 
         for var in scopea.vars.iter() {
             match var.1.kind() {
+                BindingKind::Var | BindingKind::Function
+                    if self.eval && !self.top_level && !self.code.strict =>
+                {
+                    let s: &str = &(var.0).0;
+                    self.decl_caller_var(s.intern());
+                }
                 BindingKind::Var if !self.top_level => {
                     let s: &str = &(var.0).0;
                     let name = s.intern();
@@ -1035,8 +2100,12 @@ impl ByteCompiler {
             self.access_set(var).unwrap_or_else(|_| panic!("wtf"));
         });
 
+        // eval code uses `arguments` of the caller so functions calling eval have to create it.
         for stmt in body.iter() {
-            if contains_ident(stmt, "arguments") {
+            if !self.eval
+                && (contains_ident(stmt, "arguments")
+                    || (!self.top_level && contains_direct_eval(stmt)))
+            {
                 self.code.use_arguments = true;
                 let c = self.code.var_count;
                 self.code.args_at = self
@@ -1077,6 +2146,7 @@ impl ByteCompiler {
             parent: Some(self.scope.clone()),
             depth: self.scope.borrow().depth,
             variables: Default::default(),
+            dynamic: false,
         }));
         self.scope = new_scope;
         d
@@ -1103,29 +2173,14 @@ impl ByteCompiler {
             break_(self);
         }
     }
-    pub fn decl(
-        &mut self,
-        ctx: GcPointer<Context>,
-        decl: &Decl,
-        export: bool,
-    ) -> Result<(), CompileError> {
+    pub fn decl(&mut self, ctx: GcPointer<Context>, decl: &Decl) -> Result<(), CompileError> {
         match decl {
             Decl::Var(var) => {
-                self.var_decl(ctx, var, export)?;
+                self.var_decl(ctx, var)?;
             }
 
-            Decl::Fn(fun) => {
-                let name = Self::ident_to_sym(&fun.ident);
-                if export {
-                    let var = self.access_var(name);
-                    self.access_get(var)?;
-                    let module = self.access_var("@module".intern());
-                    self.access_get(module)?;
-                    let exports = self.get_sym("@exports".intern());
-                    self.emit(Opcode::OP_GET_BY_ID, &[exports], true);
-                    let sym = self.get_sym(name);
-                    self.emit(Opcode::OP_PUT_BY_ID, &[sym], true);
-                }
+            Decl::Fn(_) => {
+                // function declarations are hoisted
             }
 
             x => {
@@ -1135,6 +2190,7 @@ impl ByteCompiler {
         Ok(())
     }
     pub fn stmt(&mut self, ctx: GcPointer<Context>, stmt: &Stmt) -> Result<(), CompileError> {
+        self.mark_position(stmt.span());
         match stmt {
             Stmt::Switch(switch) => {
                 let d = self.scope.borrow().depth;
@@ -1211,7 +2267,7 @@ impl ByteCompiler {
 
                 // self.emit(Opcode::OP_PUSH_ENV, &[], false);
                 let name = match for_in.left {
-                    VarDeclOrPat::VarDecl(ref var_decl) => self.var_decl(ctx, var_decl, false)?[0],
+                    VarDeclOrPat::VarDecl(ref var_decl) => self.var_decl(ctx, var_decl)?[0],
                     VarDeclOrPat::Pat(Pat::Ident(ref ident)) => {
                         let sym = Self::ident_to_sym(&ident.id);
                         self.emit(Opcode::OP_PUSH_UNDEF, &[], false);
@@ -1252,7 +2308,7 @@ impl ByteCompiler {
                 self.analyze(ctx, &[Stmt::ForOf(for_of.clone())])?;
 
                 let name = match for_of.left {
-                    VarDeclOrPat::VarDecl(ref var_decl) => self.var_decl(ctx, var_decl, false)?[0],
+                    VarDeclOrPat::VarDecl(ref var_decl) => self.var_decl(ctx, var_decl)?[0],
                     VarDeclOrPat::Pat(Pat::Ident(ref ident)) => {
                         let sym = Self::ident_to_sym(&ident.id);
                         self.emit(Opcode::OP_PUSH_UNDEF, &[], false);
@@ -1270,7 +2326,7 @@ impl ByteCompiler {
                 self.expr(ctx, &for_of.right, true, false)?;
                 self.emit(Opcode::OP_DUP, &[], false);
                 self.emit(Opcode::OP_GET_BY_ID, &[iterator], true);
-                self.emit(Opcode::OP_CALL, &[0], false);
+                self.emit(Opcode::OP_CALL, &[0], true);
 
                 let head = self.code.code.len();
                 self.push_lci(head as _, depth);
@@ -1278,7 +2334,7 @@ impl ByteCompiler {
                 self.emit(Opcode::OP_DUP, &[], false);
                 self.emit(Opcode::OP_DUP, &[], false);
                 self.emit(Opcode::OP_GET_BY_ID, &[next], true);
-                self.emit(Opcode::OP_CALL, &[0], false);
+                self.emit(Opcode::OP_CALL, &[0], true);
                 self.emit(Opcode::OP_DUP, &[], false);
                 self.emit(Opcode::OP_GET_BY_ID, &[done], true);
                 let end = self.cjmp(true);
@@ -1306,7 +2362,7 @@ impl ByteCompiler {
                             self.expr(ctx, e, false, false)?;
                         }
                         VarDeclOrExpr::VarDecl(ref decl) => {
-                            self.var_decl(ctx, decl, false)?;
+                            self.var_decl(ctx, decl)?;
                         }
                     },
                     None => {}
@@ -1358,7 +2414,7 @@ impl ByteCompiler {
                     }
                 }
             }
-            Stmt::Decl(decl) => self.decl(ctx, decl, false)?,
+            Stmt::Decl(decl) => self.decl(ctx, decl)?,
             Stmt::Empty(_) => {}
             Stmt::Throw(throw) => {
                 self.expr(ctx, &throw.arg, true, false)?;
@@ -1551,6 +2607,16 @@ impl ByteCompiler {
                     self.emit(Opcode::OP_POP, &[], false);
                 }
             }
+            Expr::Await(await_expr) => {
+                if !self.top_level_await {
+                    return Err(CompileError::NotYetImpl("NYI: async".to_string()));
+                }
+                self.expr(ctx, &await_expr.arg, true, false)?;
+                self.emit(Opcode::OP_AWAIT, &[], false);
+                if !used {
+                    self.emit(Opcode::OP_POP, &[], false);
+                }
+            }
             Expr::Ident(id) => {
                 // TODO: When builtins are compiled we should add `___` prefix support for builtin symbols.
                 // for example `___iterator` should become `"Symbol.iterator".intern().private()"` and as incle PUSH_LITERAL opcode.
@@ -1652,10 +2718,31 @@ impl ByteCompiler {
                                 self.emit(Opcode::OP_SWAP, &[], false);
                                 self.emit(Opcode::OP_PUT_BY_ID, &[sym], true);
                             }
-                            Prop::KeyValue(assign) => {
+                            Prop::KeyValue(_) | Prop::Method(_) => {
                                 self.emit(Opcode::OP_DUP, &[], false);
-                                self.expr(ctx, &assign.value, true, false)?;
-                                match assign.key {
+                                let key = match &**prop {
+                                    Prop::KeyValue(assign) => {
+                                        self.expr(ctx, &assign.value, true, false)?;
+                                        &assign.key
+                                    }
+                                    Prop::Method(method) => {
+                                        let name = match method.key {
+                                            PropName::Ident(ref id) => Self::ident_to_sym(id),
+                                            PropName::Str(ref s) => s.value.intern(),
+                                            _ => "<anonymous>".intern(),
+                                        };
+                                        self.function_of_kind(
+                                            ctx,
+                                            &method.function,
+                                            name,
+                                            true,
+                                            LazyKind::Method,
+                                        )?;
+                                        &method.key
+                                    }
+                                    _ => unreachable!(),
+                                };
+                                match *key {
                                     PropName::Ident(ref id) => {
                                         let ix = Self::ident_to_sym(id);
                                         let sym = self.get_sym(ix);
@@ -1722,6 +2809,19 @@ impl ByteCompiler {
                     }
                 }
             }
+            Expr::Call(call) if is_dynamic_import(call) => {
+                if call.args.len() != 1 || call.args[0].spread.is_some() {
+                    return Err(CompileError::Syntax(
+                        "import() requires exactly one argument".to_string(),
+                    ));
+                }
+                self.expr(ctx, &call.args[0].expr, true, false)?;
+                self.mark_position(call.span);
+                self.emit(Opcode::OP_DYNAMIC_IMPORT, &[], false);
+                if !used {
+                    self.emit(Opcode::OP_POP, &[], false);
+                }
+            }
             Expr::Call(call) if !is_builtin_call(expr, self.builtins) => {
                 match call.callee {
                     ExprOrSuper::Super(_) => {
@@ -1764,6 +2864,13 @@ impl ByteCompiler {
                 }
                 // self.emit(Opcode::OP_PUSH_EMPTY, &[], false);
                 let has_spread = call.args.iter().any(|x| x.spread.is_some());
+                let direct_eval = !has_spread
+                    && match call.callee {
+                        ExprOrSuper::Expr(ref callee) => {
+                            matches!(&**callee, Expr::Ident(id) if &*id.sym == "eval")
+                        }
+                        ExprOrSuper::Super(_) => false,
+                    };
                 if has_spread {
                     for arg in call.args.iter().rev() {
                         self.expr(ctx, &arg.expr, true, false)?;
@@ -1779,13 +2886,26 @@ impl ByteCompiler {
                     }
                 }
 
-                if !has_spread {
+                match call.callee {
+                    ExprOrSuper::Expr(ref callee) => self.mark_position(callee_span(callee)),
+                    ExprOrSuper::Super(_) => self.mark_position(call.span),
+                }
+                if direct_eval {
+                    let site = self.code.evals.len() as u32;
+                    let scopes = self.capture_scopes();
+                    self.code.evals.push(scopes);
+                    self.emit(
+                        Opcode::OP_CALL_EVAL,
+                        &[call.args.len() as u32, site],
+                        false,
+                    );
+                } else if !has_spread {
                     let op = if tail {
                         Opcode::OP_TAILCALL
                     } else {
                         Opcode::OP_CALL
                     };
-                    self.emit(op, &[call.args.len() as u32], false);
+                    self.emit(op, &[call.args.len() as u32], true);
                 } else {
                     self.emit(
                         Opcode::OP_CALL_BUILTIN,
@@ -1896,13 +3016,14 @@ impl ByteCompiler {
                     }
                 }
 
+                self.mark_position(callee_span(&call.callee));
                 if !has_spread {
                     let op = if tail {
                         Opcode::OP_TAILNEW
                     } else {
                         Opcode::OP_NEW
                     };
-                    self.emit(op, &[argc], false);
+                    self.emit(op, &[argc], true);
                 } else {
                     self.emit(Opcode::OP_CALL_BUILTIN, &[argc as _, 0, 1], false);
                 }
@@ -2034,7 +3155,7 @@ impl ByteCompiler {
                     BinaryOp::Lt => self.emit(Opcode::OP_LESS, &[], false),
                     BinaryOp::LtEq => self.emit(Opcode::OP_LESSEQ, &[], false),
                     BinaryOp::In => self.emit(Opcode::OP_IN, &[], false),
-                    BinaryOp::InstanceOf => self.emit(Opcode::OP_INSTANCEOF, &[], false),
+                    BinaryOp::InstanceOf => self.emit(Opcode::OP_INSTANCEOF, &[], true),
                     x => return Err(CompileError::NotYetImpl(format!("NYI: {:?}", x))),
                 }
 
@@ -2043,85 +3164,24 @@ impl ByteCompiler {
                 }
             }
             Expr::Arrow(fun) => {
-                let is_strict = match &fun.body {
-                    BlockStmtOrExpr::BlockStmt(block) => {
-                        if block.stmts.is_empty() {
-                            false
-                        } else {
-                            block.stmts[0].is_use_strict()
-                        }
-                    }
-                    _ => false,
-                };
                 let name = "<anonymous>".intern();
                 let p = self.code.path.clone();
                 let mut code = CodeBlock::new(ctx, name, false, p);
                 code.file_name = self.code.file_name.clone();
-                let mut compiler = ByteCompiler {
-                    lci: Vec::new(),
-                    top_level: false,
-                    tail_pos: false,
-                    builtins: self.builtins,
-                    code,
-                    variable_freelist: vec![],
-                    val_map: Default::default(),
-                    name_map: Default::default(),
-                    info: None,
-                    fmap: Default::default(),
-                    scope: Rc::new(RefCell::new(Scope {
-                        parent: Some(self.scope.clone()),
-                        depth: self.scope.borrow().depth + 1,
-                        variables: HashMap::new(),
-                    })),
-                    is_try: true,
-                };
-                code.strict = is_strict;
-                let mut params = vec![];
-                let mut rest_at = None;
-                let mut p = 0;
-                for x in fun.params.iter() {
-                    match x {
-                        Pat::Ident(ref x) => {
-                            params.push(Self::ident_to_sym(&x.id));
-                            p += 1;
-                            compiler
-                                .scope
-                                .borrow_mut()
-                                .add_var(Self::ident_to_sym(&x.id), p - 1);
-                        }
-                        Pat::Rest(ref r) => match &*r.arg {
-                            Pat::Ident(ref id) => {
-                                p += 1;
-                                rest_at = Some(
-                                    compiler
-                                        .scope
-                                        .borrow_mut()
-                                        .add_var(Self::ident_to_sym(&id.id), p - 1)
-                                        as u32,
-                                );
-                            }
-                            ref x => return Err(CompileError::NotYetImpl(format!("NYI: {:?}", x))),
-                        },
-                        x => {
-                            return Err(CompileError::NotYetImpl(format!("NYI: {:?}", x)));
-                        }
-                    }
+                code.length = Self::function_length(fun.params.iter());
+                code.source = self.source.and_then(|source| source.function_source(fun.span));
+                match self.lazy_function(&code, fun.span, LazyKind::Arrow) {
+                    Some(lazy) => code.lazy = Some(Box::new(lazy)),
+                    None => Self::arrow_body(
+                        ctx,
+                        code,
+                        self.scope.clone(),
+                        self.builtins,
+                        self.source,
+                        self.lines.clone(),
+                        fun,
+                    )?,
                 }
-                code.rest_at = rest_at;
-                code.param_count = params.len() as _;
-                code.var_count = p as _;
-                match &fun.body {
-                    BlockStmtOrExpr::BlockStmt(block) => {
-                        compiler.compile(ctx, &block.stmts, false)?;
-                        compiler.emit(Opcode::OP_PUSH_UNDEF, &[], false);
-                        compiler.emit(Opcode::OP_RET, &[], false);
-                    }
-                    BlockStmtOrExpr::Expr(expr) => {
-                        compiler.expr(ctx, expr, true, true)?;
-                        compiler.emit(Opcode::OP_RET, &[], false);
-                    }
-                }
-                let code = compiler.finish(ctx).map_err(|x| CompileError::Val(x))?;
 
                 let ix = self.code.codes.len();
                 self.code.codes.push(code);
@@ -2248,6 +3308,26 @@ impl ByteCompiler {
             //this.code.code[p] = ins as u8;
         }
     }
+    /// Record that code emitted from now on was compiled from source at `span`.
+    pub fn mark_position(&mut self, span: Span) {
+        let loc = match self.lines.as_ref().and_then(|lines| lines.location(span.lo)) {
+            Some(loc) => loc,
+            None => return,
+        };
+        let at = self.code.code.len();
+        let info = self.info.get_or_insert_with(Vec::new);
+        if let Some(last) = info.last_mut() {
+            if last.1 == loc {
+                return;
+            }
+            if last.0.start == at {
+                last.1 = loc;
+                return;
+            }
+            last.0.end = at;
+        }
+        info.push((at..at, loc));
+    }
     // fn declare_variable(&mut self,decl: &VarDecl) -> Vec<u32>
     pub fn emit(&mut self, op: Opcode, operands: &[u32], add_feedback: bool) {
         self.code.code.push(op as u8);
@@ -2370,6 +3450,40 @@ impl Visit for IdentFinder<'_> {
     }
 }
 
+/// Returns true if `body` calls direct `eval`, i.e `eval(...)` without spread arguments. Nested functions are not
+/// searched since their `eval` calls see their own scope.
+pub fn contains_direct_eval<N>(body: &N) -> bool
+where
+    N: VisitWith<DirectEvalFinder>,
+{
+    let mut visitor = DirectEvalFinder { found: false };
+    body.visit_with(&Invalid { span: DUMMY_SP } as _, &mut visitor);
+    visitor.found
+}
+pub struct DirectEvalFinder {
+    found: bool,
+}
+
+impl Visit for DirectEvalFinder {
+    noop_visit_type!();
+
+    fn visit_call_expr(&mut self, call: &CallExpr, _: &dyn Node) {
+        call.visit_children_with(self);
+        if let ExprOrSuper::Expr(ref callee) = call.callee {
+            if matches!(&**callee, Expr::Ident(id) if &*id.sym == "eval")
+                && call.args.iter().all(|arg| arg.spread.is_none())
+            {
+                self.found = true;
+            }
+        }
+    }
+
+    fn visit_function(&mut self, _: &Function, _: &dyn Node) {}
+    fn visit_arrow_expr(&mut self, _: &ArrowExpr, _: &dyn Node) {}
+    fn visit_getter_prop(&mut self, _: &GetterProp, _: &dyn Node) {}
+    fn visit_setter_prop(&mut self, _: &SetterProp, _: &dyn Node) {}
+}
+
 fn is_codegen_plugin_call(ctx: GcPointer<Context>, e: &Expr, builtins: bool) -> bool {
     if !builtins && !ctx.vm.options.codegen_plugins {
         return false;
@@ -2391,6 +3505,14 @@ fn is_codegen_plugin_call(ctx: GcPointer<Context>, e: &Expr, builtins: bool) ->
     false
 }
 
+/// Span reported for a call of `callee`: property name for method calls, callee itself otherwise.
+fn callee_span(callee: &Expr) -> Span {
+    match callee {
+        Expr::Member(member) if !member.computed => member.prop.span(),
+        _ => callee.span(),
+    }
+}
+
 fn is_builtin_call(e: &Expr, builtin_compilation: bool) -> bool {
     if !builtin_compilation {
         return false;
@@ -2419,6 +3541,14 @@ fn is_builtin_call(e: &Expr, builtin_compilation: bool) -> bool {
     }
     false
 }
+
+/// `import(specifier)`, parser represents it as call of `import` identifier which can't be a variable name.
+fn is_dynamic_import(call: &CallExpr) -> bool {
+    match &call.callee {
+        ExprOrSuper::Expr(expr) => matches!(&**expr, Expr::Ident(id) if &*id.sym == "import"),
+        ExprOrSuper::Super(_) => false,
+    }
+}
 impl ByteCompiler {
     pub fn handle_codegen_plugin_call(
         &mut self,
@@ -2524,7 +3654,7 @@ impl ByteCompiler {
                                 self.expr(ctx, &call.args[i].expr, true, false)?;
                             }
                             let operands: u32 = (call.args.len() - 1).try_into().unwrap();
-                            self.emit(Opcode::OP_CALL, &[operands], false);
+                            self.emit(Opcode::OP_CALL, &[operands], true);
                         } else {
                             todo!()
                         }
@@ -2540,3 +3670,45 @@ impl ByteCompiler {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::options::Options;
+    use crate::vm::context::Context;
+    use crate::Platform;
+
+    /// Evaluate `source` that returns a function and report whether the function creates `arguments` and resolves
+    /// unknown names at runtime.
+    fn eval_scope_of(source: &str) -> (bool, bool) {
+        Platform::initialize();
+        let mut options = Options::default();
+        options.eager_compilation = true;
+        let mut runtime = Platform::new_runtime(options, None);
+        let mut ctx = Context::new(&mut runtime);
+        let function = ctx.eval(source).unwrap_or_else(|_| panic!());
+        let code = function.get_jsobject().as_function().as_vm().code;
+        let mut bytecode = String::new();
+        code.display_to(&mut bytecode).unwrap();
+        (code.use_arguments, bytecode.contains("resolve_var"))
+    }
+
+    #[test]
+    fn test_direct_eval_detection() {
+        assert_eq!(
+            eval_scope_of("return function (x) { print(x); return eval('x'); };"),
+            (true, true)
+        );
+        assert_eq!(
+            eval_scope_of("return function (x) { print(x); let e = eval; return e('x') + (0, eval)('x'); };"),
+            (false, false)
+        );
+        assert_eq!(
+            eval_scope_of("return function (x) { print(x); return function () { return eval('x'); }; };"),
+            (false, false)
+        );
+        assert_eq!(
+            eval_scope_of("return function (x) { print(x); return eval(...[x]); };"),
+            (false, false)
+        );
+    }
+}
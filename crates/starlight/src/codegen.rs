@@ -100,7 +100,6 @@ pub struct Var {
 
 impl Var {
     /// Empty path means root scope.
-    #[allow(dead_code)]
     pub fn path(&self) -> &[ScopeKind] {
         &self.path
     }
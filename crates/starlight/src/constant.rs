@@ -47,6 +47,8 @@ pub const S_WEAK_REF: &str = "WeakRef";
 
 pub const S_DEREF: &str = "deref";
 
+pub const S_SHADOW_REALM: &str = "ShadowRealm";
+
 // Array
 
 pub const S_ARRAY: &str = "Array";
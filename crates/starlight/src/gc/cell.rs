@@ -87,7 +87,7 @@ pub trait GcCell: mopa::Any + Trace + Serializable + Unpin {
 mopafy!(GcCell);
 #[no_mangle]
 pub unsafe extern "C" fn get_jscell_type_id(x: *mut GcPointerBase) -> u64 {
-    transmute((*x).get_dyn().type_id())
+    transmute::<_, u128>((*x).get_dyn().type_id()) as u64
 }
 #[repr(C)]
 pub struct GcPointerBase {
@@ -1,15 +1,96 @@
 /* This Source Code Form is subject to the terms of the Mozilla Public
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+use std::fmt;
+
 use crate::vm::{context::Context, VirtualMachine};
 
-use self::serializer::SnapshotSerializer;
+use self::{
+    header::{feature_names, SectionKind, SnapshotHeader, HEADER_SIZE, SECTION_COUNT},
+    serializer::SnapshotSerializer,
+};
 
 use super::cell::GcPointer;
 
+pub mod base;
+pub mod code_cache;
 pub mod deserializer;
+pub mod header;
+pub mod references;
 pub mod serializer;
 
+/// Error returned when snapshot can't be loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// Data doesn't start with snapshot magic.
+    BadMagic,
+    /// Snapshot is shorter than its header says.
+    Truncated,
+    /// Snapshot was written in another format version.
+    UnsupportedVersion { expected: u32, found: u32 },
+    /// Snapshot was written on a platform with another pointer width.
+    PointerWidthMismatch { expected: u32, found: u32 },
+    /// Snapshot was written by engine built with another set of features.
+    FeatureMismatch { expected: u32, found: u32 },
+    /// Snapshot was written by another engine build.
+    BuildMismatch,
+    /// Section table entry points outside of the snapshot.
+    InvalidSection(SectionKind),
+    /// Body of the snapshot doesn't match its checksum.
+    ChecksumMismatch,
+    /// Snapshot is a delta and can only be loaded on top of its base snapshot.
+    MissingBase,
+    /// Snapshot is not a delta of the given base snapshot.
+    BaseMismatch,
+    /// Header is malformed.
+    Corrupted(&'static str),
+    /// Snapshot uses native reference that isn't registered in this process.
+    MissingReference(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "data is not a starlight snapshot"),
+            Self::Truncated => write!(f, "snapshot is truncated"),
+            Self::UnsupportedVersion { expected, found } => write!(
+                f,
+                "snapshot format version {} is not supported, expected version {}",
+                found, expected
+            ),
+            Self::PointerWidthMismatch { expected, found } => write!(
+                f,
+                "snapshot was written for {}-bit pointers, engine uses {}-bit pointers",
+                found, expected
+            ),
+            Self::FeatureMismatch { expected, found } => write!(
+                f,
+                "snapshot was written with features [{}], engine is built with [{}]",
+                feature_names(*found),
+                feature_names(*expected)
+            ),
+            Self::BuildMismatch => write!(f, "snapshot was written by another engine build"),
+            Self::InvalidSection(kind) => {
+                write!(f, "snapshot {} section is out of bounds", kind)
+            }
+            Self::ChecksumMismatch => write!(f, "snapshot checksum mismatch, data is corrupted"),
+            Self::MissingBase => write!(
+                f,
+                "snapshot is a delta and has to be loaded on top of its base snapshot"
+            ),
+            Self::BaseMismatch => write!(f, "snapshot is not a delta of the given base snapshot"),
+            Self::Corrupted(reason) => write!(f, "snapshot is corrupted: {}", reason),
+            Self::MissingReference(name) => write!(
+                f,
+                "snapshot references native symbol '{}' that is not registered",
+                name
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
 pub struct Snapshot {
     pub buffer: Box<[u8]>,
     pub serializer: SnapshotSerializer,
@@ -22,23 +103,55 @@ impl Snapshot {
         callback: impl FnOnce(&mut SnapshotSerializer, &mut VirtualMachine),
     ) -> Self {
         let mut serializer = serializer::SnapshotSerializer::new(log);
-        let ids_patch = serializer.output.len();
-        serializer.write_u32(0);
+        serializer.output.resize(HEADER_SIZE, 0);
         serializer.build_reference_map(runtime);
+        let symbols = serializer.output.len();
         serializer.build_symbol_table();
+        let heap = serializer.output.len();
         serializer.build_heap_reference_map(runtime);
         serializer.serialize(runtime);
+        let data = serializer.output.len();
         callback(&mut serializer, runtime);
-        let buf = (serializer.reference_map.len() as u32).to_le_bytes();
-        serializer.output[ids_patch] = buf[0];
-        serializer.output[ids_patch + 1] = buf[1];
-        serializer.output[ids_patch + 2] = buf[2];
-        serializer.output[ids_patch + 3] = buf[3];
+        let references = serializer.output.len();
+        serializer.write_native_references();
+        Self::finish(serializer, [symbols, heap, data, references], 0)
+    }
 
-        let buffer = serializer.output.into_boxed_slice();
-        serializer.output = vec![];
+    /// Take delta snapshot of `runtime` on top of the base snapshot it was loaded from. Only objects allocated or
+    /// changed after the base was loaded are serialized, see [base].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `runtime` wasn't loaded with
+    /// [Deserializer::deserialize_base](deserializer::Deserializer::deserialize_base).
+    pub fn take_delta(
+        log: bool,
+        runtime: &mut VirtualMachine,
+        callback: impl FnOnce(&mut SnapshotSerializer, &mut VirtualMachine),
+    ) -> Self {
+        let base = runtime
+            .base_snapshot
+            .clone()
+            .expect("delta snapshot can only be taken of VM loaded from base snapshot");
+        let mut serializer = serializer::SnapshotSerializer::new(log);
 
-        Snapshot { buffer, serializer }
+        runtime.heap().defer();
+
+        serializer.output.resize(HEADER_SIZE, 0);
+        let (base_objects, objects) = serializer.build_delta_reference_map(runtime, &base);
+        let symbols = serializer.output.len();
+        serializer.seed_symbol_table(&base.symbols);
+        serializer.build_symbol_table();
+        let heap = serializer.output.len();
+        serializer.serialize_delta(runtime, &base, &base_objects, &objects);
+        let data = serializer.output.len();
+        callback(&mut serializer, runtime);
+        let references = serializer.output.len();
+        serializer.write_native_references();
+
+        runtime.heap().undefer();
+
+        Self::finish(serializer, [symbols, heap, data, references], base.checksum)
     }
 
     pub fn take_context(
@@ -48,27 +161,52 @@ impl Snapshot {
         callback: impl FnOnce(&mut SnapshotSerializer, &mut Context),
     ) -> Self {
         let mut serializer = serializer::SnapshotSerializer::new(log);
-        let ids_patch = serializer.output.len();
 
         runtime.heap().defer();
 
-        serializer.write_u32(0);
+        serializer.output.resize(HEADER_SIZE, 0);
         serializer.build_reference_map(runtime);
+        let symbols = serializer.output.len();
         serializer.build_symbol_table();
+        let heap = serializer.output.len();
         serializer.build_heap_reference_map_in_context(runtime, context);
         serializer.serialize_context(runtime, context);
+        let data = serializer.output.len();
         callback(&mut serializer, &mut context);
-        let buf = (serializer.reference_map.len() as u32).to_le_bytes();
-        serializer.output[ids_patch] = buf[0];
-        serializer.output[ids_patch + 1] = buf[1];
-        serializer.output[ids_patch + 2] = buf[2];
-        serializer.output[ids_patch + 3] = buf[3];
-
-        let buffer = serializer.output.into_boxed_slice();
-        serializer.output = vec![];
+        let references = serializer.output.len();
+        serializer.write_native_references();
 
         runtime.heap().undefer();
 
+        Self::finish(serializer, [symbols, heap, data, references], 0)
+    }
+
+    /// Write header with sections starting at `starts` and take the buffer.
+    fn finish(
+        mut serializer: SnapshotSerializer,
+        starts: [usize; SECTION_COUNT],
+        base: u64,
+    ) -> Self {
+        let end = serializer.output.len();
+        let mut sections = [0..0, 0..0, 0..0, 0..0];
+        for (i, section) in sections.iter_mut().enumerate() {
+            *section = starts[i]..starts.get(i + 1).copied().unwrap_or(end);
+        }
+        SnapshotHeader {
+            reference_count: serializer.reference_map.len() as u32,
+            checksum: 0,
+            base,
+            sections,
+        }
+        .write(&mut serializer.output);
+
+        let buffer = std::mem::take(&mut serializer.output).into_boxed_slice();
+
         Snapshot { buffer, serializer }
     }
+
+    /// Validate header and checksum of `snapshot` without loading it.
+    pub fn validate(snapshot: &[u8]) -> Result<(), SnapshotError> {
+        SnapshotHeader::read(snapshot).map(|_| ())
+    }
 }
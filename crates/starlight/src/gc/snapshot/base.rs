@@ -0,0 +1,194 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+//! Layered snapshots.
+//!
+//! Base snapshot is a regular snapshot, usually holding only builtins, loaded with
+//! [Deserializer::deserialize_base](super::deserializer::Deserializer::deserialize_base). VM loaded this way keeps
+//! the base around and [Snapshot::take_delta](super::Snapshot::take_delta) writes only objects that were allocated or
+//! changed since the base was loaded, objects of the base are referenced by the IDs they have in the base.
+//!
+//! Nothing tracks writes to the heap, so objects of the base are serialized again when delta is taken and compared to
+//! their bytes in the base. Unchanged objects are skipped and changed objects are deserialized in place over the base
+//! objects when delta is loaded.
+use std::{collections::HashMap, fs::File, io, ops::Deref, ops::Range, path::Path};
+
+use memmap2::Mmap;
+
+use crate::vm::symbol_table::Symbol;
+
+/// Bytes of a snapshot. Mapped snapshots are read directly from the page cache without copying.
+pub enum SnapshotData {
+    Owned(Box<[u8]>),
+    Static(&'static [u8]),
+    Mapped(Mmap),
+}
+
+impl SnapshotData {
+    /// Map snapshot file at `path` into memory. The file must not be modified while it is mapped, write new
+    /// snapshot to another file and rename it over the old one instead.
+    pub fn map(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: see above, snapshots written by this crate's tools are always replaced by renaming.
+        unsafe { Mmap::map(&file).map(Self::Mapped) }
+    }
+}
+
+impl Deref for SnapshotData {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Owned(bytes) => bytes,
+            Self::Static(bytes) => bytes,
+            Self::Mapped(map) => map,
+        }
+    }
+}
+
+impl From<Box<[u8]>> for SnapshotData {
+    fn from(bytes: Box<[u8]>) -> Self {
+        Self::Owned(bytes)
+    }
+}
+
+impl From<Vec<u8>> for SnapshotData {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::Owned(bytes.into_boxed_slice())
+    }
+}
+
+impl From<&'static [u8]> for SnapshotData {
+    fn from(bytes: &'static [u8]) -> Self {
+        Self::Static(bytes)
+    }
+}
+
+/// Object deserialized from base snapshot.
+pub(crate) struct BaseObject {
+    /// Reference ID of the object in the base.
+    pub(crate) id: u32,
+    /// Serialized object data in the base.
+    pub(crate) data: Range<usize>,
+    pub(crate) vtable: usize,
+    pub(crate) size: usize,
+}
+
+/// Base snapshot VM was loaded from.
+pub struct BaseSnapshot {
+    pub(crate) data: SnapshotData,
+    /// Body checksum of the base, delta snapshots store it to be matched with their base.
+    pub(crate) checksum: u64,
+    /// Reference map of the base after loading, indexed by reference ID.
+    pub(crate) reference_map: Vec<usize>,
+    /// Symbols of the base indexed by their ID in the base.
+    pub(crate) symbols: Vec<Symbol>,
+    /// Objects of the base keyed by their address.
+    pub(crate) objects: HashMap<usize, BaseObject>,
+}
+
+impl BaseSnapshot {
+    /// Bytes of the base snapshot.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Body checksum of the base snapshot.
+    pub fn checksum(&self) -> u64 {
+        self.checksum
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SnapshotData;
+    use crate::gc::default_heap;
+    use crate::gc::snapshot::{deserializer::Deserializer, Snapshot, SnapshotError};
+    use crate::options::Options;
+    use crate::vm::{value::JsValue, VirtualMachineRef};
+    use crate::Platform;
+
+    /// Full snapshot of a VM with one context where `source` was evaluated.
+    fn base(source: &str) -> Box<[u8]> {
+        Platform::initialize();
+        let mut vm = Platform::new_runtime(Options::default(), None);
+        let mut ctx = vm.new_context();
+        ctx.eval(source).unwrap_or_else(|_| panic!("{}", source));
+        Snapshot::take(false, &mut vm, |_, _| {}).buffer
+    }
+
+    fn load(
+        base: impl Into<SnapshotData>,
+        delta: &[u8],
+    ) -> Result<VirtualMachineRef, SnapshotError> {
+        Deserializer::deserialize_with_delta(
+            false,
+            base.into(),
+            delta,
+            Options::default(),
+            default_heap(&Options::default()),
+            None,
+            |_, _| {},
+        )
+    }
+
+    fn eval(vm: &mut VirtualMachineRef, source: &str) -> String {
+        let mut ctx = vm.context(0);
+        let result = ctx
+            .eval(source)
+            .and_then(|value: JsValue| value.to_string(ctx));
+        result.unwrap_or_else(|_| panic!("{}", source))
+    }
+
+    #[test]
+    fn test_delta() {
+        let base_a = base("var shared = { name: 'a' }; var unchanged = [1, 2];");
+        let base_b = base("var shared = { name: 'b' }; var unchanged = [1, 2];");
+
+        let mut vm = Deserializer::deserialize_base(
+            false,
+            base_a.clone().into(),
+            Options::default(),
+            default_heap(&Options::default()),
+            None,
+            |_, _| {},
+        )
+        .unwrap_or_else(|error| panic!("{}", error));
+        eval(
+            &mut vm,
+            "shared.name += '!'; var added = { value: shared.name + unchanged.length };",
+        );
+        let delta = Snapshot::take_delta(false, &mut vm, |_, _| {}).buffer;
+        assert!(delta.len() < base_a.len());
+
+        let mut vm = load(base_a.clone(), &delta).unwrap_or_else(|error| panic!("{}", error));
+        assert_eq!(
+            eval(
+                &mut vm,
+                "return [shared.name, added.value, unchanged.join('')];"
+            ),
+            "a!,a!2,12"
+        );
+
+        let path =
+            std::env::temp_dir().join(format!("starlight-base-{}.snapshot", std::process::id()));
+        std::fs::write(&path, &base_a).unwrap();
+        let mapped = SnapshotData::map(&path).unwrap();
+        let mut vm = load(mapped, &delta).unwrap_or_else(|error| panic!("{}", error));
+        assert_eq!(eval(&mut vm, "return added.value;"), "a!2");
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            load(base_b, &delta).err(),
+            Some(SnapshotError::BaseMismatch)
+        );
+        let full = Deserializer::deserialize(
+            false,
+            &delta,
+            Options::default(),
+            default_heap(&Options::default()),
+            None,
+            |_, _| {},
+        );
+        assert_eq!(full.err(), Some(SnapshotError::MissingBase));
+    }
+}
@@ -0,0 +1,296 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+//! Per-script bytecode cache.
+//!
+//! Unlike startup snapshots which serialize whole heap, code cache stores only [CodeBlock] tree of a single script
+//! together with its literals and symbols. Cache entry is keyed by hash of the source text, compile options and
+//! [BUILD_ID] so stale caches or caches written by another engine build are simply ignored. Body of the cache is
+//! covered by checksum, truncated or corrupted caches are ignored too.
+use super::{deserializer::Deserializer, header::checksum, serializer::SnapshotSerializer};
+use crate::{
+    gc::cell::GcPointer,
+    options::Options,
+    vm::{code_block::CodeBlock, context::Context, string::JsString},
+};
+use const_random::const_random;
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    hash::{Hash, Hasher},
+    path::PathBuf,
+};
+
+/// Random ID generated for each engine build. Bytecode written by another build is never loaded.
+pub const BUILD_ID: u64 = const_random!(u64);
+const MAGIC: [u8; 4] = *b"SLBC";
+const HEADER_SIZE: usize = MAGIC.len() + 8 + 8 + 8;
+
+/// Storage for bytecode caches. Embedders can provide their own implementation with
+/// [VirtualMachine::set_code_cache](crate::vm::VirtualMachine::set_code_cache).
+pub trait CodeCache {
+    /// Load cache previously stored for script at `path`.
+    fn load(&mut self, path: &str) -> Option<Vec<u8>>;
+    /// Store cache for script at `path`.
+    fn store(&mut self, path: &str, data: &[u8]);
+}
+
+/// Code cache that stores bytecode next to the script in `<path>.slc` file.
+#[derive(Default)]
+pub struct FileCodeCache;
+
+impl FileCodeCache {
+    fn cache_path(path: &str) -> PathBuf {
+        let mut path = PathBuf::from(path).into_os_string();
+        path.push(".slc");
+        path.into()
+    }
+}
+
+impl CodeCache for FileCodeCache {
+    fn load(&mut self, path: &str) -> Option<Vec<u8>> {
+        std::fs::read(Self::cache_path(path)).ok()
+    }
+
+    fn store(&mut self, path: &str, data: &[u8]) {
+        // failing to write cache is not an error, script is just compiled again next time.
+        let _ = std::fs::write(Self::cache_path(path), data);
+    }
+}
+
+/// Compute cache key of `source` compiled as module or script with `options`. Options that change emitted code
+/// are part of the key.
+pub fn source_key(options: &Options, source: &str, module: bool) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    BUILD_ID.hash(&mut hasher);
+    options.eager_compilation.hash(&mut hasher);
+    options.strip_source.hash(&mut hasher);
+    module.hash(&mut hasher);
+    source.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Objects that are referenced by compiled code but are owned by context. They are not serialized and are
+/// replaced by the same objects of the loading context.
+fn externals(mut ctx: GcPointer<Context>) -> Vec<usize> {
+    ctx.module_loader()
+        .map(|loader| vec![loader.base.as_ptr() as usize])
+        .unwrap_or_default()
+}
+
+/// Collect all cells reachable from `code`. Returns `None` if code references objects that can't be cached.
+fn collect_cells(code: GcPointer<CodeBlock>, externals: &[usize]) -> Option<Vec<usize>> {
+    let mut seen = HashSet::new();
+    let mut cells = vec![];
+    let mut stack = vec![code];
+    while let Some(code) = stack.pop() {
+        if !seen.insert(code.base.as_ptr() as usize) {
+            continue;
+        }
+        cells.push(code.base.as_ptr() as usize);
+        stack.extend(code.codes.iter().copied());
+        let mut strings = code
+            .source
+            .as_ref()
+            .map(|source| vec![source.text])
+            .unwrap_or_default();
+        for literal in code.literals.iter() {
+            if !literal.is_object() {
+                continue;
+            }
+            let object = literal.get_object();
+            if externals.contains(&(object.base.as_ptr() as usize)) {
+                continue;
+            }
+            strings.push(object.downcast::<JsString>()?);
+        }
+        for string in strings {
+            if seen.insert(string.base.as_ptr() as usize) {
+                cells.push(string.base.as_ptr() as usize);
+            }
+        }
+    }
+    Some(cells)
+}
+
+/// Serialize `code` into bytecode cache keyed by `key`. Returns `None` if code can't be cached.
+pub fn serialize(ctx: GcPointer<Context>, code: GcPointer<CodeBlock>, key: u64) -> Option<Vec<u8>> {
+    let mut vm = ctx.vm();
+    let externals = externals(ctx);
+    let cells = collect_cells(code, &externals)?;
+    let mut serializer = SnapshotSerializer::new(false);
+    serializer.output.extend_from_slice(&MAGIC);
+    serializer.write_u64(BUILD_ID);
+    serializer.write_u64(key);
+    serializer.write_u64(0);
+    serializer.build_reference_map(&mut vm);
+    serializer.reference_map.extend(externals.iter().copied());
+    serializer.reference_map.extend(cells.iter().copied());
+    serializer.write_u32(serializer.reference_map.len() as u32);
+    let names_patch = serializer.output.len();
+    serializer.write_u32(0);
+    serializer.build_symbol_table();
+    serializer.serialize_cells(&cells);
+    serializer.write_gcpointer(code);
+    let names_at = (serializer.output.len() as u32).to_le_bytes();
+    serializer.output[names_patch..names_patch + 4].copy_from_slice(&names_at);
+    serializer.write_native_references();
+    let sum = checksum(&serializer.output[HEADER_SIZE..]).to_le_bytes();
+    serializer.output[HEADER_SIZE - 8..HEADER_SIZE].copy_from_slice(&sum);
+    Some(serializer.output)
+}
+
+/// Load code block from bytecode cache. Returns `None` if cache was written for another source or engine build, if
+/// it is corrupted or if it uses native references that aren't registered.
+pub fn deserialize(ctx: GcPointer<Context>, data: &[u8], key: u64) -> Option<GcPointer<CodeBlock>> {
+    if data.len() < HEADER_SIZE || data[..MAGIC.len()] != MAGIC {
+        return None;
+    }
+    let mut word = [0u8; 8];
+    word.copy_from_slice(&data[MAGIC.len()..MAGIC.len() + 8]);
+    if u64::from_le_bytes(word) != BUILD_ID {
+        return None;
+    }
+    word.copy_from_slice(&data[MAGIC.len() + 8..MAGIC.len() + 16]);
+    if u64::from_le_bytes(word) != key {
+        return None;
+    }
+    word.copy_from_slice(&data[MAGIC.len() + 16..HEADER_SIZE]);
+    if u64::from_le_bytes(word) != checksum(&data[HEADER_SIZE..]) {
+        return None;
+    }
+    let mut vm = ctx.vm();
+    let externals = externals(ctx);
+    unsafe { Deserializer::deserialize_code_cache(&mut vm, data, HEADER_SIZE, &externals).ok() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::value::JsValue;
+    use crate::Platform;
+    use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+    #[derive(Default, Clone)]
+    struct MemoryCodeCache {
+        entries: Rc<RefCell<HashMap<String, Vec<u8>>>>,
+        hits: Rc<RefCell<u32>>,
+    }
+
+    impl CodeCache for MemoryCodeCache {
+        fn load(&mut self, path: &str) -> Option<Vec<u8>> {
+            let data = self.entries.borrow().get(path).cloned();
+            if data.is_some() {
+                *self.hits.borrow_mut() += 1;
+            }
+            data
+        }
+
+        fn store(&mut self, path: &str, data: &[u8]) {
+            self.entries
+                .borrow_mut()
+                .insert(path.to_owned(), data.to_vec());
+        }
+    }
+
+    const SOURCE: &str = "export function f(x) { return 'value: ' + x; } export let y = f(1.5);";
+
+    fn code_of(function: JsValue) -> GcPointer<CodeBlock> {
+        function.get_jsobject().as_function().as_vm().code
+    }
+
+    #[test]
+    fn test_code_cache_round_trip() {
+        Platform::initialize();
+        let cache = MemoryCodeCache::default();
+        let mut vm = Platform::new_runtime(Options::default(), None);
+        vm.set_code_cache(Some(Box::new(cache.clone())));
+        let ctx = Context::new(&mut vm);
+        let compiled = code_of(ctx.compile_module("test.mjs", "test", SOURCE).unwrap());
+        assert_eq!(*cache.hits.borrow(), 0);
+        assert!(cache.entries.borrow().contains_key("test.mjs"));
+
+        let loaded = code_of(ctx.compile_module("test.mjs", "test", SOURCE).unwrap());
+        assert_eq!(*cache.hits.borrow(), 1);
+        assert!(!GcPointer::ptr_eq(&compiled, &loaded));
+        assert_eq!(compiled.code, loaded.code);
+        assert_eq!(compiled.codes.len(), loaded.codes.len());
+        assert_eq!(compiled.codes[0].code, loaded.codes[0].code);
+        assert_eq!(compiled.names, loaded.names);
+        assert_eq!(compiled.literals.len(), loaded.literals.len());
+    }
+
+    #[test]
+    fn test_script_code_cache() {
+        const SCRIPT: &str =
+            "var cached = [1, 2].map(function (x) { return x * 3; }).join(); cached";
+        Platform::initialize();
+        let cache = MemoryCodeCache::default();
+        let mut vm = Platform::new_runtime(Options::default(), None);
+        vm.set_code_cache(Some(Box::new(cache.clone())));
+        let ctx = Context::new(&mut vm);
+        for hits in 0..2 {
+            let result = ctx
+                .eval_internal(Some("test.js"), false, SCRIPT, false)
+                .unwrap_or_else(|_| panic!());
+            assert_eq!(result.to_string(ctx).unwrap_or_default(), "3,6");
+            assert_eq!(*cache.hits.borrow(), hits);
+        }
+        // module compiled from the same path and source does not use the script cache.
+        let module = code_of(ctx.compile_module("test.js", "test", SCRIPT).unwrap());
+        assert!(module.module.is_some());
+    }
+
+    #[test]
+    fn test_source_key_options() {
+        let key = source_key(&Options::default(), SOURCE, true);
+        assert_eq!(key, source_key(&Options::default(), SOURCE, true));
+        assert_ne!(key, source_key(&Options::default(), SOURCE, false));
+        assert_ne!(
+            key,
+            source_key(
+                &Options::default().with_eager_compilation(true),
+                SOURCE,
+                true
+            )
+        );
+        assert_ne!(
+            key,
+            source_key(&Options::default().with_strip_source(true), SOURCE, true)
+        );
+    }
+
+    #[test]
+    fn test_reject_stale_code_cache() {
+        Platform::initialize();
+        let mut vm = Platform::new_runtime(Options::default(), None);
+        let ctx = Context::new(&mut vm);
+        let code = code_of(ctx.compile_module("test.mjs", "test", SOURCE).unwrap());
+        let key = source_key(&Options::default(), SOURCE, true);
+        let data = serialize(ctx, code, key).unwrap();
+        assert!(deserialize(ctx, &data, key).is_some());
+
+        // another source
+        let other = source_key(&Options::default(), "export let y = 1;", true);
+        assert!(deserialize(ctx, &data, other).is_none());
+        // corrupted body
+        let mut bad = data.clone();
+        let middle = HEADER_SIZE + (bad.len() - HEADER_SIZE) / 2;
+        bad[middle] ^= 0xff;
+        assert!(deserialize(ctx, &bad, key).is_none());
+        // another engine build
+        let mut bad = data.clone();
+        bad[MAGIC.len()] ^= 1;
+        assert!(deserialize(ctx, &bad, key).is_none());
+        // truncated
+        assert!(deserialize(ctx, &data[..data.len() - 1], key).is_none());
+        assert!(deserialize(ctx, &data[..HEADER_SIZE - 1], key).is_none());
+        assert!(deserialize(ctx, b"not a cache", key).is_none());
+
+        // cache is stored only for the path it was written for.
+        let mut cache = MemoryCodeCache::default();
+        cache.store("other.mjs", &data);
+        vm.set_code_cache(Some(Box::new(cache.clone())));
+        ctx.compile_module("test.mjs", "test", SOURCE).unwrap();
+        assert_eq!(*cache.hits.borrow(), 0);
+    }
+}
@@ -11,21 +11,31 @@ macro_rules! unique {
 use crate::{
     bytecode::{GetByIdMode, TypeFeedBack},
     gc::cell::{vtable_of_type, GcCell, GcPointer, GcPointerBase, WeakRef},
-    gc::{cell::WeakSlot, Heap},
-    jsrt::VM_NATIVE_REFERENCES,
+    gc::{
+        cell::WeakSlot,
+        snapshot::{
+            base::{BaseObject, BaseSnapshot, SnapshotData},
+            header::{SectionKind, SnapshotHeader},
+            references::resolve_native_reference,
+            SnapshotError,
+        },
+        Heap,
+    },
     prelude::{Class, Options},
     vm::{
         self,
         arguments::JsArguments,
         array_storage::ArrayStorage,
-        code_block::{CodeBlock, FileLocation},
+        code_block::{CodeBlock, FileLocation, FunctionSource, LazyFunction, LazyKind, LazyScope},
         context::Context,
         function::{
             FuncType, JsBoundFunction, JsGeneratorFunction, JsNativeFunction, JsVMFunction,
+            JsWrappedFunction,
         },
         global::JsGlobal,
         indexed_elements::{IndexedElements, SparseArrayMap},
         interpreter::SpreadValue,
+        module::{ExportEntry, ImportEntry, ImportName, ModuleInfo},
         object::{object_size_with_additional, JsObject, ObjectTag},
         property_descriptor::{Accessor, StoredSlot},
         string::{JsString, JsStringObject},
@@ -47,25 +57,41 @@ use std::{
     hint::unreachable_unchecked,
     mem::size_of,
     mem::{transmute, ManuallyDrop},
+    ops::Range,
 };
 
 pub struct Deserializer<'a> {
     reader: &'a [u8],
     pub pc: usize,
     reference_map: Vec<usize>,
+    /// Number of native references in serializer, external references follow them in `reference_map`.
+    native_count: usize,
     symbol_map: Vec<Symbol>,
     log_deser: bool,
+    /// Contexts deserialized from heap. Every realm reachable from the snapshot has to be attached to the VM,
+    /// not only contexts registered in [VirtualMachine::contexts].
+    contexts: Vec<*mut Context>,
+    /// Reference IDs and data of deserialized objects, recorded only when loading base snapshot.
+    base_objects: Option<Vec<(u32, Range<usize>)>>,
 }
 
 impl<'a> Deserializer<'a> {
+    fn new(reader: &'a [u8], log_deser: bool) -> Self {
+        Self {
+            reader,
+            pc: 0,
+            log_deser,
+            symbol_map: Default::default(),
+            reference_map: Default::default(),
+            native_count: 0,
+            contexts: Default::default(),
+            base_objects: None,
+        }
+    }
+
     pub fn get_u32(&mut self) -> u32 {
         let mut buf = [0; 4];
-        unsafe {
-            buf[0] = *self.reader.get_unchecked(self.pc);
-            buf[1] = *self.reader.get_unchecked(self.pc + 1);
-            buf[2] = *self.reader.get_unchecked(self.pc + 2);
-            buf[3] = *self.reader.get_unchecked(self.pc + 3);
-        }
+        buf.copy_from_slice(&self.reader[self.pc..self.pc + 4]);
         self.pc += 4;
         u32::from_le_bytes(buf)
     }
@@ -77,26 +103,14 @@ impl<'a> Deserializer<'a> {
 
     pub fn get_u16(&mut self) -> u16 {
         let mut buf = [0; 2];
-        unsafe {
-            buf[0] = *self.reader.get_unchecked(self.pc);
-            buf[1] = *self.reader.get_unchecked(self.pc + 1);
-        }
+        buf.copy_from_slice(&self.reader[self.pc..self.pc + 2]);
         self.pc += 2;
         u16::from_le_bytes(buf)
     }
 
     pub fn get_u64(&mut self) -> u64 {
         let mut buf = [0; 8];
-        unsafe {
-            buf[0] = *self.reader.get_unchecked(self.pc);
-            buf[1] = *self.reader.get_unchecked(self.pc + 1);
-            buf[2] = *self.reader.get_unchecked(self.pc + 2);
-            buf[3] = *self.reader.get_unchecked(self.pc + 3);
-            buf[4] = *self.reader.get_unchecked(self.pc + 4);
-            buf[5] = *self.reader.get_unchecked(self.pc + 5);
-            buf[6] = *self.reader.get_unchecked(self.pc + 6);
-            buf[7] = *self.reader.get_unchecked(self.pc + 7);
-        }
+        buf.copy_from_slice(&self.reader[self.pc..self.pc + 8]);
         self.pc += 8;
         u64::from_le_bytes(buf)
     }
@@ -107,29 +121,61 @@ impl<'a> Deserializer<'a> {
         //  unwrap_unchecked(self.reference_map.get(&index).copied()) as *const u8
     }
 
-    unsafe fn build_reference_map(&mut self, vm: &mut VirtualMachine) {
-        let mut ix = 0;
-        VM_NATIVE_REFERENCES
-            .iter()
-            .enumerate()
-            .for_each(|(index, reference)| {
-                //let index = self.reference_map.len();
-                *self.reference_map.get_mut(ix).unwrap() = *reference;
-                ix += 1;
-            });
+    /// Resolve native references used by snapshot by their names written at `names_at`.
+    fn build_reference_map(
+        &mut self,
+        names_at: usize,
+        externals: Option<&[usize]>,
+    ) -> Result<(), SnapshotError> {
+        let resume_at = self.pc;
+        self.pc = names_at;
+        self.native_count = self.get_u32() as usize;
+        let used = self.get_u32();
+        for _ in 0..used {
+            let index = self.get_u32() as usize;
+            let len = self.get_u32() as usize;
+            let name = String::from_utf8_lossy(&self.reader[self.pc..self.pc + len]).into_owned();
+            self.pc += len;
+            let reference =
+                resolve_native_reference(&name).ok_or(SnapshotError::MissingReference(name))?;
+            self.reference_map[index] = reference;
+        }
+        self.pc = resume_at;
 
-        if let Some(references) = vm.external_references {
-            for reference in references.iter() {
-                *self.reference_map.get_mut(ix as usize).unwrap() = *reference;
-                ix += 1;
-                //self.reference_map.insert(ix as u32, *reference);
+        if let Some(references) = externals {
+            for (i, reference) in references.iter().enumerate() {
+                self.reference_map[self.native_count + i] = *reference;
             }
         }
+        Ok(())
+    }
+
+    /// Validate snapshot header, resolve references and move to the symbol table. `base` is checksum of the base
+    /// snapshot for deltas and zero for full snapshots.
+    fn load_header(
+        &mut self,
+        externals: Option<&[usize]>,
+        base: u64,
+    ) -> Result<SnapshotHeader, SnapshotError> {
+        let header = SnapshotHeader::read(self.reader)?;
+        if header.base != base {
+            return Err(if base == 0 {
+                SnapshotError::MissingBase
+            } else {
+                SnapshotError::BaseMismatch
+            });
+        }
+        self.reference_map = vec![0; header.reference_count as usize];
+        self.build_reference_map(header.section(SectionKind::References).start, externals)?;
+        self.pc = header.section(SectionKind::Symbols).start;
+        Ok(header)
     }
 
+    /// Read symbol table, symbols are added after symbols already in the map, e.g. symbols of the base.
     unsafe fn build_symbol_table(&mut self) {
         let count = self.get_u32();
-        self.symbol_map = vec![DUMMY_SYMBOL; count as usize];
+        self.symbol_map
+            .resize(self.symbol_map.len() + count as usize, DUMMY_SYMBOL);
         for _ in 0..count {
             let index = self.get_u32();
             let len = self.get_u32();
@@ -199,6 +245,7 @@ impl<'a> Deserializer<'a> {
         }
         logln_if!(self.log_deser, "- Object deserialization completed -");
         self.pc = last_stop;
+        self.attach_contexts(vm);
         let mut ctx = GcPointer::<Context>::deserialize_inplace(self);
         ctx.vm = VirtualMachineRef(vm);
         vm.contexts.push(ctx);
@@ -216,6 +263,9 @@ impl<'a> Deserializer<'a> {
                 self.get_reference(),
             );
             let offset = self.get_u32();
+            if let Some(objects) = &mut self.base_objects {
+                objects.push((ref_id, self.pc..offset as usize));
+            }
             let ptr = alloc(vm, self);
             logln_if!(
                 self.log_deser,
@@ -256,7 +306,12 @@ impl<'a> Deserializer<'a> {
         }
         logln_if!(self.log_deser, "- Object deserialization completed -");
         self.pc = last_stop;
+        self.attach_contexts(vm);
+        self.deserialize_vm_contexts(vm);
+    }
 
+    /// Read contexts of the VM written by [VirtualMachine]'s `serialize`.
+    unsafe fn deserialize_vm_contexts(&mut self, vm: &mut VirtualMachine) {
         let mut ctx_num = self.get_u32() as i32;
         while ctx_num > 0 {
             let mut ctx = GcPointer::<Context>::deserialize_inplace(self);
@@ -265,6 +320,109 @@ impl<'a> Deserializer<'a> {
             ctx_num -= 1;
         }
     }
+
+    /// Deserialize heap of a delta snapshot written by
+    /// [SnapshotSerializer::serialize_delta](super::serializer::SnapshotSerializer::serialize_delta). New objects
+    /// are allocated, changed objects of the base are dropped and deserialized in place.
+    unsafe fn deserialize_delta(&mut self, vm: &mut VirtualMachine) {
+        self.deserialize_cells(vm);
+        let count = self.get_u32();
+        for _ in 0..count {
+            let ref_id = self.get_u32();
+            let base = self.reference_map[ref_id as usize] as *mut GcPointerBase;
+            let deser = self.get_reference();
+            let _alloc = self.get_reference();
+            let end = self.get_u32();
+            assert_eq!(
+                (*base).get_dyn().deser_pair().0,
+                deser as usize,
+                "changed object #{} doesn't match object of the base",
+                ref_id
+            );
+            logln_if!(
+                self.log_deser,
+                "patch #{}:{:p} '{}'",
+                ref_id,
+                base,
+                (*base).get_dyn().type_name()
+            );
+            std::ptr::drop_in_place((*base).get_dyn());
+            transmute::<_, fn(*mut u8, &mut Self)>(deser)((*base).data::<u8>(), self);
+            self.pc = end as usize;
+        }
+        let weak_count = self.get_u32();
+        for _ in 0..weak_count {
+            let gc = self.get_reference();
+            vm.heap().weak_refs.push(transmute(gc));
+        }
+        self.attach_contexts(vm);
+        vm.contexts.clear();
+        self.deserialize_vm_contexts(vm);
+    }
+
+    /// Deserialize cells written by [SnapshotSerializer::serialize_cells](super::serializer::SnapshotSerializer::serialize_cells).
+    unsafe fn deserialize_cells(&mut self, vm: &mut VirtualMachine) {
+        let count = self.get_u32();
+        let heap_at = self.pc;
+        for _ in 0..count {
+            let ref_id = self.get_u32();
+            self.get_reference();
+            let alloc = transmute::<_, fn(&mut VirtualMachine, &mut Self) -> *mut GcPointerBase>(
+                self.get_reference(),
+            );
+            let offset = self.get_u32();
+            let ptr = alloc(vm, self);
+            self.pc = offset as usize;
+            *self.reference_map.get_mut(ref_id as usize).unwrap() = ptr as usize;
+        }
+        let last_stop = self.pc;
+        self.pc = heap_at;
+        for _ in 0..count {
+            let ref_id = self.get_u32();
+            let base = *self.reference_map.get_mut(ref_id as usize).unwrap();
+            let _deser = transmute::<_, fn(*mut u8, &mut Self)>(self.get_reference());
+            let _alloc = self.get_reference();
+            let _off = self.get_u32();
+            let data = (*(base as *mut GcPointerBase)).data::<u8>();
+            _deser(data, self);
+        }
+        self.pc = last_stop;
+        self.attach_contexts(vm);
+    }
+
+    /// Point every deserialized context to `vm`.
+    unsafe fn attach_contexts(&mut self, vm: &mut VirtualMachine) {
+        for ctx in self.contexts.drain(..) {
+            (*ctx).vm = VirtualMachineRef(vm);
+        }
+    }
+
+    /// Deserialize code block tree from bytecode cache starting at `start` in `data`. `externals` must be the same
+    /// references that were passed to serializer.
+    pub(crate) unsafe fn deserialize_code_cache(
+        vm: &mut VirtualMachine,
+        data: &'a [u8],
+        start: usize,
+        externals: &[usize],
+    ) -> Result<GcPointer<CodeBlock>, SnapshotError> {
+        let mut this = Self::new(data, false);
+        this.pc = start;
+        let ref_count = this.get_u32();
+        this.reference_map = vec![0; ref_count as usize];
+        let names_at = this.get_u32() as usize;
+        this.build_reference_map(names_at, vm.external_references)?;
+        let at = this.native_count + vm.external_references.map(|x| x.len()).unwrap_or(0);
+        for (i, external) in externals.iter().enumerate() {
+            this.reference_map[at + i] = *external;
+        }
+        vm.heap().defer();
+        this.build_symbol_table();
+        this.deserialize_cells(vm);
+        let code = this.read_gc::<CodeBlock>();
+        vm.heap().undefer();
+        Ok(code)
+    }
+
     pub unsafe fn read_opt_gc<T: GcCell + ?Sized>(&mut self) -> Option<GcPointer<T>> {
         Option::<GcPointer<T>>::deserialize_inplace(self)
     }
@@ -318,6 +476,10 @@ impl<'a> Deserializer<'a> {
             spread_builtin: self.read_opt_gc(),
             weak_ref_structure: self.read_opt_gc(),
             weak_ref_prototype: self.read_opt_gc(),
+            shadow_realm_structure: self.read_opt_gc(),
+            shadow_realm_prototype: self.read_opt_gc(),
+            shadow_realm_constructor: self.read_opt_gc(),
+            generator_function_structure: self.read_opt_gc(),
             object_constructor: self.read_opt_gc(),
             symbol_structure: self.read_opt_gc(),
             date_structure: self.read_opt_gc(),
@@ -334,24 +496,22 @@ impl<'a> Deserializer<'a> {
         }
         global_data
     }
-    /// Deserialize JS runtime from snapshot buffer. If snapshot has external references that is not part of the VM i.e some native function
-    /// was used in snapshot it should be there too.
+    /// Deserialize JS runtime from snapshot buffer. Native functions used in snapshot are resolved by name from
+    /// [references](super::references), so functions defined by embedder have to be registered before loading:
     /// ```rust,ignore
     ///
     /// fn my_native_fun(ctx: GcPointer<Context>,args: &Arguments) -> Result<JsValue,JsValue> {...}
-    /// fn another_native_fun(ctx: GcPointer<Context>,args: &Arguments) -> Result<JsValue,JsValue> {...}
-    ///
-    /// let native_refs = Box::leak(Box::new([my_naive_fun as usize,another_native_fun as usize]));
-    /// let mut vm = VirtualMachine::new(false,Some(native_refs));
-    ///
-    /// let snapshot = Snapshot::take(&mut vm);
-    ///
-    /// // Note that native references should be passed in the same order as in serialized runtime instance, otherwise
-    /// // this will lead to UB or segfault or wrong function ordering.
-    /// let rt2 = Deserializer::deserialize(false,&snapshot.buffer,Some(native_refs));
     ///
+    /// starlight::register_native_references!(my_native_fun);
     ///
+    /// let snapshot = Snapshot::take(false, &mut vm, |_, _| {});
+    /// let rt2 = Deserializer::deserialize(false, &snapshot.buffer, options, heap, None, |_, _| {})?;
     /// ```
+    ///
+    /// `external_refs` are still supported and must be passed in the same order as in serialized runtime.
+    /// Header of the snapshot is validated before anything is read from it, error is returned if snapshot is
+    /// truncated, corrupted or written by another engine build, and [SnapshotError::MissingReference] if snapshot
+    /// uses native reference that isn't registered.
     pub fn deserialize(
         log_deser: bool,
         snapshot: &'a [u8],
@@ -359,55 +519,153 @@ impl<'a> Deserializer<'a> {
         gc: Heap,
         external_refs: Option<&'static [usize]>,
         callback: impl FnOnce(&mut Self, &mut VirtualMachine),
-    ) -> VirtualMachineRef {
-        let mut runtime = VirtualMachine::new_empty(gc, options, external_refs);
-
-        let mut this = Self {
-            reader: snapshot,
-            pc: 0,
-            log_deser,
-            symbol_map: Default::default(),
-            reference_map: Default::default(),
-        };
+    ) -> Result<VirtualMachineRef, SnapshotError> {
+        let mut this = Self::new(snapshot, log_deser);
+        this.load_header(external_refs, 0)?;
 
+        let mut runtime = VirtualMachine::new_empty(gc, options, external_refs);
         runtime.heap().defer();
 
         unsafe {
-            let ref_count = this.get_u32();
-
-            this.reference_map = vec![0; ref_count as usize];
-            this.build_reference_map(&mut runtime);
             this.build_symbol_table();
             this.deserialize_internal(&mut runtime);
             callback(&mut this, &mut runtime);
         }
         runtime.heap().undefer();
-        runtime
+        Ok(runtime)
     }
 
     pub fn deserialize_context(
         vm: &mut VirtualMachine,
         log_deser: bool,
         snapshot: &'a [u8],
-    ) -> GcPointer<Context> {
-        let mut this = Self {
-            reader: snapshot,
-            pc: 0,
-            log_deser,
-            symbol_map: Default::default(),
-            reference_map: Default::default(),
-        };
+    ) -> Result<GcPointer<Context>, SnapshotError> {
+        let mut this = Self::new(snapshot, log_deser);
+        this.load_header(vm.external_references, 0)?;
 
         vm.heap().defer();
         let ctx = unsafe {
-            let ref_count = this.get_u32();
-            this.reference_map = vec![0; ref_count as usize];
-            this.build_reference_map(vm);
             this.build_symbol_table();
             this.deserialize_internal_in_context(vm)
         };
         vm.heap().undefer();
-        ctx
+        Ok(ctx)
+    }
+
+    /// Deserialize JS runtime from base snapshot. VM keeps the base, so
+    /// [Snapshot::take_delta](super::Snapshot::take_delta) can write only objects that were allocated or changed
+    /// after loading. `base` can be mapped with [SnapshotData::map] to load it without copying.
+    pub fn deserialize_base(
+        log_deser: bool,
+        base: SnapshotData,
+        options: Options,
+        gc: Heap,
+        external_refs: Option<&'static [usize]>,
+        callback: impl FnOnce(&mut Deserializer<'_>, &mut VirtualMachine),
+    ) -> Result<VirtualMachineRef, SnapshotError> {
+        Self::deserialize_layered(log_deser, base, None, options, gc, external_refs, callback)
+    }
+
+    /// Deserialize JS runtime from base snapshot and `delta` taken on top of it. `callback` reads data written to
+    /// the delta. Returns [SnapshotError::BaseMismatch] if `delta` wasn't taken on top of `base`.
+    pub fn deserialize_with_delta(
+        log_deser: bool,
+        base: SnapshotData,
+        delta: &[u8],
+        options: Options,
+        gc: Heap,
+        external_refs: Option<&'static [usize]>,
+        callback: impl FnOnce(&mut Deserializer<'_>, &mut VirtualMachine),
+    ) -> Result<VirtualMachineRef, SnapshotError> {
+        Self::deserialize_layered(
+            log_deser,
+            base,
+            Some(delta),
+            options,
+            gc,
+            external_refs,
+            callback,
+        )
+    }
+
+    fn deserialize_layered(
+        log_deser: bool,
+        base: SnapshotData,
+        delta: Option<&[u8]>,
+        options: Options,
+        gc: Heap,
+        external_refs: Option<&'static [usize]>,
+        callback: impl FnOnce(&mut Deserializer<'_>, &mut VirtualMachine),
+    ) -> Result<VirtualMachineRef, SnapshotError> {
+        let mut this = Deserializer::new(&base, log_deser);
+        this.base_objects = Some(vec![]);
+        let header = this.load_header(external_refs, 0)?;
+        // references of the delta are resolved before anything is allocated, IDs of the base are filled in after
+        // the base is loaded.
+        let mut delta = match delta {
+            Some(delta) => {
+                let mut delta = Deserializer::new(delta, log_deser);
+                delta.load_header(None, header.checksum)?;
+                if delta.reference_map.len() < this.reference_map.len() {
+                    return Err(SnapshotError::Corrupted(
+                        "delta has fewer references than its base",
+                    ));
+                }
+                Some(delta)
+            }
+            None => None,
+        };
+
+        let mut runtime = VirtualMachine::new_empty(gc, options, external_refs);
+        runtime.heap().defer();
+
+        unsafe {
+            this.build_symbol_table();
+            this.deserialize_internal(&mut runtime);
+        }
+        let objects = this
+            .base_objects
+            .take()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(id, data)| {
+                let address = this.reference_map[id as usize];
+                let object = address as *mut GcPointerBase;
+                let object = unsafe {
+                    BaseObject {
+                        id,
+                        data,
+                        vtable: (*object).vtable(),
+                        size: (*object).allocation_size(),
+                    }
+                };
+                (address, object)
+            })
+            .collect();
+
+        match delta {
+            Some(ref mut delta) => unsafe {
+                let base_count = this.reference_map.len();
+                delta.reference_map[..base_count].copy_from_slice(&this.reference_map);
+                delta.symbol_map = this.symbol_map.clone();
+                delta.build_symbol_table();
+                delta.deserialize_delta(&mut runtime);
+                callback(delta, &mut runtime);
+            },
+            None => callback(&mut this, &mut runtime),
+        }
+        runtime.heap().undefer();
+
+        let reference_map = std::mem::take(&mut this.reference_map);
+        let symbols = std::mem::take(&mut this.symbol_map);
+        runtime.base_snapshot = Some(Rc::new(BaseSnapshot {
+            data: base,
+            checksum: header.checksum,
+            reference_map,
+            symbols,
+            objects,
+        }));
+        Ok(runtime)
     }
 }
 
@@ -835,6 +1093,7 @@ impl Deserializable for Context {
 
     unsafe fn deserialize(at: *mut u8, deser: &mut Deserializer) {
         at.cast::<Self>().write(Self::deserialize_inplace(deser));
+        deser.contexts.push(at.cast());
     }
 
     unsafe fn allocate(vm: &mut VirtualMachine, deser: &mut Deserializer) -> *mut GcPointerBase {
@@ -922,6 +1181,12 @@ impl Deserializable for JsObject {
                             function: transmute(func),
                         })
                     }
+                    0x05 => {
+                        let target = deser.get_reference();
+                        FuncType::Wrapped(JsWrappedFunction {
+                            target: transmute(target),
+                        })
+                    }
                     _ => unreachable!(),
                 };
 
@@ -1512,6 +1777,93 @@ impl Deserializable for Structure {
     }
 }
 
+unsafe fn deserialize_scopes(deser: &mut Deserializer) -> Vec<LazyScope> {
+    let count = u32::deserialize_inplace(deser);
+    let mut scopes = Vec::with_capacity(count as _);
+    for _ in 0..count {
+        let depth = u32::deserialize_inplace(deser);
+        let dynamic = bool::deserialize_inplace(deser);
+        let count = u32::deserialize_inplace(deser);
+        let mut variables = Vec::with_capacity(count as _);
+        for _ in 0..count {
+            let name = Symbol::deserialize_inplace(deser);
+            let index = u16::deserialize_inplace(deser);
+            variables.push((name, index));
+        }
+        let count = u32::deserialize_inplace(deser);
+        let mut imports = Vec::with_capacity(count as _);
+        for _ in 0..count {
+            let name = Symbol::deserialize_inplace(deser);
+            let index = u16::deserialize_inplace(deser);
+            imports.push((name, index));
+        }
+        scopes.push(LazyScope {
+            depth,
+            variables,
+            imports,
+            dynamic,
+        });
+    }
+    scopes
+}
+
+unsafe fn deserialize_import_name(deser: &mut Deserializer) -> ImportName {
+    match deser.get_u8() {
+        0x0 => ImportName::Name(Symbol::deserialize_inplace(deser)),
+        0x1 => ImportName::Namespace,
+        _ => unreachable!(),
+    }
+}
+
+unsafe fn deserialize_module(deser: &mut Deserializer) -> ModuleInfo {
+    let count = u32::deserialize_inplace(deser);
+    let mut requests = Vec::with_capacity(count as _);
+    for _ in 0..count {
+        requests.push(String::deserialize_inplace(deser));
+    }
+    let count = u32::deserialize_inplace(deser);
+    let mut imports = Vec::with_capacity(count as _);
+    for _ in 0..count {
+        let local = Symbol::deserialize_inplace(deser);
+        let request = u32::deserialize_inplace(deser);
+        let name = deserialize_import_name(deser);
+        imports.push(ImportEntry {
+            local,
+            request,
+            name,
+        });
+    }
+    let count = u32::deserialize_inplace(deser);
+    let mut exports = Vec::with_capacity(count as _);
+    for _ in 0..count {
+        exports.push(match deser.get_u8() {
+            0x0 => ExportEntry::Local {
+                name: Symbol::deserialize_inplace(deser),
+                slot: u32::deserialize_inplace(deser),
+            },
+            0x1 => ExportEntry::Indirect {
+                name: Symbol::deserialize_inplace(deser),
+                request: u32::deserialize_inplace(deser),
+                import: deserialize_import_name(deser),
+            },
+            _ => unreachable!(),
+        });
+    }
+    let count = u32::deserialize_inplace(deser);
+    let mut star_exports = Vec::with_capacity(count as _);
+    for _ in 0..count {
+        star_exports.push(u32::deserialize_inplace(deser));
+    }
+    let body_start = u32::deserialize_inplace(deser);
+    ModuleInfo {
+        requests,
+        imports,
+        exports,
+        star_exports,
+        body_start,
+    }
+}
+
 impl Deserializable for CodeBlock {
     unsafe fn deserialize_inplace(deser: &mut Deserializer) -> Self {
         let name = Symbol::deserialize_inplace(deser);
@@ -1525,6 +1877,7 @@ impl Deserializable for CodeBlock {
         let use_arguments = bool::deserialize_inplace(deser);
         let filename = String::deserialize_inplace(deser);
         let rest_at = Option::<u32>::deserialize_inplace(deser);
+        let length = u32::deserialize_inplace(deser);
         let var_count = u32::deserialize_inplace(deser);
         let param_count = u32::deserialize_inplace(deser);
         let args_at = u32::deserialize_inplace(deser);
@@ -1542,6 +1895,47 @@ impl Deserializable for CodeBlock {
         let path: Rc<str> = path.into();
         let is_generator = bool::deserialize_inplace(deser);
         let is_async = bool::deserialize_inplace(deser);
+        let stack_size = u32::deserialize_inplace(deser);
+        let source = if bool::deserialize_inplace(deser) {
+            let text = GcPointer::<JsString>::deserialize_inplace(deser);
+            let start = u32::deserialize_inplace(deser);
+            let end = u32::deserialize_inplace(deser);
+            Some(FunctionSource {
+                text,
+                span: start..end,
+            })
+        } else {
+            None
+        };
+        let lazy = if bool::deserialize_inplace(deser) {
+            let builtins = bool::deserialize_inplace(deser);
+            let kind = match u8::deserialize_inplace(deser) {
+                0 => LazyKind::Function,
+                1 => LazyKind::Arrow,
+                _ => LazyKind::Method,
+            };
+            let line = u32::deserialize_inplace(deser);
+            let col = u32::deserialize_inplace(deser);
+            let scopes = deserialize_scopes(deser);
+            Some(Box::new(LazyFunction {
+                scopes,
+                builtins,
+                kind,
+                position: FileLocation { line, col },
+            }))
+        } else {
+            None
+        };
+        let count = u32::deserialize_inplace(deser);
+        let mut evals = Vec::with_capacity(count as _);
+        for _ in 0..count {
+            evals.push(deserialize_scopes(deser));
+        }
+        let module = if bool::deserialize_inplace(deser) {
+            Some(Box::new(deserialize_module(deser)))
+        } else {
+            None
+        };
         Self {
             is_async,
             is_generator,
@@ -1560,10 +1954,15 @@ impl Deserializable for CodeBlock {
             codes,
             file_name: filename,
             rest_at,
+            length,
             var_count,
             param_count,
             is_constructor,
-            stack_size: u32::deserialize_inplace(deser),
+            stack_size,
+            source,
+            lazy,
+            evals,
+            module,
         }
     }
 
@@ -1611,8 +2010,9 @@ impl Deserializable for TypeFeedBack {
                     0 => GetByIdMode::ArrayLength,
                     1 => GetByIdMode::Default,
                     2 => {
-                        let gc = GcPointer::<JsObject>::deserialize_inplace(deser);
-                        GetByIdMode::ProtoLoad(gc)
+                        let holder = GcPointer::<JsObject>::deserialize_inplace(deser);
+                        let chain = GcPointer::<StructureChain>::deserialize_inplace(deser);
+                        GetByIdMode::ProtoLoad(holder, chain)
                     }
                     _ => unreachable!(),
                 };
@@ -0,0 +1,385 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+//! Snapshot header.
+//!
+//! Every snapshot starts with a fixed size header:
+//!
+//! | field           | size                   |
+//! |-----------------|------------------------|
+//! | magic           | 8                      |
+//! | format version  | 4                      |
+//! | pointer width   | 4                      |
+//! | feature flags   | 4                      |
+//! | reference count | 4                      |
+//! | build ID        | 8                      |
+//! | body checksum   | 8                      |
+//! | base checksum   | 8                      |
+//! | section count   | 4                      |
+//! | section table   | 12 * [SECTION_COUNT]   |
+//!
+//! Section table entries are `kind`, `offset` and `length` of each [SectionKind], offsets are from the start of the
+//! snapshot. Body is everything after the header and checksum covers all of it, so truncated or corrupted snapshots
+//! are rejected before anything is read from them. Base checksum is the body checksum of the snapshot a delta
+//! snapshot was taken on top of, or zero for full snapshots.
+use super::{code_cache::BUILD_ID, SnapshotError};
+use std::{convert::TryInto, fmt, ops::Range};
+
+pub const MAGIC: [u8; 8] = *b"SLSNAPSH";
+/// Version of snapshot format, bump it when layout of serialized data changes.
+pub const FORMAT_VERSION: u32 = 4;
+pub const SECTION_COUNT: usize = 4;
+pub const HEADER_SIZE: usize = MAGIC.len() + 4 * 4 + 8 * 3 + 4 + SECTION_COUNT * 12;
+
+pub const FEATURE_VAL_AS_U64: u32 = 0b01;
+pub const FEATURE_VAL_AS_F64: u32 = 0b10;
+/// Features that change layout of serialized data.
+pub const FEATURES: u32 = {
+    let mut features = 0;
+    if cfg!(feature = "val-as-u64") {
+        features |= FEATURE_VAL_AS_U64;
+    }
+    if cfg!(feature = "val-as-f64") {
+        features |= FEATURE_VAL_AS_F64;
+    }
+    features
+};
+pub const POINTER_WIDTH: u32 = (std::mem::size_of::<usize>() * 8) as u32;
+
+/// Sections of snapshot body in the order they're written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum SectionKind {
+    /// Symbol table.
+    Symbols = 0,
+    /// Serialized objects, weak slots and VM or context state.
+    Heap,
+    /// Data written by snapshot callback.
+    Data,
+    /// Names of native references used by snapshot.
+    References,
+}
+
+impl SectionKind {
+    pub const ALL: [SectionKind; SECTION_COUNT] = [
+        SectionKind::Symbols,
+        SectionKind::Heap,
+        SectionKind::Data,
+        SectionKind::References,
+    ];
+}
+
+impl fmt::Display for SectionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Symbols => "symbols",
+            Self::Heap => "heap",
+            Self::Data => "data",
+            Self::References => "references",
+        })
+    }
+}
+
+/// Names of `features` for error messages.
+pub(crate) fn feature_names(features: u32) -> String {
+    let mut names = vec![];
+    if features & FEATURE_VAL_AS_U64 != 0 {
+        names.push("val-as-u64");
+    }
+    if features & FEATURE_VAL_AS_F64 != 0 {
+        names.push("val-as-f64");
+    }
+    if names.is_empty() {
+        "none".to_owned()
+    } else {
+        names.join(", ")
+    }
+}
+
+/// 64-bit FNV-1a hash of `bytes`.
+pub fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotHeader {
+    pub reference_count: u32,
+    /// Checksum of the body, computed when header is written.
+    pub checksum: u64,
+    /// Checksum of the base snapshot for delta snapshots, zero otherwise.
+    pub base: u64,
+    pub sections: [Range<usize>; SECTION_COUNT],
+}
+
+impl SnapshotHeader {
+    /// Byte range of `kind` section.
+    pub fn section(&self, kind: SectionKind) -> Range<usize> {
+        self.sections[kind as usize].clone()
+    }
+
+    /// Write header to the start of `output`, which must have [HEADER_SIZE] bytes reserved for it, and compute
+    /// checksum of the body.
+    pub(crate) fn write(&mut self, output: &mut [u8]) {
+        self.checksum = checksum(&output[HEADER_SIZE..]);
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        header.extend_from_slice(&MAGIC);
+        header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        header.extend_from_slice(&POINTER_WIDTH.to_le_bytes());
+        header.extend_from_slice(&FEATURES.to_le_bytes());
+        header.extend_from_slice(&self.reference_count.to_le_bytes());
+        header.extend_from_slice(&BUILD_ID.to_le_bytes());
+        header.extend_from_slice(&self.checksum.to_le_bytes());
+        header.extend_from_slice(&self.base.to_le_bytes());
+        header.extend_from_slice(&(SECTION_COUNT as u32).to_le_bytes());
+        for (kind, section) in SectionKind::ALL.iter().zip(self.sections.iter()) {
+            header.extend_from_slice(&(*kind as u32).to_le_bytes());
+            header.extend_from_slice(&(section.start as u32).to_le_bytes());
+            header.extend_from_slice(&(section.len() as u32).to_le_bytes());
+        }
+        debug_assert_eq!(header.len(), HEADER_SIZE);
+        output[..HEADER_SIZE].copy_from_slice(&header);
+    }
+
+    /// Read and validate header of `snapshot`. Checks that snapshot was written by this engine build with the same
+    /// features, that all sections are inside of the snapshot and that body checksum matches.
+    pub fn read(snapshot: &[u8]) -> Result<Self, SnapshotError> {
+        if snapshot.len() < MAGIC.len() || snapshot[..MAGIC.len()] != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        if snapshot.len() < HEADER_SIZE {
+            return Err(SnapshotError::Truncated);
+        }
+        let mut at = MAGIC.len();
+        let mut next_u32 = || {
+            at += 4;
+            read_u32(snapshot, at - 4)
+        };
+        let version = next_u32();
+        if version != FORMAT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion {
+                expected: FORMAT_VERSION,
+                found: version,
+            });
+        }
+        let pointer_width = next_u32();
+        if pointer_width != POINTER_WIDTH {
+            return Err(SnapshotError::PointerWidthMismatch {
+                expected: POINTER_WIDTH,
+                found: pointer_width,
+            });
+        }
+        let features = next_u32();
+        if features != FEATURES {
+            return Err(SnapshotError::FeatureMismatch {
+                expected: FEATURES,
+                found: features,
+            });
+        }
+        let reference_count = next_u32();
+        if read_u64(snapshot, at) != BUILD_ID {
+            return Err(SnapshotError::BuildMismatch);
+        }
+        let body_checksum = read_u64(snapshot, at + 8);
+        let base = read_u64(snapshot, at + 16);
+        at += 24;
+        if read_u32(snapshot, at) as usize != SECTION_COUNT {
+            return Err(SnapshotError::Corrupted("unexpected number of sections"));
+        }
+        at += 4;
+        let mut sections = [0..0, 0..0, 0..0, 0..0];
+        for (kind, section) in SectionKind::ALL.iter().zip(sections.iter_mut()) {
+            if read_u32(snapshot, at) != *kind as u32 {
+                return Err(SnapshotError::Corrupted("section table is out of order"));
+            }
+            let start = read_u32(snapshot, at + 4) as usize;
+            let end = start
+                .checked_add(read_u32(snapshot, at + 8) as usize)
+                .filter(|_| start >= HEADER_SIZE)
+                .ok_or(SnapshotError::InvalidSection(*kind))?;
+            if end > snapshot.len() {
+                return Err(SnapshotError::Truncated);
+            }
+            *section = start..end;
+            at += 12;
+        }
+        if checksum(&snapshot[HEADER_SIZE..]) != body_checksum {
+            return Err(SnapshotError::ChecksumMismatch);
+        }
+        Ok(Self {
+            reference_count,
+            checksum: body_checksum,
+            base,
+            sections,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gc::snapshot::{deserializer::Deserializer, Snapshot};
+    use crate::options::Options;
+    use crate::Platform;
+
+    fn snapshot_with_body(body: &[u8]) -> Vec<u8> {
+        let mut output = vec![0; HEADER_SIZE];
+        output.extend_from_slice(body);
+        let end = output.len();
+        SnapshotHeader {
+            reference_count: 3,
+            checksum: 0,
+            base: 42,
+            sections: [
+                HEADER_SIZE..HEADER_SIZE + 1,
+                HEADER_SIZE + 1..HEADER_SIZE + 3,
+                HEADER_SIZE + 3..HEADER_SIZE + 3,
+                HEADER_SIZE + 3..end,
+            ],
+        }
+        .write(&mut output);
+        output
+    }
+
+    fn patch_u32(snapshot: &mut [u8], at: usize, value: u32) {
+        snapshot[at..at + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let snapshot = snapshot_with_body(b"abcdef");
+        let header = SnapshotHeader::read(&snapshot).unwrap();
+        assert_eq!(header.reference_count, 3);
+        assert_eq!(header.base, 42);
+        assert_eq!(header.checksum, checksum(b"abcdef"));
+        assert_eq!(header.section(SectionKind::Symbols), HEADER_SIZE..HEADER_SIZE + 1);
+        assert_eq!(header.section(SectionKind::Heap), HEADER_SIZE + 1..HEADER_SIZE + 3);
+        assert!(header.section(SectionKind::Data).is_empty());
+        assert_eq!(
+            header.section(SectionKind::References),
+            HEADER_SIZE + 3..snapshot.len()
+        );
+    }
+
+    #[test]
+    fn test_mismatched_header() {
+        let snapshot = snapshot_with_body(b"abcdef");
+
+        let mut bad = snapshot.clone();
+        bad[0] = b'X';
+        assert_eq!(SnapshotHeader::read(&bad), Err(SnapshotError::BadMagic));
+        assert_eq!(SnapshotHeader::read(&[]), Err(SnapshotError::BadMagic));
+        assert_eq!(
+            SnapshotHeader::read(&snapshot[..HEADER_SIZE - 1]),
+            Err(SnapshotError::Truncated)
+        );
+
+        let mut bad = snapshot.clone();
+        patch_u32(&mut bad, 8, FORMAT_VERSION + 1);
+        assert_eq!(
+            SnapshotHeader::read(&bad),
+            Err(SnapshotError::UnsupportedVersion {
+                expected: FORMAT_VERSION,
+                found: FORMAT_VERSION + 1
+            })
+        );
+
+        let mut bad = snapshot.clone();
+        patch_u32(&mut bad, 12, POINTER_WIDTH / 2);
+        assert_eq!(
+            SnapshotHeader::read(&bad),
+            Err(SnapshotError::PointerWidthMismatch {
+                expected: POINTER_WIDTH,
+                found: POINTER_WIDTH / 2
+            })
+        );
+
+        let mut bad = snapshot.clone();
+        patch_u32(&mut bad, 16, FEATURES ^ FEATURE_VAL_AS_F64);
+        assert_eq!(
+            SnapshotHeader::read(&bad),
+            Err(SnapshotError::FeatureMismatch {
+                expected: FEATURES,
+                found: FEATURES ^ FEATURE_VAL_AS_F64
+            })
+        );
+
+        let mut bad = snapshot.clone();
+        bad[24] ^= 1;
+        assert_eq!(SnapshotHeader::read(&bad), Err(SnapshotError::BuildMismatch));
+    }
+
+    #[test]
+    fn test_corrupted_snapshot() {
+        let snapshot = snapshot_with_body(b"abcdef");
+        let table = HEADER_SIZE - SECTION_COUNT * 12;
+
+        let mut bad = snapshot.clone();
+        *bad.last_mut().unwrap() ^= 1;
+        assert_eq!(SnapshotHeader::read(&bad), Err(SnapshotError::ChecksumMismatch));
+        assert_eq!(
+            SnapshotHeader::read(&snapshot[..snapshot.len() - 1]),
+            Err(SnapshotError::Truncated)
+        );
+
+        let mut bad = snapshot.clone();
+        patch_u32(&mut bad, table - 4, SECTION_COUNT as u32 + 1);
+        assert_eq!(
+            SnapshotHeader::read(&bad),
+            Err(SnapshotError::Corrupted("unexpected number of sections"))
+        );
+
+        let mut bad = snapshot.clone();
+        patch_u32(&mut bad, table, SectionKind::Heap as u32);
+        assert_eq!(
+            SnapshotHeader::read(&bad),
+            Err(SnapshotError::Corrupted("section table is out of order"))
+        );
+
+        let mut bad = snapshot.clone();
+        patch_u32(&mut bad, table + 12 + 4, 0);
+        assert_eq!(
+            SnapshotHeader::read(&bad),
+            Err(SnapshotError::InvalidSection(SectionKind::Heap))
+        );
+
+        let mut bad = snapshot;
+        patch_u32(&mut bad, table + 2 * 12 + 8, 100);
+        assert_eq!(SnapshotHeader::read(&bad), Err(SnapshotError::Truncated));
+    }
+
+    #[test]
+    fn test_reject_corrupted_context_snapshot() {
+        Platform::initialize();
+        let mut vm = Platform::new_runtime(Options::default(), None);
+        let ctx = vm.new_context();
+        let snapshot = Snapshot::take_context(false, &mut vm, ctx, |_, _| {}).buffer;
+        assert!(Snapshot::validate(&snapshot).is_ok());
+
+        let mut bad = snapshot.to_vec();
+        let middle = HEADER_SIZE + (bad.len() - HEADER_SIZE) / 2;
+        bad[middle] ^= 0xff;
+        assert_eq!(
+            Deserializer::deserialize_context(&mut vm, false, &bad).err(),
+            Some(SnapshotError::ChecksumMismatch)
+        );
+        let truncated = &snapshot[..snapshot.len() - 1];
+        assert_eq!(
+            Deserializer::deserialize_context(&mut vm, false, truncated).err(),
+            Some(SnapshotError::Truncated)
+        );
+        let mut bad = snapshot.to_vec();
+        patch_u32(&mut bad, 8, 0);
+        assert!(Deserializer::deserialize_context(&mut vm, false, &bad).is_err());
+        assert!(Deserializer::deserialize_context(&mut vm, false, &snapshot).is_ok());
+    }
+}
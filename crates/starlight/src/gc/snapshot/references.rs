@@ -0,0 +1,150 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+//! Registry of native functions, classes and deserializers that can be referenced from snapshots.
+//!
+//! References are registered at startup by [register_native_references!](crate::register_native_references)
+//! next to their definitions. Snapshot stores names of the native references it uses and deserializer resolves
+//! them by name, so registration order doesn't matter and a reference that is missing in the loading process is
+//! reported by name.
+use std::{borrow::Cow, collections::HashMap};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+#[derive(Default)]
+struct Registry {
+    by_name: HashMap<Cow<'static, str>, usize>,
+    by_address: HashMap<usize, Cow<'static, str>>,
+}
+
+static REGISTRY: Lazy<Mutex<Registry>> = Lazy::new(Default::default);
+
+/// Register `address` under `name`. Registering the same pair twice does nothing.
+///
+/// # Panics
+///
+/// Panics if `name` is already registered with another address.
+pub fn register_native_reference(name: impl Into<Cow<'static, str>>, address: usize) {
+    let name = name.into();
+    let mut registry = REGISTRY.lock();
+    if let Some(registered) = registry.by_name.get(&name) {
+        assert_eq!(
+            *registered, address,
+            "native reference '{}' is already registered with another address",
+            name
+        );
+        return;
+    }
+    // functions with identical code may be merged by linker, first name is used for them.
+    registry
+        .by_address
+        .entry(address)
+        .or_insert_with(|| name.clone());
+    registry.by_name.insert(name, address);
+}
+
+/// Remove reference registered at `address`.
+pub fn remove_native_reference(address: usize) {
+    let mut registry = REGISTRY.lock();
+    registry
+        .by_name
+        .retain(|_, registered| *registered != address);
+    registry.by_address.remove(&address);
+}
+
+/// Address registered under `name`.
+pub fn resolve_native_reference(name: &str) -> Option<usize> {
+    REGISTRY.lock().by_name.get(name).copied()
+}
+
+/// Name `address` is registered under.
+pub fn native_reference_name(address: usize) -> Option<String> {
+    REGISTRY
+        .lock()
+        .by_address
+        .get(&address)
+        .map(|name| name.to_string())
+}
+
+/// All registered references sorted by name, index in this list is the reference ID used by serializer.
+pub(crate) fn native_references() -> Vec<(String, usize)> {
+    let registry = REGISTRY.lock();
+    let mut references = registry
+        .by_address
+        .iter()
+        .map(|(address, name)| (name.to_string(), *address))
+        .collect::<Vec<_>>();
+    references.sort_unstable();
+    references
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gc::cell::GcPointer;
+    use crate::gc::snapshot::{deserializer::Deserializer, Snapshot, SnapshotError};
+    use crate::vm::{
+        arguments::Arguments,
+        context::Context,
+        function::{JsAPI, JsNativeFunction},
+        symbol_table::Internable,
+        tests::test_runtime,
+        value::JsValue,
+    };
+
+    fn answer(_ctx: GcPointer<Context>, _args: &Arguments) -> Result<JsValue, JsValue> {
+        Ok(JsValue::new(0x5eed))
+    }
+
+    #[test]
+    fn test_registry() {
+        fn other(_ctx: GcPointer<Context>, _args: &Arguments) -> Result<JsValue, JsValue> {
+            Ok(JsValue::new(0xbeef))
+        }
+        let address = other as JsAPI as usize;
+        let name = "references::tests::test_registry::other";
+        register_native_reference(name, address);
+        register_native_reference(name, address);
+        assert_eq!(resolve_native_reference(name), Some(address));
+        assert_eq!(native_reference_name(address).as_deref(), Some(name));
+        assert!(native_references().contains(&(name.to_owned(), address)));
+
+        let conflict = std::panic::catch_unwind(|| register_native_reference(name, address + 1));
+        assert!(conflict.is_err());
+
+        remove_native_reference(address);
+        assert_eq!(resolve_native_reference(name), None);
+        assert_eq!(native_reference_name(address), None);
+    }
+
+    #[test]
+    fn test_missing_reference() {
+        let name = "references::tests::answer";
+        let address = answer as JsAPI as usize;
+        register_native_reference(name, address);
+
+        let (mut runtime, _jobs) = test_runtime();
+        let mut ctx = Context::new(&mut runtime);
+        let function = JsNativeFunction::new(ctx, "answer".intern(), answer, 0);
+        let mut global = ctx.global_object();
+        global
+            .put(ctx, "answer".intern(), JsValue::new(function), false)
+            .unwrap();
+        let buffer = Snapshot::take_context(false, &mut runtime, ctx, |_, _| {}).buffer;
+
+        remove_native_reference(address);
+        match Deserializer::deserialize_context(&mut runtime, false, &buffer) {
+            Err(error) => {
+                assert_eq!(error, SnapshotError::MissingReference(name.to_owned()));
+                assert!(error.to_string().contains(name));
+            }
+            Ok(_) => panic!("snapshot loaded without '{}'", name),
+        }
+
+        register_native_reference(name, address);
+        let mut ctx = Deserializer::deserialize_context(&mut runtime, false, &buffer).unwrap();
+        let result = ctx.eval("answer()").unwrap();
+        assert_eq!(result.get_number(), 0x5eed as f64);
+    }
+}
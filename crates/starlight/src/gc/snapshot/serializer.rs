@@ -8,12 +8,13 @@ use crate::{
         arguments::JsArguments,
         array_storage::ArrayStorage,
         attributes::AttrSafe,
-        code_block::CodeBlock,
+        code_block::{CodeBlock, LazyScope},
         context::Context,
         function::{FuncType, JsFunction},
         global::JsGlobal,
         indexed_elements::*,
         interpreter::SpreadValue,
+        module::{ExportEntry, ImportName, ModuleInfo},
         object::{JsObject, ObjectTag},
         property_descriptor::{Accessor, StoredSlot},
         slot::*,
@@ -27,11 +28,27 @@ use crate::{
         GlobalData,
     },
 };
-use crate::{jsrt::VM_NATIVE_REFERENCES, vm::VirtualMachine};
-use std::{collections::HashMap, io::Write, u8};
+use crate::{
+    gc::snapshot::{base::BaseSnapshot, references},
+    vm::VirtualMachine,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    u8,
+};
+
+/// Reference map entry that doesn't match any address, used for IDs of the base that are not alive anymore.
+const DEAD_REFERENCE: usize = usize::MAX;
 
 pub struct SnapshotSerializer {
     pub(crate) reference_map: Vec<usize>,
+    /// Names of native references, they're in `reference_map` starting at `native_base`.
+    native_names: Vec<String>,
+    /// Index of the first native reference in `reference_map`, non-zero only for delta snapshots.
+    native_base: usize,
+    /// Native references written to snapshot.
+    used_natives: Vec<bool>,
     pub(crate) output: Vec<u8>,
     symbol_map: HashMap<Symbol, u32>,
     log: bool,
@@ -42,54 +59,106 @@ impl SnapshotSerializer {
         Self {
             log,
             reference_map: Vec::new(),
+            native_names: Vec::new(),
+            native_base: 0,
+            used_natives: Vec::new(),
             output: vec![],
             symbol_map: HashMap::new(),
         }
     }
     pub(crate) fn build_reference_map(&mut self, vm: &mut VirtualMachine) {
-        let mut indexx = 0;
-        unsafe {
-            VM_NATIVE_REFERENCES
-                .iter()
-                .enumerate()
-                .for_each(|(_index, reference)| {
-                    /*match self.reference_map.insert(*reference, indexx) {
-                        Some(p) => {
-                            backtrace::resolve(*reference as *mut _, |sym| {
-                                if let Some(name) = sym.name() {
-                                    panic!(
-                                        "duplicate reference #{}: {:x} '{}'",
-                                        _index,
-                                        *reference,
-                                        name.as_str().unwrap()
-                                    );
-                                } else {
-                                    panic!("duplicate reference #{}: {:x}", _index, *reference);
-                                }
-                            });
-                            panic!("duplicate {:x} at {}({})", *reference, _index, p);
-                        }
-                        _ => (),
-                    }*/
-                    self.reference_map.push(*reference);
-                    indexx += 1;
-                });
+        for (name, reference) in references::native_references() {
+            self.reference_map.push(reference);
+            self.native_names.push(name);
+        }
+        self.used_natives = vec![false; self.native_names.len()];
 
-            if let Some(references) = vm.external_references {
-                for (_index, reference) in references.iter().enumerate() {
-                    /* let result = self.reference_map.insert(*reference, indexx);
-                    indexx += 1;
-                    match result {
-                        Some(_) => {
-                            panic!("Reference 0x{:x}", reference);
-                        }
-                        _ => (),
-                    }*/
-                    self.reference_map.push(*reference);
+        if let Some(references) = vm.external_references {
+            self.reference_map.extend(references.iter().copied());
+        }
+    }
+
+    /// Build reference map of a delta snapshot on top of `base`. IDs of the base are kept and live objects are
+    /// split into objects of the base and objects allocated after the base was loaded, which get IDs after native
+    /// references. Object of the base that was freed and whose memory was reused by an object of another type or size
+    /// is treated as a new object.
+    ///
+    /// Returns objects of the base and new objects.
+    pub(crate) fn build_delta_reference_map(
+        &mut self,
+        vm: &mut VirtualMachine,
+        base: &BaseSnapshot,
+    ) -> (Vec<usize>, Vec<usize>) {
+        self.reference_map = base
+            .reference_map
+            .iter()
+            .map(|&reference| {
+                if reference == 0 {
+                    DEAD_REFERENCE
+                } else {
+                    reference
+                }
+            })
+            .collect();
+        for object in base.objects.values() {
+            self.reference_map[object.id as usize] = DEAD_REFERENCE;
+        }
+        let mut base_objects = vec![];
+        let mut objects = vec![];
+        vm.heap().walk(&mut |object, _| {
+            let address = object as usize;
+            let base_object = base.objects.get(&address).filter(|base_object| unsafe {
+                base_object.vtable == (*object).vtable()
+                    && base_object.size == (*object).allocation_size()
+            });
+            match base_object {
+                Some(base_object) => {
+                    self.reference_map[base_object.id as usize] = address;
+                    base_objects.push(address);
                 }
+                None => objects.push(address),
             }
+            true
+        });
+
+        self.native_base = self.reference_map.len();
+        for (name, reference) in references::native_references() {
+            self.reference_map.push(reference);
+            self.native_names.push(name);
         }
+        self.used_natives = vec![false; self.native_names.len()];
+        self.reference_map.extend(objects.iter().copied());
+        (base_objects, objects)
     }
+
+    /// Write names of native references used by snapshot. Deserializer resolves references by these names.
+    pub(crate) fn write_native_references(&mut self) {
+        self.write_u32(self.native_names.len() as u32);
+        let used = self
+            .used_natives
+            .iter()
+            .enumerate()
+            .filter(|(_, used)| **used)
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+        self.write_u32(used.len() as u32);
+        for index in used {
+            self.write_u32((self.native_base + index) as u32);
+            let name = std::mem::take(&mut self.native_names[index]);
+            self.write_u32(name.len() as u32);
+            self.output.extend_from_slice(name.as_bytes());
+            self.native_names[index] = name;
+        }
+    }
+
+    /// Keep IDs of `symbols` of the base snapshot, [build_symbol_table](Self::build_symbol_table) writes only
+    /// symbols that are not in the base then.
+    pub(crate) fn seed_symbol_table(&mut self, symbols: &[Symbol]) {
+        for (index, symbol) in symbols.iter().enumerate() {
+            self.symbol_map.insert(*symbol, index as u32);
+        }
+    }
+
     pub(crate) fn build_symbol_table(&mut self) {
         let symtab = symbol_table();
         let patch_at = self.output.len();
@@ -98,6 +167,9 @@ impl SnapshotSerializer {
         for entry in symtab.symbols.iter() {
             let key = entry.key();
             let index = entry.value();
+            if self.symbol_map.contains_key(&Symbol::Key(SymbolID(*index))) {
+                continue;
+            }
             let ix = self.symbol_map.len() as u32;
             self.symbol_map.insert(Symbol::Key(SymbolID(*index)), ix);
             self.write_u32(ix);
@@ -284,6 +356,72 @@ impl SnapshotSerializer {
         vm.serialize(self);
     }
 
+    /// Serialize only `cells` using the same per-object layout as [serialize](Self::serialize). All cells must
+    /// already be in reference map.
+    pub(crate) fn serialize_cells(&mut self, cells: &[usize]) {
+        self.write_u32(cells.len() as u32);
+        for &object in cells.iter() {
+            self.serialize_cell(object);
+        }
+    }
+
+    /// Write record of a single cell, returns offset of the cell data.
+    fn serialize_cell(&mut self, object: usize) -> usize {
+        let base = unsafe { &mut *(object as *mut GcPointerBase) };
+        self.write_reference(object as *const u8);
+        self.try_write_reference(base.get_dyn().deser_pair().0 as *const u8)
+            .unwrap_or_else(|| {
+                panic!("no deserializer for type '{}'", base.get_dyn().type_name());
+            });
+        self.write_reference(base.get_dyn().deser_pair().1 as *const u8);
+        let patch_at = self.output.len();
+        self.write_u32(0);
+        base.get_dyn().serialize(self);
+        let buf = (self.output.len() as u32).to_le_bytes();
+        self.output[patch_at..patch_at + 4].copy_from_slice(&buf);
+        patch_at + 4
+    }
+
+    /// Serialize heap of a delta snapshot: `objects` allocated after `base` was loaded, objects of the base whose
+    /// data differs from the base, weak slots that are not in the base and state of the VM.
+    pub(crate) fn serialize_delta(
+        &mut self,
+        vm: &mut VirtualMachine,
+        base: &BaseSnapshot,
+        base_objects: &[usize],
+        objects: &[usize],
+    ) {
+        self.serialize_cells(objects);
+
+        let patch_at = self.output.len();
+        self.write_u32(0);
+        let mut count: u32 = 0;
+        for &object in base_objects {
+            let start = self.output.len();
+            let data_at = self.serialize_cell(object);
+            let base_data = &base.data[base.objects[&object].data.clone()];
+            if self.output[data_at..] == *base_data {
+                self.output.truncate(start);
+            } else {
+                count += 1;
+            }
+        }
+        self.output[patch_at..patch_at + 4].copy_from_slice(&count.to_le_bytes());
+
+        let base_objects = base_objects.iter().copied().collect::<HashSet<_>>();
+        let mut weak_slots = vec![];
+        vm.heap().weak_slots(&mut |weak_slot| {
+            if !base_objects.contains(&(weak_slot.base.as_ptr() as usize)) {
+                weak_slots.push(weak_slot);
+            }
+        });
+        self.write_u32(weak_slots.len() as u32);
+        for weak_slot in weak_slots {
+            weak_slot.serialize(self);
+        }
+        vm.serialize(self);
+    }
+
     pub fn get_gcpointer<T: GcCell + ?Sized>(&self, at: GcPointer<T>) -> u32 {
         self.reference_map
             .iter()
@@ -359,14 +497,19 @@ impl SnapshotSerializer {
     }
 
     pub fn write_reference<T>(&mut self, ref_: *const T) {
-        let ix = self
-            .reference_map
-            .iter()
-            .enumerate()
-            .find(|x| x.1 == &(ref_ as usize))
-            .unwrap()
-            .0 as u32;
-        self.write_u32(ix);
+        self.try_write_reference(ref_).unwrap_or_else(|| {
+            let mut name = None;
+            backtrace::resolve(ref_ as *mut _, |symbol| {
+                if name.is_none() {
+                    name = symbol.name().map(|name| name.to_string());
+                }
+            });
+            panic!(
+                "native reference {:p} ('{}') is not registered, add it to `register_native_references!`",
+                ref_,
+                name.as_deref().unwrap_or("<unknown>")
+            );
+        })
     }
 
     pub fn try_write_reference<T>(&mut self, ref_: *const T) -> Option<()> {
@@ -375,8 +518,14 @@ impl SnapshotSerializer {
             .iter()
             .enumerate()
             .find(|x| x.1 == &(ref_ as usize))?
-            .0 as u32;
-        self.write_u32(ix);
+            .0;
+        if let Some(used) = ix
+            .checked_sub(self.native_base)
+            .and_then(|index| self.used_natives.get_mut(index))
+        {
+            *used = true;
+        }
+        self.write_u32(ix as u32);
         Some(())
     }
 }
@@ -541,6 +690,10 @@ impl Serializable for JsFunction {
                 serializer.write_u8(0x04);
                 gen_fn.function.serialize(serializer);
             }
+            FuncType::Wrapped(wrapped_fn) => {
+                serializer.write_u8(0x05);
+                wrapped_fn.target.serialize(serializer);
+            }
         }
     }
 }
@@ -579,9 +732,10 @@ impl Serializable for TypeFeedBack {
                 match mode {
                     &crate::bytecode::GetByIdMode::ArrayLength => serializer.write_u8(0),
                     &crate::bytecode::GetByIdMode::Default => serializer.write_u8(1),
-                    &crate::bytecode::GetByIdMode::ProtoLoad(slot) => {
+                    &crate::bytecode::GetByIdMode::ProtoLoad(holder, chain) => {
                         serializer.write_u8(2);
-                        slot.serialize(serializer);
+                        holder.serialize(serializer);
+                        chain.serialize(serializer);
                     }
                 }
             }
@@ -602,7 +756,8 @@ impl Serializable for TypeFeedBack {
                 structure_chain.serialize(serializer);
             }
             _ => {
-                // other type feedback is ignored
+                // other type feedback (polymorphic and megamorphic caches) is ignored
+                // and is repopulated at runtime
                 serializer.write_u8(0x0);
             }
         }
@@ -625,6 +780,7 @@ impl Serializable for CodeBlock {
         self.file_name.serialize(serializer);
 
         self.rest_at.serialize(serializer);
+        self.length.serialize(serializer);
         self.var_count.serialize(serializer);
         self.param_count.serialize(serializer);
         self.args_at.serialize(serializer);
@@ -641,7 +797,104 @@ impl Serializable for CodeBlock {
         self.is_generator.serialize(serializer);
         self.is_async.serialize(serializer);
         self.stack_size.serialize(serializer);
+        match self.source {
+            Some(ref source) => {
+                true.serialize(serializer);
+                source.text.serialize(serializer);
+                source.span.start.serialize(serializer);
+                source.span.end.serialize(serializer);
+            }
+            None => false.serialize(serializer),
+        }
+        match self.lazy {
+            Some(ref lazy) => {
+                true.serialize(serializer);
+                lazy.builtins.serialize(serializer);
+                (lazy.kind as u8).serialize(serializer);
+                lazy.position.line.serialize(serializer);
+                lazy.position.col.serialize(serializer);
+                serialize_scopes(&lazy.scopes, serializer);
+            }
+            None => false.serialize(serializer),
+        }
+        (self.evals.len() as u32).serialize(serializer);
+        for scopes in self.evals.iter() {
+            serialize_scopes(scopes, serializer);
+        }
+        match self.module {
+            Some(ref module) => {
+                true.serialize(serializer);
+                serialize_module(module, serializer);
+            }
+            None => false.serialize(serializer),
+        }
+    }
+}
+
+fn serialize_scopes(scopes: &[LazyScope], serializer: &mut SnapshotSerializer) {
+    (scopes.len() as u32).serialize(serializer);
+    for scope in scopes.iter() {
+        scope.depth.serialize(serializer);
+        scope.dynamic.serialize(serializer);
+        (scope.variables.len() as u32).serialize(serializer);
+        for (name, index) in scope.variables.iter() {
+            name.serialize(serializer);
+            index.serialize(serializer);
+        }
+        (scope.imports.len() as u32).serialize(serializer);
+        for (name, index) in scope.imports.iter() {
+            name.serialize(serializer);
+            index.serialize(serializer);
+        }
+    }
+}
+
+fn serialize_import_name(name: ImportName, serializer: &mut SnapshotSerializer) {
+    match name {
+        ImportName::Name(name) => {
+            serializer.write_u8(0x0);
+            name.serialize(serializer);
+        }
+        ImportName::Namespace => serializer.write_u8(0x1),
+    }
+}
+
+fn serialize_module(module: &ModuleInfo, serializer: &mut SnapshotSerializer) {
+    (module.requests.len() as u32).serialize(serializer);
+    for request in module.requests.iter() {
+        request.serialize(serializer);
+    }
+    (module.imports.len() as u32).serialize(serializer);
+    for import in module.imports.iter() {
+        import.local.serialize(serializer);
+        import.request.serialize(serializer);
+        serialize_import_name(import.name, serializer);
+    }
+    (module.exports.len() as u32).serialize(serializer);
+    for export in module.exports.iter() {
+        match *export {
+            ExportEntry::Local { name, slot } => {
+                serializer.write_u8(0x0);
+                name.serialize(serializer);
+                slot.serialize(serializer);
+            }
+            ExportEntry::Indirect {
+                name,
+                request,
+                import,
+            } => {
+                serializer.write_u8(0x1);
+                name.serialize(serializer);
+                request.serialize(serializer);
+                serialize_import_name(import, serializer);
+            }
+        }
+    }
+    (module.star_exports.len() as u32).serialize(serializer);
+    for request in module.star_exports.iter() {
+        request.serialize(serializer);
     }
+    module.body_start.serialize(serializer);
 }
 
 impl Serializable for AttrSafe {
@@ -901,6 +1154,10 @@ impl Serializable for GlobalData {
         self.spread_builtin.serialize(serializer);
         self.weak_ref_structure.serialize(serializer);
         self.weak_ref_prototype.serialize(serializer);
+        self.shadow_realm_structure.serialize(serializer);
+        self.shadow_realm_prototype.serialize(serializer);
+        self.shadow_realm_constructor.serialize(serializer);
+        self.generator_function_structure.serialize(serializer);
         self.object_constructor.serialize(serializer);
         self.symbol_structure.serialize(serializer);
         self.date_structure.serialize(serializer);
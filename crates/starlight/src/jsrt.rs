@@ -10,14 +10,16 @@ use crate::{
         array_storage::ArrayStorage, attributes::*, builder::Builtin, class::JsClass,
         code_block::CodeBlock, context::Context, data_view::JsDataView, environment::Environment,
         error::*, function::*, global::JsGlobal, indexed_elements::IndexedElements,
-        interpreter::SpreadValue, number::*, object::*, property_descriptor::*, string::*,
-        structure::*, structure_chain::StructureChain, symbol_table::*, value::*, ModuleKind,
+        interpreter::SpreadValue, module::ModuleRecord, module_loader::ModuleSource, number::*,
+        object::*, promise::JsPromise, property_descriptor::*, string::*, structure::*,
+        structure_chain::StructureChain, symbol_table::*, value::*, ModuleKind,
     },
 };
-use std::{collections::HashMap, rc::Rc};
+use std::{collections::HashMap, path::Path, rc::Rc};
 pub mod array;
 pub mod array_buffer;
 pub mod boolean;
+pub mod commonjs;
 pub mod data_view;
 pub mod date;
 pub mod error;
@@ -33,6 +35,7 @@ pub mod number;
 pub mod object;
 pub mod promise;
 pub mod regexp;
+pub mod shadow_realm;
 pub mod string;
 pub mod symbol;
 pub mod weak_ref;
@@ -105,313 +108,74 @@ impl GcPointer<Context> {
 }
 
 use crate::gc::snapshot::deserializer::*;
-use once_cell::sync::Lazy;
 
-pub static mut VM_NATIVE_REFERENCES: Lazy<Vec<usize>> = Lazy::new(|| {
-    let mut refs = vec![
-        /* deserializer functions */
-        // following GcPointer and WeakRef method references is obtained from `T = u8`
-        // but they should be the same for all types that is allocated in GC heap.
-        Vec::<crate::gc::cell::GcPointer<crate::vm::structure::Structure>>::deserialize as _,
-        Vec::<crate::gc::cell::GcPointer<crate::vm::structure::Structure>>::allocate as _,
-        GcPointer::<u8>::deserialize as _,
-        GcPointer::<u8>::allocate as _,
-        WeakRef::<u8>::deserialize as _,
-        WeakRef::<u8>::allocate as _,
-        Context::deserialize as _,
-        Context::allocate as _,
-        JsObject::deserialize as _,
-        JsObject::allocate as _,
-        JsValue::deserialize as _,
-        JsValue::allocate as _,
-        TargetTable::deserialize as _,
-        TargetTable::allocate as _,
-        SpreadValue::deserialize as _,
-        SpreadValue::allocate as _,
-        Structure::deserialize as _,
-        Structure::allocate as _,
-        crate::vm::structure::Table::deserialize as _,
-        crate::vm::structure::Table::allocate as _,
-        ArrayStorage::deserialize as _,
-        ArrayStorage::allocate as _,
-        DeletedEntry::deserialize as _,
-        DeletedEntry::allocate as _,
-        JsString::deserialize as _,
-        JsString::allocate as _,
-        u8::deserialize as _,
-        u8::allocate as _,
-        u16::deserialize as _,
-        u16::allocate as _,
-        u32::deserialize as _,
-        u32::allocate as _,
-        u64::deserialize as _,
-        u64::allocate as _,
-        i8::deserialize as _,
-        i8::allocate as _,
-        i16::deserialize as _,
-        i16::allocate as _,
-        i32::deserialize as _,
-        i32::allocate as _,
-        i64::deserialize as _,
-        i64::allocate as _,
-        HashMap::<u32, StoredSlot>::deserialize as _,
-        HashMap::<u32, StoredSlot>::allocate as _,
-        IndexedElements::deserialize as _,
-        IndexedElements::allocate as _,
-        CodeBlock::deserialize as _,
-        CodeBlock::allocate as _,
-        JsArguments::class() as *const _ as usize,
-        JsObject::class() as *const _ as usize,
-        JsArray::class() as *const _ as usize,
-        JsFunction::class() as *const _ as usize,
-        JsError::class() as *const _ as usize,
-        JsTypeError::class() as *const _ as usize,
-        JsSyntaxError::class() as *const _ as usize,
-        JsReferenceError::class() as *const _ as usize,
-        JsRangeError::class() as *const _ as usize,
-        JsEvalError::class() as *const _ as usize,
-        JsURIError::class() as *const _ as usize,
-        JsGlobal::class() as *const _ as usize,
-        function::function_bind as usize,
-        function::function_prototype as usize,
-        function::function_to_string as usize,
-        function::function_apply as usize,
-        function::function_call as usize,
-        object::object_constructor as usize,
-        object::object_create as usize,
-        object::object_to_string as usize,
-        object::object_define_property as usize,
-        object::object_has_own_property as usize,
-        object::object_property_is_enumerable as usize,
-        object::object_keys as usize,
-        object::object_get_own_property_descriptor as usize,
-        object::object_freeze as _,
-        object::object_seal as _,
-        object::object_get_prototype_of as _,
-        object::object_is_extensible as _,
-        object::object_is_sealed as _,
-        object::object_is_frozen as _,
-        object::object_prevent_extensions as _,
-        array::array_ctor as usize,
-        array::array_from as usize,
-        array::array_is_array as usize,
-        array::array_join as usize,
-        array::array_of as usize,
-        array::array_pop as usize,
-        array::array_push as usize,
-        array::array_reduce as usize,
-        array::array_to_string as usize,
-        array::array_concat as usize,
-        array::array_for_each as _,
-        array::array_filter as _,
-        array::array_map as _,
-        array::array_shift as _,
-        array::array_slice as _,
-        array::array_index_of as _,
-        error::error_constructor as usize,
-        error::error_to_string as usize,
-        error::eval_error_constructor as usize,
-        error::range_error_constructor as usize,
-        error::reference_error_constructor as usize,
-        error::syntax_error_constructor as usize,
-        error::type_error_constructor as usize,
-        error::uri_error_constructor as usize,
-        print as usize,
-        global::is_finite as _,
-        global::is_nan as _,
-        global::parse_float as _,
-        global::parse_int as _,
-        global::read_line as _,
-        global::gc as _,
-        global::___is_constructor as _,
-        global::___is_callable as _,
-        global::___trunc as _,
-        global::to_string as _,
-        string::string_concat as _,
-        string::string_trim as _,
-        string::string_trim_start as _,
-        string::string_trim_end as _,
-        string::string_pad_start as _,
-        string::string_pad_end as _,
-        string::string_split as _,
-        string::string_constructor as _,
-        string::string_to_string as _,
-        string::string_index_of as _,
-        string::string_last_index_of as _,
-        string::string_substr as _,
-        string::string_substring as _,
-        string::string_replace as _,
-        string::string_value_of as _,
-        string::string_char_at as _,
-        string::string_char_code_at as _,
-        string::string_code_point_at as _,
-        string::string_starts_with as _,
-        string::string_ends_with as _,
-        string::string_repeat as _,
-        string::string_to_lowercase as _,
-        string::string_to_uppercase as _,
-        string::string_includes as _,
-        string::string_slice as _,
-        JsStringObject::class() as *const _ as usize,
-        JsNumber::class() as *const _ as usize,
-        Environment::deserialize as _,
-        Environment::allocate as _,
-        number::number_constructor as _,
-        number::number_clz as _,
-        number::number_is_finite as _,
-        number::number_is_integer as _,
-        number::number_is_nan as _,
-        number::number_to_int as _,
-        number::number_to_precisiion as _,
-        number::number_to_fixed as _,
-        number::number_to_string as _,
-        number::number_value_of as _,
-        number::number_is_safe_integer as _,
-        number::number_to_local_string as _,
-        StructureChain::deserialize as _,
-        StructureChain::allocate as _,
-        HashValueZero::deserialize as _,
-        HashValueZero::allocate as _,
-        regexp::regexp_constructor as _,
-        regexp::regexp_exec as _,
-        regexp::regexp_test as _,
-        regexp::regexp_to_string as _,
-        regexp::regexp_match as _,
-        regexp::regexp_split_fast as _,
-        symbol::symbol_ctor as _,
-        symbol::symbol_for as _,
-        symbol::symbol_key_for as _,
-        symbol::symbol_to_string as _,
-        symbol::symbol_value_of as _,
-        JsSymbol::deserialize as _,
-        JsSymbol::allocate as _,
-        Accessor::deserialize as _,
-        Accessor::allocate as _,
-        module_load as _,
-        jsstd::init_js_std as _,
-        jsstd::file::std_file_open as _,
-        jsstd::file::std_file_read as _,
-        jsstd::file::std_file_write as _,
-        jsstd::file::std_file_write_all as _,
-        jsstd::file::std_file_read_bytes as _,
-        jsstd::file::std_file_read_bytes_exact as _,
-        jsstd::file::std_file_read_bytes_to_end as _,
-        jsstd::file::std_file_close as _,
-        jsstd::std_args as _,
-        promise::promise_constructor as _,
-        promise::promise_then as _,
-        promise::promise_catch as _,
-        promise::promise_finally as _,
-        promise::promise_resolve as _,
-        promise::promise_reject as _,
-        promise::promise_static_resolve as _,
-        promise::promise_static_reject as _,
-        promise::promise_static_race as _,
-        promise::promise_static_all as _,
-        promise::promise_static_all_settled as _,
-        promise::promise_static_any as _,
-        generator::generator_next as _,
-        generator::generator_iterator as _,
-        generator::generator_return as _,
-        generator::generator_throw as _,
-        array_buffer::array_buffer_constructor as _,
-        array_buffer::array_buffer_byte_length as _,
-        array_buffer::array_buffer_slice as _,
-        JsArrayBuffer::class() as *const _ as usize,
-        JsDataView::class() as *const _ as usize,
-        data_view::data_view_constructor as _,
-        data_view::data_view_prototype_buffer as _,
-        data_view::data_view_prototype_byte_length as _,
-        data_view::data_view_prototype_byte_offset as _,
-        data_view::data_view_prototype_get::<u8> as _,
-        data_view::data_view_prototype_get::<u16> as _,
-        data_view::data_view_prototype_get::<u32> as _,
-        data_view::data_view_prototype_get::<i8> as _,
-        data_view::data_view_prototype_get::<i16> as _,
-        data_view::data_view_prototype_get::<i32> as _,
-        data_view::data_view_prototype_get::<f32> as _,
-        data_view::data_view_prototype_get::<f64> as _,
-        data_view::data_view_prototype_set::<u8> as _,
-        data_view::data_view_prototype_set::<u16> as _,
-        data_view::data_view_prototype_set::<u32> as _,
-        data_view::data_view_prototype_set::<i8> as _,
-        data_view::data_view_prototype_set::<i16> as _,
-        data_view::data_view_prototype_set::<i32> as _,
-        data_view::data_view_prototype_set::<f32> as _,
-        data_view::data_view_prototype_set::<f64> as _,
-        weak_ref::JsWeakRef::class() as *const _ as _,
-        weak_ref::weak_ref_constructor as _,
-        weak_ref::weak_ref_prototype_deref as _,
-        WeakSlot::deserialize as _,
-        WeakSlot::allocate as _,
-        boolean::boolean_constructor as _,
-        boolean::boolean_to_string as _,
-        boolean::boolean_value_of as _,
-        boolean::JsBoolean::class() as *const _ as _,
-        date::date_constructor as _,
-        date::date_to_string as _,
-        date::Date::class() as *const _ as _,
-        date::date_now as _,
-        date::date_set_date as _,
-        date::date_set_full_year as _,
-        date::date_set_hours as _,
-        date::date_set_milliseconds as _,
-        date::date_set_minutes as _,
-        date::date_set_month as _,
-        date::date_set_seconds as _,
-        date::date_set_year as _,
-        date::date_set_time as _,
-        date::date_set_utc_date as _,
-        date::date_set_utc_full_year as _,
-        date::date_set_utc_hours as _,
-        date::date_set_utc_minutes as _,
-        date::date_set_utc_month as _,
-        date::date_set_utc_seconds as _,
-        date::date_get_date as _,
-        date::date_get_day as _,
-        date::date_get_full_year as _,
-        date::date_get_hours as _,
-        date::date_get_milliseconds as _,
-        date::date_get_minutes as _,
-        date::date_get_month as _,
-        date::date_get_seconds as _,
-        date::date_get_seconds as _,
-        date::date_get_time as _,
-        date::date_get_year as _,
-        date::date_get_utc_date as _,
-        date::date_get_utc_day as _,
-        date::date_get_utc_full_year as _,
-        date::date_get_utc_hours as _,
-        date::date_get_utc_minutes as _,
-        date::date_get_utc_milliseconds as _,
-        date::date_get_utc_month as _,
-        date::date_get_utc_seconds as _,
-        date::date_to_json as _,
-        date::date_to_time_string as _,
-        date::date_value_of as _,
-        date::date_to_gmt_string as _,
-        date::date_to_iso_string as _,
-        date::date_to_utc_string as _,
-        date::date_to_date_string as _,
-        date::date_parse as _,
-        date::date_utc as _,
-        js262::_262_create_realm as _,
-        js262::_262_eval_script as _,
-    ];
+// Deserializer functions of types allocated in GC heap. Native functions are registered next to their definitions.
+register_native_references!(
+    // following GcPointer and WeakRef method references is obtained from `T = u8`
+    // but they should be the same for all types that is allocated in GC heap.
+    Vec::<crate::gc::cell::GcPointer<crate::vm::structure::Structure>>::deserialize,
+    Vec::<crate::gc::cell::GcPointer<crate::vm::structure::Structure>>::allocate,
+    GcPointer::<u8>::deserialize,
+    GcPointer::<u8>::allocate,
+    WeakRef::<u8>::deserialize,
+    WeakRef::<u8>::allocate,
+    Context::deserialize,
+    Context::allocate,
+    JsObject::deserialize,
+    JsObject::allocate,
+    JsValue::deserialize,
+    JsValue::allocate,
+    TargetTable::deserialize,
+    TargetTable::allocate,
+    SpreadValue::deserialize,
+    SpreadValue::allocate,
+    Structure::deserialize,
+    Structure::allocate,
+    crate::vm::structure::Table::deserialize,
+    crate::vm::structure::Table::allocate,
+    ArrayStorage::deserialize,
+    ArrayStorage::allocate,
+    DeletedEntry::deserialize,
+    DeletedEntry::allocate,
+    JsString::deserialize,
+    JsString::allocate,
+    HashMap::<u32, StoredSlot>::deserialize,
+    HashMap::<u32, StoredSlot>::allocate,
+    IndexedElements::deserialize,
+    IndexedElements::allocate,
+    CodeBlock::deserialize,
+    CodeBlock::allocate,
+    Environment::deserialize,
+    Environment::allocate,
+    StructureChain::deserialize,
+    StructureChain::allocate,
+    HashValueZero::deserialize,
+    HashValueZero::allocate,
+    JsSymbol::deserialize,
+    JsSymbol::allocate,
+    Accessor::deserialize,
+    Accessor::allocate,
+    WeakSlot::deserialize,
+    WeakSlot::allocate,
+    u8::deserialize,
+    u8::allocate,
+    u16::deserialize,
+    u16::allocate,
+    u32::deserialize,
+    u32::allocate,
+    u64::deserialize,
+    u64::allocate,
+    i8::deserialize,
+    i8::allocate,
+    i16::deserialize,
+    i16::allocate,
+    i32::deserialize,
+    i32::allocate,
+    i64::deserialize,
+    i64::allocate,
+);
 
-    #[cfg(all(target_pointer_width = "64", feature = "ffi"))]
-    {
-        refs.push(ffi::ffi_function_attach as _);
-        refs.push(ffi::ffi_function_call as _);
-        refs.push(ffi::ffi_library_open as _);
-    }
-
-    unsafe {
-        refs.append(&mut Math::native_references());
-    }
-
-    refs
-});
+register_native_references!(print, module_load);
 
 pub fn get_length(ctx: GcPointer<Context>, val: &mut GcPointer<JsObject>) -> Result<u32, JsValue> {
     if std::ptr::eq(val.class, JsArray::class()) {
@@ -536,84 +300,163 @@ pub fn to_property_descriptor(
 }
 
 pub(crate) fn module_load(
-    mut ctx: GcPointer<Context>,
+    ctx: GcPointer<Context>,
     args: &Arguments,
 ) -> Result<JsValue, JsValue> {
     let name = args.at(0).to_string(ctx)?;
-    let rel_path = unsafe { (*ctx.stack.current).code_block.unwrap().path.clone() };
-    let _is_js_load = (name.starts_with("./")
-        || name.starts_with("../")
-        || name.starts_with('/')
-        || name.ends_with(".js"))
-        && name.ends_with(".js");
-    let spath = name;
-    let mut spath = if rel_path.is_empty() {
-        spath
-    } else {
-        format!("{}/{}", rel_path, spath)
-    };
-    if cfg!(windows) {
-        spath = spath.replace("/", "\\");
-    }
-    let path = std::path::Path::new(&spath);
-    let path = match path.canonicalize() {
-        Err(e) => {
-            return Err(JsValue::new(ctx.new_reference_error(format!(
-                "Module '{}' not found: '{}'",
-                path.display(),
-                e
-            ))))
+    let referrer = unsafe { (*ctx.stack.current).code_block.unwrap().file_name.clone() };
+    let module = load_module(ctx, name, &referrer)?;
+    module.namespace(ctx).map(JsValue::new)
+}
+
+/// Load module `name` imported by `referrer` into `ctx`, link and evaluate it. Modules are evaluated once per
+/// context and cached module record is returned after that. Module with top level `await` may still be evaluating
+/// when it is returned, see [ModuleRecord::promise].
+pub(crate) fn load_module(
+    ctx: GcPointer<Context>,
+    name: String,
+    referrer: &str,
+) -> Result<GcPointer<ModuleRecord>, JsValue> {
+    let module = load_requested(ctx, &name, referrer)?;
+    module.evaluate(ctx)?;
+    Ok(module)
+}
+
+/// `import(specifier)` in code of `referrer`. Module is loaded by a job, returned promise is resolved with
+/// namespace of the module once it is evaluated. Errors, including conversion of `specifier` to string, reject the
+/// promise.
+pub(crate) fn import_module(
+    mut ctx: GcPointer<Context>,
+    specifier: JsValue,
+    referrer: &str,
+) -> Result<JsValue, JsValue> {
+    let promise = JsPromise::new_unresolving(ctx)?;
+    let name = match specifier.to_string(ctx) {
+        Ok(name) => name,
+        Err(error) => {
+            promise
+                .get_jsobject()
+                .data::<JsPromise>()
+                .reject(ctx, promise, error)?;
+            return Ok(promise);
         }
-        Ok(path) => path,
     };
+    let root = ctx.vm.add_persistent_root(promise);
+    let referrer = referrer.to_string();
+    ctx.schedule_async(move |ctx| {
+        let promise = root.get_value();
+        let result = load_module(ctx, name, &referrer).and_then(|module| module.promise(ctx));
+        let object = promise.get_jsobject();
+        let data = object.data::<JsPromise>();
+        // promise is settled only here, so settling it can't fail.
+        let _ = match result {
+            Ok(value) => data.resolve(ctx, promise, value),
+            Err(error) => data.reject(ctx, promise, error),
+        };
+    })?;
+    Ok(promise)
+}
+
+/// Key of module file `path` in [Context::modules]. Bundled files don't have to exist on the file system.
+pub(crate) fn module_key(path: &Path) -> std::io::Result<String> {
+    match path.canonicalize() {
+        Ok(path) => Ok(path.to_string_lossy().into_owned()),
+        Err(e) => match crate::bundle::bundled_path(path) {
+            Some(path) => Ok(path.to_string_lossy().into_owned()),
+            None => Err(e),
+        },
+    }
+}
+
+/// Key of module `name` in [Context::modules]. Names of registered modules are used as is, other names are resolved
+/// by module loader of the context.
+fn resolve_module(ctx: GcPointer<Context>, name: &str, referrer: &str) -> Result<String, JsValue> {
+    if ctx.modules.contains_key(name) {
+        return Ok(name.to_string());
+    }
+    ctx.with_module_loader(|loader| loader.resolve(name, referrer))
+        .map_err(|e| {
+            JsValue::new(ctx.new_reference_error(format!("Module '{}' not found: {}", name, e)))
+        })
+}
+
+/// Create module object of native module and initialize it with `init`.
+fn init_native_module(
+    ctx: GcPointer<Context>,
+    init: fn(GcPointer<Context>, GcPointer<JsObject>) -> Result<(), JsValue>,
+) -> Result<GcPointer<JsObject>, JsValue> {
     let stack = ctx.shadowstack();
     letroot!(module_object = stack, JsObject::new_empty(ctx));
-    let mut exports = JsObject::new_empty(ctx);
+    let exports = JsObject::new_empty(ctx);
     module_object.put(ctx, S_EXPORTS.intern(), JsValue::new(exports), false)?;
-    let mut args = [JsValue::new(*module_object)];
-    letroot!(
-        args = stack,
-        Arguments::new(JsValue::encode_undefined_value(), &mut args)
-    );
-    if let Some(module) = ctx.modules().get(&spath).copied() {
-        match module {
-            ModuleKind::Initialized(x) => {
-                return Ok(JsValue::new(x));
-            }
-            ModuleKind::NativeUninit(init) => {
-                let mut module = *module_object;
-                init(ctx, module)?;
-                ctx.modules()
-                    .insert(spath.clone(), ModuleKind::Initialized(module));
+    init(ctx, *module_object)?;
+    Ok(*module_object)
+}
 
-                return Ok(JsValue::new(module));
+/// Load module `name` requested by code of `referrer` and modules it requests. Modules are not linked.
+fn load_requested(
+    mut ctx: GcPointer<Context>,
+    name: &str,
+    referrer: &str,
+) -> Result<GcPointer<ModuleRecord>, JsValue> {
+    let key = resolve_module(ctx, name, referrer)?;
+    let object = match ctx.modules().get(&key).copied() {
+        Some(ModuleKind::Record(module)) => return Ok(module),
+        Some(ModuleKind::Initialized(object)) => object,
+        Some(ModuleKind::NativeUninit(init)) => init_native_module(ctx, init)?,
+        None => {
+            let source = ctx
+                .with_module_loader(|loader| loader.load(&key))
+                .map_err(|e| {
+                    JsValue::new(
+                        ctx.new_type_error(format!("Failed to read module '{}': {}", key, e)),
+                    )
+                })?;
+            match source {
+                ModuleSource::Source(source) => {
+                    let name = Path::new(&key)
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                    let function = ctx.compile_module(&key, &name, &source)?;
+                    return instantiate_module(ctx, key, function.get_jsobject());
+                }
+                ModuleSource::Native(init) => init_native_module(ctx, init)?,
+                ModuleSource::CommonJs(source) => commonjs::import_object(ctx, &key, source)?,
             }
         }
-    }
-    if !path.exists() {
-        return Err(JsValue::new(
-            ctx.new_type_error(format!("Module '{}' not found", spath)),
-        ));
-    }
-    let source = match std::fs::read_to_string(&path) {
-        Ok(source) => source,
-        Err(e) => {
-            return Err(JsValue::new(ctx.new_type_error(format!(
-                "Failed to read module '{}': {}",
-                spath,
-                e.to_string()
-            ))));
-        }
     };
-    let name = path.file_name().unwrap().to_str().unwrap().to_string();
-    let module_fun = ctx.compile_module(&spath, &name, &source)?;
-    let mut module_fun = module_fun.get_jsobject();
-    module_fun
-        .as_function_mut()
-        .call(ctx, &mut args, JsValue::encode_undefined_value())?;
+    let module = ModuleRecord::native(ctx, key.clone(), object);
+    ctx.modules().insert(key, ModuleKind::Record(module));
+    Ok(module)
+}
+
+/// Create record of module `function` stored under `key` in [Context::modules] and load modules it requests.
+/// Record is removed again if a requested module fails to load.
+pub(crate) fn instantiate_module(
+    mut ctx: GcPointer<Context>,
+    key: String,
+    function: GcPointer<JsObject>,
+) -> Result<GcPointer<ModuleRecord>, JsValue> {
+    let stack = ctx.shadowstack();
+    letroot!(
+        module = stack,
+        ModuleRecord::new(ctx, key.clone(), function)
+    );
+    // record is registered first so that cyclic requests resolve to it.
     ctx.modules()
-        .insert(spath.clone(), ModuleKind::Initialized(*module_object));
-    Ok(JsValue::new(*module_object))
+        .insert(key.clone(), ModuleKind::Record(*module));
+    let requests = module.info().unwrap().requests.clone();
+    for request in requests.iter() {
+        match load_requested(ctx, request, &key) {
+            Ok(requested) => module.requested.push(requested),
+            Err(e) => {
+                ctx.modules().remove(&key);
+                return Err(e);
+            }
+        }
+    }
+    Ok(*module)
 }
 
 pub fn to_index(ctx: GcPointer<Context>, val: JsValue) -> Result<usize, JsValue> {
@@ -670,14 +513,6 @@ pub fn define_lazy_property(
     Ok(())
 }
 
-#[macro_export]
-macro_rules! define_register_native_reference {
-    ($class: ident, $VM_NATIVE_REFERENCES: ident) => {
-        let references = $class::native_references();
-        $VM_NATIVE_REFERENCES.append(&mut references);
-    };
-}
-
 #[macro_export]
 macro_rules! define_op_builtins {
     ($op: ident) => {
@@ -697,6 +532,7 @@ macro_rules! define_op_builtins {
         $op!(JsArrayBuffer);
         $op!(JsDataView);
         $op!(JsWeakRef);
+        $op!(JsShadowRealm);
         $op!(Date);
         $op!(JsBoolean);
         $op!(SelfHost);
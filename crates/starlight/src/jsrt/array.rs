@@ -605,3 +605,22 @@ impl Builtin for JsArray {
         Ok(())
     }
 }
+
+register_native_references!(
+    array_ctor,
+    array_from,
+    array_is_array,
+    array_join,
+    array_of,
+    array_pop,
+    array_push,
+    array_reduce,
+    array_to_string,
+    array_concat,
+    array_for_each,
+    array_filter,
+    array_map,
+    array_shift,
+    array_slice,
+    array_index_of,
+);
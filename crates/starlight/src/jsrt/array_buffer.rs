@@ -119,6 +119,7 @@ impl Builtin for JsArrayBuffer {
             data: std::ptr::null_mut(),
 
             attached: false,
+            shared: None,
         });
 
         ctx.global_data.array_buffer_prototype = Some(prototype);
@@ -140,3 +141,9 @@ impl Builtin for JsArrayBuffer {
         Ok(())
     }
 }
+
+register_native_references!(
+    array_buffer_constructor,
+    array_buffer_byte_length,
+    array_buffer_slice,
+);
@@ -99,3 +99,5 @@ impl Builtin for JsBoolean {
         Ok(())
     }
 }
+
+register_native_references!(boolean_constructor, boolean_to_string, boolean_value_of);
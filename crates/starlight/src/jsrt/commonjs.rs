@@ -0,0 +1,370 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+//! CommonJS modules.
+//!
+//! CommonJS is opt in, [enable_commonjs](GcPointer::<Context>::enable_commonjs) defines global `require` and
+//! makes the context import modules with [NodeModuleLoader]. Specifiers are resolved like in Node, see [resolve].
+//! `.json` files are parsed as JSON, `.mjs` files and `.js` files of `"type": "module"` packages are ES modules and
+//! the rest are CommonJS modules run as a function of `exports`, `require`, `module`, `__filename` and `__dirname`.
+//!
+//! `module` objects are cached in `require.cache` by module key, so every module runs once per context unless it is
+//! deleted from the cache. Module is cached before it runs and `require` of a module that is still running returns
+//! its `module.exports` as they are so far. `require` of ES module returns its namespace, modules with top level
+//! `await` have to be imported with `import()`. `import` of CommonJS module gives `module.exports` as the default
+//! export and its own enumerable properties as named exports.
+pub mod json;
+pub mod resolve;
+
+use self::{json::Json, resolve::Format};
+use crate::prelude::*;
+use crate::vm::{
+    array_storage::ArrayStorage,
+    context::Context,
+    module::ModuleStatus,
+    module_loader::{ModuleLoader, ModuleSource},
+};
+use std::path::Path;
+
+/// Module loader that resolves `import` like Node. CommonJS and JSON modules are loaded as
+/// [ModuleSource::CommonJs].
+#[derive(Default)]
+pub struct NodeModuleLoader;
+
+impl ModuleLoader for NodeModuleLoader {
+    fn resolve(&mut self, specifier: &str, referrer: &str) -> Result<String, String> {
+        resolve::resolve_import(specifier, referrer)
+    }
+
+    fn load(&mut self, key: &str) -> Result<ModuleSource, String> {
+        let source = resolve::read_file(Path::new(key))?;
+        match resolve::format(Path::new(key)) {
+            Format::Module => Ok(ModuleSource::Source(source)),
+            Format::CommonJs | Format::Json => Ok(ModuleSource::CommonJs(source)),
+        }
+    }
+}
+
+impl GcPointer<Context> {
+    /// Define global `require` and load modules imported in this context with [NodeModuleLoader]. Global `require`
+    /// resolves specifiers relative to the file of the calling code.
+    pub fn enable_commonjs(mut self) -> Result<(), JsValue> {
+        let stack = self.shadowstack();
+        letroot!(
+            require = stack,
+            JsNativeFunction::new(self, "require".intern(), global_require, 1)
+        );
+        let resolve = JsNativeFunction::new(self, "resolve".intern(), global_require_resolve, 1);
+        init_require(self, *require, resolve)?;
+        let mut global_object = self.global_object();
+        def_native_property!(self, global_object, require, *require, W | C)?;
+        self.set_module_loader(Some(Box::new(NodeModuleLoader)));
+        Ok(())
+    }
+}
+
+/// File name of code that called native function, empty if it's called from Rust.
+fn caller_file(ctx: GcPointer<Context>) -> String {
+    unsafe {
+        if ctx.stack.current.is_null() {
+            String::new()
+        } else {
+            (*ctx.stack.current)
+                .code_block
+                .map(|code_block| code_block.file_name.clone())
+                .unwrap_or_else(String::new)
+        }
+    }
+}
+
+fn module_file(ctx: GcPointer<Context>, module: JsValue) -> Result<String, JsValue> {
+    module
+        .to_object(ctx)?
+        .get(ctx, "filename".intern())?
+        .to_string(ctx)
+}
+
+/// Global `require(id)`.
+pub fn global_require(ctx: GcPointer<Context>, args: &Arguments) -> Result<JsValue, JsValue> {
+    let id = args.at(0).to_string(ctx)?;
+    require(ctx, &id, &caller_file(ctx))
+}
+
+/// Global `require.resolve(request)`.
+pub fn global_require_resolve(
+    ctx: GcPointer<Context>,
+    args: &Arguments,
+) -> Result<JsValue, JsValue> {
+    let request = args.at(0).to_string(ctx)?;
+    let key = resolve_require(ctx, &request, &caller_file(ctx))?;
+    Ok(JsValue::new(JsString::new(ctx, key)))
+}
+
+/// `require(id)` of a module, bound to the `module` object.
+pub fn module_require(ctx: GcPointer<Context>, args: &Arguments) -> Result<JsValue, JsValue> {
+    let referrer = module_file(ctx, args.this)?;
+    let id = args.at(0).to_string(ctx)?;
+    require(ctx, &id, &referrer)
+}
+
+/// `require.resolve(request)` of a module, bound to the `module` object.
+pub fn module_require_resolve(
+    ctx: GcPointer<Context>,
+    args: &Arguments,
+) -> Result<JsValue, JsValue> {
+    let referrer = module_file(ctx, args.this)?;
+    let request = args.at(0).to_string(ctx)?;
+    let key = resolve_require(ctx, &request, &referrer)?;
+    Ok(JsValue::new(JsString::new(ctx, key)))
+}
+
+/// Registered modules like `std` are required by name, they are what core modules are in Node.
+fn is_registered(mut ctx: GcPointer<Context>, id: &str) -> bool {
+    !Path::new(id).is_absolute() && ctx.modules().contains_key(id)
+}
+
+fn resolve_require(ctx: GcPointer<Context>, id: &str, referrer: &str) -> Result<String, JsValue> {
+    if is_registered(ctx, id) {
+        return Ok(id.to_string());
+    }
+    resolve::resolve_require(id, referrer).map_err(|e| JsValue::new(ctx.new_reference_error(e)))
+}
+
+/// `require(id)` in code of `referrer`.
+fn require(ctx: GcPointer<Context>, id: &str, referrer: &str) -> Result<JsValue, JsValue> {
+    let key = resolve_require(ctx, id, referrer)?;
+    if is_registered(ctx, id) || resolve::format(Path::new(&key)) == Format::Module {
+        let module = crate::jsrt::load_module(ctx, key.clone(), referrer)?;
+        return match module.status() {
+            ModuleStatus::Evaluated => module.namespace(ctx).map(JsValue::new),
+            ModuleStatus::EvaluatingAsync => Err(JsValue::new(ctx.new_type_error(format!(
+                "require() of ES module '{}' that uses top level await, use import() instead",
+                key
+            )))),
+            _ => Err(JsValue::new(ctx.new_type_error(format!(
+                "require() of ES module '{}' that is being evaluated in a cycle",
+                key
+            )))),
+        };
+    }
+    load(ctx, &key, None)?.get(ctx, "exports".intern())
+}
+
+/// `require.cache` of `ctx`. Cache is kept in a private property of the global object so that every `require`
+/// of the context shares it.
+fn require_cache(mut ctx: GcPointer<Context>) -> Result<GcPointer<JsObject>, JsValue> {
+    let name = "@@requireCache".intern().private();
+    let mut global_object = ctx.global_object();
+    let cache = global_object.get(ctx, name)?;
+    if cache.is_jsobject() {
+        return Ok(cache.get_jsobject());
+    }
+    let cache = JsObject::new_empty(ctx);
+    global_object.define_own_property(
+        ctx,
+        name,
+        &*DataDescriptor::new(JsValue::new(cache), NONE),
+        false,
+    )?;
+    Ok(cache)
+}
+
+/// Add `resolve` and `cache` properties to `require` function.
+fn init_require(
+    ctx: GcPointer<Context>,
+    mut require: GcPointer<JsObject>,
+    resolve: GcPointer<JsObject>,
+) -> Result<GcPointer<JsObject>, JsValue> {
+    let cache = require_cache(ctx)?;
+    def_native_property!(ctx, require, resolve, resolve)?;
+    def_native_property!(ctx, require, cache, cache)?;
+    Ok(require)
+}
+
+/// `target` with `this` bound to `module`.
+fn bind_module(
+    ctx: GcPointer<Context>,
+    target: GcPointer<JsObject>,
+    module: GcPointer<JsObject>,
+) -> GcPointer<JsObject> {
+    JsFunction::new(
+        ctx,
+        FuncType::Bound(JsBoundFunction {
+            args: ArrayStorage::with_size(ctx, 0, 0),
+            this: JsValue::new(module),
+            target,
+        }),
+        false,
+    )
+}
+
+/// Create `module` object of module `key`.
+fn new_module(ctx: GcPointer<Context>, key: &str) -> Result<GcPointer<JsObject>, JsValue> {
+    let stack = ctx.shadowstack();
+    letroot!(module = stack, JsObject::new_empty(ctx));
+    let filename = JsString::new(ctx, key);
+    let path = Path::new(key)
+        .parent()
+        .map(|dir| dir.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let path = JsString::new(ctx, path);
+    let exports = JsObject::new_empty(ctx);
+    def_native_property!(ctx, module, id, filename)?;
+    def_native_property!(ctx, module, filename, filename)?;
+    def_native_property!(ctx, module, path, path)?;
+    def_native_property!(ctx, module, exports, exports)?;
+    def_native_property!(ctx, module, loaded, false)?;
+
+    let require = JsNativeFunction::new(ctx, "require".intern(), module_require, 1);
+    let require = bind_module(ctx, require, *module);
+    let resolve = JsNativeFunction::new(ctx, "resolve".intern(), module_require_resolve, 1);
+    let resolve = bind_module(ctx, resolve, *module);
+    let require = init_require(ctx, require, resolve)?;
+    def_native_property!(ctx, module, require, require)?;
+    Ok(*module)
+}
+
+/// `module` object of CommonJS or JSON module `key`, the module is run when it is not in `require.cache`. Module
+/// source is read from the file if `source` is `None`.
+pub(crate) fn load(
+    ctx: GcPointer<Context>,
+    key: &str,
+    source: Option<String>,
+) -> Result<GcPointer<JsObject>, JsValue> {
+    let stack = ctx.shadowstack();
+    letroot!(cache = stack, require_cache(ctx)?);
+    let name = key.intern();
+    let cached = cache.get(ctx, name)?;
+    if cached.is_jsobject() {
+        return Ok(cached.get_jsobject());
+    }
+    let source = match source {
+        Some(source) => source,
+        None => resolve::read_file(Path::new(key)).map_err(|e| {
+            JsValue::new(ctx.new_type_error(format!("Failed to read module '{}': {}", key, e)))
+        })?,
+    };
+    letroot!(module = stack, new_module(ctx, key)?);
+    // module is cached before it runs so that cyclic `require` gets its unfinished exports.
+    cache.put(ctx, name, JsValue::new(*module), false)?;
+    if let Err(error) = run(ctx, *module, key, &source) {
+        cache.delete(ctx, name, false)?;
+        return Err(error);
+    }
+    def_native_property!(ctx, module, loaded, true)?;
+    Ok(*module)
+}
+
+/// Run module `key` with its `module` object.
+fn run(
+    ctx: GcPointer<Context>,
+    mut module: GcPointer<JsObject>,
+    key: &str,
+    source: &str,
+) -> Result<(), JsValue> {
+    if resolve::format(Path::new(key)) == Format::Json {
+        let json = Json::parse(source)
+            .map_err(|e| JsValue::new(ctx.new_syntax_error(format!("{}: {}", key, e))))?;
+        let exports = json_to_value(ctx, &json)?;
+        return module.put(ctx, "exports".intern(), exports, false);
+    }
+    let stack = ctx.shadowstack();
+    let params = ["exports", "require", "module", "__filename", "__dirname"]
+        .iter()
+        .map(|param| param.to_string())
+        .collect::<Vec<_>>();
+    let name = Path::new(key)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let function = ctx
+        .compile_file_function(key, &name, source, &params)
+        .map_err(|e| JsValue::new(ctx.new_syntax_error(format!("Compile Error {:?}", e))))?;
+    letroot!(function = stack, function.get_jsobject());
+    letroot!(callee = stack, *function);
+    let exports = module.get(ctx, "exports".intern())?;
+    let mut values = [
+        exports,
+        module.get(ctx, "require".intern())?,
+        JsValue::new(module),
+        JsValue::new(JsString::new(ctx, key)),
+        module.get(ctx, "path".intern())?,
+    ];
+    letroot!(args = stack, Arguments::new(exports, &mut values));
+    function
+        .as_function_mut()
+        .call(ctx, &mut args, JsValue::new(*callee))?;
+    Ok(())
+}
+
+/// JS value of parsed JSON, like result of `JSON.parse`.
+fn json_to_value(ctx: GcPointer<Context>, json: &Json) -> Result<JsValue, JsValue> {
+    let stack = ctx.shadowstack();
+    Ok(match json {
+        Json::Null => JsValue::encode_null_value(),
+        Json::Bool(value) => JsValue::new(*value),
+        Json::Number(value) => JsValue::new(*value),
+        Json::String(value) => JsValue::new(JsString::new(ctx, value)),
+        Json::Array(items) => {
+            letroot!(array = stack, JsArray::new(ctx, items.len() as u32));
+            for (index, item) in items.iter().enumerate() {
+                let value = json_to_value(ctx, item)?;
+                array.put(ctx, Symbol::Index(index as u32), value, false)?;
+            }
+            JsValue::new(*array)
+        }
+        Json::Object(members) => {
+            letroot!(object = stack, JsObject::new_empty(ctx));
+            for (key, value) in members.iter() {
+                let value = json_to_value(ctx, value)?;
+                // defined instead of put, `__proto__` is an own property like in `JSON.parse`
+                object.define_own_property(
+                    ctx,
+                    key.intern(),
+                    &*DataDescriptor::new(value, W | E | C),
+                    false,
+                )?;
+            }
+            JsValue::new(*object)
+        }
+    })
+}
+
+/// Object of native module record of CommonJS module `key` imported by an ES module, `module.exports` is the
+/// default export and its own enumerable properties except `default` are named exports.
+pub(crate) fn import_object(
+    ctx: GcPointer<Context>,
+    key: &str,
+    source: String,
+) -> Result<GcPointer<JsObject>, JsValue> {
+    let stack = ctx.shadowstack();
+    let exports = load(ctx, key, Some(source))?.get(ctx, "exports".intern())?;
+    letroot!(exports = stack, exports);
+    letroot!(object = stack, JsObject::new_empty(ctx));
+    letroot!(named = stack, JsObject::new_empty(ctx));
+    if exports.is_jsobject() {
+        let mut exports = exports.get_jsobject();
+        let mut names = vec![];
+        exports.get_own_property_names(
+            ctx,
+            &mut |name, _| names.push(name),
+            EnumerationMode::Default,
+        );
+        for name in names {
+            if name != "default".intern() {
+                let value = exports.get(ctx, name)?;
+                named.put(ctx, name, value, false)?;
+            }
+        }
+    }
+    def_native_property!(ctx, object, S_EXPORTS.intern(), *named)?;
+    def_native_property!(ctx, object, "@default".intern(), *exports)?;
+    Ok(*object)
+}
+
+register_native_references!(
+    global_require,
+    global_require_resolve,
+    module_require,
+    module_require_resolve,
+);
@@ -0,0 +1,375 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+//! JSON parser of `package.json` files and `.json` modules.
+
+/// Parsed JSON value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    /// Members in source order, order of conditions in `exports` of `package.json` matters. Value of a duplicated
+    /// key is the last one like in `JSON.parse`.
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Parse JSON `text`. Leading byte order mark is ignored.
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+        let mut parser = Parser {
+            text: text.as_bytes(),
+            pos: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos != parser.text.len() {
+            return parser.error("Unexpected token");
+        }
+        Ok(value)
+    }
+
+    /// Value of member `key` of object.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(string) => Some(string),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, msg: &str) -> Result<T, String> {
+        Err(format!("{} in JSON at position {}", msg, self.pos))
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.eat(byte) {
+            Ok(())
+        } else {
+            self.error(&format!("Expected '{}'", byte as char))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(
+            self.peek(),
+            Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r')
+        ) {
+            self.pos += 1;
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'n') => self.keyword("null", Json::Null),
+            Some(b't') => self.keyword("true", Json::Bool(true)),
+            Some(b'f') => self.keyword("false", Json::Bool(false)),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = vec![];
+                self.skip_whitespace();
+                if !self.eat(b']') {
+                    loop {
+                        items.push(self.value()?);
+                        self.skip_whitespace();
+                        if self.eat(b']') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+                Ok(Json::Array(items))
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let mut members: Vec<(String, Json)> = vec![];
+                self.skip_whitespace();
+                if !self.eat(b'}') {
+                    loop {
+                        self.skip_whitespace();
+                        if self.peek() != Some(b'"') {
+                            return self.error("Expected property name");
+                        }
+                        let key = self.string()?;
+                        self.expect(b':')?;
+                        let value = self.value()?;
+                        match members.iter_mut().find(|(name, _)| *name == key) {
+                            Some(member) => member.1 = value,
+                            None => members.push((key, value)),
+                        }
+                        self.skip_whitespace();
+                        if self.eat(b'}') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+                Ok(Json::Object(members))
+            }
+            Some(b'-') | Some(b'0'..=b'9') => self.number(),
+            Some(_) => self.error("Unexpected token"),
+            None => self.error("Unexpected end"),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str, value: Json) -> Result<Json, String> {
+        if self.text[self.pos..].starts_with(keyword.as_bytes()) {
+            self.pos += keyword.len();
+            Ok(value)
+        } else {
+            self.error("Unexpected token")
+        }
+    }
+
+    fn digits(&mut self) -> bool {
+        let start = self.pos;
+        while matches!(self.peek(), Some(b'0'..=b'9')) {
+            self.pos += 1;
+        }
+        self.pos != start
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.pos;
+        self.eat(b'-');
+        if !self.eat(b'0') && !self.digits() {
+            return self.error("Invalid number");
+        }
+        if self.eat(b'.') && !self.digits() {
+            return self.error("Invalid number");
+        }
+        if self.eat(b'e') || self.eat(b'E') {
+            if !self.eat(b'+') {
+                self.eat(b'-');
+            }
+            if !self.digits() {
+                return self.error("Invalid number");
+            }
+        }
+        // number is ASCII only
+        let number = std::str::from_utf8(&self.text[start..self.pos]).unwrap();
+        match number.parse::<f64>() {
+            Ok(number) => Ok(Json::Number(number)),
+            Err(_) => self.error("Invalid number"),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        // opening quote
+        self.pos += 1;
+        let mut bytes = vec![];
+        loop {
+            match self.peek() {
+                None => return self.error("Unterminated string"),
+                Some(b'"') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escaped = self.peek();
+                    self.pos += 1;
+                    let c = match escaped {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => self.unicode_escape()?,
+                        _ => return self.error("Invalid escape"),
+                    };
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+                Some(byte) if byte < 0x20 => return self.error("Bad control character in string"),
+                Some(byte) => {
+                    bytes.push(byte);
+                    self.pos += 1;
+                }
+            }
+        }
+        // text is valid UTF-8 and strings are split only at ASCII characters
+        Ok(String::from_utf8(bytes).unwrap())
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = match self.text.get(self.pos..self.pos + 4) {
+            Some(digits) if digits.iter().all(u8::is_ascii_hexdigit) => digits,
+            _ => return self.error("Invalid unicode escape"),
+        };
+        self.pos += 4;
+        Ok(u32::from_str_radix(std::str::from_utf8(digits).unwrap(), 16).unwrap())
+    }
+
+    /// Character of `\uXXXX` escape, `\u` is already consumed. Unpaired surrogates are replaced with U+FFFD.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let code = self.hex4()?;
+        if (0xd800..0xdc00).contains(&code) && self.text[self.pos..].starts_with(b"\\u") {
+            let pos = self.pos;
+            self.pos += 2;
+            let low = self.hex4()?;
+            if (0xdc00..0xe000).contains(&low) {
+                let code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                return Ok(char::from_u32(code).unwrap());
+            }
+            // not a pair, the second escape is read on its own
+            self.pos = pos;
+        }
+        Ok(char::from_u32(code).unwrap_or('\u{fffd}'))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let json = Json::parse(
+            " { \"name\": \"pkg\", \"version\": 1.5e1, \"list\": [true, false, null, -0.25], \"nested\": {} } ",
+        )
+        .unwrap();
+        assert_eq!(json.get("name").and_then(Json::as_str), Some("pkg"));
+        assert_eq!(json.get("version"), Some(&Json::Number(15.0)));
+        assert_eq!(
+            json.get("list"),
+            Some(&Json::Array(vec![
+                Json::Bool(true),
+                Json::Bool(false),
+                Json::Null,
+                Json::Number(-0.25)
+            ]))
+        );
+        assert_eq!(json.get("nested"), Some(&Json::Object(vec![])));
+        assert_eq!(json.get("missing"), None);
+        assert_eq!(json.get("version").and_then(Json::as_str), None);
+        assert_eq!(Json::Null.get("name"), None);
+    }
+
+    #[test]
+    fn test_bom_and_duplicate_keys() {
+        assert_eq!(
+            Json::parse("\u{feff}{\"a\": 1}").unwrap().get("a"),
+            Some(&Json::Number(1.0))
+        );
+        // last value wins but member keeps its first position
+        assert_eq!(
+            Json::parse("{\"a\": 1, \"b\": 2, \"a\": 3}"),
+            Ok(Json::Object(vec![
+                ("a".to_owned(), Json::Number(3.0)),
+                ("b".to_owned(), Json::Number(2.0))
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_strings() {
+        assert_eq!(
+            Json::parse(r#""a\"\\\/\b\f\n\r\té""#),
+            Ok(Json::String("a\"\\/\u{8}\u{c}\n\r\té".to_owned()))
+        );
+        assert_eq!(
+            Json::parse(r#""😀 \uD83D""#),
+            Ok(Json::String("😀 \u{fffd}".to_owned()))
+        );
+        assert_eq!(
+            Json::parse(r#""\ude00\ud83dA""#),
+            Ok(Json::String("\u{fffd}\u{fffd}A".to_owned()))
+        );
+        assert_eq!(Json::parse("\"ü\""), Ok(Json::String("ü".to_owned())));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            Json::parse("{\"a\": 1,}"),
+            Err("Expected property name in JSON at position 8".to_owned())
+        );
+        assert_eq!(
+            Json::parse("[1 2]"),
+            Err("Expected ',' in JSON at position 3".to_owned())
+        );
+        assert_eq!(
+            Json::parse("{\"a\" 1}"),
+            Err("Expected ':' in JSON at position 5".to_owned())
+        );
+        assert_eq!(
+            Json::parse("[1] x"),
+            Err("Unexpected token in JSON at position 4".to_owned())
+        );
+        assert_eq!(
+            Json::parse(""),
+            Err("Unexpected end in JSON at position 0".to_owned())
+        );
+        assert_eq!(
+            Json::parse("nul"),
+            Err("Unexpected token in JSON at position 0".to_owned())
+        );
+        assert_eq!(
+            Json::parse("01"),
+            Err("Unexpected token in JSON at position 1".to_owned())
+        );
+        assert_eq!(
+            Json::parse("1."),
+            Err("Invalid number in JSON at position 2".to_owned())
+        );
+        assert_eq!(
+            Json::parse("-"),
+            Err("Invalid number in JSON at position 1".to_owned())
+        );
+        assert_eq!(
+            Json::parse("\"abc"),
+            Err("Unterminated string in JSON at position 4".to_owned())
+        );
+        assert_eq!(
+            Json::parse("\"a\nb\""),
+            Err("Bad control character in string in JSON at position 2".to_owned())
+        );
+        assert_eq!(
+            Json::parse(r#""\x""#),
+            Err("Invalid escape in JSON at position 3".to_owned())
+        );
+        assert_eq!(
+            Json::parse(r#""\u12g4""#),
+            Err("Invalid unicode escape in JSON at position 3".to_owned())
+        );
+    }
+}
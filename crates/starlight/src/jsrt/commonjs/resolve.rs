@@ -0,0 +1,671 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+//! Node module resolution.
+//!
+//! [resolve_require] follows the algorithm of `require` and [resolve_import] the one of `import`: relative
+//! specifiers, `node_modules` lookup, `main`, `exports` and `imports` fields of `package.json` and packages
+//! referencing themselves by name. `require` also tries `.js` and `.json` extensions and `index` files of
+//! directories, `import` needs the exact file name. Resolved modules are returned as keys of
+//! [Context::modules](crate::vm::context::Context::modules). Files of standalone bundle are found before the file
+//! system.
+use super::json::Json;
+use std::{
+    cmp::Ordering,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+/// Conditions of `exports` and `imports` that match for `require`, `default` always matches.
+pub const REQUIRE_CONDITIONS: &[&str] = &["node", "require"];
+/// Conditions of `exports` and `imports` that match for `import`, `default` always matches.
+pub const IMPORT_CONDITIONS: &[&str] = &["node", "import"];
+
+/// How a module file is run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    CommonJs,
+    Json,
+    Module,
+}
+
+/// Format of module file `path`. `.mjs` files are ES modules, `.js` files are ES modules if the nearest
+/// `package.json` has `"type": "module"` and other files are CommonJS modules.
+pub fn format(path: &Path) -> Format {
+    match path.extension().and_then(OsStr::to_str) {
+        Some("mjs") => Format::Module,
+        Some("json") => Format::Json,
+        Some("js") => {
+            let scope = path
+                .parent()
+                .and_then(|dir| package_scope(dir).ok().flatten());
+            match scope {
+                Some((_, package))
+                    if package.get("type").and_then(Json::as_str) == Some("module") =>
+                {
+                    Format::Module
+                }
+                _ => Format::CommonJs,
+            }
+        }
+        _ => Format::CommonJs,
+    }
+}
+
+/// Key of module `specifier` required by code of `referrer`.
+pub fn resolve_require(specifier: &str, referrer: &str) -> Result<String, String> {
+    Resolver {
+        conditions: REQUIRE_CONDITIONS,
+        require: true,
+    }
+    .resolve(specifier, referrer)
+}
+
+/// Key of module `specifier` imported by code of `referrer`.
+pub fn resolve_import(specifier: &str, referrer: &str) -> Result<String, String> {
+    Resolver {
+        conditions: IMPORT_CONDITIONS,
+        require: false,
+    }
+    .resolve(specifier, referrer)
+}
+
+/// Read module file or `package.json` at `path`.
+pub fn read_file(path: &Path) -> Result<String, String> {
+    match crate::bundle::bundled_file(path) {
+        Some(source) => Ok(String::from_utf8_lossy(source).into_owned()),
+        None => std::fs::read_to_string(path).map_err(|e| e.to_string()),
+    }
+}
+
+fn is_file(path: &Path) -> bool {
+    path.is_file() || crate::bundle::bundled_file(path).is_some()
+}
+
+/// Join `path` with `/` separators to `dir`.
+fn join(dir: &Path, path: &str) -> PathBuf {
+    if cfg!(windows) {
+        dir.join(path.replace("/", "\\"))
+    } else {
+        dir.join(path)
+    }
+}
+
+fn is_path(specifier: &str) -> bool {
+    specifier == "."
+        || specifier == ".."
+        || specifier.starts_with("./")
+        || specifier.starts_with("../")
+        || specifier.starts_with('/')
+        || Path::new(specifier).is_absolute()
+}
+
+/// `package.json` of package in `dir`.
+fn read_package(dir: &Path) -> Result<Option<Json>, String> {
+    let path = dir.join("package.json");
+    if !is_file(&path) {
+        return Ok(None);
+    }
+    let text = read_file(&path).map_err(|e| format!("'{}': {}", path.display(), e))?;
+    Json::parse(&text)
+        .map(Some)
+        .map_err(|e| format!("Invalid package config '{}': {}", path.display(), e))
+}
+
+/// Nearest package containing `dir`. Packages don't extend past `node_modules` directories.
+fn package_scope(dir: &Path) -> Result<Option<(PathBuf, Json)>, String> {
+    for dir in dir.ancestors() {
+        if dir.file_name() == Some(OsStr::new("node_modules")) {
+            break;
+        }
+        if let Some(package) = read_package(dir)? {
+            return Ok(Some((dir.to_path_buf(), package)));
+        }
+    }
+    Ok(None)
+}
+
+/// Split bare specifier into package name and subpath, `@scope/name/sub/path` is split to `@scope/name` and
+/// `/sub/path`.
+fn split_package(specifier: &str) -> Result<(&str, &str), String> {
+    let end = if specifier.starts_with('@') {
+        match specifier.find('/') {
+            Some(slash) => specifier[slash + 1..].find('/').map(|end| slash + 1 + end),
+            None => return Err(format!("Invalid module specifier '{}'", specifier)),
+        }
+    } else {
+        specifier.find('/')
+    };
+    let (name, subpath) = match end {
+        Some(end) => specifier.split_at(end),
+        None => (specifier, ""),
+    };
+    if name.is_empty() || name.starts_with('.') || name.contains('\\') || name.contains('%') {
+        return Err(format!("Invalid module specifier '{}'", specifier));
+    }
+    Ok((name, subpath))
+}
+
+/// Order of `*` patterns of `exports` and `imports`, keys with longer prefix before `*` are tried first and then
+/// longer keys.
+fn pattern_key_order(a: &str, b: &str) -> Ordering {
+    let base = |key: &str| key.find('*').unwrap();
+    base(b).cmp(&base(a)).then(b.len().cmp(&a.len()))
+}
+
+fn has_invalid_segment(path: &str) -> bool {
+    path.split(|c| c == '/' || c == '\\').any(|segment| {
+        segment == "." || segment == ".." || segment.eq_ignore_ascii_case("node_modules")
+    })
+}
+
+struct Resolver {
+    conditions: &'static [&'static str],
+    require: bool,
+}
+
+impl Resolver {
+    fn resolve(&self, specifier: &str, referrer: &str) -> Result<String, String> {
+        let mut dir = match Path::new(referrer).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        if dir.is_relative() {
+            if let Ok(cwd) = std::env::current_dir() {
+                dir = cwd.join(dir);
+            }
+        }
+        let resolved = if is_path(specifier) {
+            let path = join(&dir, specifier);
+            if self.require {
+                match self.load_as_file(&path) {
+                    Some(path) => Some(path),
+                    None => self.load_as_directory(&path)?,
+                }
+            } else if is_file(&path) {
+                Some(path)
+            } else {
+                None
+            }
+        } else if specifier.starts_with('#') {
+            Some(self.package_imports(specifier, &dir)?)
+        } else {
+            self.package_resolve(specifier, &dir)?
+        };
+        match resolved {
+            Some(path) => {
+                crate::jsrt::module_key(&path).map_err(|e| format!("'{}': {}", path.display(), e))
+            }
+            None => Err(format!(
+                "Cannot find module '{}' from '{}'",
+                specifier,
+                dir.display()
+            )),
+        }
+    }
+
+    /// `path` itself or `path` with `.js` or `.json` extension appended.
+    fn load_as_file(&self, path: &Path) -> Option<PathBuf> {
+        if is_file(path) {
+            return Some(path.to_path_buf());
+        }
+        [".js", ".json"].iter().find_map(|extension| {
+            let mut name = path.as_os_str().to_owned();
+            name.push(extension);
+            let path = PathBuf::from(name);
+            if is_file(&path) {
+                Some(path)
+            } else {
+                None
+            }
+        })
+    }
+
+    fn load_index(&self, dir: &Path) -> Option<PathBuf> {
+        ["index.js", "index.json"]
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| is_file(path))
+    }
+
+    /// Module of directory `dir`, `main` of its `package.json` or its `index` file.
+    fn load_as_directory(&self, dir: &Path) -> Result<Option<PathBuf>, String> {
+        let package = read_package(dir)?;
+        if let Some(main) = package
+            .as_ref()
+            .and_then(|p| p.get("main"))
+            .and_then(Json::as_str)
+        {
+            let main = join(dir, main);
+            if let Some(path) = self.load_as_file(&main).or_else(|| self.load_index(&main)) {
+                return Ok(Some(path));
+            }
+            // Node falls back to index of the package when main doesn't exist.
+            return match self.load_index(dir) {
+                Some(path) => Ok(Some(path)),
+                None => Err(format!(
+                    "Cannot find module '{}', \"main\" of '{}' is invalid",
+                    main.display(),
+                    dir.join("package.json").display()
+                )),
+            };
+        }
+        Ok(self.load_index(dir))
+    }
+
+    /// Resolve bare `specifier` from `dir` as a package referencing itself or as a package in `node_modules`.
+    fn package_resolve(&self, specifier: &str, dir: &Path) -> Result<Option<PathBuf>, String> {
+        let (name, subpath) = split_package(specifier)?;
+        let subpath = format!(".{}", subpath);
+        if let Some((package_dir, package)) = package_scope(dir)? {
+            if package.get("name").and_then(Json::as_str) == Some(name) {
+                if let Some(exports) = package.get("exports") {
+                    return self
+                        .package_exports(&package_dir, &subpath, exports)
+                        .map(Some);
+                }
+            }
+        }
+        let ancestors = dir
+            .ancestors()
+            .filter(|dir| dir.file_name() != Some(OsStr::new("node_modules")));
+        for dir in ancestors {
+            let package_dir = join(&dir.join("node_modules"), name);
+            let package = read_package(&package_dir)?;
+            if let Some(exports) = package.as_ref().and_then(|p| p.get("exports")) {
+                return self
+                    .package_exports(&package_dir, &subpath, exports)
+                    .map(Some);
+            }
+            if self.require {
+                let path = join(&dir.join("node_modules"), specifier);
+                match self.load_as_file(&path) {
+                    Some(path) => return Ok(Some(path)),
+                    None => {
+                        if let Some(path) = self.load_as_directory(&path)? {
+                            return Ok(Some(path));
+                        }
+                    }
+                }
+            } else if package.is_some() || package_dir.is_dir() {
+                // `import` uses the first package found even if it doesn't have the module.
+                if subpath == "." {
+                    return self.load_as_directory(&package_dir);
+                }
+                let path = join(&package_dir, &subpath);
+                return Ok(if is_file(&path) { Some(path) } else { None });
+            }
+        }
+        Ok(None)
+    }
+
+    /// Resolve `subpath` of package in `package_dir` with its `exports` field. Resolved file has to exist.
+    fn package_exports(
+        &self,
+        package_dir: &Path,
+        subpath: &str,
+        exports: &Json,
+    ) -> Result<PathBuf, String> {
+        let package_json = package_dir.join("package.json");
+        let subpath_keys = match exports {
+            Json::Object(members) => {
+                let dots = members
+                    .iter()
+                    .filter(|(key, _)| key.starts_with('.'))
+                    .count();
+                if dots != 0 && dots != members.len() {
+                    return Err(format!(
+                        "Invalid package config '{}': \"exports\" cannot contain some keys starting with '.' and some not",
+                        package_json.display()
+                    ));
+                }
+                dots != 0
+            }
+            _ => false,
+        };
+        let resolved = if subpath == "." {
+            let main = if subpath_keys {
+                exports.get(".")
+            } else {
+                Some(exports)
+            };
+            match main {
+                Some(target) => self.package_target(package_dir, target, None, false)?,
+                None => None,
+            }
+        } else if subpath_keys {
+            self.imports_exports(subpath, exports, package_dir, false)?
+        } else {
+            None
+        };
+        match resolved {
+            Some(path) if is_file(&path) => Ok(path),
+            Some(path) => Err(format!("Cannot find module '{}'", path.display())),
+            None => Err(format!(
+                "Package subpath '{}' is not defined by \"exports\" in '{}'",
+                subpath,
+                package_json.display()
+            )),
+        }
+    }
+
+    /// Resolve `#` specifier with `imports` field of the package containing `dir`.
+    fn package_imports(&self, specifier: &str, dir: &Path) -> Result<PathBuf, String> {
+        if specifier == "#" || specifier.starts_with("#/") {
+            return Err(format!("Invalid module specifier '{}'", specifier));
+        }
+        let (package_dir, package) = match package_scope(dir)? {
+            Some(scope) => scope,
+            None => {
+                return Err(format!(
+                    "Package import specifier '{}' is not defined, there is no package.json",
+                    specifier
+                ))
+            }
+        };
+        let package_json = package_dir.join("package.json");
+        let resolved = match package.get("imports") {
+            Some(imports) => self.imports_exports(specifier, imports, &package_dir, true)?,
+            None => None,
+        };
+        match resolved {
+            Some(path) if is_file(&path) => Ok(path),
+            Some(path) => Err(format!("Cannot find module '{}'", path.display())),
+            None => Err(format!(
+                "Package import specifier '{}' is not defined in '{}'",
+                specifier,
+                package_json.display()
+            )),
+        }
+    }
+
+    /// Target of `key` in `exports` or `imports` object `map`. Keys with `*` match any string and the matched
+    /// part replaces `*` in their targets.
+    fn imports_exports(
+        &self,
+        key: &str,
+        map: &Json,
+        package_dir: &Path,
+        imports: bool,
+    ) -> Result<Option<PathBuf>, String> {
+        let members = match map {
+            Json::Object(members) => members,
+            _ => return Ok(None),
+        };
+        if !key.contains('*') {
+            if let Some(target) = map.get(key) {
+                return self.package_target(package_dir, target, None, imports);
+            }
+        }
+        let mut best: Option<(&str, &Json)> = None;
+        for (pattern, target) in members.iter() {
+            let star = match pattern.find('*') {
+                Some(star) if pattern.rfind('*') == Some(star) => star,
+                _ => continue,
+            };
+            let (base, trailer) = (&pattern[..star], &pattern[star + 1..]);
+            let matches = key.starts_with(base)
+                && key != base
+                && (trailer.is_empty() || (key.ends_with(trailer) && key.len() >= pattern.len()));
+            let better = match best {
+                Some((best, _)) => pattern_key_order(pattern, best) == Ordering::Less,
+                None => true,
+            };
+            if matches && better {
+                best = Some((pattern, target));
+            }
+        }
+        match best {
+            Some((pattern, target)) => {
+                let star = pattern.find('*').unwrap();
+                let trailer = pattern.len() - star - 1;
+                let pattern_match = &key[star..key.len() - trailer];
+                self.package_target(package_dir, target, Some(pattern_match), imports)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Resolve `target` of `exports` or `imports`. Conditions are tried in order and the first matching one that
+    /// resolves is used, array targets are fallbacks. `None` is returned if nothing matches.
+    fn package_target(
+        &self,
+        package_dir: &Path,
+        target: &Json,
+        pattern_match: Option<&str>,
+        imports: bool,
+    ) -> Result<Option<PathBuf>, String> {
+        let invalid_target = |target: &str| {
+            Err(format!(
+                "Invalid package target '{}' in '{}'",
+                target,
+                package_dir.join("package.json").display()
+            ))
+        };
+        match target {
+            Json::String(target) => {
+                let target = match pattern_match {
+                    Some(pattern_match) => target.replace("*", pattern_match),
+                    None => target.clone(),
+                };
+                if let Some(path) = target.strip_prefix("./") {
+                    if has_invalid_segment(path) {
+                        return invalid_target(&target);
+                    }
+                    return Ok(Some(join(package_dir, path)));
+                }
+                if !imports
+                    || target.starts_with("../")
+                    || target.starts_with('/')
+                    || target.contains(':')
+                {
+                    return invalid_target(&target);
+                }
+                // `imports` can map to other packages
+                match self.package_resolve(&target, package_dir)? {
+                    Some(path) => Ok(Some(path)),
+                    None => Err(format!(
+                        "Cannot find package '{}' from '{}'",
+                        target,
+                        package_dir.display()
+                    )),
+                }
+            }
+            Json::Object(members) => {
+                for (condition, target) in members.iter() {
+                    if condition == "default" || self.conditions.contains(&condition.as_str()) {
+                        let resolved =
+                            self.package_target(package_dir, target, pattern_match, imports)?;
+                        if resolved.is_some() {
+                            return Ok(resolved);
+                        }
+                    }
+                }
+                Ok(None)
+            }
+            Json::Array(targets) => {
+                let mut last = Ok(None);
+                for target in targets.iter() {
+                    match self.package_target(package_dir, target, pattern_match, imports) {
+                        Ok(Some(path)) => return Ok(Some(path)),
+                        result => last = result,
+                    }
+                }
+                last
+            }
+            Json::Null => Ok(None),
+            _ => invalid_target("<non-string>"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(path: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/resolve/app")
+            .join(path)
+    }
+
+    fn key(path: &str) -> String {
+        fixture(path)
+            .canonicalize()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn require(specifier: &str, referrer: &str) -> Result<String, String> {
+        resolve_require(specifier, fixture(referrer).to_str().unwrap())
+    }
+
+    fn import(specifier: &str, referrer: &str) -> Result<String, String> {
+        resolve_import(specifier, fixture(referrer).to_str().unwrap())
+    }
+
+    #[test]
+    fn test_relative_files() {
+        assert_eq!(
+            require("../lib/helper.js", "src/entry.js"),
+            Ok(key("lib/helper.js"))
+        );
+        // file with extension is tried before directory, `.js` before `.json`
+        assert_eq!(
+            require("../lib/helper", "src/entry.js"),
+            Ok(key("lib/helper.js"))
+        );
+        assert_eq!(
+            require("../lib/both", "src/entry.js"),
+            Ok(key("lib/both.js"))
+        );
+        assert_eq!(
+            require("../lib/data", "src/entry.js"),
+            Ok(key("lib/data.json"))
+        );
+        assert_eq!(require("../lib", "src/entry.js"), Ok(key("lib/index.js")));
+        assert_eq!(
+            require("./lib/helper/", "main.js"),
+            Ok(key("lib/helper/index.js"))
+        );
+        // `import` needs exact file name
+        assert_eq!(
+            import("../lib/helper.js", "src/entry.js"),
+            Ok(key("lib/helper.js"))
+        );
+        assert!(import("../lib/helper", "src/entry.js").is_err());
+        assert!(import("../lib", "src/entry.js").is_err());
+        assert!(require("../lib/missing", "src/entry.js")
+            .unwrap_err()
+            .starts_with("Cannot find module '../lib/missing'"));
+    }
+
+    #[test]
+    fn test_node_modules_walk() {
+        // nearest `node_modules` is searched first
+        assert_eq!(
+            require("plain", "src/entry.js"),
+            Ok(key("src/node_modules/plain/index.js"))
+        );
+        assert_eq!(
+            require("plain", "main.js"),
+            Ok(key("node_modules/plain/index.js"))
+        );
+        assert_eq!(
+            require("with-main", "src/entry.js"),
+            Ok(key("node_modules/with-main/lib/entry.js"))
+        );
+        // missing `main` falls back to index of the package
+        assert_eq!(
+            require("broken-main", "src/entry.js"),
+            Ok(key("node_modules/broken-main/index.js"))
+        );
+        assert_eq!(
+            require("@scope/pkg", "src/entry.js"),
+            Ok(key("node_modules/@scope/pkg/main.js"))
+        );
+        assert_eq!(
+            import("with-main", "main.js"),
+            Ok(key("node_modules/with-main/lib/entry.js"))
+        );
+        assert!(require("missing", "src/entry.js")
+            .unwrap_err()
+            .starts_with("Cannot find module 'missing'"));
+        assert!(require("@scope", "src/entry.js")
+            .unwrap_err()
+            .starts_with("Invalid module specifier"));
+    }
+
+    #[test]
+    fn test_conditional_exports() {
+        assert_eq!(
+            require("conditional", "main.js"),
+            Ok(key("node_modules/conditional/cjs.js"))
+        );
+        assert_eq!(
+            import("conditional", "main.js"),
+            Ok(key("node_modules/conditional/esm.mjs"))
+        );
+        assert!(require("conditional/cjs.js", "main.js")
+            .unwrap_err()
+            .starts_with("Package subpath './cjs.js' is not defined by \"exports\""));
+    }
+
+    #[test]
+    fn test_exports_patterns() {
+        assert_eq!(
+            require("patterns/features/a.js", "main.js"),
+            Ok(key("node_modules/patterns/src/features/a.js"))
+        );
+        assert_eq!(
+            require("patterns/x.js", "main.js"),
+            Ok(key("node_modules/patterns/root/x.js"))
+        );
+        // `null` target excludes subpaths matched by a less specific pattern
+        assert!(require("patterns/features/internal/a.js", "main.js")
+            .unwrap_err()
+            .starts_with("Package subpath './features/internal/a.js' is not defined"));
+        assert!(require("patterns/y.js", "main.js")
+            .unwrap_err()
+            .starts_with("Cannot find module"));
+    }
+
+    #[test]
+    fn test_self_reference_and_imports() {
+        assert_eq!(require("app", "src/entry.js"), Ok(key("main.js")));
+        assert_eq!(
+            require("app/feature", "src/entry.js"),
+            Ok(key("feature.cjs"))
+        );
+        assert_eq!(
+            import("app/feature", "src/entry.js"),
+            Ok(key("feature.mjs"))
+        );
+        assert_eq!(
+            require("#internal/util", "src/entry.js"),
+            Ok(key("internal/util.js"))
+        );
+        assert!(require("#missing", "src/entry.js")
+            .unwrap_err()
+            .starts_with("Package import specifier '#missing' is not defined"));
+        assert!(require("#", "src/entry.js").is_err());
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format(&fixture("feature.mjs")), Format::Module);
+        assert_eq!(format(&fixture("feature.cjs")), Format::CommonJs);
+        assert_eq!(format(&fixture("lib/data.json")), Format::Json);
+        assert_eq!(format(&fixture("main.js")), Format::CommonJs);
+        assert_eq!(
+            format(&fixture("node_modules/esm-pkg/index.js")),
+            Format::Module
+        );
+        // packages don't extend past `node_modules`
+        assert_eq!(
+            format(&fixture("node_modules/plain/index.js")),
+            Format::CommonJs
+        );
+    }
+}
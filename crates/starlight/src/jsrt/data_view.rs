@@ -259,3 +259,26 @@ impl Builtin for JsDataView {
         Ok(())
     }
 }
+
+register_native_references!(
+    data_view_constructor,
+    data_view_prototype_buffer,
+    data_view_prototype_byte_length,
+    data_view_prototype_byte_offset,
+    data_view_prototype_get::<u8>,
+    data_view_prototype_get::<u16>,
+    data_view_prototype_get::<u32>,
+    data_view_prototype_get::<i8>,
+    data_view_prototype_get::<i16>,
+    data_view_prototype_get::<i32>,
+    data_view_prototype_get::<f32>,
+    data_view_prototype_get::<f64>,
+    data_view_prototype_set::<u8>,
+    data_view_prototype_set::<u16>,
+    data_view_prototype_set::<u32>,
+    data_view_prototype_set::<i8>,
+    data_view_prototype_set::<i16>,
+    data_view_prototype_set::<i32>,
+    data_view_prototype_set::<f32>,
+    data_view_prototype_set::<f64>,
+);
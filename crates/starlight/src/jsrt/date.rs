@@ -516,9 +516,9 @@ impl Date {
     /// Converts the `Date` to a local `DateTime`.
     ///
     /// If the `Date` is invalid (i.e. NAN), this function will return `None`.
-    pub fn to_local(self) -> Option<DateTime<Local>> {
+    pub fn to_local(self) -> Option<DateTime<chrono::Local>> {
         self.0
-            .map(|utc| Local::now().timezone().from_utc_datetime(&utc))
+            .map(|utc| chrono::Local::now().timezone().from_utc_datetime(&utc))
     }
 
     /// Converts the `Date` to a UTC `DateTime`.
@@ -655,7 +655,7 @@ impl Date {
     }
 
     fn make_date_string(ctx: GcPointer<Context>) -> JsValue {
-        JsValue::new(JsString::new(ctx, Local::now().to_rfc3339()))
+        JsValue::new(JsString::new(ctx, chrono::Local::now().to_rfc3339()))
     }
     /// `Date()`
     ///
@@ -1407,3 +1407,51 @@ impl Builtin for Date {
         Ok(())
     }
 }
+
+register_native_references!(
+    date_constructor,
+    date_to_string,
+    date_now,
+    date_set_date,
+    date_set_full_year,
+    date_set_hours,
+    date_set_milliseconds,
+    date_set_minutes,
+    date_set_month,
+    date_set_seconds,
+    date_set_year,
+    date_set_time,
+    date_set_utc_date,
+    date_set_utc_full_year,
+    date_set_utc_hours,
+    date_set_utc_minutes,
+    date_set_utc_month,
+    date_set_utc_seconds,
+    date_get_date,
+    date_get_day,
+    date_get_full_year,
+    date_get_hours,
+    date_get_milliseconds,
+    date_get_minutes,
+    date_get_month,
+    date_get_seconds,
+    date_get_time,
+    date_get_year,
+    date_get_utc_date,
+    date_get_utc_day,
+    date_get_utc_full_year,
+    date_get_utc_hours,
+    date_get_utc_minutes,
+    date_get_utc_milliseconds,
+    date_get_utc_month,
+    date_get_utc_seconds,
+    date_to_json,
+    date_to_time_string,
+    date_value_of,
+    date_to_gmt_string,
+    date_to_iso_string,
+    date_to_utc_string,
+    date_to_date_string,
+    date_parse,
+    date_utc,
+);
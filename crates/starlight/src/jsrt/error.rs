@@ -6,7 +6,7 @@ use crate::constant::{S_REFERENCE_ERROR, S_SYNTAX_ERROR};
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 use crate::vm::attributes::*;
 use crate::vm::class::JsClass;
-use crate::vm::property_descriptor::DataDescriptor;
+use crate::vm::property_descriptor::{AccessorDescriptor, DataDescriptor};
 use crate::{
     constant::{S_ERROR, S_EVAL_ERROR, S_RANGE_ERROR, S_TYPE_ERROR, S_URI_ERROR},
     gc::cell::GcPointer,
@@ -18,6 +18,10 @@ use crate::{
         error::*,
         function::JsNativeFunction,
         object::{JsObject, ObjectTag},
+        stack_trace::{
+            error_capture_stack_trace, error_stack_getter, error_stack_setter,
+            DEFAULT_STACK_TRACE_LIMIT,
+        },
         string::JsString,
         structure::Structure,
         symbol_table::*,
@@ -155,6 +159,10 @@ impl Builtin for JsError {
 
         let structure = Structure::new_unique_with_proto(ctx, Some(obj_proto), false);
         let mut prototype = JsObject::new(ctx, &structure, JsError::class(), ObjectTag::Ordinary);
+        ctx.global_data
+            .error_structure
+            .unwrap()
+            .change_prototype_with_no_transition(prototype);
         ctx.global_data.error = Some(prototype);
 
         let mut constructor = JsNativeFunction::new(ctx, S_ERROR, error_constructor, 1);
@@ -169,6 +177,21 @@ impl Builtin for JsError {
         def_native_property!(ctx, prototype, message, message, W | C)?;
         def_native_method!(ctx, prototype, toString, error_to_string, 0, W | C)?;
 
+        let getter = JsNativeFunction::new(ctx, "stack".intern(), error_stack_getter, 0);
+        let setter = JsNativeFunction::new(ctx, "stack".intern(), error_stack_setter, 1);
+        def_native_accessor!(ctx, prototype, stack, getter, setter, C)?;
+
+        def_native_method!(
+            ctx,
+            constructor,
+            captureStackTrace,
+            error_capture_stack_trace,
+            2,
+            W | C
+        )?;
+        let limit = JsValue::new(DEFAULT_STACK_TRACE_LIMIT as i32);
+        def_native_property!(ctx, constructor, stackTraceLimit, limit, W | E | C)?;
+
         let mut global_object = ctx.global_object();
         def_native_property!(ctx, global_object, Error, constructor, W | C)?;
 
@@ -351,3 +374,14 @@ impl Builtin for JsError {
         Ok(())
     }
 }
+
+register_native_references!(
+    error_constructor,
+    error_to_string,
+    eval_error_constructor,
+    range_error_constructor,
+    reference_error_constructor,
+    syntax_error_constructor,
+    type_error_constructor,
+    uri_error_constructor,
+);
@@ -919,3 +919,5 @@ pub fn ffi_function_call(ctx: GcPointer<Context>, args: &Arguments) -> Result<Js
         Err(e) => Err(*e),
     }
 }
+
+register_native_references!(ffi_function_attach, ffi_function_call, ffi_library_open);
@@ -30,18 +30,28 @@ pub fn function_to_string(ctx: GcPointer<Context>, args: &Arguments) -> Result<J
     let obj = &args.this;
     if obj.is_callable() {
         letroot!(func = stack, obj.to_object(ctx)?);
+        let mut bound = false;
+        if func.tag() == ObjectTag::Function {
+            bound = func.as_function().is_bound();
+            let code = match func.as_function().ty {
+                FuncType::User(ref function) => Some(function.code),
+                FuncType::Generator(ref function) => {
+                    Some(function.function.as_function().as_vm().code)
+                }
+                _ => None,
+            };
+            if let Some(source) = code.as_ref().and_then(|code| code.source.as_ref()) {
+                return Ok(JsValue::new(JsString::new(ctx, source.as_str())));
+            }
+        }
         let mut slot = Slot::new();
         let mut fmt = "function ".to_string();
-        if func.get_own_property_slot(ctx, "name".intern(), &mut slot) {
+        // bound functions are shown without name as they are named `bound <target>`
+        if !bound && func.get_own_property_slot(ctx, "name".intern(), &mut slot) {
             let name = slot.get(ctx, *obj)?;
-            let name_str = name.to_string(ctx)?;
-            if name_str.is_empty() {
-                fmt.push_str("<anonymous>");
-            } else {
-                fmt.push_str(&name_str);
+            if name.is_jsstring() {
+                fmt.push_str(&name.to_string(ctx)?);
             }
-        } else {
-            fmt.push_str("<anonymous>");
         }
 
         fmt.push_str("() { [native code] }");
@@ -53,21 +63,79 @@ pub fn function_to_string(ctx: GcPointer<Context>, args: &Arguments) -> Result<J
     )));
 }
 
-pub fn function_prototype(ctx: GcPointer<Context>, args: &Arguments) -> Result<JsValue, JsValue> {
-    let mut params = vec![];
+/// Create function from `(...params, body)` arguments. `prefix` selects kind of function e.g `function*`.
+fn create_dynamic_function(
+    ctx: GcPointer<Context>,
+    args: &Arguments,
+    prefix: &str,
+) -> Result<JsValue, JsValue> {
+    let mut params = String::new();
     if args.size() >= 2 {
         for i in 0..args.size() - 1 {
-            params.push(args.at(i).to_string(ctx)?);
+            if i != 0 {
+                params.push(',');
+            }
+            params.push_str(&args.at(i).to_string(ctx)?);
         }
     }
     let body = if args.size() == 0 {
-        "{ }".to_owned()
+        String::new()
     } else {
-        format!("{{ {} }}", args.at(args.size() - 1).to_string(ctx)?)
+        args.at(args.size() - 1).to_string(ctx)?
     };
-    //let rel_path = unsafe { (*ctx.stack.current).code_block.unwrap().path.clone() };
-    ByteCompiler::compile_code(ctx, &params, &".", body, false)
-        .map_err(|e| JsValue::from(ctx.new_syntax_error(format!("Compile Error {:?}", e))))
+    ByteCompiler::compile_dynamic_function(ctx, prefix, &params, &body)
+}
+
+pub fn function_prototype(ctx: GcPointer<Context>, args: &Arguments) -> Result<JsValue, JsValue> {
+    create_dynamic_function(ctx, args, "function")
+}
+
+pub fn generator_function_constructor(
+    ctx: GcPointer<Context>,
+    args: &Arguments,
+) -> Result<JsValue, JsValue> {
+    create_dynamic_function(ctx, args, "function*")
+}
+
+pub fn async_function_constructor(
+    ctx: GcPointer<Context>,
+    args: &Arguments,
+) -> Result<JsValue, JsValue> {
+    create_dynamic_function(ctx, args, "async function")
+}
+
+pub fn async_generator_function_constructor(
+    ctx: GcPointer<Context>,
+    args: &Arguments,
+) -> Result<JsValue, JsValue> {
+    create_dynamic_function(ctx, args, "async function*")
+}
+
+/// Create `%GeneratorFunction%`-like constructor `name` and its prototype that inherits from `Function.prototype`.
+/// Returns the constructor and structure for functions of this kind.
+pub(crate) fn define_function_kind(
+    ctx: GcPointer<Context>,
+    name: &str,
+    constructor: JsAPI,
+) -> Result<(GcPointer<JsObject>, GcPointer<Structure>), JsValue> {
+    let func_proto = ctx.global_data().func_prototype.unwrap();
+    let structure = Structure::new_indexed(ctx, Some(func_proto), false);
+    let mut prototype = JsObject::new(ctx, &structure, JsObject::class(), ObjectTag::Ordinary);
+    let mut constructor = JsNativeFunction::new(ctx, name.intern(), constructor, 1);
+    def_native_property!(ctx, constructor, prototype, prototype, NONE)?;
+    def_native_property!(ctx, prototype, constructor, constructor, C)?;
+    let tag = JsString::new(ctx, name);
+    def_native_property!(
+        ctx,
+        prototype,
+        "Symbol.toStringTag".intern().private(),
+        tag,
+        C
+    )?;
+    Ok((
+        constructor,
+        Structure::new_indexed(ctx, Some(prototype), false),
+    ))
 }
 
 pub fn function_bind(ctx: GcPointer<Context>, args: &Arguments) -> Result<JsValue, JsValue> {
@@ -219,6 +287,167 @@ impl Builtin for JsFunction {
 
         ctx.global_object().put(ctx, name, constructor, false)?;
 
+        // No function object leads to these constructors while async functions are not compiled, so they are
+        // reachable from the global object. Creating a function with them throws `SyntaxError`.
+        let mut global_object = ctx.global_object();
+        let (constructor, _) =
+            define_function_kind(ctx, "AsyncFunction", async_function_constructor)?;
+        def_native_property!(ctx, global_object, AsyncFunction, constructor, W | C)?;
+        let (constructor, _) = define_function_kind(
+            ctx,
+            "AsyncGeneratorFunction",
+            async_generator_function_constructor,
+        )?;
+        def_native_property!(
+            ctx,
+            global_object,
+            AsyncGeneratorFunction,
+            constructor,
+            W | C
+        )?;
+
         Ok(())
     }
 }
+
+register_native_references!(
+    function_bind,
+    function_prototype,
+    generator_function_constructor,
+    async_function_constructor,
+    async_generator_function_constructor,
+    function_to_string,
+    function_apply,
+    function_call,
+);
+
+#[cfg(test)]
+mod tests {
+    use crate::gc::cell::GcPointer;
+    use crate::gc::snapshot::{deserializer::Deserializer, Snapshot};
+    use crate::options::Options;
+    use crate::vm::context::Context;
+    use crate::vm::symbol_table::Symbol;
+    use crate::vm::tests::eval_string;
+    use crate::Platform;
+
+    #[test]
+    fn test_function_length() {
+        assert_eq!(
+            eval_string(
+                "return [Function('a', 'b = 1', 'c', '').length, \
+                 Function('a', '...b', '').length, \
+                 (function (a, [b], {c}) {}).length, \
+                 ((a, b = 2) => a).length].join()"
+            ),
+            "1,1,3,1"
+        );
+    }
+
+    #[test]
+    fn test_pattern_params() {
+        assert_eq!(
+            eval_string(
+                "let closed = false; \
+                 let it = { i: 0, next: function () { this.i++; return { done: this.i > 5, value: this.i }; }, \
+                 return: function () { closed = true; return {}; } }; \
+                 it[Symbol.iterator] = function () { return this; }; \
+                 let f = Function('[a, , b]', 'return a + b'); \
+                 let g = Function('[a, ...rest]', '{x, ...others}', \
+                 'return [a, rest.join(\"\"), x, Object.keys(others).length].join()'); \
+                 let sum = f(it); \
+                 return [sum, closed, g([1, 2, 3], {x: 4, y: 5, z: 6})].join(';')"
+            ),
+            "4;true;1,23,4,2"
+        );
+    }
+
+    #[test]
+    fn test_lazy_arrows_and_methods() {
+        Platform::initialize();
+        let mut runtime = Platform::new_runtime(Options::default(), None);
+        let mut ctx = Context::new(&mut runtime);
+        let functions = ctx
+            .eval("let k = 1; return [(a, b = 2) => a + b + k, { twice(x) { return x * 2; } }.twice];")
+            .unwrap_or_else(|_| panic!());
+        let mut functions = functions.get_jsobject();
+        for i in 0..2 {
+            let function = functions.get(ctx, Symbol::Index(i)).unwrap_or_else(|_| panic!());
+            assert!(function.get_jsobject().as_function().as_vm().code.lazy.is_some());
+        }
+        assert_eq!(
+            eval_string(
+                "let k = 1; let add = (a, b = 2) => a + b + k; \
+                 let o = { n: 3, times(x) { return x * this.n; } }; \
+                 return [add(1), add.length, o.times(2), o.times.name].join()"
+            ),
+            "4,1,6,times"
+        );
+    }
+
+    #[test]
+    fn test_lazy_compile_error_on_call() {
+        const SOURCE: &str = "function f() { class A {} return 1; } \
+             var loaded = typeof f, errors = []; \
+             for (var i = 0; i < 2; i++) { \
+                 try { f(); errors.push('ok'); } catch (e) { errors.push(e instanceof SyntaxError); } \
+             } \
+             return [loaded, errors.join()].join(';')";
+        assert_eq!(eval_string(SOURCE), "function;true,true");
+
+        let mut runtime =
+            Platform::new_runtime(Options::default().with_eager_compilation(true), None);
+        let mut ctx = Context::new(&mut runtime);
+        assert!(ctx.eval(SOURCE).is_err());
+    }
+
+    #[test]
+    fn test_lazy_functions_in_snapshot() {
+        Platform::initialize();
+        let mut runtime = Platform::new_runtime(Options::default(), None);
+        let mut ctx = Context::new(&mut runtime);
+        ctx.eval(
+            "var k = 10; function lazy(x) { return x + k; } \
+             var compiled = (x) => x * k; compiled(1);",
+        )
+        .unwrap_or_else(|_| panic!());
+        let is_lazy = |mut ctx: GcPointer<Context>, name: &str| {
+            let function = ctx.eval(name).unwrap_or_else(|_| panic!());
+            function.get_jsobject().as_function().as_vm().code.is_lazy()
+        };
+        assert!(is_lazy(ctx, "return lazy"));
+        assert!(!is_lazy(ctx, "return compiled"));
+
+        let snapshot = Snapshot::take_context(false, &mut runtime, ctx, |_, _| {}).buffer;
+        let mut ctx = Deserializer::deserialize_context(&mut runtime, false, &snapshot)
+            .unwrap_or_else(|_| panic!());
+        assert!(is_lazy(ctx, "return lazy"));
+        let result = ctx
+            .eval("return [lazy(1), compiled(2), lazy.toString()].join();")
+            .unwrap_or_else(|error| panic!("{}", error.to_string(ctx).unwrap_or_default()));
+        assert_eq!(
+            result.to_string(ctx).unwrap_or_default(),
+            "11,20,function lazy(x) { return x + k; }"
+        );
+        assert!(!is_lazy(ctx, "return lazy"));
+    }
+
+    #[test]
+    fn test_async_constructors() {
+        assert_eq!(
+            eval_string(
+                "let results = [typeof Object.getPrototypeOf(function* () {}).constructor]; \
+                 let check = function (F, name) { \
+                 let proto = Object.getPrototypeOf(F.prototype); \
+                 results.push(typeof F, proto === Function.prototype, F.prototype[Symbol.toStringTag] === name); \
+                 try { F('return 1'); } catch (e) { results.push(e instanceof SyntaxError, /NYI/.test(e.message)); } \
+                 try { new F('a', '}'); } catch (e) { results.push(e instanceof SyntaxError, /NYI/.test(e.message)); } \
+                 }; \
+                 check(AsyncFunction, 'AsyncFunction'); \
+                 check(AsyncGeneratorFunction, 'AsyncGeneratorFunction'); \
+                 return results;"
+            ),
+            "function,function,true,true,true,true,true,false,function,true,true,true,true,true,false"
+        );
+    }
+}
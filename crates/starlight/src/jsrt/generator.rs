@@ -2,6 +2,7 @@ use std::intrinsics::unlikely;
 
 use crate::prelude::*;
 use crate::vm::builder::Builtin;
+use crate::jsrt::function::{define_function_kind, generator_function_constructor};
 use crate::vm::{context::Context, function::*};
 
 impl Builtin for JsGeneratorFunction {
@@ -35,6 +36,22 @@ impl Builtin for JsGeneratorFunction {
         ctx.global_data.generator_prototype = Some(generator);
         ctx.global_data.generator_structure =
             Some(Structure::new_indexed(ctx, Some(generator), false));
+
+        let (_, structure) =
+            define_function_kind(ctx, "GeneratorFunction", generator_function_constructor)?;
+        // %GeneratorFunction.prototype%.prototype is prototype of generator objects
+        let mut function_prototype = *structure.prototype().unwrap();
+        def_native_property!(ctx, function_prototype, prototype, generator, C)?;
+        def_native_property!(ctx, generator, constructor, function_prototype, C)?;
+        let tag = JsString::new(ctx, "Generator");
+        def_native_property!(
+            ctx,
+            generator,
+            "Symbol.toStringTag".intern().private(),
+            tag,
+            C
+        )?;
+        ctx.global_data.generator_function_structure = Some(structure);
         Ok(())
     }
 }
@@ -108,3 +125,10 @@ pub fn generator_throw(ctx: GcPointer<Context>, args: &Arguments) -> Result<JsVa
     }
     Ok(ret)
 }
+
+register_native_references!(
+    generator_next,
+    generator_iterator,
+    generator_return,
+    generator_throw,
+);
@@ -154,6 +154,17 @@ pub fn is_finite(ctx: GcPointer<Context>, args: &Arguments) -> Result<JsValue, J
     Ok(JsValue::encode_bool_value(false))
 }
 
+/// Indirect `eval(x)`, source is evaluated as global eval code by [Context::indirect_eval]. Direct `eval` calls are
+/// handled by interpreter.
+pub fn eval(ctx: GcPointer<Context>, args: &Arguments) -> Result<JsValue, JsValue> {
+    let source = args.at(0);
+    if !source.is_jsstring() {
+        return Ok(source);
+    }
+    let source = source.get_jsstring().as_str().to_string();
+    ctx.indirect_eval(&source)
+}
+
 pub fn gc(mut ctx: GcPointer<Context>, args: &Arguments) -> Result<JsValue, JsValue> {
     ctx.heap().gc();
     let _ = args;
@@ -233,6 +244,7 @@ impl Builtin for JsGlobal {
         def_native_method!(ctx, global_object, readLine, global::read_line, 1)?;
         def_native_method!(ctx, global_object, parseFloat, global::parse_float, 1)?;
         def_native_method!(ctx, global_object, gc, global::gc, 0)?;
+        def_native_method!(ctx, global_object, eval, global::eval, 1)?;
         def_native_method!(ctx, global_object, ___trunc, global::___trunc, 1)?;
         def_native_method!(ctx, global_object, ___isCallable, global::___is_callable, 1)?;
         def_native_method!(
@@ -247,3 +259,60 @@ impl Builtin for JsGlobal {
         Ok(())
     }
 }
+
+register_native_references!(
+    is_finite,
+    is_nan,
+    parse_float,
+    parse_int,
+    read_line,
+    gc,
+    eval,
+    ___is_constructor,
+    ___is_callable,
+    ___trunc,
+    to_string,
+);
+
+#[cfg(test)]
+mod tests {
+    use crate::vm::tests::eval_string;
+
+    #[test]
+    fn test_indirect_eval_global_lookup() {
+        assert_eq!(
+            eval_string(
+                "var o = 10; \
+                 let f = function () { var o = 1; return [(0, eval)('o'), eval('o')]; }; \
+                 let indirect = eval; \
+                 return [(0, eval)('o'), indirect('typeof f'), f(), (0, eval)(42)];"
+            ),
+            "10,undefined,10,1,42"
+        );
+    }
+
+    #[test]
+    fn test_sloppy_eval_var_leak() {
+        assert_eq!(
+            eval_string(
+                "(0, eval)('var leaked = 1; function leakedFn() { return 2; } let local = 3;'); \
+                 let f = function () { eval('var direct = 4'); return direct; }; \
+                 return [leaked, leakedFn(), typeof local, f(), typeof direct];"
+            ),
+            "1,2,undefined,4,undefined"
+        );
+    }
+
+    #[test]
+    fn test_strict_eval_isolation() {
+        assert_eq!(
+            eval_string(
+                "let result = (0, eval)('\"use strict\"; var isolated = 1; function isolatedFn() {} isolated'); \
+                 let f = function () { eval('\"use strict\"; var direct = 2'); return typeof direct; }; \
+                 let g = function () { 'use strict'; eval('var direct = 3'); return typeof direct; }; \
+                 return [result, typeof isolated, typeof isolatedFn, f(), g()];"
+            ),
+            "1,undefined,undefined,undefined,undefined"
+        );
+    }
+}
@@ -1,5 +1,21 @@
 use crate::prelude::*;
-use crate::vm::context::*;
+use crate::vm::{
+    array_buffer::{JsArrayBuffer, SharedDataBlock},
+    context::*,
+    object::TypedJsObject,
+    JobQueue, VirtualMachine,
+};
+use once_cell::sync::Lazy;
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
 
 pub fn _262_create_realm(ctx: GcPointer<Context>, _: &Arguments) -> Result<JsValue, JsValue> {
     let new_ctx = ctx.vm().new_context();
@@ -12,6 +28,24 @@ pub fn _262_eval_script(mut ctx: GcPointer<Context>, args: &Arguments) -> Result
         Ok(JsValue::encode_undefined_value())
     }
 }
+/// `$262.detachArrayBuffer(buffer)`
+pub fn _262_detach_array_buffer(
+    ctx: GcPointer<Context>,
+    args: &Arguments,
+) -> Result<JsValue, JsValue> {
+    let buffer = args.at(0);
+    if !buffer.is_jsobject() || !buffer.get_jsobject().is_class(JsArrayBuffer::class()) {
+        return Err(JsValue::new(ctx.new_type_error(
+            "detachArrayBuffer: argument is not an ArrayBuffer",
+        )));
+    }
+    TypedJsObject::<JsArrayBuffer>::new(buffer).detach();
+    Ok(JsValue::encode_null_value())
+}
+/// `[[Call]]` of `$262.IsHTMLDDA`, returns `null`.
+pub fn _262_is_htmldda(_ctx: GcPointer<Context>, _args: &Arguments) -> Result<JsValue, JsValue> {
+    Ok(JsValue::encode_null_value())
+}
 pub fn init(mut ctx: GcPointer<Context>, as_: Symbol) -> Result<GcPointer<JsObject>, JsValue> {
     let mut global_object = ctx.global_object();
 
@@ -24,7 +58,380 @@ pub fn init(mut ctx: GcPointer<Context>, as_: Symbol) -> Result<GcPointer<JsObje
     let gc = JsNativeFunction::new(ctx, "gc".intern(), crate::jsrt::global::gc, 0);
     object.put(ctx, "gc".intern(), JsValue::new(gc), false)?;
     object.put(ctx, "global".intern(), JsValue::new(global_object), false)?;
+    let detach = JsNativeFunction::new(
+        ctx,
+        "detachArrayBuffer".intern(),
+        _262_detach_array_buffer,
+        1,
+    );
+    object.put(
+        ctx,
+        "detachArrayBuffer".intern(),
+        JsValue::new(detach),
+        false,
+    )?;
+    let mut htmldda = JsNativeFunction::new(ctx, "IsHTMLDDA".intern(), _262_is_htmldda, 0);
+    htmldda.set_htmldda(true);
+    object.put(ctx, "IsHTMLDDA".intern(), JsValue::new(htmldda), false)?;
+    let agent = init_main_agent(ctx)?;
+    object.put(ctx, "agent".intern(), JsValue::new(agent), false)?;
     global_object.put(ctx, as_, JsValue::new(object), false)?;
-    object.put(ctx, "agent".intern(), JsValue::UNDEFINED, false)?;
     Ok(object)
 }
+
+/// Message sent to agents by `$262.agent.broadcast`.
+struct Broadcast {
+    block: Arc<SharedDataBlock>,
+    number: Option<f64>,
+    received: Sender<()>,
+}
+
+/// Agent started by `$262.agent.start`.
+struct Agent {
+    broadcasts: Sender<Broadcast>,
+    thread: JoinHandle<()>,
+}
+
+/// Agents started by code running on this thread and reports they sent.
+#[derive(Default)]
+struct MainAgent {
+    agents: Vec<Agent>,
+    reports: Arc<Mutex<VecDeque<String>>>,
+}
+
+/// State of agent running on this thread.
+struct AgentState {
+    broadcasts: Receiver<Broadcast>,
+    reports: Arc<Mutex<VecDeque<String>>>,
+    leaving: bool,
+}
+
+thread_local! {
+    static MAIN_AGENT: RefCell<MainAgent> = RefCell::new(MainAgent::default());
+    static AGENT: RefCell<Option<AgentState>> = RefCell::new(None);
+}
+
+/// Time origin of `$262.agent.monotonicNow`.
+static START: Lazy<Instant> = Lazy::new(Instant::now);
+
+/// Stop agents started by `$262.agent.start` on this thread and wait until they exit. Agents waiting for a
+/// broadcast see that there are no more broadcasts and exit. Embedders call it when a test finishes.
+pub fn stop_agents() {
+    let main = MAIN_AGENT.with(|main| std::mem::take(&mut *main.borrow_mut()));
+    let (senders, threads): (Vec<_>, Vec<_>) = main
+        .agents
+        .into_iter()
+        .map(|agent| (agent.broadcasts, agent.thread))
+        .unzip();
+    drop(senders);
+    for thread in threads {
+        let _ = thread.join();
+    }
+}
+
+/// `$262.agent.start(source)`
+pub fn _262_agent_start(ctx: GcPointer<Context>, args: &Arguments) -> Result<JsValue, JsValue> {
+    let source = args.at(0).to_string(ctx)?;
+    let (broadcasts, receiver) = mpsc::channel();
+    MAIN_AGENT.with(|main| {
+        let mut main = main.borrow_mut();
+        let reports = main.reports.clone();
+        let thread = thread::spawn(move || run_agent(source, reports, receiver));
+        main.agents.push(Agent { broadcasts, thread });
+    });
+    Ok(JsValue::encode_undefined_value())
+}
+
+/// `$262.agent.broadcast(buffer, number)`, blocks until every agent received the buffer.
+pub fn _262_agent_broadcast(ctx: GcPointer<Context>, args: &Arguments) -> Result<JsValue, JsValue> {
+    let buffer = args.at(0);
+    if !buffer.is_jsobject() || !buffer.get_jsobject().is_class(JsArrayBuffer::class()) {
+        return Err(JsValue::new(
+            ctx.new_type_error("broadcast: argument is not a SharedArrayBuffer"),
+        ));
+    }
+    let block = match TypedJsObject::<JsArrayBuffer>::new(buffer).share() {
+        Some(block) => block,
+        None => {
+            return Err(JsValue::new(
+                ctx.new_type_error("broadcast: buffer is detached"),
+            ))
+        }
+    };
+    let number = if args.at(1).is_undefined() {
+        None
+    } else {
+        Some(args.at(1).to_number(ctx)?)
+    };
+    let (received, acks) = mpsc::channel();
+    let sent = MAIN_AGENT.with(|main| {
+        main.borrow()
+            .agents
+            .iter()
+            .filter(|agent| {
+                let message = Broadcast {
+                    block: block.clone(),
+                    number,
+                    received: received.clone(),
+                };
+                agent.broadcasts.send(message).is_ok()
+            })
+            .count()
+    });
+    drop(received);
+    // agents that exited drop the message and disconnect the channel.
+    for _ in 0..sent {
+        if acks.recv().is_err() {
+            break;
+        }
+    }
+    Ok(JsValue::encode_undefined_value())
+}
+
+/// `$262.agent.getReport()`, `null` if no agent sent a report.
+pub fn _262_agent_get_report(
+    ctx: GcPointer<Context>,
+    _args: &Arguments,
+) -> Result<JsValue, JsValue> {
+    let report = MAIN_AGENT.with(|main| main.borrow().reports.lock().unwrap().pop_front());
+    match report {
+        Some(report) => Ok(JsValue::new(JsString::new(ctx, report))),
+        None => Ok(JsValue::encode_null_value()),
+    }
+}
+
+/// `$262.agent.sleep(ms)`
+pub fn _262_agent_sleep(ctx: GcPointer<Context>, args: &Arguments) -> Result<JsValue, JsValue> {
+    let ms = args.at(0).to_number(ctx)?;
+    if ms > 0.0 {
+        thread::sleep(Duration::from_millis(ms as u64));
+    }
+    Ok(JsValue::encode_undefined_value())
+}
+
+/// `$262.agent.monotonicNow()`
+pub fn _262_agent_monotonic_now(
+    _ctx: GcPointer<Context>,
+    _args: &Arguments,
+) -> Result<JsValue, JsValue> {
+    Ok(JsValue::new(START.elapsed().as_millis() as f64))
+}
+
+/// `$262.agent.receiveBroadcast(callback)` of an agent.
+pub fn _262_agent_receive_broadcast(
+    mut ctx: GcPointer<Context>,
+    args: &Arguments,
+) -> Result<JsValue, JsValue> {
+    let callback = args.at(0);
+    if !callback.is_callable() {
+        return Err(JsValue::new(
+            ctx.new_type_error("receiveBroadcast: callback is not a function"),
+        ));
+    }
+    ctx.global_object()
+        .put(ctx, broadcast_callback(), callback, false)?;
+    Ok(JsValue::encode_undefined_value())
+}
+
+/// `$262.agent.report(value)` of an agent.
+pub fn _262_agent_report(ctx: GcPointer<Context>, args: &Arguments) -> Result<JsValue, JsValue> {
+    let value = args.at(0).to_string(ctx)?;
+    AGENT.with(|agent| {
+        if let Some(agent) = agent.borrow().as_ref() {
+            agent.reports.lock().unwrap().push_back(value);
+        }
+    });
+    Ok(JsValue::encode_undefined_value())
+}
+
+/// `$262.agent.leaving()` of an agent.
+pub fn _262_agent_leaving(_ctx: GcPointer<Context>, _args: &Arguments) -> Result<JsValue, JsValue> {
+    AGENT.with(|agent| {
+        if let Some(agent) = agent.borrow_mut().as_mut() {
+            agent.leaving = true;
+        }
+    });
+    Ok(JsValue::encode_undefined_value())
+}
+
+fn broadcast_callback() -> Symbol {
+    "[[BroadcastCallback]]".intern().private()
+}
+
+fn add_agent_function(
+    ctx: GcPointer<Context>,
+    mut agent: GcPointer<JsObject>,
+    name: &str,
+    f: fn(GcPointer<Context>, &Arguments) -> Result<JsValue, JsValue>,
+    length: u32,
+) -> Result<(), JsValue> {
+    let fun = JsNativeFunction::new(ctx, name.intern(), f, length);
+    agent.put(ctx, name.intern(), JsValue::new(fun), false)
+}
+
+/// `$262.agent` of the main agent.
+fn init_main_agent(ctx: GcPointer<Context>) -> Result<GcPointer<JsObject>, JsValue> {
+    let agent = JsObject::new_empty(ctx);
+    add_agent_function(ctx, agent, "start", _262_agent_start, 1)?;
+    add_agent_function(ctx, agent, "broadcast", _262_agent_broadcast, 2)?;
+    add_agent_function(ctx, agent, "getReport", _262_agent_get_report, 0)?;
+    add_agent_function(ctx, agent, "sleep", _262_agent_sleep, 1)?;
+    add_agent_function(ctx, agent, "monotonicNow", _262_agent_monotonic_now, 0)?;
+    Ok(agent)
+}
+
+/// Replace `$262.agent` of `ctx` with agent of a thread started by `$262.agent.start`.
+fn init_agent(ctx: GcPointer<Context>) -> Result<(), JsValue> {
+    let mut object = init(ctx, "$262".intern())?;
+    let agent = JsObject::new_empty(ctx);
+    add_agent_function(
+        ctx,
+        agent,
+        "receiveBroadcast",
+        _262_agent_receive_broadcast,
+        1,
+    )?;
+    add_agent_function(ctx, agent, "report", _262_agent_report, 1)?;
+    add_agent_function(ctx, agent, "leaving", _262_agent_leaving, 0)?;
+    add_agent_function(ctx, agent, "sleep", _262_agent_sleep, 1)?;
+    add_agent_function(ctx, agent, "monotonicNow", _262_agent_monotonic_now, 0)?;
+    object.put(ctx, "agent".intern(), JsValue::new(agent), false)
+}
+
+/// Body of an agent thread. Agent runs `source` in its own VM and then calls its broadcast callback for each
+/// broadcast until it calls `$262.agent.leaving` or the main agent stops it.
+fn run_agent(
+    source: String,
+    reports: Arc<Mutex<VecDeque<String>>>,
+    broadcasts: Receiver<Broadcast>,
+) {
+    AGENT.with(|agent| {
+        *agent.borrow_mut() = Some(AgentState {
+            broadcasts,
+            reports,
+            leaving: false,
+        })
+    });
+    let jobs = JobQueue::default();
+    let mut vm =
+        VirtualMachine::new(Default::default(), None).with_async_scheduler(jobs.scheduler());
+    let mut ctx = vm.new_context();
+    if init_agent(ctx).is_ok() && ctx.eval(&source).is_ok() {
+        jobs.run(ctx);
+        while !AGENT.with(|agent| agent.borrow().as_ref().unwrap().leaving) {
+            let callback = match ctx.global_object().get(ctx, broadcast_callback()) {
+                Ok(callback) if callback.is_callable() => callback,
+                _ => break,
+            };
+            let message =
+                match AGENT.with(|agent| agent.borrow().as_ref().unwrap().broadcasts.recv()) {
+                    Ok(message) => message,
+                    Err(_) => break,
+                };
+            let stack = ctx.shadowstack();
+            letroot!(
+                buffer = stack,
+                JsArrayBuffer::new_shared(ctx, message.block)
+            );
+            let _ = message.received.send(());
+            let number = match message.number {
+                Some(number) => JsValue::new(number),
+                None => JsValue::encode_undefined_value(),
+            };
+            let mut args = [JsValue::new(*buffer), number];
+            letroot!(
+                args = stack,
+                Arguments::new(JsValue::encode_undefined_value(), &mut args)
+            );
+            letroot!(callback = stack, callback.get_jsobject());
+            if callback
+                .as_function_mut()
+                .call(ctx, &mut args, JsValue::encode_undefined_value())
+                .is_err()
+            {
+                break;
+            }
+            jobs.run(ctx);
+        }
+    }
+    AGENT.with(|agent| agent.borrow_mut().take());
+    vm.remove_context(ctx);
+    unsafe {
+        vm.dispose();
+    }
+}
+
+register_native_references!(
+    _262_create_realm,
+    _262_eval_script,
+    _262_detach_array_buffer,
+    _262_is_htmldda,
+    _262_agent_start,
+    _262_agent_broadcast,
+    _262_agent_get_report,
+    _262_agent_sleep,
+    _262_agent_monotonic_now,
+    _262_agent_receive_broadcast,
+    _262_agent_report,
+    _262_agent_leaving,
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::tests::test_runtime;
+
+    fn eval_262(source: &str) -> String {
+        let (mut runtime, _) = test_runtime();
+        let mut ctx = Context::new(&mut runtime);
+        init(ctx, "$262".intern()).unwrap_or_else(|_| panic!());
+        let result = match ctx.eval(source) {
+            Ok(value) => value.to_string(ctx).unwrap_or_default(),
+            Err(error) => panic!("{}", error.to_string(ctx).unwrap_or_default()),
+        };
+        stop_agents();
+        result
+    }
+
+    #[test]
+    fn test_is_htmldda() {
+        assert_eq!(
+            eval_262(
+                "var dda = $262.IsHTMLDDA; \
+                 return [typeof dda, !dda, dda ? 1 : 2, dda == null, dda == undefined, dda === null, \
+                 dda === undefined, dda(), typeof Object(dda)].join();"
+            ),
+            "undefined,true,2,true,true,false,false,,undefined"
+        );
+    }
+
+    #[test]
+    fn test_agent() {
+        assert_eq!(
+            eval_262(
+                "$262.agent.start('$262.agent.receiveBroadcast(function (sab, n) { \
+                     var view = new DataView(sab); view.setUint8(0, view.getUint8(0) + n); $262.agent.report(view.getUint8(0)); $262.agent.leaving(); });'); \
+                 var buffer = new ArrayBuffer(4); new DataView(buffer).setUint8(0, 40); \
+                 var start = $262.agent.monotonicNow(); \
+                 $262.agent.broadcast(buffer, 2); \
+                 var report; \
+                 while ((report = $262.agent.getReport()) === null) { $262.agent.sleep(1); } \
+                 return [report, new DataView(buffer).getUint8(0), $262.agent.getReport(), \
+                 $262.agent.monotonicNow() >= start].join();"
+            ),
+            "42,42,,true"
+        );
+    }
+
+    #[test]
+    fn test_detach_array_buffer() {
+        assert_eq!(
+            eval_262(
+                "var buffer = new ArrayBuffer(8); $262.detachArrayBuffer(buffer); \
+                 var error; try { $262.detachArrayBuffer({}); } catch (e) { error = e; } \
+                 return [buffer.byteLength, error instanceof TypeError].join();"
+            ),
+            "0,true"
+        );
+    }
+}
@@ -11,7 +11,7 @@ pub fn init_js_std(
 ) -> Result<(), JsValue> {
     ctx.heap().defer();
     let mut std = JsObject::new_empty(ctx);
-    module.put(ctx, "@expoctxs".intern(), JsValue::new(std), false)?;
+    module.put(ctx, "@exports".intern(), JsValue::new(std), false)?;
     module.put(ctx, "@default".intern(), JsValue::new(std), false)?;
     file::std_init_file(ctx, std)?;
     def_native_method!(ctx, std, args, std_args, 0)?;
@@ -25,3 +25,5 @@ pub fn std_args(ctx: GcPointer<Context>, _args: &Arguments) -> Result<JsValue, J
         .collect::<Vec<_>>();
     Ok(JsValue::new(JsArray::from_slice(ctx, &args)))
 }
+
+register_native_references!(init_js_std, std_args);
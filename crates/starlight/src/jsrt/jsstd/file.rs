@@ -5,7 +5,7 @@ use crate::{gc::cell::GcPointer, vm::object::JsObject};
 use std::{
     fs::{File, OpenOptions},
     intrinsics::unlikely,
-    io::{Read, Write},
+    io::{self, Cursor, Read, Write},
     mem::ManuallyDrop,
 };
 
@@ -39,6 +39,12 @@ pub fn std_file_open(mut ctx: GcPointer<Context>, args: &Arguments) -> Result<Js
     };
 
     let path = std::path::Path::new(&path);
+    let read_only = !flags.contains(|flag| matches!(flag, 'w' | '+' | 'a' | 't'));
+    let bundled = if read_only {
+        crate::bundle::bundled_file(path)
+    } else {
+        None
+    };
     let mut opts = OpenOptions::new();
 
     let mut opts_ = opts
@@ -48,7 +54,11 @@ pub fn std_file_open(mut ctx: GcPointer<Context>, args: &Arguments) -> Result<Js
         .append(flags.contains('a'))
         .truncate(flags.contains('t'));
 
-    let file = match opts_.open(&path) {
+    let file = match bundled {
+        Some(data) => Ok(FileHandle::Bundled(Cursor::new(data))),
+        None => opts_.open(&path).map(FileHandle::Fs),
+    };
+    let file = match file {
         Ok(file) => file,
         Err(e) => {
             return Err(JsValue::new(ctx.new_reference_error(format!(
@@ -299,8 +309,42 @@ pub fn std_file_close(ctx: GcPointer<Context>, args: &Arguments) -> Result<JsVal
     }
 }
 
+/// File opened by `std.File`, files of the running bundle are opened read-only from memory.
+pub enum FileHandle {
+    Fs(File),
+    Bundled(Cursor<&'static [u8]>),
+}
+
+impl Read for FileHandle {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Fs(file) => file.read(buf),
+            Self::Bundled(data) => data.read(buf),
+        }
+    }
+}
+
+impl Write for FileHandle {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Fs(file) => file.write(buf),
+            Self::Bundled(_) => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "bundled files are read-only",
+            )),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Fs(file) => file.flush(),
+            Self::Bundled(_) => Ok(()),
+        }
+    }
+}
+
 pub struct FileObject {
-    pub file: Option<File>,
+    pub file: Option<FileHandle>,
 }
 
 extern "C" fn drop_file_fn(obj: GcPointer<JsObject>) {
@@ -331,3 +375,14 @@ impl JsClass for FileObject {
         )
     }
 }
+
+register_native_references!(
+    std_file_open,
+    std_file_read,
+    std_file_write,
+    std_file_write_all,
+    std_file_read_bytes,
+    std_file_read_bytes_exact,
+    std_file_read_bytes_to_end,
+    std_file_close,
+);
@@ -222,47 +222,45 @@ pub fn math_tanh(ctx:GcPointer<Context>, args: &Arguments) -> Result<JsValue,JsV
     Ok(JsValue::new(number.tanh()))
 }
 
+register_native_references!(
+    math_abs,
+    math_acos,
+    math_acosh,
+    math_asin,
+    math_asinh,
+    math_atan,
+    math_atan2,
+    math_atanh,
+    math_cbrt,
+    math_ceil,
+    math_clz32,
+    math_cos,
+    math_cosh,
+    math_exp,
+    math_expm1,
+    math_floor,
+    math_fround,
+    math_hypot,
+    math_imul,
+    math_log,
+    math_log10,
+    math_log1p,
+    math_log2,
+    math_pow,
+    math_random,
+    math_round,
+    math_sign,
+    math_sin,
+    math_sinh,
+    math_sqrt,
+    math_tan,
+    math_tanh,
+    math_trunc,
+);
+
 pub struct Math;
 
 impl Builtin for Math {
-    fn native_references() -> Vec<usize> {
-        vec![
-            math_abs as _,
-            math_acos as _,
-            math_acosh as _,
-            math_asin as _,
-            math_asinh as _,
-            math_atan as _,
-            math_atan2 as _,
-            math_atanh as _,
-            math_cbrt as _,
-            math_ceil as _,
-            math_clz32 as _,
-            math_cos as _,
-            math_cosh as _,
-            math_exp as _,
-            math_expm1 as _,
-            math_floor as _,
-            math_fround as _,
-            math_hypot as _,
-            math_imul as _,
-            math_log as _,
-            math_log10 as _,
-            math_log1p as _,
-            math_log2 as _,
-            math_pow as _,
-            math_random as _,
-            math_round as _,
-            math_sign as _,
-            math_sin as _,
-            math_sinh as _,
-            math_sqrt as _,
-            math_tan as _,
-            math_tanh as _,
-            math_trunc as _
-        ]
-    }
-
     fn init(mut ctx: GcPointer<Context>) -> Result<(), JsValue> {
         let mut math = JsObject::new_empty(ctx);
 
@@ -586,3 +586,18 @@ impl Builtin for JsNumber {
         Ok(())
     }
 }
+
+register_native_references!(
+    number_constructor,
+    number_clz,
+    number_is_finite,
+    number_is_integer,
+    number_is_nan,
+    number_to_int,
+    number_to_precisiion,
+    number_to_fixed,
+    number_to_string,
+    number_value_of,
+    number_is_safe_integer,
+    number_to_local_string,
+);
@@ -497,3 +497,21 @@ impl Builtin for JsObject {
         Ok(())
     }
 }
+
+register_native_references!(
+    object_constructor,
+    object_create,
+    object_to_string,
+    object_define_property,
+    object_has_own_property,
+    object_property_is_enumerable,
+    object_keys,
+    object_get_own_property_descriptor,
+    object_freeze,
+    object_seal,
+    object_get_prototype_of,
+    object_is_extensible,
+    object_is_sealed,
+    object_is_frozen,
+    object_prevent_extensions,
+);
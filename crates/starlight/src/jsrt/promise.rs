@@ -78,7 +78,7 @@ pub fn promise_then(ctx: GcPointer<Context>, args: &Arguments) -> Result<JsValue
             on_rejected_opt = Some(rejected);
         }
 
-        prom.then(ctx, on_resolved_opt, on_rejected_opt, None)
+        prom.then(ctx, args.this, on_resolved_opt, on_rejected_opt, None)
     })
 }
 
@@ -92,7 +92,7 @@ pub fn promise_catch(ctx: GcPointer<Context>, args: &Arguments) -> Result<JsValu
                     "rejected argument is not a Function",
                 )))
             } else {
-                prom.then(ctx, None, Some(rejected), None)
+                prom.then(ctx, args.this, None, Some(rejected), None)
             }
         } else {
             Err(JsValue::encode_object_value(JsString::new(
@@ -113,7 +113,7 @@ pub fn promise_finally(ctx: GcPointer<Context>, args: &Arguments) -> Result<JsVa
                     "finally argument is not a Function",
                 )))
             } else {
-                prom.then(ctx, None, None, Some(finally))
+                prom.then(ctx, args.this, None, None, Some(finally))
             }
         } else {
             Err(JsValue::encode_object_value(JsString::new(
@@ -282,3 +282,18 @@ impl Builtin for JsPromise {
         Ok(())
     }
 }
+
+register_native_references!(
+    promise_constructor,
+    promise_then,
+    promise_catch,
+    promise_finally,
+    promise_resolve,
+    promise_reject,
+    promise_static_resolve,
+    promise_static_reject,
+    promise_static_race,
+    promise_static_all,
+    promise_static_all_settled,
+    promise_static_any,
+);
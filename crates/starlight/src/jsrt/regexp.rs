@@ -498,10 +498,10 @@ pub struct RegexSearcher<'r, 't> {
     next_match: Option<(usize, usize)>,
 }
 pub struct RegexPattern<'r>(&'r Regex);
-impl<'r, 't> Pattern<'t> for RegexPattern<'r> {
-    type Searcher = RegexSearcher<'r, 't>;
+impl<'r> Pattern for RegexPattern<'r> {
+    type Searcher<'t> = RegexSearcher<'r, 't>;
 
-    fn into_searcher(self, haystack: &'t str) -> RegexSearcher<'r, 't> {
+    fn into_searcher<'t>(self, haystack: &'t str) -> RegexSearcher<'r, 't> {
         RegexSearcher {
             haystack,
             it: self.0.find_iter(haystack),
@@ -549,3 +549,12 @@ unsafe impl<'r, 't> Searcher<'t> for RegexSearcher<'r, 't> {
         }
     }
 }
+
+register_native_references!(
+    regexp_constructor,
+    regexp_exec,
+    regexp_test,
+    regexp_to_string,
+    regexp_match,
+    regexp_split_fast,
+);
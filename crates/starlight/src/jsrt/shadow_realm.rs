@@ -0,0 +1,346 @@
+use std::intrinsics::unlikely;
+use std::mem::ManuallyDrop;
+
+use crate::define_jsclass;
+use crate::jsrt::shadow_realm;
+use crate::prelude::*;
+use crate::vm::builder::Builtin;
+use crate::vm::class::JsClass;
+use crate::vm::context::Context;
+use crate::vm::function::{get_prototype_from_constructor, get_wrapped_value};
+use crate::vm::object::TypedJsObject;
+use crate::JsTryFrom;
+
+/// `ShadowRealm` instance. Owns its own context which is kept alive by the instance only.
+pub struct JsShadowRealm {
+    realm: GcPointer<Context>,
+}
+
+extern "C" fn fsz() -> usize {
+    std::mem::size_of::<JsShadowRealm>()
+}
+
+extern "C" fn ser(object: &JsObject, serializer: &mut SnapshotSerializer) {
+    object.data::<JsShadowRealm>().realm.serialize(serializer);
+}
+
+extern "C" fn deser(object: &mut JsObject, deser: &mut Deserializer) {
+    *object.data::<JsShadowRealm>() = ManuallyDrop::new(JsShadowRealm {
+        realm: unsafe { deser.read_gc() },
+    });
+}
+
+#[allow(improper_ctypes_definitions)]
+extern "C" fn trace(tracer: &mut dyn Tracer, obj: &mut JsObject) {
+    obj.data::<JsShadowRealm>().realm.trace(tracer);
+}
+
+impl JsClass for JsShadowRealm {
+    fn class() -> &'static Class {
+        define_jsclass!(
+            JsShadowRealm,
+            ShadowRealm,
+            None,
+            Some(trace),
+            Some(deser),
+            Some(ser),
+            Some(fsz)
+        )
+    }
+}
+
+pub fn shadow_realm_constructor(
+    ctx: GcPointer<Context>,
+    args: &Arguments,
+) -> Result<JsValue, JsValue> {
+    if unlikely(!args.ctor_call) {
+        return Err(JsValue::new(
+            ctx.new_type_error("ShadowRealm: Constructor requires 'new'"),
+        ));
+    }
+    // Native constructors get the constructor itself as newTarget (bound functions forward to their target), so
+    // newTarget is %ShadowRealm% of the realm this function belongs to.
+    let constructor = ctx.global_data().shadow_realm_constructor.unwrap();
+    let proto =
+        get_prototype_from_constructor(ctx, constructor, |data| data.shadow_realm_prototype)?;
+    let map = if GcPointer::ptr_eq(&proto, &ctx.global_data().shadow_realm_prototype.unwrap()) {
+        ctx.global_data().shadow_realm_structure.unwrap()
+    } else {
+        Structure::new_indexed(ctx, Some(proto), false)
+    };
+    let stack = ctx.shadowstack();
+    letroot!(map = stack, map);
+    let mut vm = ctx.vm();
+    let realm = vm.new_context();
+    // realm is reachable only through the ShadowRealm object
+    vm.remove_context(realm);
+    letroot!(realm = stack, realm);
+    let mut shadow_realm = JsObject::new(ctx, &map, JsShadowRealm::class(), ObjectTag::Ordinary);
+    *shadow_realm.data::<JsShadowRealm>() = ManuallyDrop::new(JsShadowRealm { realm: *realm });
+    Ok(JsValue::new(shadow_realm))
+}
+
+pub fn shadow_realm_prototype_evaluate(
+    ctx: GcPointer<Context>,
+    args: &Arguments,
+) -> Result<JsValue, JsValue> {
+    let shadow_realm = TypedJsObject::<JsShadowRealm>::try_from(ctx, args.this)?;
+    let source = args.at(0);
+    if unlikely(!source.is_jsstring()) {
+        return Err(JsValue::new(
+            ctx.new_type_error("ShadowRealm.prototype.evaluate: source text must be a string"),
+        ));
+    }
+    let source = source.to_string(ctx)?;
+    // parse errors are thrown in the caller realm
+    let (fm, script) = ctx.parse_script(&source)?;
+    let realm = shadow_realm.realm;
+    match realm.eval_parsed(&fm, &script) {
+        Ok(value) => get_wrapped_value(ctx, value),
+        Err(error) => {
+            let message = error
+                .to_string(realm)
+                .unwrap_or_else(|_| "unknown error".to_string());
+            Err(JsValue::new(ctx.new_type_error(format!(
+                "ShadowRealm.prototype.evaluate: {}",
+                message
+            ))))
+        }
+    }
+}
+
+pub fn shadow_realm_prototype_import_value(
+    ctx: GcPointer<Context>,
+    args: &Arguments,
+) -> Result<JsValue, JsValue> {
+    let shadow_realm = TypedJsObject::<JsShadowRealm>::try_from(ctx, args.this)?;
+    let specifier = args.at(0).to_string(ctx)?;
+    let export_name = args.at(1);
+    if unlikely(!export_name.is_jsstring()) {
+        return Err(JsValue::new(ctx.new_type_error(
+            "ShadowRealm.prototype.importValue: export name must be a string",
+        )));
+    }
+    let export_name = export_name.to_string(ctx)?;
+    let referrer = unsafe {
+        if ctx.stack.current.is_null() {
+            String::new()
+        } else {
+            (*ctx.stack.current)
+                .code_block
+                .map(|code_block| code_block.file_name.clone())
+                .unwrap_or_else(String::new)
+        }
+    };
+    let realm = shadow_realm.realm;
+    // module is loaded by the job of `import()` in the realm, its namespace is unwrapped in the caller realm.
+    let specifier = JsValue::new(JsString::new(realm, specifier));
+    let stack = ctx.shadowstack();
+    letroot!(import = stack, crate::jsrt::import_module(realm, specifier, &referrer)?);
+    let name = export_name.as_str().intern();
+    let on_resolved = JsClosureFunction::new(
+        ctx,
+        "importValue".intern(),
+        move |ctx, args| {
+            let mut namespace = args.at(0).get_jsobject();
+            if !namespace.has_own_property(realm, name) {
+                return Err(JsValue::new(ctx.new_type_error(format!(
+                    "ShadowRealm.prototype.importValue: module does not export '{}'",
+                    export_name
+                ))));
+            }
+            let value = namespace.get(realm, name)?;
+            get_wrapped_value(ctx, value)
+        },
+        1,
+    );
+    let on_rejected = JsClosureFunction::new(
+        ctx,
+        "importValue".intern(),
+        move |ctx, args| {
+            let message = args
+                .at(0)
+                .to_string(realm)
+                .unwrap_or_else(|_| "unknown error".to_string());
+            Err(JsValue::new(ctx.new_type_error(format!(
+                "ShadowRealm.prototype.importValue: {}",
+                message
+            ))))
+        },
+        1,
+    );
+    import.get_jsobject().as_promise_mut().then(
+        ctx,
+        *import,
+        Some(JsValue::new(on_resolved)),
+        Some(JsValue::new(on_rejected)),
+        None,
+    )
+}
+
+impl Builtin for JsShadowRealm {
+    fn init(mut ctx: GcPointer<Context>) -> Result<(), JsValue> {
+        let obj_proto = ctx.global_data().object_prototype.unwrap();
+        ctx.global_data.shadow_realm_structure = Some(Structure::new_indexed(ctx, None, false));
+        let proto_map = ctx
+            .global_data
+            .shadow_realm_structure
+            .unwrap()
+            .change_prototype_transition(ctx, Some(obj_proto));
+        let mut prototype = JsObject::new(ctx, &proto_map, JsObject::class(), ObjectTag::Ordinary);
+        ctx.global_data
+            .shadow_realm_structure
+            .unwrap()
+            .change_prototype_with_no_transition(prototype);
+
+        let mut constructor = JsNativeFunction::new(
+            ctx,
+            S_SHADOW_REALM.intern(),
+            shadow_realm::shadow_realm_constructor,
+            0,
+        );
+
+        def_native_property!(ctx, prototype, constructor, constructor, W | C)?;
+        def_native_property!(ctx, constructor, prototype, prototype, NONE)?;
+
+        def_native_method!(
+            ctx,
+            prototype,
+            evaluate,
+            shadow_realm::shadow_realm_prototype_evaluate,
+            1,
+            W | C
+        )?;
+        def_native_method!(
+            ctx,
+            prototype,
+            importValue,
+            shadow_realm::shadow_realm_prototype_import_value,
+            2,
+            W | C
+        )?;
+        let tag = JsString::new(ctx, S_SHADOW_REALM);
+        def_native_property!(
+            ctx,
+            prototype,
+            "Symbol.toStringTag".intern().private(),
+            tag,
+            C
+        )?;
+
+        ctx.global_data.shadow_realm_prototype = Some(prototype);
+        ctx.global_data.shadow_realm_constructor = Some(constructor);
+
+        let mut global_object = ctx.global_object();
+
+        def_native_property!(ctx, global_object, ShadowRealm, constructor, W | C)?;
+        Ok(())
+    }
+}
+
+register_native_references!(
+    shadow_realm_constructor,
+    shadow_realm_prototype_evaluate,
+    shadow_realm_prototype_import_value,
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::module_loader::{ModuleLoader, ModuleSource};
+    use crate::vm::tests::{eval_string, eval_with_jobs, test_runtime};
+
+    struct TestLoader;
+
+    impl ModuleLoader for TestLoader {
+        fn resolve(&mut self, specifier: &str, _referrer: &str) -> Result<String, String> {
+            match specifier {
+                "math" => Ok(specifier.to_string()),
+                _ => Err(format!("unknown module '{}'", specifier)),
+            }
+        }
+
+        fn load(&mut self, _key: &str) -> Result<ModuleSource, String> {
+            Ok(ModuleSource::Source(
+                "export function double(x) { return x * 2; } export let object = {};".to_string(),
+            ))
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        assert_eq!(
+            eval_string(
+                "let results = []; \
+                 let realm = new ShadowRealm(); \
+                 results.push(Object.getPrototypeOf(realm) === ShadowRealm.prototype); \
+                 results.push(realm.evaluate('globalThis.y = 20; y + 1'), typeof y); \
+                 results.push(realm.evaluate('(function (x) { return x + y; })')(22)); \
+                 try { realm.evaluate('({})'); } catch (e) { results.push(e instanceof TypeError); } \
+                 try { realm.evaluate('throw 1'); } catch (e) { results.push(e instanceof TypeError); } \
+                 try { realm.evaluate('('); } catch (e) { results.push(e instanceof SyntaxError); } \
+                 try { ShadowRealm(); } catch (e) { results.push(e instanceof TypeError); } \
+                 return results;"
+            ),
+            "true,21,undefined,42,true,true,true,true"
+        );
+    }
+
+    #[test]
+    fn test_constructor_prototype() {
+        assert_eq!(
+            eval_string(
+                "let Bound = ShadowRealm.bind(null); \
+                 return [Object.getPrototypeOf(new Bound()) === ShadowRealm.prototype, \
+                 Object.getPrototypeOf(new ShadowRealm()) === Object.getPrototypeOf(new ShadowRealm())];"
+            ),
+            "true,true"
+        );
+
+        let (mut runtime, _jobs) = test_runtime();
+        let mut ctx = Context::new(&mut runtime);
+        let object_prototype = ctx.global_data().object_prototype.unwrap();
+        let shadow_realm_prototype = ctx.global_data().shadow_realm_prototype.unwrap();
+        let constructor = ctx
+            .eval("return function () {};")
+            .unwrap_or_else(|_| panic!())
+            .get_jsobject();
+        // explicit %Object.prototype% is kept, only non-objects fall back to the default prototype.
+        let mut set_prototype = |value: JsValue| {
+            let mut constructor = constructor;
+            constructor
+                .put(ctx, "prototype".intern(), value, false)
+                .unwrap_or_else(|_| panic!());
+            get_prototype_from_constructor(ctx, constructor, |data| data.shadow_realm_prototype)
+                .unwrap_or_else(|_| panic!())
+        };
+        let proto = set_prototype(JsValue::new(object_prototype));
+        assert!(GcPointer::ptr_eq(&proto, &object_prototype));
+        let proto = set_prototype(JsValue::encode_f64_value(1.0));
+        assert!(GcPointer::ptr_eq(&proto, &shadow_realm_prototype));
+    }
+
+    #[test]
+    fn test_import_value() {
+        let (mut runtime, jobs) = test_runtime();
+        runtime.set_module_loader(Some(Box::new(TestLoader)));
+        assert_eq!(
+            eval_with_jobs(
+                &mut runtime,
+                &jobs,
+                "let results = []; \
+                 let realm = new ShadowRealm(); \
+                 let fail = function (name) { \
+                 return function (e) { results.push(name + ':' + (e instanceof TypeError)); }; }; \
+                 let promise = realm.importValue('math', 'double'); \
+                 results.push(promise instanceof Promise); \
+                 promise.then(function (double) { results.push('double:' + double(21)); }); \
+                 realm.importValue('math', 'object').catch(fail('object')); \
+                 realm.importValue('math', 'missing').catch(fail('missing')); \
+                 realm.importValue('unknown', 'x').catch(fail('unknown')); \
+                 return results;"
+            ),
+            "true,unknown:true,double:42,object:true,missing:true"
+        );
+    }
+}
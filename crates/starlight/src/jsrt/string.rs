@@ -732,3 +732,31 @@ pub fn string_pad_end(ctx: GcPointer<Context>, args: &Arguments) -> Result<JsVal
 pub fn string_pad_start(ctx: GcPointer<Context>, args: &Arguments) -> Result<JsValue, JsValue> {
     string_pad(ctx, args, Alignment::Stactx)
 }
+
+register_native_references!(
+    string_concat,
+    string_trim,
+    string_trim_start,
+    string_trim_end,
+    string_pad_start,
+    string_pad_end,
+    string_split,
+    string_constructor,
+    string_to_string,
+    string_index_of,
+    string_last_index_of,
+    string_substr,
+    string_substring,
+    string_replace,
+    string_value_of,
+    string_char_at,
+    string_char_code_at,
+    string_code_point_at,
+    string_starts_with,
+    string_ends_with,
+    string_repeat,
+    string_to_lowercase,
+    string_to_uppercase,
+    string_includes,
+    string_slice,
+);
@@ -111,3 +111,11 @@ pub fn symbol_value_of(_ctx: GcPointer<Context>, args: &Arguments) -> Result<JsV
         TypedJsObject::<JsSymbolObject>::try_from(_ctx, args.this)?.symbol(),
     ))
 }
+
+register_native_references!(
+    symbol_ctor,
+    symbol_for,
+    symbol_key_for,
+    symbol_to_string,
+    symbol_value_of,
+);
@@ -106,3 +106,5 @@ impl Builtin for JsWeakRef {
         Ok(())
     }
 }
+
+register_native_references!(weak_ref_constructor, weak_ref_prototype_deref);
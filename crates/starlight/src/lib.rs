@@ -4,7 +4,6 @@
 #![allow(incomplete_features)]
 #![feature(
     core_intrinsics,
-    llvm_asm,
     linked_list_cursors,
     destructuring_assignment,
     const_raw_ptr_deref,
@@ -33,9 +32,34 @@
     clippy::needless_range_loop
 )]
 
+// lets code generated by `starlight_derive` refer to `::starlight` inside this crate too.
+extern crate self as starlight;
+
 use gc::{cell::GcPointer, snapshot::deserializer::Deserializer};
 use std::sync::atomic::AtomicBool;
 use vm::{arguments::Arguments, object::JsObject, value::JsValue, VirtualMachineRef};
+#[doc(hidden)]
+pub use ctor as __ctor;
+
+/// Register native functions, deserializers and other addresses that can be stored in snapshots. References are
+/// registered at startup under `module_path!()` and their expression, e.g. `starlight::jsrt::array::array_push`.
+#[macro_export]
+macro_rules! register_native_references {
+    ($($reference: expr),* $(,)?) => {
+        const _: () = {
+            #[$crate::__ctor::ctor]
+            fn register_native_references() {
+                $(
+                    $crate::gc::snapshot::references::register_native_reference(
+                        concat!(module_path!(), "::", stringify!($reference)),
+                        $reference as usize,
+                    );
+                )*
+            }
+        };
+    };
+}
+
 #[macro_export]
 macro_rules! def_native_method {
     ($vm: expr,$obj: expr,$name: ident,$func: expr,$argc: expr) => {{
@@ -172,6 +196,7 @@ macro_rules! as_atomic {
 pub mod utils;
 #[macro_use]
 pub mod gc;
+pub mod bundle;
 pub mod bytecode;
 pub mod bytecompiler;
 pub mod codegen;
@@ -224,8 +249,50 @@ pub unsafe extern "C" fn __execute_bundle(array: *const u8, size: usize) {
         |deser, _rt| {
             function = Some(GcPointer::<JsObject>::deserialize_inplace(deser));
         },
-    );
-    let mut ctx = Context::new(&mut vm);
+    )
+    .unwrap_or_else(|error| panic!("Failed to load bundle: {}", error));
+    let ctx = Context::new(&mut vm);
+    run_bundle(vm, ctx, function);
+}
+
+/// Run bundle that was written as a delta on top of base snapshot at `base` path.
+#[no_mangle]
+#[doc(hidden)]
+pub unsafe extern "C" fn __execute_layered_bundle(
+    base: *const std::os::raw::c_char,
+    array: *const u8,
+    size: usize,
+) {
+    let mut function = None;
+
+    let base = std::ffi::CStr::from_ptr(base)
+        .to_string_lossy()
+        .into_owned();
+    let data = gc::snapshot::base::SnapshotData::map(&base)
+        .unwrap_or_else(|error| panic!("Failed to map base snapshot {}: {}", base, error));
+    let options = Options::default();
+    let gc = gc::default_heap(&options);
+    let mut vm = Deserializer::deserialize_with_delta(
+        false,
+        data,
+        std::slice::from_raw_parts(array, size),
+        options,
+        gc,
+        None,
+        |deser, _rt| {
+            function = Some(GcPointer::<JsObject>::deserialize_inplace(deser));
+        },
+    )
+    .unwrap_or_else(|error| panic!("Failed to load bundle: {}", error));
+    let ctx = vm.context(0);
+    run_bundle(vm, ctx, function);
+}
+
+unsafe fn run_bundle(
+    vm: VirtualMachineRef,
+    mut ctx: GcPointer<Context>,
+    function: Option<GcPointer<JsObject>>,
+) {
     let stack = vm.shadowstack();
 
     letroot!(function = stack, function.expect("No function"));
@@ -261,8 +328,8 @@ pub unsafe extern "C" fn __execute_bundle(array: *const u8, size: usize) {
 
 pub mod prelude {
     pub use super::gc::{
-        cell::*, snapshot::deserializer::*, snapshot::serializer::*, snapshot::Snapshot, Heap,
-        MarkingConstraint, SimpleMarkingConstraint,
+        cell::*, snapshot::base::SnapshotData, snapshot::deserializer::*, snapshot::serializer::*,
+        snapshot::Snapshot, Heap, MarkingConstraint, SimpleMarkingConstraint,
     };
     pub use super::letroot;
     pub use super::options::Options;
@@ -272,8 +339,10 @@ pub mod prelude {
         array::JsArray,
         attributes::*,
         class::{Class, JsClass},
+        convert::{FromJs, IntoJs},
         error::*,
         function::*,
+        handle_scope::HandleScope,
         method_table::MethodTable,
         object::{EnumerationMode, JsHint, JsObject, ObjectTag},
         property_descriptor::*,
@@ -288,6 +357,7 @@ pub mod prelude {
     pub use crate::constant::*;
     pub use crate::define_additional_size;
     pub use crate::js_method_table;
+    pub use starlight_derive::{js_class, js_impl, GcTrace};
 }
 
 pub trait JsTryFrom<T>: Sized {
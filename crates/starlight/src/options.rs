@@ -2,7 +2,7 @@ use std::{num::ParseIntError, path::PathBuf};
 
 use structopt::StructOpt;
 
-#[derive(StructOpt, Debug)]
+#[derive(StructOpt, Debug, Clone)]
 pub struct Options {
     #[structopt(
         long = "sizeClassProgression",
@@ -35,6 +35,11 @@ pub struct Options {
 
     #[structopt(long = "enable-ffi", help = "Enable FFI and CFunction objects for use")]
     pub enable_ffi: bool,
+    #[structopt(
+        long = "commonjs",
+        help = "Enable CommonJS require and Node module resolution"
+    )]
+    pub commonjs: bool,
     #[structopt(
         long = "dumpStats",
         help = "Dump various statistics at the end of execution"
@@ -44,6 +49,18 @@ pub struct Options {
     pub codegen_plugins: bool,
     #[structopt(long = "verboseGC", help = "Verbose GC cycle")]
     pub verbose_gc: bool,
+    #[structopt(
+        long = "eagerCompilation",
+        help = "Compile all functions at load time instead of on first call"
+    )]
+    pub eager_compilation: bool,
+    #[structopt(long = "codeCache", help = "Cache compiled bytecode next to scripts")]
+    pub code_cache: bool,
+    #[structopt(
+        long = "stripSource",
+        help = "Do not keep source text of functions, implies eager compilation"
+    )]
+    pub strip_source: bool,
 }
 
 impl Default for Options {
@@ -55,12 +72,16 @@ impl Default for Options {
             dump_size_classes: false,
             dump_stats: false,
             enable_ffi: false,
+            commonjs: false,
             size_class_progression: 1.4,
             heap_size: 2 * 1024 * 1024 * 1024,
             file: PathBuf::new(),
             gc_threads: 4,
             verbose_gc: false,
             codegen_plugins: false,
+            eager_compilation: false,
+            code_cache: false,
+            strip_source: false,
         }
     }
 }
@@ -112,10 +133,30 @@ impl Options {
         self
     }
 
+    pub fn with_commonjs(mut self, enable: bool) -> Self {
+        self.commonjs = enable;
+        self
+    }
+
     pub fn with_dump_stats(mut self, enable: bool) -> Self {
         self.dump_stats = enable;
         self
     }
+
+    pub fn with_eager_compilation(mut self, enable: bool) -> Self {
+        self.eager_compilation = enable;
+        self
+    }
+
+    pub fn with_code_cache(mut self, enable: bool) -> Self {
+        self.code_cache = enable;
+        self
+    }
+
+    pub fn with_strip_source(mut self, enable: bool) -> Self {
+        self.strip_source = enable;
+        self
+    }
 }
 
 fn parse_size_from_str(s: &str) -> Result<usize, ParseIntError> {
@@ -2,9 +2,11 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 use self::{
-    attributes::*, context::Context, object::JsObject, structure::Structure, symbol_table::Symbol,
+    attributes::*, context::Context, module_loader::ModuleLoader, object::JsObject,
+    structure::Structure, symbol_table::Symbol,
 };
 use crate::{
+    bytecode::inline_cache::{InlineCacheStats, MegamorphicCache},
     bytecompiler::{ByteCompiler, CompileError},
     gc::default_heap,
     gc::shadowstack::ShadowStack,
@@ -15,11 +17,14 @@ use crate::{
         cell::{GcCell, GcPointerBase, Tracer},
         SimpleMarkingConstraint,
     },
-    gc::{safepoint::GlobalSafepoint, snapshot::Snapshot},
+    gc::{
+        safepoint::GlobalSafepoint,
+        snapshot::{base::BaseSnapshot, code_cache::CodeCache, Snapshot},
+    },
     options::Options,
 };
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     ops::{Deref, DerefMut},
     u32, u8, usize,
 };
@@ -48,14 +53,18 @@ pub mod builder;
 pub mod builtins;
 pub mod code_block;
 pub mod context;
+pub mod convert;
 pub mod data_view;
 pub mod environment;
 pub mod error;
 pub mod function;
 pub mod global;
+pub mod handle_scope;
 pub mod indexed_elements;
 pub mod interpreter;
 pub mod map;
+pub mod module;
+pub mod module_loader;
 pub mod native_iterator;
 pub mod number;
 pub mod object;
@@ -63,6 +72,8 @@ pub mod operations;
 pub mod perf;
 pub mod property_descriptor;
 pub mod slot;
+pub mod source_map;
+pub mod stack_trace;
 pub mod string;
 pub mod structure;
 pub mod structure_builder;
@@ -80,6 +91,8 @@ pub mod promise;
 pub enum ModuleKind {
     Initialized(GcPointer<JsObject>),
     NativeUninit(fn(GcPointer<Context>, GcPointer<JsObject>) -> Result<(), JsValue>),
+    /// Module loaded by the module loader.
+    Record(GcPointer<module::ModuleRecord>),
 }
 impl GcCell for ModuleKind {
     fn deser_pair(&self) -> (usize, usize) {
@@ -88,8 +101,10 @@ impl GcCell for ModuleKind {
 }
 unsafe impl Trace for ModuleKind {
     fn trace(&mut self, visitor: &mut dyn Tracer) {
-        if let Self::Initialized(x) = self {
-            x.trace(visitor)
+        match self {
+            Self::Initialized(x) => x.trace(visitor),
+            Self::Record(x) => x.trace(visitor),
+            Self::NativeUninit(_) => {}
         }
     }
 }
@@ -105,6 +120,10 @@ impl Serializable for ModuleKind {
                 serializer.write_u8(0x1);
                 serializer.write_reference((*x) as *const u8);
             }
+            Self::Record(x) => {
+                serializer.write_u8(0x2);
+                x.serialize(serializer);
+            }
         }
     }
 }
@@ -119,6 +138,9 @@ impl Deserializable for ModuleKind {
         match byte {
             0x0 => ModuleKind::Initialized(GcPointer::<JsObject>::deserialize_inplace(deser)),
             0x1 => ModuleKind::NativeUninit(std::mem::transmute(deser.get_reference())),
+            0x2 => ModuleKind::Record(GcPointer::<module::ModuleRecord>::deserialize_inplace(
+                deser,
+            )),
             _ => unreachable!(),
         }
     }
@@ -152,12 +174,27 @@ pub struct VirtualMachine {
     /// String that contains all the source code passed to [VirtualMachine::eval] and [VirtualMachine::evalm]
     pub(crate) eval_history: String,
     pub(crate) persistent_roots: Rc<RefCell<HashMap<usize, JsValue>>>,
+    /// Values rooted by live [HandleScope](handle_scope::HandleScope)s, innermost scope last.
+    pub(crate) handle_scopes: Vec<Vec<JsValue>>,
     pub(crate) sched_async_func: Option<Box<dyn Fn(Box<dyn FnOnce(GcPointer<Context>)>)>>,
     pub(crate) safepoint: GlobalSafepoint,
 
     pub(crate) contexts: Vec<GcPointer<Context>>,
 
     pub(crate) context_snapshot: Rc<Box<[u8]>>,
+    /// Global cache used by megamorphic property access sites.
+    pub(crate) megamorphic_cache: MegamorphicCache,
+    pub(crate) ic_stats: InlineCacheStats,
+    /// Bytecode cache used when compiling modules.
+    pub(crate) code_cache: Option<Box<dyn CodeCache>>,
+    /// Module loader of contexts that don't have their own.
+    pub(crate) module_loader: Option<Box<dyn ModuleLoader>>,
+    /// Source maps of loaded scripts keyed by [source_map::file_key] of the script.
+    pub(crate) source_maps: HashMap<String, source_map::SourceMap>,
+    /// Keys in `source_maps` of script file names source maps were registered for.
+    pub(crate) source_map_keys: HashMap<String, String>,
+    /// Base snapshot this VM was loaded from, delta snapshots are taken on top of it.
+    pub(crate) base_snapshot: Option<Rc<BaseSnapshot>>,
 }
 
 impl VirtualMachine {
@@ -209,6 +246,40 @@ impl VirtualMachine {
         &self.options
     }
 
+    /// Set bytecode cache used by [Context::compile_module](context::Context::compile_module). Pass `None` to disable caching.
+    pub fn set_code_cache(&mut self, cache: Option<Box<dyn CodeCache>>) {
+        self.code_cache = cache;
+    }
+
+    /// Set loader of modules imported in contexts of this VM. Pass `None` to load module files.
+    pub fn set_module_loader(&mut self, loader: Option<Box<dyn ModuleLoader>>) {
+        self.module_loader = loader;
+    }
+
+    /// Register source map for script `file`. Locations in stack traces of this script are reported in original sources.
+    pub fn add_source_map(&mut self, file: &str, map: source_map::SourceMap) {
+        // path is canonicalized once here, lookups from stack traces use the name script was registered with.
+        let key = source_map::file_key(file);
+        self.source_map_keys.insert(file.to_owned(), key.clone());
+        self.source_maps.insert(key, map);
+    }
+
+    /// Source map registered for script `file`.
+    pub fn source_map(&self, file: &str) -> Option<&source_map::SourceMap> {
+        self.source_maps.get(self.source_map_keys.get(file)?)
+    }
+
+    /// Base snapshot this VM was loaded from with
+    /// [Deserializer::deserialize_base](crate::gc::snapshot::deserializer::Deserializer::deserialize_base).
+    pub fn base_snapshot(&self) -> Option<&BaseSnapshot> {
+        self.base_snapshot.as_deref()
+    }
+
+    /// Inline cache counters collected so far.
+    pub fn ic_stats(&self) -> &InlineCacheStats {
+        &self.ic_stats
+    }
+
     pub fn new_raw(
         gc: Heap,
         options: Options,
@@ -224,10 +295,18 @@ impl VirtualMachine {
             perf: perf::Perf::new(),
             eval_history: String::new(),
             persistent_roots: Default::default(),
+            handle_scopes: vec![],
             sched_async_func: None,
             codegen_plugins: HashMap::new(),
             contexts: vec![],
             context_snapshot: Rc::new(Box::new([])),
+            megamorphic_cache: MegamorphicCache::new(),
+            ic_stats: InlineCacheStats::default(),
+            code_cache: None,
+            module_loader: None,
+            source_maps: HashMap::new(),
+            source_map_keys: HashMap::new(),
+            base_snapshot: None,
         })))
     }
 
@@ -272,12 +351,18 @@ impl VirtualMachine {
             "Mark VM roots",
             move |visitor| {
                 let vm = unsafe { &mut *vm };
+                // megamorphic cache entries are not traced, flush them so no stale structure survives GC.
+                if !vm.megamorphic_cache.is_empty() {
+                    vm.megamorphic_cache.invalidate();
+                    vm.ic_stats.invalidations += 1;
+                }
                 // vm.shadowstack.trace(visitor);
                 vm.contexts.iter_mut().for_each(|ctx| ctx.trace(visitor));
                 let pr = &mut *vm.persistent_roots.borrow_mut();
                 pr.iter_mut().for_each(|entry| {
                     entry.1.trace(visitor);
                 });
+                vm.handle_scopes.trace(visitor);
             },
         ));
     }
@@ -346,6 +431,7 @@ impl VirtualMachine {
         } else {
             let snapshot = self.context_snapshot.clone();
             Deserializer::deserialize_context(self, false, &snapshot)
+                .expect("context snapshot is taken by this process")
         }
     }
 }
@@ -368,6 +454,32 @@ impl Drop for PersistentRooted {
     }
 }
 
+/// Jobs scheduled by promises and `import()` for embedders without an event loop. Install [JobQueue::scheduler]
+/// with [VirtualMachine::with_async_scheduler] and call [JobQueue::run] after running a script.
+#[derive(Clone, Default)]
+pub struct JobQueue {
+    jobs: Rc<RefCell<VecDeque<Box<dyn FnOnce(GcPointer<Context>)>>>>,
+}
+
+impl JobQueue {
+    /// Scheduler that adds jobs to the queue.
+    pub fn scheduler(&self) -> Box<dyn Fn(Box<dyn FnOnce(GcPointer<Context>)>)> {
+        let jobs = self.jobs.clone();
+        Box::new(move |job| jobs.borrow_mut().push_back(job))
+    }
+
+    /// Run jobs in `ctx` until the queue is empty, jobs scheduled by running jobs are run too.
+    pub fn run(&self, ctx: GcPointer<Context>) {
+        loop {
+            let job = self.jobs.borrow_mut().pop_front();
+            match job {
+                Some(job) => job(ctx),
+                None => break,
+            }
+        }
+    }
+}
+
 use starlight_derive::GcTrace;
 use wtf_rs::unwrap_unchecked;
 
@@ -421,6 +533,10 @@ pub struct GlobalData {
     pub(crate) spread_builtin: Option<GcPointer<JsObject>>,
     pub(crate) weak_ref_structure: Option<GcPointer<Structure>>,
     pub(crate) weak_ref_prototype: Option<GcPointer<JsObject>>,
+    pub(crate) shadow_realm_structure: Option<GcPointer<Structure>>,
+    pub(crate) shadow_realm_prototype: Option<GcPointer<JsObject>>,
+    pub(crate) shadow_realm_constructor: Option<GcPointer<JsObject>>,
+    pub(crate) generator_function_structure: Option<GcPointer<Structure>>,
     pub(crate) symbol_structure: Option<GcPointer<Structure>>,
     pub(crate) date_structure: Option<GcPointer<Structure>>,
     pub(crate) date_prototype: Option<GcPointer<JsObject>>,
@@ -509,6 +625,9 @@ impl Drop for VirtualMachine {
         {
             self.perf.print_perf();
         }
+        if self.options.dump_stats {
+            self.ic_stats.print_stats();
+        }
     }
 }
 
@@ -543,20 +662,56 @@ pub fn parse(script: &str, strict_mode: bool) -> Result<Program, Error> {
 pub(crate) fn init_es_config() -> EsConfig {
     let mut es_config: EsConfig = Default::default();
     es_config.dynamic_import = true;
+    es_config.top_level_await = true;
     es_config
 }
 
 #[cfg(test)]
 pub mod tests {
     use crate::gc::cell::GcPointer;
+    use crate::gc::snapshot::{deserializer::Deserializer, Snapshot};
     use crate::options::Options;
     use crate::vm::symbol_table::Internable;
     use crate::vm::value::JsValue;
-    use crate::vm::{arguments, context::Context, VirtualMachine};
+    use crate::vm::{arguments, context::Context, JobQueue, VirtualMachine, VirtualMachineRef};
     use crate::Platform;
     use std::cell::RefCell;
     use std::rc::Rc;
 
+    /// Runtime with default options whose promise jobs are queued in the returned [JobQueue].
+    pub(crate) fn test_runtime() -> (VirtualMachineRef, JobQueue) {
+        Platform::initialize();
+        let jobs = JobQueue::default();
+        let runtime =
+            Platform::new_runtime(Options::default(), None).with_async_scheduler(jobs.scheduler());
+        (runtime, jobs)
+    }
+
+    /// Evaluate `source` in a new context of `runtime`, run queued jobs and return completion value converted to
+    /// string. Conversion happens after the jobs, so returned arrays include values pushed by them. Panics with
+    /// the message of an uncaught error.
+    pub(crate) fn eval_with_jobs(
+        runtime: &mut VirtualMachineRef,
+        jobs: &JobQueue,
+        source: &str,
+    ) -> String {
+        let mut ctx = Context::new(runtime);
+        let result = ctx.eval(source).map(|value| {
+            jobs.run(ctx);
+            value
+        });
+        match result.and_then(|value| value.to_string(ctx)) {
+            Ok(value) => value,
+            Err(error) => panic!("{}", error.to_string(ctx).unwrap_or_default()),
+        }
+    }
+
+    /// [eval_with_jobs] on a fresh [test_runtime].
+    pub(crate) fn eval_string(source: &str) -> String {
+        let (mut runtime, jobs) = test_runtime();
+        eval_with_jobs(&mut runtime, &jobs, source)
+    }
+
     #[test]
     fn test_simple_async() {
         // start a runtime
@@ -714,6 +869,100 @@ pub mod tests {
         assert!(result.is_err(), "Should return JsValue error");
         //
     }
+
+    fn eval_in(mut ctx: GcPointer<Context>, source: &str) -> JsValue {
+        match ctx.eval(source) {
+            Ok(value) => value,
+            Err(error) => panic!("{}", error.to_string(ctx).unwrap_or_default()),
+        }
+    }
+
+    #[test]
+    fn test_cross_realm() {
+        Platform::initialize();
+        let mut vm = Platform::new_runtime(Options::default(), None);
+        let a = vm.new_context();
+        // second context is deserialized from snapshot of the first one.
+        let mut b = vm.new_context();
+        let exports = eval_in(
+            a,
+            "globalThis.x = 'a'; \
+             let F = function () { this.tag = x; }; F.prototype = 1; \
+             let G = function () {}; \
+             return { F: F, G: G, getX: function () { return x; }, \
+             makeArray: function () { return []; }, objectProto: Object.prototype, Array: Array };",
+        );
+        b.global_object()
+            .put(b, "a".intern(), exports, false)
+            .unwrap_or_else(|_| panic!());
+        let result = eval_in(
+            b,
+            "globalThis.x = 'b'; \
+             let f = new a.F(); \
+             let g = new a.G(); \
+             return [a.getX(), f.tag, \
+             Object.getPrototypeOf(f) === a.objectProto, Object.getPrototypeOf(f) !== Object.prototype, \
+             Object.getPrototypeOf(g) === a.G.prototype, \
+             a.Array !== Array, a.makeArray() instanceof a.Array, !(a.makeArray() instanceof Array)].join();",
+        );
+        assert_eq!(
+            result.to_string(b).unwrap_or_else(|_| panic!()),
+            "a,a,true,true,true,true,true,true"
+        );
+    }
+
+    #[test]
+    fn test_multi_realm_snapshot() {
+        Platform::initialize();
+        let mut vm = Platform::new_runtime(Options::default(), None);
+        let a = vm.new_context();
+        let mut b = vm.new_context();
+        // `c` is only reachable from heap of `b` once it is removed from the VM.
+        let c = vm.new_context();
+        let exports = eval_in(
+            a,
+            "globalThis.x = 'a'; \
+             var F = function () { this.tag = x; }; F.prototype = 1; \
+             return { F: F, getX: function () { return x; }, objectProto: Object.prototype, Array: Array };",
+        );
+        b.global_object()
+            .put(b, "a".intern(), exports, false)
+            .unwrap_or_else(|_| panic!());
+        let get_c = eval_in(c, "globalThis.x = 'c'; return function () { return x; };");
+        b.global_object()
+            .put(b, "getC".intern(), get_c, false)
+            .unwrap_or_else(|_| panic!());
+        eval_in(b, "globalThis.x = 'b';");
+        vm.remove_context(c);
+
+        let snapshot = Snapshot::take(false, &mut vm, |_, _| {});
+        let mut vm = Deserializer::deserialize(
+            false,
+            &snapshot.buffer,
+            Options::default(),
+            default_heap(&Options::default()),
+            None,
+            |_, _| {},
+        )
+        .unwrap_or_else(|_| panic!());
+        assert_eq!(vm.contexts.len(), 2);
+        let a = vm.context(0);
+        let b = vm.context(1);
+        assert_eq!(
+            eval_in(a, "return x;").to_string(a).unwrap_or_default(),
+            "a"
+        );
+        let result = eval_in(
+            b,
+            "let f = new a.F(); \
+             return [x, a.getX(), getC(), f.tag, Object.getPrototypeOf(f) === a.objectProto, \
+             a.objectProto !== Object.prototype, a.Array !== Array, [] instanceof Array].join();",
+        );
+        assert_eq!(
+            result.to_string(b).unwrap_or_default(),
+            "b,a,c,a,true,true,true,true"
+        );
+    }
 }
 
 pub type VM = VirtualMachineRef;
@@ -4,18 +4,51 @@ use std::{
     intrinsics::unlikely,
     mem::{size_of, ManuallyDrop},
     ptr::null_mut,
+    sync::Arc,
 };
 
 use super::{class::JsClass, object::TypedJsObject, Context};
 pub struct JsArrayBuffer {
     pub(crate) data: *mut u8,
     pub(crate) attached: bool,
+    /// Data block shared with buffers of other agents. `data` points into it and is freed by the block once no
+    /// buffer uses it.
+    pub(crate) shared: Option<Arc<SharedDataBlock>>,
+}
+
+/// Data block of an array buffer shared between agents running on different threads. Memory is freed when the
+/// last reference to the block is dropped.
+pub struct SharedDataBlock {
+    data: *mut u8,
+    size: usize,
+}
+
+// Block is only a piece of memory, agents synchronize access to its contents themselves.
+unsafe impl Send for SharedDataBlock {}
+unsafe impl Sync for SharedDataBlock {}
+
+impl SharedDataBlock {
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+impl Drop for SharedDataBlock {
+    fn drop(&mut self) {
+        unsafe {
+            libc::free(self.data.cast());
+        }
+    }
 }
 
 extern "C" fn drop_array_buffer(x: GcPointer<JsObject>) {
     unsafe {
-        TypedJsObject::<JsArrayBuffer>::new(x).detach();
-        ManuallyDrop::drop(x.data::<JsArrayBuffer>());
+        // slots of the object may be swept already, so only the data block is released.
+        let data = x.data::<JsArrayBuffer>();
+        if data.shared.is_none() && !data.data.is_null() {
+            libc::free(data.data.cast());
+        }
+        ManuallyDrop::drop(data);
     }
 }
 
@@ -48,6 +81,7 @@ extern "C" fn array_buffer_deserialize(x: &mut JsObject, deser: &mut Deserialize
         *x.data::<JsArrayBuffer>() = ManuallyDrop::new(JsArrayBuffer {
             attached,
             data: buf,
+            shared: None,
         })
     }
 }
@@ -82,12 +116,26 @@ impl JsArrayBuffer {
         *this.data::<Self>() = ManuallyDrop::new(Self {
             data: null_mut(),
             attached: false,
+            shared: None,
         });
 
         *this.direct_mut(Self::BYTE_LENGTH_OFFSET) = JsValue::new(0u32);
         this
     }
 
+    /// Create buffer that uses shared data `block`. The block stays alive for as long as the buffer is attached.
+    pub fn new_shared(ctx: GcPointer<Context>, block: Arc<SharedDataBlock>) -> GcPointer<JsObject> {
+        let mut this = Self::new(ctx);
+        let size = block.size;
+        *this.data::<Self>() = ManuallyDrop::new(Self {
+            data: block.data,
+            attached: true,
+            shared: Some(block),
+        });
+        *this.direct_mut(Self::BYTE_LENGTH_OFFSET) = JsValue::new(size as u32);
+        this
+    }
+
     pub fn attached(&self) -> bool {
         self.attached
     }
@@ -134,12 +182,31 @@ impl TypedJsObject<JsArrayBuffer> {
     pub fn detach(&mut self) {
         if !self.data.is_null() {
             unsafe {
-                libc::free(self.data.cast());
+                if self.shared.is_none() {
+                    libc::free(self.data.cast());
+                }
                 self.data = null_mut();
                 self.set_size(0);
             }
         }
         self.attached = false;
+        self.shared = None;
+    }
+
+    /// Data block of the buffer as a block that can be shared with other agents, see
+    /// [JsArrayBuffer::new_shared]. Buffer itself starts to use the shared block. Returns `None` if the buffer is
+    /// detached.
+    pub fn share(&mut self) -> Option<Arc<SharedDataBlock>> {
+        if !self.attached {
+            return None;
+        }
+        if self.shared.is_none() {
+            self.shared = Some(Arc::new(SharedDataBlock {
+                data: self.data,
+                size: self.size(),
+            }));
+        }
+        self.shared.clone()
     }
     pub unsafe fn set_size(&mut self, size: usize) {
         *self.object().direct_mut(JsArrayBuffer::BYTE_LENGTH_OFFSET) = JsValue::new(size as u32);
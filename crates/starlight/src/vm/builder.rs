@@ -27,9 +27,6 @@ pub trait Builtin {
     fn init(mut _ctx: GcPointer<Context>) -> Result<(), JsValue> {
         todo!();
     }
-    fn native_references() -> Vec<usize> {
-        vec![]
-    }
 }
 
 pub trait ClassConstructor {
@@ -44,16 +41,21 @@ pub trait ClassConstructor {
 }
 
 default impl<T: JsClass> ClassConstructor for T {
-    fn raw_constructor(mut ctx: GcPointer<Context>, args: &Arguments) -> Result<JsValue, JsValue> {
-        let name = Self::class().name.into();
-        let structure = ctx.get_structure(name).unwrap();
-        let object = JsObject::new(ctx, &structure, Self::class(), ObjectTag::Ordinary);
+    fn raw_constructor(ctx: GcPointer<Context>, args: &Arguments) -> Result<JsValue, JsValue> {
         let data = Self::constructor(ctx, args)?;
-        *object.data::<Self>() = ManuallyDrop::new(data);
-        Ok(object.into())
+        Ok(new_instance(ctx, data).into())
     }
 }
 
+/// Create object of class `T` holding `data`. `T` must be registered with `Context::register_class`.
+pub fn new_instance<T: JsClass>(mut ctx: GcPointer<Context>, data: T) -> GcPointer<JsObject> {
+    let name = T::class().name.into();
+    let structure = ctx.get_structure(name).unwrap();
+    let object = JsObject::new(ctx, &structure, T::class(), ObjectTag::Ordinary);
+    *object.data::<T>() = ManuallyDrop::new(data);
+    object
+}
+
 pub struct ObjectBuilder {
     context: GcPointer<Context>,
     object: GcPointer<JsObject>
@@ -277,7 +279,7 @@ impl ClassBuilder {
         setter: V,
         attribute: Raw,
     ) -> Result<&mut Self, JsValue> {
-        def_native_getter!(
+        def_native_setter!(
             self.context,
             self.constructor,
             name.into(),
@@ -9,7 +9,8 @@
 //!
 
 use super::{
-    arguments::*, array::*, error::*, interpreter::frame::CallFrame, string::*, symbol_table::*,
+    arguments::*, array::*, error::*, interpreter::frame::CallFrame, object::*, string::*,
+    symbol_table::*,
 };
 use super::{value::*, Context};
 use crate::gc::cell::GcPointer;
@@ -86,9 +87,50 @@ pub unsafe fn to_object(
     Ok(())
 }
 
+/// `CopyDataProperties` of object rest pattern. Pops array of excluded keys and source value, pushes new object
+/// with own enumerable properties of source that are not excluded.
+pub unsafe fn copy_data_properties(
+    ctx: GcPointer<Context>,
+    frame: &mut CallFrame,
+    _ip: &mut *mut u8,
+    _argc: u32,
+    _effect: u8,
+) -> Result<(), JsValue> {
+    let gcstack = ctx.shadowstack();
+    let excluded = frame.pop();
+    let source = frame.pop();
+    if source.is_null() || source.is_undefined() {
+        return Err(JsValue::new(
+            ctx.new_type_error("Cannot destructure 'undefined' or 'null'"),
+        ));
+    }
+    letroot!(source = gcstack, source.to_object(ctx)?);
+    letroot!(excluded = gcstack, excluded.get_jsobject());
+    let mut skip = vec![];
+    for i in 0..get_length(ctx, &mut excluded)? {
+        skip.push(excluded.get(ctx, Symbol::Index(i))?.to_symbol(ctx)?);
+    }
+    let mut names = vec![];
+    source.get_own_property_names(
+        ctx,
+        &mut |name, _| names.push(name),
+        EnumerationMode::Default,
+    );
+    letroot!(target = gcstack, JsObject::new_empty(ctx));
+    for name in names {
+        if skip.contains(&name) {
+            continue;
+        }
+        let value = source.get(ctx, name)?;
+        target.put(ctx, name, value, false)?;
+    }
+    frame.push(JsValue::new(*target));
+    Ok(())
+}
+
 pub type Builtin =
     unsafe fn(GcPointer<Context>, &mut CallFrame, &mut *mut u8, u32, u8) -> Result<(), JsValue>;
 
-pub static BUILTIN_FUNCS: [Builtin; 1] = [reflect_apply];
+pub static BUILTIN_FUNCS: [Builtin; 2] = [reflect_apply, copy_data_properties];
 
-pub const BUILTIN_ARGS: [usize; 1] = [3];
+pub const BUILTIN_ARGS: [usize; 2] = [3, 2];
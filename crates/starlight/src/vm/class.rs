@@ -33,6 +33,7 @@ pub struct Class {
 /// Define JS class. `$class` is type that will be passed to JS, $name` is class name, and `$sym` is internal class type.
 /// There's second macro arm that is used to pass additional methods to class.
 #[macro_export]
+/// Classes are registered as native references so objects using them can be stored in snapshots.
 macro_rules! define_jsclass {
     ($class: ident, $name: ident) => {{
         define_additional_size!($class);
@@ -45,6 +46,7 @@ macro_rules! define_jsclass {
             deserialize: None,
             additional_size: Some(additional_size),
         };
+        $crate::register_class_reference!($class, CLASS);
         &CLASS
     }};
     ($class: ident,$name : ident ,$fin: expr,$trace: expr,$deser: expr,$ser: expr,$size: expr) => {{
@@ -57,10 +59,25 @@ macro_rules! define_jsclass {
             serialize: $ser,
             additional_size: $size,
         };
+        $crate::register_class_reference!($class, CLASS);
         &CLASS
     }};
 }
 
+#[macro_export]
+#[doc(hidden)]
+macro_rules! register_class_reference {
+    ($class: ident, $static: ident) => {
+        #[$crate::__ctor::ctor]
+        fn register_class_reference() {
+            $crate::gc::snapshot::references::register_native_reference(
+                concat!(module_path!(), "::", stringify!($class), "::class"),
+                &$static as *const $crate::vm::class::Class as usize,
+            );
+        }
+    };
+}
+
 #[macro_export]
 macro_rules! define_additional_size {
     ($class:ident) => {
@@ -218,3 +235,172 @@ pub trait JsClass {
         JsObject::GetIndexedPropertySlotMethod(obj, ctx, index, slot)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use crate::vm::{context::Context, tests::test_runtime};
+
+    #[js_class]
+    #[derive(GcTrace)]
+    struct Counter {
+        count: i32,
+    }
+
+    #[js_impl]
+    impl Counter {
+        #[js_constructor]
+        fn new(start: Option<i32>) -> Self {
+            Counter {
+                count: start.unwrap_or(0),
+            }
+        }
+
+        #[js_method]
+        fn increment_by(&mut self, by: Option<i32>) -> i32 {
+            self.count += by.unwrap_or(1);
+            self.count
+        }
+
+        #[js_method(name = "toString")]
+        fn describe(&self, mut ctx: GcPointer<Context>) -> Result<String, JsValue> {
+            let label = ctx
+                .global_object()
+                .get(ctx, "counterLabel".intern())?
+                .to_string(ctx)?;
+            Ok(format!("{}({})", label, self.count))
+        }
+
+        #[js_method]
+        fn max_value() -> i32 {
+            i32::MAX
+        }
+
+        #[js_getter]
+        fn get_count(&self) -> i32 {
+            self.count
+        }
+
+        #[js_setter]
+        fn set_count(&mut self, count: i32) {
+            self.count = count;
+        }
+    }
+
+    #[js_class(name = "Vec2", snapshot)]
+    #[derive(GcTrace)]
+    struct Point {
+        x: f64,
+        y: f64,
+    }
+
+    #[js_impl]
+    impl Point {
+        #[js_constructor]
+        fn new(x: f64, y: f64) -> Result<Self, JsValue> {
+            Ok(Point { x, y })
+        }
+
+        #[js_method]
+        fn length(&self) -> f64 {
+            (self.x * self.x + self.y * self.y).sqrt()
+        }
+
+        #[js_getter]
+        fn x(&self) -> f64 {
+            self.x
+        }
+    }
+
+    impl Serializable for Point {
+        fn serialize(&self, serializer: &mut SnapshotSerializer) {
+            self.x.serialize(serializer);
+            self.y.serialize(serializer);
+        }
+    }
+
+    impl Deserializable for Point {
+        unsafe fn deserialize_inplace(deser: &mut Deserializer) -> Self {
+            Point {
+                x: f64::deserialize_inplace(deser),
+                y: f64::deserialize_inplace(deser),
+            }
+        }
+
+        unsafe fn deserialize(at: *mut u8, deser: &mut Deserializer) {
+            at.cast::<Self>().write(Self::deserialize_inplace(deser));
+        }
+
+        unsafe fn allocate(
+            _vm: &mut VirtualMachine,
+            _deser: &mut Deserializer,
+        ) -> *mut GcPointerBase {
+            unreachable!()
+        }
+    }
+
+    /// Marker classes without `#[js_constructor]` throw when constructed.
+    #[js_class]
+    #[derive(GcTrace)]
+    struct Opaque {}
+
+    #[js_impl]
+    impl Opaque {}
+
+    fn eval(mut ctx: GcPointer<Context>, source: &str) -> String {
+        match ctx.eval(source).and_then(|value| value.to_string(ctx)) {
+            Ok(value) => value,
+            Err(error) => panic!("{}", error.to_string(ctx).unwrap_or_default()),
+        }
+    }
+
+    #[test]
+    fn test_js_class() {
+        let (mut runtime, _jobs) = test_runtime();
+        let mut ctx = Context::new(&mut runtime);
+        ctx.register_class::<Counter>().unwrap();
+        ctx.register_class::<Opaque>().unwrap();
+        assert_eq!(
+            eval(
+                ctx,
+                "var counterLabel = 'Counter'; \
+                 let counter = new Counter(); \
+                 let typeError = function (f) { try { f(); } catch (e) { return e instanceof TypeError; } }; \
+                 let results = []; \
+                 results.push(counter.incrementBy()); \
+                 results.push(counter.incrementBy(5)); \
+                 results.push(counter.count); \
+                 counter.count = 10; \
+                 results.push(counter.count, String(counter), Counter.maxValue(), Counter.length); \
+                 results.push(Counter.prototype.incrementBy.length, counter instanceof Counter); \
+                 results.push(Object.prototype.toString.call(counter)); \
+                 results.push(typeError(function () { Counter.prototype.incrementBy.call({}); })); \
+                 results.push(typeError(function () { counter.count = 'x'; })); \
+                 results.push(typeError(function () { new Opaque(); })); \
+                 return results;"
+            ),
+            "1,6,6,10,Counter(10),2147483647,1,1,true,[object Counter],true,true,true"
+        );
+    }
+
+    #[test]
+    fn test_js_class_snapshot() {
+        let (mut runtime, _jobs) = test_runtime();
+        let mut ctx = Context::new(&mut runtime);
+        ctx.register_class::<Counter>().unwrap();
+        ctx.register_class::<Point>().unwrap();
+        eval(ctx, "var point = new Vec2(3, 4); var pointX = point.x;");
+        let buffer = Snapshot::take_context(false, &mut runtime, ctx, |_, _| {}).buffer;
+
+        // classes are resolved through references registered at startup, `register_class` isn't called again.
+        let ctx = Deserializer::deserialize_context(&mut runtime, false, &buffer).unwrap();
+        assert_eq!(
+            eval(
+                ctx,
+                "let counter = new Counter(1); counter.incrementBy(2); \
+                 return [pointX, point.length(), point.x, point instanceof Vec2, new Vec2(6, 8).length(), counter.count];"
+            ),
+            "3,5,3,true,10,3"
+        );
+    }
+}
@@ -2,6 +2,8 @@ use super::context::Context;
 /* This Source Code Form is subject to the terms of the Mozilla Public
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+use super::module::ModuleInfo;
+use super::string::JsString;
 use super::symbol_table::Symbol;
 use super::value::JsValue;
 use crate::bytecode::opcodes::*;
@@ -14,6 +16,8 @@ use crate::{
 use std::rc::Rc;
 use std::{fmt::Write, ops::Range};
 
+/// Position in a script. Both line and column are zero based, column is counted in UTF-16 code units.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
 pub struct FileLocation {
     pub line: u32,
     pub col: u32,
@@ -69,6 +73,53 @@ impl StackSizeState {
         Ok(())
     }
 }
+/// Compile-time scope captured for lazily compiled function.
+pub struct LazyScope {
+    pub depth: u32,
+    /// Variables declared in this scope and their indexes in environment.
+    pub variables: Vec<(Symbol, u16)>,
+    /// Imports declared in module scope and their indexes in [ModuleInfo::imports].
+    pub imports: Vec<(Symbol, u16)>,
+    /// Scope of function that calls direct `eval`.
+    pub dynamic: bool,
+}
+
+/// Source text of a function.
+#[derive(Clone)]
+pub struct FunctionSource {
+    /// Source of the script the function was declared in. Shared by all functions of the script.
+    pub text: GcPointer<JsString>,
+    /// Byte range of the function in `text`.
+    pub span: Range<u32>,
+}
+
+impl FunctionSource {
+    pub fn as_str(&self) -> &str {
+        &self.text.as_str()[self.span.start as usize..self.span.end as usize]
+    }
+}
+
+/// Syntax of lazily compiled function, tells how [CodeBlock::source] is parsed again.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LazyKind {
+    /// `function` declaration or expression.
+    Function,
+    Arrow,
+    /// Method of object literal.
+    Method,
+}
+
+/// Function that is not compiled yet. Its body is compiled into the owning [CodeBlock] from
+/// [CodeBlock::source] on first call.
+pub struct LazyFunction {
+    /// Scopes visible from the function body, innermost first.
+    pub scopes: Vec<LazyScope>,
+    pub builtins: bool,
+    pub kind: LazyKind,
+    /// Location of the function in the script.
+    pub position: FileLocation,
+}
+
 /// A type representing single JS function bytecode.
 //#[derive(GcTrace)]
 #[repr(C)]
@@ -83,6 +134,8 @@ pub struct CodeBlock {
     pub param_count: u32,
     /// Rest parameter position in argument list
     pub rest_at: Option<u32>,
+    /// Parameters before the first one with default value or rest parameter, value of function `length`.
+    pub length: u32,
     /// Names
     pub names: Vec<Symbol>,
     /// Bytecode
@@ -108,14 +161,26 @@ pub struct CodeBlock {
 
     pub is_constructor: bool,
 
+    /// Bytecode ranges and locations of the source they were compiled from, sorted by range.
     pub loc: Vec<(Range<usize>, FileLocation)>,
     pub path: Rc<str>,
     pub is_generator: bool,
     pub is_async: bool,
+    /// Source text of the function. Not present for top level code and when source is stripped.
+    pub source: Option<FunctionSource>,
+    /// Set when function body is not compiled yet.
+    pub lazy: Option<Box<LazyFunction>>,
+    /// Scopes visible at direct `eval` call sites, innermost first. Indexed by operand of `call_eval`.
+    pub evals: Vec<Vec<LazyScope>>,
+    /// Imports and exports of module code.
+    pub module: Option<Box<ModuleInfo>>,
 }
 
 unsafe impl Trace for CodeBlock {
     fn trace(&mut self, visitor: &mut dyn Tracer) {
+        if let Some(ref mut source) = self.source {
+            source.text.trace(visitor);
+        }
         self.codes.trace(visitor);
         self.literals.trace(visitor);
         self.feedback.trace(visitor);
@@ -123,11 +188,48 @@ unsafe impl Trace for CodeBlock {
 }
 
 impl CodeBlock {
+    /// Returns true if frame for this code can be set up directly from call arguments i.e function
+    /// does not use `arguments` object nor rest parameter.
+    pub fn can_use_fast_call(&self) -> bool {
+        !self.is_lazy() && !self.use_arguments && self.rest_at.is_none()
+    }
+    /// Returns location of the source that instruction at `offset` was compiled from.
+    pub fn location(&self, offset: usize) -> Option<FileLocation> {
+        let ix = self.loc.partition_point(|(range, _)| range.end <= offset);
+        self.loc
+            .get(ix)
+            .filter(|(range, _)| range.contains(&offset))
+            .map(|(_, loc)| *loc)
+    }
+    /// Returns true if function body was not compiled yet.
+    pub fn is_lazy(&self) -> bool {
+        self.lazy.is_some()
+    }
+    /// Drop bytecode and tables emitted by body compilation that failed, so the body can be compiled again.
+    pub(crate) fn discard_body(&mut self) {
+        self.code.clear();
+        self.codes.clear();
+        self.literals.clear();
+        self.literals_ptr = core::ptr::null();
+        self.feedback.clear();
+        self.names.clear();
+        self.loc.clear();
+        self.evals.clear();
+        self.stack_size = 0;
+        self.var_count = 0;
+        self.param_count = 0;
+        self.rest_at = None;
+        self.use_arguments = false;
+        self.args_at = 0;
+    }
     /// Print bytecode to `output`.
     pub fn display_to<T: Write>(&self, output: &mut T) -> std::fmt::Result {
         unsafe {
             writeln!(output, "is strict?={}", self.strict)?;
             writeln!(output, "stack size={}", self.stack_size)?;
+            if self.is_lazy() {
+                return writeln!(output, "<not compiled yet>");
+            }
             let start = self.code.as_ptr() as *mut u8;
             let mut pc = self.code.as_ptr() as *mut u8;
             while pc <= self.code.last().unwrap() as *const u8 as *mut u8 {
@@ -244,20 +346,35 @@ impl CodeBlock {
                     Opcode::OP_CALL => {
                         let argc = pc.cast::<u32>().read_unaligned();
                         pc = pc.add(4);
-                        writeln!(output, "call <{}>", argc)?;
+                        let feedback = pc.cast::<u32>().read_unaligned();
+                        pc = pc.add(4);
+                        writeln!(output, "call <{}> fdbk {}", argc, feedback)?;
+                    }
+                    Opcode::OP_CALL_EVAL => {
+                        let argc = pc.cast::<u32>().read_unaligned();
+                        pc = pc.add(4);
+                        let site = pc.cast::<u32>().read_unaligned();
+                        pc = pc.add(4);
+                        writeln!(output, "call_eval <{}> site {}", argc, site)?;
                     }
                     Opcode::OP_TAILCALL => {
                         let argc = pc.cast::<u32>().read_unaligned();
                         pc = pc.add(4);
-                        writeln!(output, "tail_call <{}>", argc)?;
+                        let feedback = pc.cast::<u32>().read_unaligned();
+                        pc = pc.add(4);
+                        writeln!(output, "tail_call <{}> fdbk {}", argc, feedback)?;
                     }
                     Opcode::OP_INSTANCEOF => {
-                        writeln!(output, "instanceof")?;
+                        let feedback = pc.cast::<u32>().read_unaligned();
+                        pc = pc.add(4);
+                        writeln!(output, "instanceof fdbk {}", feedback)?;
                     }
                     Opcode::OP_TAILNEW => {
                         let argc = pc.cast::<u32>().read_unaligned();
                         pc = pc.add(4);
-                        writeln!(output, "tail_new <{}>", argc)?;
+                        let feedback = pc.cast::<u32>().read_unaligned();
+                        pc = pc.add(4);
+                        writeln!(output, "tail_new <{}> fdbk {}", argc, feedback)?;
                     }
                     Opcode::OP_CALL_BUILTIN => {
                         let argc = pc.cast::<u32>().read_unaligned();
@@ -275,7 +392,9 @@ impl CodeBlock {
                     Opcode::OP_NEW => {
                         let argc = pc.cast::<u32>().read_unaligned();
                         pc = pc.add(4);
-                        writeln!(output, "new <{}>", argc)?;
+                        let feedback = pc.cast::<u32>().read_unaligned();
+                        pc = pc.add(4);
+                        writeln!(output, "new <{}> fdbk {}", argc, feedback)?;
                     }
                     Opcode::OP_RET => {
                         writeln!(output, "ret")?;
@@ -472,6 +591,27 @@ impl CodeBlock {
                     Opcode::OP_GLOBALTHIS => {
                         writeln!(output, "global_object")?;
                     }
+                    Opcode::OP_RESOLVE_VAR => {
+                        let name = pc.cast::<u32>().read_unaligned();
+                        pc = pc.add(4);
+                        writeln!(output, "resolve_var {}", name)?;
+                    }
+                    Opcode::OP_DECL_VAR => {
+                        let name = pc.cast::<u32>().read_unaligned();
+                        pc = pc.add(4);
+                        writeln!(output, "decl_var {}", name)?;
+                    }
+                    Opcode::OP_GET_IMPORT => {
+                        let index = pc.cast::<u32>().read_unaligned();
+                        pc = pc.add(4);
+                        writeln!(output, "get_import {}", index)?;
+                    }
+                    Opcode::OP_SET_IMPORT => {
+                        let index = pc.cast::<u32>().read_unaligned();
+                        pc = pc.add(4);
+                        writeln!(output, "set_import {}", index)?;
+                    }
+                    Opcode::OP_DYNAMIC_IMPORT => writeln!(output, "dynamic_import")?,
                     Opcode::OP_LOOPHINT => {
                         writeln!(output, "loophint")?;
                     }
@@ -543,7 +683,21 @@ impl CodeBlock {
                         self.code[p + 2],
                         self.code[p + 3],
                     ]);
-                    pos += 4;
+                    pos += 8; // SKIP ARGC AND FEEDBACK
+                    stack_len -= argc as u16;
+                    stack_len -= 2;
+
+                    stack_len += 1;
+                }
+                OP_CALL_EVAL => {
+                    let p = pos as usize;
+                    let argc = u32::from_ne_bytes([
+                        self.code[p],
+                        self.code[p + 1],
+                        self.code[p + 2],
+                        self.code[p + 3],
+                    ]);
+                    pos += 8; // SKIP ARGC AND CALL SITE
                     stack_len -= argc as u16;
                     stack_len -= 2;
 
@@ -557,7 +711,7 @@ impl CodeBlock {
                         self.code[p + 2],
                         self.code[p + 3],
                     ]);
-                    pos += 4;
+                    pos += 8; // SKIP ARGC AND FEEDBACK
                     stack_len -= argc as u16;
                     stack_len -= 2;
                     skip_check = true;
@@ -580,7 +734,12 @@ impl CodeBlock {
                     pos += 4;
                     stack_len -= 3;
                 }
-                OP_GREATEREQ | OP_GREATER | OP_INSTANCEOF | OP_IN | OP_NSTRICTEQ | OP_NEQ
+                OP_INSTANCEOF => {
+                    pos += 4; // SKIP FEEDBACK
+                    stack_len -= 2;
+                    stack_len += 1;
+                }
+                OP_GREATEREQ | OP_GREATER | OP_IN | OP_NSTRICTEQ | OP_NEQ
                 | OP_LESS | OP_LESSEQ | OP_USHR | OP_SHR | OP_SHL | OP_EQ | OP_STRICTEQ => {
                     stack_len -= 2;
                     stack_len += 1;
@@ -694,6 +853,21 @@ impl CodeBlock {
                 OP_GLOBALTHIS => {
                     stack_len += 1;
                 }
+                OP_RESOLVE_VAR => {
+                    pos += 4;
+                    stack_len += 1;
+                }
+                OP_DECL_VAR => {
+                    pos += 4;
+                }
+                OP_GET_IMPORT => {
+                    pos += 4;
+                }
+                OP_SET_IMPORT => {
+                    pos += 4;
+                    stack_len -= 2;
+                }
+                OP_DYNAMIC_IMPORT => {}
                 OP_NEWOBJECT => {
                     stack_len += 1;
                 }
@@ -803,6 +977,7 @@ impl CodeBlock {
             code: vec![],
             is_constructor: true,
             rest_at: None,
+            length: 0,
             literals_ptr: core::ptr::null_mut(),
             use_arguments: false,
             literals: vec![],
@@ -811,6 +986,10 @@ impl CodeBlock {
             param_count: 0,
             is_async: false,
             is_generator: false,
+            source: None,
+            lazy: None,
+            evals: vec![],
+            module: None,
         };
 
         ctx.heap().allocate(this)
@@ -1,18 +1,25 @@
-use crate::{define_op_builtins, gc::cell::GcCell, jsrt::VM_NATIVE_REFERENCES, vm::Lrc};
-use std::{collections::HashMap, ptr::null};
-use swc_common::{errors::Handler, input::StringInput, FileName, SourceMap};
-use swc_ecmascript::parser::{Parser, Syntax};
+use crate::{define_op_builtins, gc::cell::GcCell, gc::snapshot::references, vm::Lrc};
+use std::{borrow::Cow, collections::HashMap, ptr::null};
+use swc_common::{errors::Handler, input::StringInput, FileName, SourceFile, SourceMap};
+use swc_ecmascript::{
+    ast::Script,
+    parser::{Parser, Syntax},
+};
 
 use crate::{
     bytecompiler::{ByteCompiler, CompileError},
     gc::{
         cell::{GcPointer, Trace, Tracer},
         shadowstack::ShadowStack,
+        snapshot::code_cache,
         Heap,
     },
     jsrt,
     vm::{
         arguments::{Arguments, JsArguments},
+        code_block::CodeBlock,
+        source_map,
+        stack_trace::StackFrame,
         environment::Environment,
         error::JsSyntaxError,
         function::JsVMFunction,
@@ -25,13 +32,17 @@ use super::{
     array_buffer::JsArrayBuffer,
     builder::{Builtin, ClassBuilder, ClassConstructor},
     class::JsClass,
+    convert::{new_native_fn, NativeFn},
     data_view::JsDataView,
     error::JsError,
     error::{JsRangeError, JsReferenceError, JsTypeError},
     function::JsNativeFunction,
     function::{JsFunction, JsGeneratorFunction},
     global::JsGlobal,
+    handle_scope::HandleScope,
     interpreter::{frame::CallFrame, stack::Stack},
+    module::ModuleRecord,
+    module_loader::{FileModuleLoader, ModuleLoader},
     number::JsNumber,
     object::{JsObject, ObjectTag},
     promise::JsPromise,
@@ -48,6 +59,7 @@ use crate::jsrt::boolean::JsBoolean;
 use crate::jsrt::date::Date;
 use crate::jsrt::math::Math;
 use crate::jsrt::regexp::RegExp;
+use crate::jsrt::shadow_realm::JsShadowRealm;
 use crate::jsrt::weak_ref::JsWeakRef;
 use crate::jsrt::SelfHost;
 
@@ -62,6 +74,8 @@ pub struct Context {
     pub(crate) stacktrace: String,
     pub(crate) module_loader: Option<GcPointer<JsObject>>,
     pub(crate) modules: HashMap<String, ModuleKind>,
+    /// Module loader of this context, overrides loader of the VM.
+    pub(crate) loader: Option<Box<dyn ModuleLoader>>,
     pub(crate) stack_len_max: u32,
     pub(crate) symbol_table: HashMap<Symbol, GcPointer<JsSymbol>>,
 }
@@ -112,6 +126,7 @@ impl Context {
             stacktrace: String::new(),
             module_loader: None,
             modules: HashMap::new(),
+            loader: None,
             symbol_table: HashMap::new(),
         }
     }
@@ -126,6 +141,7 @@ impl Context {
             stacktrace: String::new(),
             module_loader: None,
             modules: HashMap::new(),
+            loader: None,
             symbol_table: HashMap::new(),
         };
         let ctx = vm.heap().allocate(context);
@@ -144,20 +160,14 @@ impl Context {
     }
 }
 impl GcPointer<Context> {
-    pub fn register_native_reference(reference: usize) {
-        unsafe {
-            VM_NATIVE_REFERENCES.push(reference);
-        }
+    /// Register `reference` under `name` so it can be stored in snapshots. References known at compile time
+    /// should be registered with [register_native_references!](crate::register_native_references) instead.
+    pub fn register_native_reference(name: impl Into<Cow<'static, str>>, reference: usize) {
+        references::register_native_reference(name, reference);
     }
 
     pub fn remove_reference(reference: usize) {
-        unsafe {
-            let index = VM_NATIVE_REFERENCES
-                .iter()
-                .position(|r| *r == reference)
-                .expect("Reference not found");
-            VM_NATIVE_REFERENCES.remove(index);
-        }
+        references::remove_native_reference(reference);
     }
 
     pub fn register_class<T>(mut self) -> Result<(), JsValue>
@@ -187,13 +197,33 @@ impl GcPointer<Context> {
         let mut global_object = self.global_object();
         def_native_property!(self, global_object, name.intern(), constructor)?;
 
-        unsafe {
-            VM_NATIVE_REFERENCES.push(T::class() as *const _ as _);
-            VM_NATIVE_REFERENCES.push(T::raw_constructor as _);
-        }
+        // constructors of classes defined without `#[js_impl]` aren't registered at startup.
+        references::register_native_reference(
+            format!("{}::constructor", std::any::type_name::<T>()),
+            T::raw_constructor as usize,
+        );
         Ok(())
     }
 
+    /// Define global function `name` that calls `f`. Arguments are converted with [FromJs] which coerces
+    /// primitives and throws `TypeError` for other mismatched types, result is converted with [IntoJs].
+    /// Functions defined this way can't be stored in snapshots.
+    ///
+    /// [FromJs]: super::convert::FromJs
+    /// [IntoJs]: super::convert::IntoJs
+    pub fn register_fn<Args, F: NativeFn<Args>>(mut self, name: &str, f: F) -> Result<(), JsValue> {
+        let stack = self.shadowstack();
+        letroot!(function = stack, new_native_fn(self, name.intern(), f));
+        let mut global_object = self.global_object();
+        def_native_property!(self, global_object, name.intern(), *function)?;
+        Ok(())
+    }
+
+    /// Run `f` with a [HandleScope] that roots values created through it until `f` returns.
+    pub fn scope<R>(self, f: impl for<'s> FnOnce(&HandleScope<'s>) -> R) -> R {
+        f(&HandleScope::new(self))
+    }
+
     pub fn register_builtin<T>(mut self) -> Result<(), JsValue>
     where
         T: Builtin,
@@ -254,7 +284,27 @@ impl GcPointer<Context> {
         code: &str,
         params: &[String],
     ) -> Result<JsValue, CompileError> {
-        let mut code = ByteCompiler::compile_code(self, params, "", code.to_owned(), false)?;
+        let mut code = ByteCompiler::compile_code(self, params, "", "", code.to_owned(), false)?;
+        code.get_jsobject().as_function_mut().as_vm_mut().code.name = name.intern();
+
+        Ok(code)
+    }
+    /// Compile function with parameters `params` and body `code` read from file `path`. Stack traces and
+    /// `import()` of the function refer to `path`.
+    pub fn compile_file_function(
+        self,
+        path: &str,
+        name: &str,
+        code: &str,
+        params: &[String],
+    ) -> Result<JsValue, CompileError> {
+        self.load_source_map(path, code);
+        let dir = std::path::Path::new(path)
+            .parent()
+            .map(|dir| dir.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut code =
+            ByteCompiler::compile_code(self, params, &dir, path, code.to_owned(), false)?;
         code.get_jsobject().as_function_mut().as_vm_mut().code.name = name.intern();
 
         Ok(code)
@@ -268,42 +318,51 @@ impl GcPointer<Context> {
         script: &str,
         builtins: bool,
     ) -> Result<JsValue, CompileError> {
-        let cm: Lrc<SourceMap> = Default::default();
-        let _e = BufferedError::default();
+        self.load_source_map(path, script);
+        let compile = || {
+            let cm: Lrc<SourceMap> = Default::default();
+            let _e = BufferedError::default();
 
-        let handler = Handler::with_emitter(true, false, Box::new(MyEmiter::default()));
+            let handler = Handler::with_emitter(true, false, Box::new(MyEmiter::default()));
 
-        let fm = cm.new_source_file(FileName::Custom(name.into()), script.into());
+            let fm = cm.new_source_file(FileName::Custom(name.into()), script.into());
 
-        let mut parser = Parser::new(
-            Syntax::Es(Default::default()),
-            StringInput::from(&*fm),
-            None,
-        );
-
-        for e in parser.take_errors() {
-            e.into_diagnostic(&handler).emit();
-        }
+            let mut parser = Parser::new(
+                Syntax::Es(Default::default()),
+                StringInput::from(&*fm),
+                None,
+            );
 
-        let script = match parser.parse_script() {
-            Ok(script) => script,
-            Err(_e) => {
-                // let msg = JsString::new(self, e.kind().msg());
-                return Err(CompileError::NotYetImpl("parser error".to_string()));
+            for e in parser.take_errors() {
+                e.into_diagnostic(&handler).emit();
             }
+
+            let script = match parser.parse_script() {
+                Ok(script) => script,
+                Err(_e) => {
+                    // let msg = JsString::new(self, e.kind().msg());
+                    return Err(CompileError::NotYetImpl("parser error".to_string()));
+                }
+            };
+            ByteCompiler::compile_script(
+                self,
+                &script,
+                &std::path::Path::new(&path)
+                    .canonicalize()
+                    .unwrap()
+                    .parent()
+                    .map(|x| x.to_str().unwrap().to_string())
+                    .unwrap_or_else(|| "".to_string()),
+                path.to_owned(),
+                builtins,
+                Some(&*fm),
+            )
+        };
+        let mut code = if builtins {
+            compile()?
+        } else {
+            self.cached_code(path, script, false, compile)?
         };
-        let mut code = ByteCompiler::compile_script(
-            self,
-            &script,
-            &std::path::Path::new(&path)
-                .canonicalize()
-                .unwrap()
-                .parent()
-                .map(|x| x.to_str().unwrap().to_string())
-                .unwrap_or_else(|| "".to_string()),
-            path.to_owned(),
-            builtins,
-        )?;
         code.name = name.intern();
         //code.display_to(&mut OutBuf).unwrap();
 
@@ -311,12 +370,49 @@ impl GcPointer<Context> {
         let fun = JsVMFunction::new(self, code, env);
         Ok(JsValue::encode_object_value(fun))
     }
-    pub fn compile_module(
+    /// Load code of `script` at `path` from code cache of the VM or compile it with `compile` and store it in
+    /// the cache. Scripts and modules are cached under different keys.
+    fn cached_code<E>(
         mut self,
         path: &str,
+        script: &str,
+        module: bool,
+        compile: impl FnOnce() -> Result<GcPointer<CodeBlock>, E>,
+    ) -> Result<GcPointer<CodeBlock>, E> {
+        if self.vm.code_cache.is_none() {
+            return compile();
+        }
+        let key = code_cache::source_key(self.vm.options(), script, module);
+        let cached = self
+            .vm
+            .code_cache
+            .as_mut()
+            .and_then(|cache| cache.load(path))
+            .and_then(|data| code_cache::deserialize(self, &data, key));
+        if let Some(code) = cached {
+            return Ok(code);
+        }
+        let code = compile()?;
+        if let Some(data) = code_cache::serialize(self, code, key) {
+            if let Some(cache) = self.vm.code_cache.as_mut() {
+                cache.store(path, &data);
+            }
+        }
+        Ok(code)
+    }
+    /// Register source map referenced by `//# sourceMappingURL=` comment of `script`.
+    fn load_source_map(mut self, path: &str, script: &str) {
+        if let Some(map) = source_map::SourceMap::from_script(script, path) {
+            self.vm.add_source_map(path, map);
+        }
+    }
+
+    fn compile_module_code(
+        self,
+        path: &str,
         name: &str,
         script: &str,
-    ) -> Result<JsValue, JsValue> {
+    ) -> Result<GcPointer<CodeBlock>, JsValue> {
         let cm: Lrc<SourceMap> = Default::default();
         let _e = BufferedError::default();
 
@@ -340,19 +436,33 @@ impl GcPointer<Context> {
             }
         };
 
-        let mut code = ByteCompiler::compile_module(
+        // bundled modules don't have to exist on the file system.
+        ByteCompiler::compile_module(
             self,
             path,
             &std::path::Path::new(&path)
                 .canonicalize()
-                .unwrap()
+                .unwrap_or_else(|_| std::path::PathBuf::from(path))
                 .parent()
                 .map(|x| x.to_str().unwrap().to_string())
                 .unwrap_or_else(|| "".to_string()),
             name,
             &module,
+            Some(&*fm),
         )
-        .map_err(|e| self.new_syntax_error(format!("Compile Error {:?}", e)))?;
+        .map_err(|e| JsValue::new(self.new_syntax_error(format!("Compile Error {:?}", e))))
+    }
+
+    pub fn compile_module(
+        mut self,
+        path: &str,
+        name: &str,
+        script: &str,
+    ) -> Result<JsValue, JsValue> {
+        self.load_source_map(path, script);
+        let mut code = self.cached_code(path, script, true, || {
+            self.compile_module_code(path, name, script)
+        })?;
         code.name = name.intern();
 
         let env = Environment::new(self, 0);
@@ -369,69 +479,112 @@ impl GcPointer<Context> {
     ///
     /// TODO: Return script execution result. Right now just `undefined` value is returned.
     pub fn eval_internal(
-        mut self,
+        self,
         path: Option<&str>,
         force_strict: bool,
         script: &str,
         builtins: bool,
     ) -> Result<JsValue, JsValue> {
-        let res = {
-            let cm: Lrc<SourceMap> = Default::default();
-            let _e = BufferedError::default();
+        let compile = || {
+            let (fm, script) = self.parse_script(script)?;
+            self.compile_parsed(path, &fm, &script, builtins)
+        };
+        let code = match path {
+            Some(path) if !builtins => self.cached_code(path, script, false, compile)?,
+            _ => compile()?,
+        };
+        self.run_script(code, force_strict)
+    }
 
-            let handler = Handler::with_emitter(true, false, Box::new(MyEmiter::default()));
+    /// Parse `script`. Parse error is returned as `SyntaxError` of this context.
+    pub(crate) fn parse_script(self, script: &str) -> Result<(Lrc<SourceFile>, Script), JsValue> {
+        let cm: Lrc<SourceMap> = Default::default();
+        let _e = BufferedError::default();
 
-            let fm = cm.new_source_file(FileName::Custom("<script>".into()), script.into());
+        let handler = Handler::with_emitter(true, false, Box::new(MyEmiter::default()));
 
-            let mut parser =
-                Parser::new(Syntax::Es(init_es_config()), StringInput::from(&*fm), None);
+        let fm = cm.new_source_file(FileName::Custom("<script>".into()), script.into());
 
-            for e in parser.take_errors() {
-                e.into_diagnostic(&handler).emit();
+        let mut parser = Parser::new(Syntax::Es(init_es_config()), StringInput::from(&*fm), None);
+
+        for e in parser.take_errors() {
+            e.into_diagnostic(&handler).emit();
+        }
+
+        match parser.parse_script() {
+            Ok(script) => Ok((fm, script)),
+            Err(e) => {
+                let msg = JsString::new(self, e.kind().msg());
+                Err(JsValue::encode_object_value(JsSyntaxError::new(
+                    self, msg, None,
+                )))
             }
+        }
+    }
 
-            let script = match parser.parse_script() {
-                Ok(script) => script,
-                Err(e) => {
-                    let msg = JsString::new(self, e.kind().msg());
-                    return Err(JsValue::encode_object_value(JsSyntaxError::new(
-                        self, msg, None,
-                    )));
-                }
-            };
-            let mut code = ByteCompiler::compile_eval(
-                self,
-                &script,
-                &path
-                    .map(|path| match std::path::Path::new(&path).canonicalize() {
-                        Ok(x) => x
-                            .parent()
-                            .map(|x| x.to_str().unwrap().to_string())
-                            .unwrap_or_else(|| "".to_string()),
-                        Err(_) => String::new(),
-                    })
-                    .unwrap_or_else(|| "".to_string()),
-                path.map(|x| x.to_owned()).unwrap_or_else(String::new),
-                builtins,
-            )
-            .map_err(|e| self.new_syntax_error(format!("Compile Error {:?}", &e)))?;
-            code.strict = code.strict || force_strict;
-            // code.file_name = path.map(|x| x.to_owned()).unwrap_or_else(|| String::new());
-            //code.display_to(&mut OutBuf).unwrap();
-            let stack = self.shadowstack();
-
-            letroot!(env = stack, Environment::new(self, 0));
-            letroot!(fun = stack, JsVMFunction::new(self, code, *env));
-            letroot!(func = stack, *fun);
-            letroot!(
-                args = stack,
-                Arguments::new(JsValue::encode_undefined_value(), &mut [])
-            );
+    /// Indirect `eval(script)`: evaluate `script` as global eval code of this context.
+    pub fn indirect_eval(self, script: &str) -> Result<JsValue, JsValue> {
+        let (fm, script) = self.parse_script(script)?;
+        self.eval_parsed(&fm, &script)
+    }
 
-            fun.as_function_mut()
-                .call(self, &mut args, JsValue::new(*func))
-        };
-        res
+    /// Run `script` parsed by [Context::parse_script] from source file `fm` as global eval code. Sloppy eval code
+    /// declares its variables and functions on the global object, strict eval code keeps them to itself.
+    pub(crate) fn eval_parsed(self, fm: &SourceFile, script: &Script) -> Result<JsValue, JsValue> {
+        let code = ByteCompiler::compile_indirect_eval(self, script, Some(fm))
+            .map_err(|e| JsValue::new(self.new_syntax_error(format!("Compile Error {:?}", &e))))?;
+        self.run_script(code, false)
+    }
+
+    /// Compile `script` parsed by [Context::parse_script] from source file `fm`.
+    fn compile_parsed(
+        self,
+        path: Option<&str>,
+        fm: &SourceFile,
+        script: &Script,
+        builtins: bool,
+    ) -> Result<GcPointer<CodeBlock>, JsValue> {
+        ByteCompiler::compile_eval(
+            self,
+            script,
+            &path
+                .map(|path| match std::path::Path::new(&path).canonicalize() {
+                    Ok(x) => x
+                        .parent()
+                        .map(|x| x.to_str().unwrap().to_string())
+                        .unwrap_or_else(|| "".to_string()),
+                    Err(_) => String::new(),
+                })
+                .unwrap_or_else(|| "".to_string()),
+            path.map(|x| x.to_owned()).unwrap_or_else(String::new),
+            builtins,
+            Some(fm),
+        )
+        .map_err(|e| JsValue::new(self.new_syntax_error(format!("Compile Error {:?}", &e))))
+    }
+
+    /// Run top level `code` of script or eval code. Its environment has no parent, so names that the code doesn't
+    /// declare itself are resolved on the global object.
+    fn run_script(
+        mut self,
+        mut code: GcPointer<CodeBlock>,
+        force_strict: bool,
+    ) -> Result<JsValue, JsValue> {
+        code.strict = code.strict || force_strict;
+        // code.file_name = path.map(|x| x.to_owned()).unwrap_or_else(|| String::new());
+        //code.display_to(&mut OutBuf).unwrap();
+        let stack = self.shadowstack();
+
+        letroot!(env = stack, Environment::new(self, 0));
+        letroot!(fun = stack, JsVMFunction::new(self, code, *env));
+        letroot!(func = stack, *fun);
+        letroot!(
+            args = stack,
+            Arguments::new(JsValue::encode_undefined_value(), &mut [])
+        );
+
+        fun.as_function_mut()
+            .call(self, &mut args, JsValue::new(*func))
     }
     pub fn evalm(
         mut self,
@@ -478,51 +631,122 @@ impl GcPointer<Context> {
                     .unwrap_or_else(|| "".to_string()),
                 &path.map(|x| x.to_owned()).unwrap_or_else(String::new),
                 &script,
+                Some(&*fm),
             )
             .map_err(|e| self.new_syntax_error(format!("Compile Error {:?}", &e)))?;
             code.strict = code.strict || force_strict;
 
-            let stack = self.shadowstack();
-
-            letroot!(env = stack, Environment::new(self, 0));
-            letroot!(fun = stack, JsVMFunction::new(self, code, *env));
-            letroot!(func = stack, *fun);
-            letroot!(module_object = stack, JsObject::new_empty(self));
-            let exports = JsObject::new_empty(self);
-            module_object
-                .put(self, "@exports".intern(), JsValue::new(exports), false)
-                .unwrap_or_else(|_| unreachable!());
-            let mut args = [JsValue::new(*module_object)];
-            letroot!(
-                args = stack,
-                Arguments::new(
-                    JsValue::encode_object_value(self.global_object()),
-                    &mut args
-                )
-            );
-
-            fun.as_function_mut()
-                .call(self, &mut args, JsValue::new(*func))
+            let env = Environment::new(self, 0);
+            let fun = JsVMFunction::new(self, code, env);
+            self.evaluate_module(path.unwrap_or("<script>"), fun)
+                .map(|_| JsValue::encode_undefined_value())
         };
         res
     }
 
-    /// Collect stacktrace.
+    /// Evaluate module `function` compiled by [compile_module](Self::compile_module) from file at `path`. Modules it
+    /// imports are loaded, linked and evaluated before it. Evaluation of module that uses top level `await` completes
+    /// in jobs, see [ModuleRecord::promise].
+    pub fn evaluate_module(
+        self,
+        path: &str,
+        function: GcPointer<JsObject>,
+    ) -> Result<GcPointer<ModuleRecord>, JsValue> {
+        let key = jsrt::module_key(std::path::Path::new(path)).unwrap_or_else(|_| path.to_string());
+        let module = jsrt::instantiate_module(self, key, function)?;
+        module.evaluate(self)?;
+        Ok(module)
+    }
+
+    /// Collect stacktrace. Locations of scripts with source map are reported in original sources.
     pub fn stacktrace(&mut self) -> String {
         let mut result = String::new();
+        for frame in self.capture_stack_trace(usize::MAX, None) {
+            result.push_str(&format!("    {}\n", frame));
+        }
+        result
+    }
+
+    /// Collect at most `limit` stack frames, innermost first. When `skip_until` is given, frames above and including
+    /// the topmost call of that function are omitted. Locations of scripts with source map are reported in original sources.
+    pub fn capture_stack_trace(
+        &mut self,
+        limit: usize,
+        skip_until: Option<JsValue>,
+    ) -> Vec<StackFrame> {
+        let mut frames = vec![];
         let mut frame = self.stack.current;
         unsafe {
-            while !frame.is_null() {
-                if let Some(cb) = (*frame).code_block {
-                    let name = self.description(cb.name);
-                    result.push_str(&format!("  at '{}':'{}'\n", cb.file_name, name));
-                } else {
-                    result.push_str(" at '<native code>\n");
+            if let Some(callee) = skip_until {
+                let mut scan = frame;
+                while !scan.is_null() {
+                    if (*scan).callee == callee {
+                        frame = (*scan).prev;
+                        break;
+                    }
+                    scan = (*scan).prev;
                 }
+            }
+            // one more frame is collected to find original name of the last function.
+            while !frame.is_null() && frames.len() <= limit {
+                frames.push((*frame).code_block.map(|cb| {
+                    // saved `ip` points past the instruction that is being executed.
+                    let offset = ((*frame).ip as usize)
+                        .saturating_sub(cb.code.as_ptr() as usize)
+                        .saturating_sub(1);
+                    (cb, cb.location(offset))
+                }));
                 frame = (*frame).prev;
             }
         }
-        result
+        let vm = self.vm();
+        let original = frames
+            .iter()
+            .map(|frame| {
+                let (cb, loc) = (*frame)?;
+                vm.source_map(&cb.file_name)?.lookup(loc?)
+            })
+            .collect::<Vec<_>>();
+        frames
+            .iter()
+            .enumerate()
+            .take(limit)
+            .map(|(i, frame)| {
+                let (cb, loc) = match frame {
+                    Some(frame) => *frame,
+                    None => {
+                        return StackFrame {
+                            function_name: String::new(),
+                            file_name: None,
+                            line: None,
+                            column: None,
+                        }
+                    }
+                };
+                // name of the function in original source is recorded at the call site in the caller.
+                let mut function_name = original
+                    .get(i + 1)
+                    .and_then(|caller| caller.as_ref()?.name.clone())
+                    .unwrap_or_else(|| self.description(cb.name));
+                if function_name == "<anonymous>" {
+                    function_name.clear();
+                }
+                match (&original[i], loc) {
+                    (Some(original), _) => StackFrame {
+                        function_name,
+                        file_name: Some(original.source.clone()),
+                        line: Some(original.line + 1),
+                        column: Some(original.col + 1),
+                    },
+                    (None, loc) => StackFrame {
+                        function_name,
+                        file_name: Some(cb.file_name.clone()),
+                        line: loc.map(|loc| loc.line + 1),
+                        column: loc.map(|loc| loc.col + 1),
+                    },
+                }
+            })
+            .collect()
     }
 
     pub fn init_module_loader(mut self) {
@@ -553,6 +777,22 @@ impl GcPointer<Context> {
         Ok(self.modules.insert(name.to_string(), module_object))
     }
 
+    /// Set loader of modules imported in this context. Pass `None` to use loader of the VM.
+    pub fn set_module_loader(mut self, loader: Option<Box<dyn ModuleLoader>>) {
+        self.loader = loader;
+    }
+
+    /// Call `f` with module loader of this context, loader of the VM or [FileModuleLoader] when neither is set.
+    pub(crate) fn with_module_loader<R>(mut self, f: impl FnOnce(&mut dyn ModuleLoader) -> R) -> R {
+        if let Some(loader) = self.loader.as_mut() {
+            return f(&mut **loader);
+        }
+        if let Some(loader) = self.vm.module_loader.as_mut() {
+            return f(&mut **loader);
+        }
+        f(&mut FileModuleLoader)
+    }
+
     /// Find call frame that has try catch block in it. (Does not clean the stack!)
     pub(crate) unsafe fn unwind(&mut self) -> Option<*mut CallFrame> {
         let mut frame = self.stack.current;
@@ -580,12 +820,13 @@ impl GcPointer<Context> {
         }
     }
 
+    /// Schedule `job` with scheduler of the VM. Job runs in this context whichever context the scheduler passes to it.
     pub(crate) fn schedule_async<F>(mut self, job: F) -> Result<(), JsValue>
     where
         F: FnOnce(GcPointer<Context>) + 'static,
     {
         if let Some(scheduler) = &self.vm.sched_async_func {
-            scheduler(Box::new(job));
+            scheduler(Box::new(move |_| job(self)));
             Ok(())
         } else {
             Err(JsValue::encode_object_value(JsString::new(self, "In order to use async you have to init the VirtualMachineOptions with with_async_scheduler()")))
@@ -0,0 +1,489 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+//! Typed conversions between Rust and JS values.
+//!
+//! [FromJs] converts JS values into Rust types. Primitives are coerced like in JS (`ToNumber`, `ToString`,
+//! `ToBoolean`), other types throw `TypeError` if value has wrong type. [IntoJs] converts Rust values into JS values. Both are used by [Context::register_fn] to call plain Rust closures from JS:
+//!
+//! ```rust,ignore
+//! ctx.register_fn("add", |a: f64, b: f64| a + b)?;
+//! ctx.register_fn("greet", |name: Option<String>| format!("Hello, {}!", name.unwrap_or_default()))?;
+//! ```
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use super::{
+    arguments::Arguments, array::JsArray, context::Context, function::JsClosureFunction,
+    object::EnumerationMode, object::JsObject, object::ObjectTag, string::JsString,
+    symbol_table::Internable, symbol_table::Symbol, value::JsValue,
+};
+use crate::gc::cell::GcPointer;
+
+/// Conversion from JS value into Rust value.
+pub trait FromJs: Sized {
+    fn from_js(ctx: GcPointer<Context>, value: JsValue) -> Result<Self, JsValue>;
+}
+
+/// Conversion from Rust value into JS value.
+pub trait IntoJs {
+    fn into_js(self, ctx: GcPointer<Context>) -> Result<JsValue, JsValue>;
+}
+
+fn type_error(ctx: GcPointer<Context>, expected: &str, value: JsValue) -> JsValue {
+    let got = if value.is_null() {
+        "null"
+    } else {
+        value.type_of()
+    };
+    JsValue::new(ctx.new_type_error(format!("expected {}, got {}", expected, got)))
+}
+
+impl FromJs for JsValue {
+    fn from_js(_ctx: GcPointer<Context>, value: JsValue) -> Result<Self, JsValue> {
+        Ok(value)
+    }
+}
+
+impl IntoJs for JsValue {
+    fn into_js(self, _ctx: GcPointer<Context>) -> Result<JsValue, JsValue> {
+        Ok(self)
+    }
+}
+
+/// Any value is converted with `ToBoolean`.
+impl FromJs for bool {
+    fn from_js(_ctx: GcPointer<Context>, value: JsValue) -> Result<Self, JsValue> {
+        Ok(value.to_boolean())
+    }
+}
+
+impl IntoJs for bool {
+    fn into_js(self, _ctx: GcPointer<Context>) -> Result<JsValue, JsValue> {
+        Ok(JsValue::new(self))
+    }
+}
+
+/// Any value is converted with `ToNumber`.
+impl FromJs for f64 {
+    fn from_js(ctx: GcPointer<Context>, value: JsValue) -> Result<Self, JsValue> {
+        value.to_number(ctx)
+    }
+}
+
+impl IntoJs for f64 {
+    fn into_js(self, _ctx: GcPointer<Context>) -> Result<JsValue, JsValue> {
+        Ok(JsValue::new(self))
+    }
+}
+
+impl FromJs for f32 {
+    fn from_js(ctx: GcPointer<Context>, value: JsValue) -> Result<Self, JsValue> {
+        f64::from_js(ctx, value).map(|x| x as f32)
+    }
+}
+
+impl IntoJs for f32 {
+    fn into_js(self, _ctx: GcPointer<Context>) -> Result<JsValue, JsValue> {
+        Ok(JsValue::new(self))
+    }
+}
+
+/// Value is converted with `ToNumber` and accepted only if number is integral and fits into the type.
+macro_rules! integer_conversions {
+    ($($t: ty),*) => {$(
+        impl FromJs for $t {
+            fn from_js(ctx: GcPointer<Context>, value: JsValue) -> Result<Self, JsValue> {
+                let number = f64::from_js(ctx, value)?;
+                if number.trunc() != number || number < <$t>::MIN as f64 || number > <$t>::MAX as f64 {
+                    return Err(JsValue::new(ctx.new_type_error(format!(
+                        "expected {}, got {}",
+                        stringify!($t),
+                        number
+                    ))));
+                }
+                Ok(number as $t)
+            }
+        }
+
+        impl IntoJs for $t {
+            fn into_js(self, _ctx: GcPointer<Context>) -> Result<JsValue, JsValue> {
+                Ok(JsValue::new(self))
+            }
+        }
+    )*};
+}
+
+integer_conversions!(u8, i8, u16, i16, u32, i32, u64, i64);
+
+impl FromJs for usize {
+    fn from_js(ctx: GcPointer<Context>, value: JsValue) -> Result<Self, JsValue> {
+        u64::from_js(ctx, value).map(|x| x as usize)
+    }
+}
+
+impl IntoJs for usize {
+    fn into_js(self, _ctx: GcPointer<Context>) -> Result<JsValue, JsValue> {
+        Ok(JsValue::new(self as u64))
+    }
+}
+
+/// Any value is converted with `ToString`.
+impl FromJs for String {
+    fn from_js(ctx: GcPointer<Context>, value: JsValue) -> Result<Self, JsValue> {
+        value.to_string(ctx)
+    }
+}
+
+impl IntoJs for String {
+    fn into_js(self, ctx: GcPointer<Context>) -> Result<JsValue, JsValue> {
+        Ok(JsValue::new(JsString::new(ctx, self)))
+    }
+}
+
+impl IntoJs for &str {
+    fn into_js(self, ctx: GcPointer<Context>) -> Result<JsValue, JsValue> {
+        Ok(JsValue::new(JsString::new(ctx, self)))
+    }
+}
+
+impl IntoJs for () {
+    fn into_js(self, _ctx: GcPointer<Context>) -> Result<JsValue, JsValue> {
+        Ok(JsValue::encode_undefined_value())
+    }
+}
+
+impl FromJs for GcPointer<JsObject> {
+    fn from_js(ctx: GcPointer<Context>, value: JsValue) -> Result<Self, JsValue> {
+        if value.is_jsobject() {
+            Ok(value.get_jsobject())
+        } else {
+            Err(type_error(ctx, "object", value))
+        }
+    }
+}
+
+impl IntoJs for GcPointer<JsObject> {
+    fn into_js(self, _ctx: GcPointer<Context>) -> Result<JsValue, JsValue> {
+        Ok(JsValue::new(self))
+    }
+}
+
+/// `undefined` and `null` are converted to `None`.
+impl<T: FromJs> FromJs for Option<T> {
+    fn from_js(ctx: GcPointer<Context>, value: JsValue) -> Result<Self, JsValue> {
+        if value.is_undefined() || value.is_null() {
+            Ok(None)
+        } else {
+            T::from_js(ctx, value).map(Some)
+        }
+    }
+}
+
+/// `None` is converted to `undefined`.
+impl<T: IntoJs> IntoJs for Option<T> {
+    fn into_js(self, ctx: GcPointer<Context>) -> Result<JsValue, JsValue> {
+        match self {
+            Some(value) => value.into_js(ctx),
+            None => Ok(JsValue::encode_undefined_value()),
+        }
+    }
+}
+
+/// `Err` is thrown as JS exception.
+impl<T: IntoJs> IntoJs for Result<T, JsValue> {
+    fn into_js(self, ctx: GcPointer<Context>) -> Result<JsValue, JsValue> {
+        self.and_then(|value| value.into_js(ctx))
+    }
+}
+
+/// Returns elements of array `value`.
+fn array_elements(ctx: GcPointer<Context>, value: JsValue) -> Result<Vec<JsValue>, JsValue> {
+    if !value.is_jsobject() || value.get_jsobject().tag() != ObjectTag::Array {
+        return Err(type_error(ctx, "array", value));
+    }
+    let stack = ctx.shadowstack();
+    letroot!(array = stack, value.get_jsobject());
+    let length = array.get(ctx, "length".intern())?.to_length(ctx)?;
+    let mut elements = Vec::with_capacity(length as _);
+    for i in 0..length {
+        elements.push(array.get(ctx, Symbol::Index(i))?);
+    }
+    Ok(elements)
+}
+
+/// Create array from `elements`. `elements` must be rooted by the caller.
+fn new_array(ctx: GcPointer<Context>, elements: &[JsValue]) -> Result<JsValue, JsValue> {
+    let stack = ctx.shadowstack();
+    letroot!(array = stack, JsArray::new(ctx, elements.len() as _));
+    for (i, element) in elements.iter().enumerate() {
+        array.put(ctx, Symbol::Index(i as _), *element, false)?;
+    }
+    Ok(JsValue::new(*array))
+}
+
+impl<T: FromJs> FromJs for Vec<T> {
+    fn from_js(ctx: GcPointer<Context>, value: JsValue) -> Result<Self, JsValue> {
+        let stack = ctx.shadowstack();
+        letroot!(elements = stack, array_elements(ctx, value)?);
+        elements
+            .iter()
+            .map(|element| T::from_js(ctx, *element))
+            .collect()
+    }
+}
+
+impl<T: IntoJs> IntoJs for Vec<T> {
+    fn into_js(self, ctx: GcPointer<Context>) -> Result<JsValue, JsValue> {
+        let stack = ctx.shadowstack();
+        letroot!(elements = stack, Vec::<JsValue>::with_capacity(self.len()));
+        for element in self {
+            elements.push(element.into_js(ctx)?);
+        }
+        new_array(ctx, &elements)
+    }
+}
+
+/// Own enumerable properties of object are converted to map entries.
+impl<K: FromJs + Eq + Hash, V: FromJs> FromJs for HashMap<K, V> {
+    fn from_js(ctx: GcPointer<Context>, value: JsValue) -> Result<Self, JsValue> {
+        if !value.is_jsobject() {
+            return Err(type_error(ctx, "object", value));
+        }
+        let stack = ctx.shadowstack();
+        letroot!(object = stack, value.get_jsobject());
+        let mut names = vec![];
+        object.get_own_property_names(
+            ctx,
+            &mut |name, _| names.push(name),
+            EnumerationMode::Default,
+        );
+        let mut map = HashMap::with_capacity(names.len());
+        for name in names {
+            let key = JsValue::new(JsString::new(ctx, ctx.description(name)));
+            let key = K::from_js(ctx, key)?;
+            let value = object.get(ctx, name)?;
+            map.insert(key, V::from_js(ctx, value)?);
+        }
+        Ok(map)
+    }
+}
+
+impl<K: AsRef<str>, V: IntoJs> IntoJs for HashMap<K, V> {
+    fn into_js(self, ctx: GcPointer<Context>) -> Result<JsValue, JsValue> {
+        let stack = ctx.shadowstack();
+        letroot!(object = stack, JsObject::new_empty(ctx));
+        for (key, value) in self {
+            let value = value.into_js(ctx)?;
+            object.put(ctx, key.as_ref().intern(), value, false)?;
+        }
+        Ok(JsValue::new(*object))
+    }
+}
+
+/// Tuples are converted from and into arrays.
+macro_rules! tuple_conversions {
+    ($count: expr; $($name: ident $index: tt),*) => {
+        impl<$($name: FromJs),*> FromJs for ($($name,)*) {
+            fn from_js(ctx: GcPointer<Context>, value: JsValue) -> Result<Self, JsValue> {
+                let stack = ctx.shadowstack();
+                letroot!(elements = stack, array_elements(ctx, value)?);
+                if elements.len() != $count {
+                    return Err(JsValue::new(ctx.new_type_error(format!(
+                        "expected array of length {}, got {}",
+                        $count,
+                        elements.len()
+                    ))));
+                }
+                Ok(($($name::from_js(ctx, elements[$index])?,)*))
+            }
+        }
+
+        impl<$($name: IntoJs),*> IntoJs for ($($name,)*) {
+            fn into_js(self, ctx: GcPointer<Context>) -> Result<JsValue, JsValue> {
+                let stack = ctx.shadowstack();
+                letroot!(elements = stack, Vec::<JsValue>::with_capacity($count));
+                $(elements.push(self.$index.into_js(ctx)?);)*
+                new_array(ctx, &elements)
+            }
+        }
+    };
+}
+
+tuple_conversions!(1; A 0);
+tuple_conversions!(2; A 0, B 1);
+tuple_conversions!(3; A 0, B 1, C 2);
+tuple_conversions!(4; A 0, B 1, C 2, D 3);
+tuple_conversions!(5; A 0, B 1, C 2, D 3, E 4);
+tuple_conversions!(6; A 0, B 1, C 2, D 3, E 4, F 5);
+
+/// Rust function that can be called from JS. Implemented for closures whose arguments implement [FromJs] and
+/// whose result implements [IntoJs]. `Args` is tuple of argument types.
+pub trait NativeFn<Args>: 'static {
+    /// Number of arguments, used as `length` of JS function.
+    fn arity(&self) -> u32;
+    fn invoke(&self, ctx: GcPointer<Context>, args: &Arguments) -> Result<JsValue, JsValue>;
+}
+
+macro_rules! native_fn {
+    ($count: expr; $($name: ident $index: tt),*) => {
+        impl<Func, Ret, $($name),*> NativeFn<($($name,)*)> for Func
+        where
+            Func: Fn($($name),*) -> Ret + 'static,
+            Ret: IntoJs,
+            $($name: FromJs,)*
+        {
+            fn arity(&self) -> u32 {
+                $count
+            }
+
+            #[allow(unused_variables)]
+            fn invoke(&self, ctx: GcPointer<Context>, args: &Arguments) -> Result<JsValue, JsValue> {
+                (self)($($name::from_js(ctx, args.at($index))?),*).into_js(ctx)
+            }
+        }
+    };
+}
+
+native_fn!(0;);
+native_fn!(1; A 0);
+native_fn!(2; A 0, B 1);
+native_fn!(3; A 0, B 1, C 2);
+native_fn!(4; A 0, B 1, C 2, D 3);
+native_fn!(5; A 0, B 1, C 2, D 3, E 4);
+native_fn!(6; A 0, B 1, C 2, D 3, E 4, F 5);
+
+/// Create JS function calling `f`. Functions created from closures can't be stored in snapshots.
+pub fn new_native_fn<Args, F: NativeFn<Args>>(
+    ctx: GcPointer<Context>,
+    name: Symbol,
+    f: F,
+) -> GcPointer<JsObject> {
+    let arity = f.arity();
+    JsClosureFunction::new(ctx, name, move |ctx, args| f.invoke(ctx, args), arity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FromJs, IntoJs};
+    use crate::gc::cell::GcPointer;
+    use crate::vm::{context::Context, tests::test_runtime, value::JsValue};
+    use std::collections::HashMap;
+
+    fn from_str<T: FromJs>(ctx: GcPointer<Context>, source: &str) -> Result<T, JsValue> {
+        let mut ctx = ctx;
+        let value = ctx.eval(source).unwrap_or_else(|_| panic!("{}", source));
+        T::from_js(ctx, value)
+    }
+
+    fn is_type_error<T>(ctx: GcPointer<Context>, result: Result<T, JsValue>) -> bool {
+        match result {
+            Ok(_) => false,
+            Err(error) => error
+                .to_string(ctx)
+                .map_or(false, |message| message.starts_with("TypeError")),
+        }
+    }
+
+    #[test]
+    fn test_primitive_coercion() {
+        let (mut runtime, _jobs) = test_runtime();
+        let ctx = Context::new(&mut runtime);
+        assert_eq!(from_str::<f64>(ctx, "'5'").ok(), Some(5.0));
+        assert!(from_str::<f64>(ctx, "undefined").unwrap().is_nan());
+        assert_eq!(
+            from_str::<f64>(ctx, "({ valueOf: function () { return 2; } })").ok(),
+            Some(2.0)
+        );
+        assert_eq!(from_str::<i32>(ctx, "'7'").ok(), Some(7));
+        assert_eq!(from_str::<u8>(ctx, "true").ok(), Some(1));
+        assert_eq!(from_str::<String>(ctx, "1.5").ok(), Some("1.5".to_owned()));
+        assert_eq!(
+            from_str::<String>(ctx, "null").ok(),
+            Some("null".to_owned())
+        );
+        assert_eq!(from_str::<bool>(ctx, "''").ok(), Some(false));
+        assert_eq!(from_str::<bool>(ctx, "({})").ok(), Some(true));
+        assert_eq!(from_str::<Option<f64>>(ctx, "null").ok(), Some(None));
+        assert_eq!(from_str::<Option<f64>>(ctx, "'3'").ok(), Some(Some(3.0)));
+
+        assert!(is_type_error(ctx, from_str::<i32>(ctx, "1.5")));
+        assert!(is_type_error(ctx, from_str::<u8>(ctx, "'300'")));
+        assert!(is_type_error(ctx, from_str::<i64>(ctx, "undefined")));
+        assert!(is_type_error(ctx, from_str::<f64>(ctx, "Symbol()")));
+    }
+
+    #[test]
+    fn test_compound_conversions() {
+        let (mut runtime, _jobs) = test_runtime();
+        let ctx = Context::new(&mut runtime);
+        assert_eq!(
+            from_str::<Vec<i32>>(ctx, "[1, '2', 3]").ok(),
+            Some(vec![1, 2, 3])
+        );
+        assert_eq!(
+            from_str::<(String, f64)>(ctx, "['a', 1]").ok(),
+            Some(("a".to_owned(), 1.0))
+        );
+        let map = from_str::<HashMap<String, u32>>(ctx, "({ a: 1, b: '2' })").unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!((map["a"], map["b"]), (1, 2));
+
+        assert!(is_type_error(ctx, from_str::<Vec<f64>>(ctx, "'12'")));
+        assert!(is_type_error(
+            ctx,
+            from_str::<Vec<f64>>(ctx, "({ length: 1, 0: 1 })")
+        ));
+        assert!(is_type_error(ctx, from_str::<(f64, f64)>(ctx, "[1]")));
+        assert!(is_type_error(
+            ctx,
+            from_str::<HashMap<String, f64>>(ctx, "1")
+        ));
+        assert!(is_type_error(
+            ctx,
+            from_str::<GcPointer<crate::vm::object::JsObject>>(ctx, "'x'")
+        ));
+
+        let value = (vec![1u8, 2], "x", Some(true), None::<f64>)
+            .into_js(ctx)
+            .unwrap();
+        assert_eq!(value.to_string(ctx).ok(), Some("1,2,x,true,".to_owned()));
+        let mut map = HashMap::new();
+        map.insert("key", vec![1.5]);
+        let value = map.into_js(ctx).unwrap();
+        assert_eq!(
+            <HashMap<String, Vec<f64>>>::from_js(ctx, value).ok(),
+            Some(vec![("key".to_owned(), vec![1.5])].into_iter().collect())
+        );
+    }
+
+    #[test]
+    fn test_register_fn() {
+        let (mut runtime, _jobs) = test_runtime();
+        let mut ctx = Context::new(&mut runtime);
+        ctx.register_fn("add", |a: f64, b: f64| a + b).unwrap();
+        ctx.register_fn("greet", |name: Option<String>| {
+            format!("Hello {}", name.unwrap_or_else(|| "world".to_owned()))
+        })
+        .unwrap();
+        ctx.register_fn("sum", |values: Vec<i32>| values.iter().sum::<i32>())
+            .unwrap();
+        ctx.register_fn("fail", |message: String| -> Result<(), JsValue> {
+            Err(JsValue::new(message.len() as u32))
+        })
+        .unwrap();
+        let result = ctx
+            .eval(
+                "let typeError = function (f) { try { f(); } catch (e) { return e instanceof TypeError; } }; \
+                 let thrown = function (f) { try { f(); } catch (e) { return e; } }; \
+                 return [add('2', 3), add(1), greet(), greet(1), sum([1, '2', 3]), add.length, greet.name, \
+                     typeError(function () { sum(1); }), typeError(function () { sum([1.5]); }), \
+                     thrown(function () { fail('abc'); })];",
+            )
+            .and_then(|value| value.to_string(ctx));
+        assert_eq!(
+            result.ok(),
+            Some("5,NaN,Hello world,Hello 1,6,2,greet,true,true,3".to_owned())
+        );
+    }
+}
@@ -22,6 +22,8 @@ pub struct Environment {
     pub parent: Option<GcPointer<Self>>,
     pub values_ptr: *mut Variable,
     pub values_count: u32,
+    /// Variables declared at runtime by sloppy mode direct `eval`.
+    pub dynamic: Option<GcPointer<JsObject>>,
 }
 
 impl Environment {
@@ -40,6 +42,7 @@ impl Environment {
                 parent: None,
                 values_ptr: ptr,
                 values_count: cap,
+                dynamic: None,
             })
         }
     }
@@ -73,6 +76,7 @@ impl GcCell for Environment {
 unsafe impl Trace for Environment {
     fn trace(&mut self, visitor: &mut dyn Tracer) {
         self.parent.trace(visitor);
+        self.dynamic.trace(visitor);
         for var in self.as_slice_mut() {
             var.value.trace(visitor);
         }
@@ -90,11 +94,13 @@ impl Deserializable for Environment {
             let mutable = bool::deserialize_inplace(deser);
             ptr.add(i as _).write(Variable { value, mutable });
         }
+        let dynamic = Option::<GcPointer<JsObject>>::deserialize_inplace(deser);
         //let values = Vec::<(JsValue, bool)>::deserialize_inplace(deser);
         Self {
             values_ptr: ptr,
             values_count: cap,
             parent,
+            dynamic,
         }
     }
     unsafe fn deserialize(at: *mut u8, deser: &mut Deserializer) {
@@ -118,5 +124,6 @@ impl Serializable for Environment {
             value.value.serialize(serializer);
             value.mutable.serialize(serializer);
         }
+        self.dynamic.serialize(serializer);
     }
 }
@@ -3,7 +3,7 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 use super::{
     attributes::*, method_table::*, object::*, property_descriptor::*, string::JsString,
-    structure::*, symbol_table::*, value::JsValue, Context,
+    stack_trace::JsStackTrace, structure::*, symbol_table::*, value::JsValue, Context,
 };
 use crate::gc::cell::GcPointer;
 
@@ -35,14 +35,7 @@ impl JsError {
             structure.unwrap_or_else(|| ctx.global_data().error_structure.unwrap())
         );
         let mut obj = JsObject::new(ctx, &shape, Self::class(), ObjectTag::Ordinary);
-        let stack = ctx.stacktrace();
-        let str = JsString::new(ctx, stack);
-        let _ = obj.define_own_property(
-            ctx,
-            "stack".intern(),
-            &*DataDescriptor::new(JsValue::new(str), W | C),
-            false,
-        );
+        JsStackTrace::attach(ctx, obj, None);
         if !s.as_str().is_empty() {
             let _ = obj.define_own_property(
                 ctx,
@@ -74,14 +67,7 @@ impl JsEvalError {
             structure.unwrap_or_else(|| ctx.global_data().eval_error_structure.unwrap())
         );
         let mut obj = JsObject::new(ctx, &shape, Self::class(), ObjectTag::Ordinary);
-        let stack = ctx.stacktrace();
-        let str = JsString::new(ctx, stack);
-        let _ = obj.define_own_property(
-            ctx,
-            "stack".intern(),
-            &*DataDescriptor::new(JsValue::new(str), W | C),
-            false,
-        );
+        JsStackTrace::attach(ctx, obj, None);
         if !s.as_str().is_empty() {
             let _ = obj.define_own_property(
                 ctx,
@@ -113,14 +99,7 @@ impl JsRangeError {
             structure.unwrap_or_else(|| ctx.global_data().range_error_structure.unwrap())
         );
         let mut obj = JsObject::new(ctx, &shape, Self::class(), ObjectTag::Ordinary);
-        let stack = ctx.stacktrace();
-        let str = JsString::new(ctx, stack);
-        let _ = obj.define_own_property(
-            ctx,
-            "stack".intern(),
-            &*DataDescriptor::new(JsValue::new(str), W | C),
-            false,
-        );
+        JsStackTrace::attach(ctx, obj, None);
         if !s.as_str().is_empty() {
             let _ = obj.define_own_property(
                 ctx,
@@ -152,14 +131,7 @@ impl JsReferenceError {
             structure.unwrap_or_else(|| ctx.global_data().reference_error_structure.unwrap())
         );
         let mut obj = JsObject::new(ctx, &shape, Self::class(), ObjectTag::Ordinary);
-        let stack = ctx.stacktrace();
-        let str = JsString::new(ctx, stack);
-        let _ = obj.define_own_property(
-            ctx,
-            "stack".intern(),
-            &*DataDescriptor::new(JsValue::new(str), W | C),
-            false,
-        );
+        JsStackTrace::attach(ctx, obj, None);
         if !s.as_str().is_empty() {
             let _ = obj.define_own_property(
                 ctx,
@@ -191,14 +163,7 @@ impl JsSyntaxError {
             structure.unwrap_or_else(|| ctx.global_data().syntax_error_structure.unwrap())
         );
         let mut obj = JsObject::new(ctx, &shape, Self::class(), ObjectTag::Ordinary);
-        let stack = ctx.stacktrace();
-        let str = JsString::new(ctx, stack);
-        let _ = obj.define_own_property(
-            ctx,
-            "stack".intern(),
-            &*DataDescriptor::new(JsValue::new(str), W | C),
-            false,
-        );
+        JsStackTrace::attach(ctx, obj, None);
         if !s.as_str().is_empty() {
             let _ = obj.define_own_property(
                 ctx,
@@ -229,14 +194,7 @@ impl JsTypeError {
             structure.unwrap_or_else(|| ctx.global_data().type_error_structure.unwrap())
         );
         let mut obj = JsObject::new(ctx, &shape, Self::class(), ObjectTag::Ordinary);
-        let stack = ctx.stacktrace();
-        let str = JsString::new(ctx, stack);
-        let _ = obj.define_own_property(
-            ctx,
-            "stack".intern(),
-            &*DataDescriptor::new(JsValue::new(str), W | C),
-            false,
-        );
+        JsStackTrace::attach(ctx, obj, None);
         if !s.as_str().is_empty() {
             let _ = obj.define_own_property(
                 ctx,
@@ -268,14 +226,7 @@ impl JsURIError {
             structure.unwrap_or_else(|| ctx.global_data().uri_error_structure.unwrap())
         );
         let mut obj = JsObject::new(ctx, &shape, Self::class(), ObjectTag::Ordinary);
-        let stack = ctx.stacktrace();
-        let str = JsString::new(ctx, stack);
-        let _ = obj.define_own_property(
-            ctx,
-            "stack".intern(),
-            &*DataDescriptor::new(JsValue::new(str), W | C),
-            false,
-        );
+        JsStackTrace::attach(ctx, obj, None);
         if !s.as_str().is_empty() {
             let _ = obj.define_own_property(
                 ctx,
@@ -6,6 +6,7 @@ use super::interpreter::eval;
 use super::structure::Structure;
 use super::symbol_table::Symbol;
 use super::value::*;
+use super::GlobalData;
 use super::{arguments::*, code_block::CodeBlock};
 use super::{array_storage::ArrayStorage, property_descriptor::*};
 use super::{attributes::*, symbol_table::Internable};
@@ -28,12 +29,42 @@ pub struct JsFunction {
     pub ty: FuncType,
 }
 
+/// `GetFunctionRealm(obj)`: realm of function `obj` or of the target of a bound function. Returns `ctx` for
+/// objects that are not functions.
+pub fn get_function_realm(ctx: GcPointer<Context>, obj: GcPointer<JsObject>) -> GcPointer<Context> {
+    if !obj.is_class(JsFunction::class()) {
+        return ctx;
+    }
+    let func = obj.as_function();
+    match func.ty {
+        FuncType::Bound(ref bound) => get_function_realm(ctx, bound.target),
+        _ => func.ctx,
+    }
+}
+
+/// `GetPrototypeFromConstructor(constructor, intrinsicDefaultProto)`: `prototype` of `constructor` or, when it is not
+/// an object, the `default` intrinsic of the constructor's realm.
+pub fn get_prototype_from_constructor(
+    ctx: GcPointer<Context>,
+    mut constructor: GcPointer<JsObject>,
+    default: impl FnOnce(&GlobalData) -> Option<GcPointer<JsObject>>,
+) -> Result<GcPointer<JsObject>, JsValue> {
+    let proto = constructor.get(ctx, "prototype".intern())?;
+    if proto.is_jsobject() {
+        return Ok(proto.get_jsobject());
+    }
+    let realm = get_function_realm(ctx, constructor);
+    Ok(default(realm.global_data()).unwrap())
+}
+
 pub enum FuncType {
     Native(JsNativeFunction),
     Closure(JsClosureFunction),
     User(JsVMFunction),
     Bound(JsBoundFunction),
     Generator(JsGeneratorFunction),
+    /// Wrapped function exotic object created at `ShadowRealm` boundary.
+    Wrapped(JsWrappedFunction),
 }
 
 impl JsClass for JsFunction {
@@ -67,6 +98,17 @@ impl JsFunction {
         }
 
         let got = this.get(ctx, "prototype".intern())?;
+        Self::has_instance_with_prototype(ctx, got, val)
+    }
+    /// Walks prototype chain of `val` looking for already loaded `prototype` of constructor.
+    pub fn has_instance_with_prototype(
+        ctx: GcPointer<Context>,
+        got: JsValue,
+        val: JsValue,
+    ) -> Result<bool, JsValue> {
+        if !val.is_jsobject() {
+            return Ok(false);
+        }
         if !got.is_jsobject() {
             let msg = JsString::new(ctx, "'prototype' is not object");
             return Err(JsValue::encode_object_value(JsTypeError::new(
@@ -92,6 +134,7 @@ impl JsFunction {
             FuncType::User(ref x) => x.code.strict,
             FuncType::Bound(ref x) => x.target.as_function().is_strict(),
             FuncType::Generator(ref x) => x.function.as_function().is_strict(),
+            FuncType::Wrapped(_) => false,
         }
     }
 
@@ -185,16 +228,19 @@ impl JsFunction {
             }
             FuncType::Bound(ref mut x) => {
                 let mut ctx = self.ctx;
-
+                // bound arguments are followed by arguments of the call
+                let mut values = x.args.as_slice().to_vec();
+                values.extend_from_slice(args.values);
                 let mut args = Arguments {
                     this: x.this,
                     ctor_call: args.ctor_call,
-                    values: x.args.as_slice_mut(),
+                    values: &mut values,
                 };
                 let mut target = x.target;
                 target.as_function_mut().call(ctx, &mut args, this)
             }
             FuncType::Generator(ref mut x) => x.call(self.ctx, args, this),
+            FuncType::Wrapped(ref x) => x.call(self.ctx, args),
         }
     } /*
       pub fn call_with_env<'a>(
@@ -591,6 +637,9 @@ unsafe impl Trace for JsFunction {
             FuncType::Generator(ref mut x) => {
                 x.function.trace(tracer);
             }
+            FuncType::Wrapped(ref mut x) => {
+                x.target.trace(tracer);
+            }
             _ => (),
         }
     }
@@ -631,6 +680,12 @@ impl JsVMFunction {
             &*DataDescriptor::new(JsValue::encode_object_value(*this), W | C),
             false,
         );
+        let _ = this.define_own_property(
+            ctx,
+            "length".intern(),
+            &*DataDescriptor::new(JsValue::new(code.length as i32), C),
+            false,
+        );
         let _ = this.define_own_property(
             ctx,
             "prototype".intern(),
@@ -670,7 +725,8 @@ impl GcPointer<JsObject> {
                 if res.is_object() && res.get_object().is::<JsObject>() {
                     Some(res.get_object().downcast_unchecked())
                 } else {
-                    Some(ctx.global_data().get_object_prototype())
+                    // GetPrototypeFromConstructor: fall back to %Object.prototype% of the constructor's realm.
+                    Some(get_function_realm(ctx, *obj).global_data().get_object_prototype())
                 },
                 false,
             )
@@ -699,6 +755,123 @@ pub struct JsBoundFunction {
     pub target: GcPointer<JsObject>,
 }
 
+/// Function that forwards calls to `target` from another realm. Only primitives and callables cross the boundary,
+/// callables are wrapped again on the other side.
+pub struct JsWrappedFunction {
+    pub target: GcPointer<JsObject>,
+}
+
+impl JsWrappedFunction {
+    /// `WrappedFunctionCreate(callerRealm, target)`
+    pub fn create(
+        caller_realm: GcPointer<Context>,
+        target: GcPointer<JsObject>,
+    ) -> Result<GcPointer<JsObject>, JsValue> {
+        let stack = caller_realm.shadowstack();
+        letroot!(target = stack, target);
+        letroot!(
+            wrapped = stack,
+            JsFunction::new(
+                caller_realm,
+                FuncType::Wrapped(JsWrappedFunction { target: *target }),
+                false,
+            )
+        );
+        // CopyNameAndLength(wrapped, target)
+        let length = match target.get(caller_realm, "length".intern()) {
+            Ok(length) if length.is_number() => {
+                let length = length.get_number();
+                if length.is_infinite() && length > 0.0 {
+                    length
+                } else if length.is_nan() || length <= 0.0 {
+                    0.0
+                } else {
+                    length.trunc()
+                }
+            }
+            Ok(_) => 0.0,
+            Err(_) => {
+                return Err(JsValue::new(
+                    caller_realm.new_type_error("ShadowRealm: could not read length of wrapped function"),
+                ))
+            }
+        };
+        let name = match target.get(caller_realm, "name".intern()) {
+            Ok(name) if name.is_jsstring() => name,
+            Ok(_) => JsValue::new(JsString::new(caller_realm, "")),
+            Err(_) => {
+                return Err(JsValue::new(
+                    caller_realm.new_type_error("ShadowRealm: could not read name of wrapped function"),
+                ))
+            }
+        };
+        wrapped.define_own_property(
+            caller_realm,
+            "length".intern(),
+            &*DataDescriptor::new(JsValue::new(length), C),
+            false,
+        )?;
+        wrapped.define_own_property(
+            caller_realm,
+            "name".intern(),
+            &*DataDescriptor::new(name, C),
+            false,
+        )?;
+        Ok(*wrapped)
+    }
+
+    /// `[[Call]]` of wrapped function exotic objects. Arguments are wrapped into the realm of target and result is
+    /// wrapped back into `caller_realm`. Any error thrown by target is replaced by `TypeError` from `caller_realm`.
+    fn call(&self, caller_realm: GcPointer<Context>, args: &mut Arguments) -> Result<JsValue, JsValue> {
+        if args.ctor_call {
+            return Err(JsValue::new(
+                caller_realm.new_type_error("wrapped function is not a constructor"),
+            ));
+        }
+        let target_realm = get_function_realm(caller_realm, self.target);
+        let stack = caller_realm.shadowstack();
+        let result = (|| {
+            let this = get_wrapped_value(target_realm, args.this)?;
+            let mut values = Vec::with_capacity(args.size());
+            for i in 0..args.size() {
+                values.push(get_wrapped_value(target_realm, args.at(i))?);
+            }
+            letroot!(args = stack, Arguments::new(this, &mut values));
+            letroot!(target = stack, self.target);
+            let callee = JsValue::new(*target);
+            target
+                .as_function_mut()
+                .call(target_realm, &mut args, callee)
+        })();
+        match result {
+            Ok(value) => get_wrapped_value(caller_realm, value),
+            Err(error) => {
+                let message = error
+                    .to_string(target_realm)
+                    .unwrap_or_else(|_| "unknown error".to_string());
+                Err(JsValue::new(caller_realm.new_type_error(format!(
+                    "wrapped function threw: {}",
+                    message
+                ))))
+            }
+        }
+    }
+}
+
+/// `GetWrappedValue(realm, value)`: primitives are returned as is, callables are wrapped into `realm`, other
+/// objects throw `TypeError`.
+pub fn get_wrapped_value(realm: GcPointer<Context>, value: JsValue) -> Result<JsValue, JsValue> {
+    if !value.is_jsobject() {
+        return Ok(value);
+    }
+    if !value.is_callable() {
+        return Err(JsValue::new(realm.new_type_error(
+            "ShadowRealm: only primitives and callables can cross realm boundary",
+        )));
+    }
+    JsWrappedFunction::create(realm, value.get_jsobject()).map(JsValue::new)
+}
+
 /// interpreter call frame copied allocated on the heap. It is used again copied to interpreter stack
 /// when function execution state is restored.
 pub struct HeapCallFrame {
@@ -806,9 +979,13 @@ impl JsGeneratorFunction {
         let code = func.as_function().as_vm().code;
         let f = JsGeneratorFunction { function: func };
         ctx.heap().defer();
+        let structure = ctx
+            .global_data()
+            .generator_function_structure
+            .unwrap_or_else(|| ctx.global_data().get_function_struct());
         letroot!(
             this = stack,
-            JsFunction::new(ctx, FuncType::Generator(f), false)
+            JsFunction::new_with_struct(ctx, &structure, FuncType::Generator(f), false)
         );
         letroot!(proto = stack, JsObject::new_empty(ctx));
         ctx.heap().undefer();
@@ -820,6 +997,12 @@ impl JsGeneratorFunction {
         );
         let desc = ctx.description(code.name);
         letroot!(s = stack, JsString::new(ctx, desc));
+        let _ = this.define_own_property(
+            ctx,
+            "length".intern(),
+            &*DataDescriptor::new(JsValue::new(code.length as i32), C),
+            false,
+        );
         let _ = this.define_own_property(
             ctx,
             "prototype".intern(),
@@ -873,9 +1056,27 @@ pub enum GeneratorMagic {
     Return,
     Throw,
 }
-fn async_func_resume(
+/// Resume function saved in `state` where it was suspended.
+pub(crate) fn async_func_resume(
+    ctx: GcPointer<Context>,
+    state: &mut AsyncFunctionState,
+) -> Result<JsValue, JsValue> {
+    async_func_enter(ctx, state, None)
+}
+
+/// Resume function saved in `state` by throwing `error` where it was suspended.
+pub(crate) fn async_func_throw(
+    ctx: GcPointer<Context>,
+    state: &mut AsyncFunctionState,
+    error: JsValue,
+) -> Result<JsValue, JsValue> {
+    async_func_enter(ctx, state, Some(error))
+}
+
+fn async_func_enter(
     mut ctx: GcPointer<Context>,
     state: &mut AsyncFunctionState,
+    mut error: Option<JsValue>,
 ) -> Result<JsValue, JsValue> {
     let mut frame = ctx
         .stack
@@ -888,7 +1089,10 @@ fn async_func_resume(
         state.frame.restore(&mut *frame);
         (*frame).exit_on_return = true;
         loop {
-            let result = eval(ctx, frame);
+            let result = match error.take() {
+                Some(error) => Err(error),
+                None => eval(ctx, frame),
+            };
             match result {
                 Ok(value) => return Ok(value),
                 Err(e) => {
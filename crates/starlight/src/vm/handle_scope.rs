@@ -0,0 +1,214 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+//! Scoped handles for embedders.
+//!
+//! [Context::scope] roots every value created through [HandleScope] until the closure returns. Values are
+//! handed out as [Local] handles whose lifetime is bound to the scope so they can't outlive their roots:
+//!
+//! ```rust,ignore
+//! let sum: f64 = ctx.scope(|scope| {
+//!     let add = scope.eval("(function (a, b) { return a + b; })")?;
+//!     let a = scope.local(1.0)?;
+//!     let b = scope.local(2.0)?;
+//!     scope.call(add, None, &[a, b])?.to(scope.context())
+//! })?;
+//! ```
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+use super::{
+    arguments::Arguments,
+    context::Context,
+    convert::{FromJs, IntoJs},
+    slot::Slot,
+    symbol_table::Internable,
+    value::JsValue,
+};
+use crate::gc::cell::GcPointer;
+
+/// Scope that keeps values alive. Created by [Context::scope].
+///
+/// Rooted values are stored in the VM and traced by its GC constraint, the shadow stack is only scanned
+/// conservatively and wouldn't keep values stored in a heap allocated vector alive.
+pub struct HandleScope<'s> {
+    ctx: GcPointer<Context>,
+    /// Index of this scope in [VirtualMachine::handle_scopes](super::VirtualMachine).
+    index: usize,
+    marker: PhantomData<&'s ()>,
+}
+
+/// Value rooted by [HandleScope] `'s`.
+#[derive(Clone, Copy)]
+pub struct Local<'s> {
+    value: JsValue,
+    marker: PhantomData<&'s ()>,
+}
+
+impl<'s> HandleScope<'s> {
+    pub(crate) fn new(ctx: GcPointer<Context>) -> Self {
+        let mut vm = ctx.vm();
+        vm.handle_scopes.push(vec![]);
+        Self {
+            ctx,
+            index: vm.handle_scopes.len() - 1,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn context(&self) -> GcPointer<Context> {
+        self.ctx
+    }
+
+    /// Root `value` in this scope.
+    pub fn local(&self, value: impl IntoJs) -> Result<Local<'s>, JsValue> {
+        let value = value.into_js(self.ctx)?;
+        self.ctx.vm().handle_scopes[self.index].push(value);
+        Ok(Local {
+            value,
+            marker: PhantomData,
+        })
+    }
+
+    pub fn global(&self) -> Local<'s> {
+        let mut ctx = self.ctx;
+        self.local(ctx.global_object())
+            .expect("global object conversion can't fail")
+    }
+
+    /// Evaluate `script` in the global scope and root its result.
+    pub fn eval(&self, script: &str) -> Result<Local<'s>, JsValue> {
+        let mut ctx = self.ctx;
+        let value = ctx.eval(script)?;
+        self.local(value)
+    }
+
+    /// Get property `name` of `object`.
+    pub fn get(&self, object: Local<'s>, name: &str) -> Result<Local<'s>, JsValue> {
+        let value = object
+            .value
+            .get_slot(self.ctx, name.intern(), &mut Slot::new())?;
+        self.local(value)
+    }
+
+    /// Set property `name` of `object` to `value`.
+    pub fn set(&self, object: Local<'s>, name: &str, value: impl IntoJs) -> Result<(), JsValue> {
+        let value = self.local(value)?;
+        let mut object = object.value.to_object(self.ctx)?;
+        object.put(self.ctx, name.intern(), value.value, true)
+    }
+
+    /// Call `function` with `this` (`undefined` if `None`) and `args`.
+    pub fn call(
+        &self,
+        function: Local<'s>,
+        this: Option<Local<'s>>,
+        args: &[Local<'s>],
+    ) -> Result<Local<'s>, JsValue> {
+        if !function.value.is_callable() {
+            return Err(JsValue::new(
+                self.ctx.new_type_error("value is not a function"),
+            ));
+        }
+        let this = this
+            .map(|this| this.value)
+            .unwrap_or_else(JsValue::encode_undefined_value);
+        let mut values = args.iter().map(|arg| arg.value).collect::<Vec<_>>();
+        let mut arguments = Arguments::new(this, &mut values);
+        let result = function.value.get_jsobject().as_function_mut().call(
+            self.ctx,
+            &mut arguments,
+            function.value,
+        )?;
+        self.local(result)
+    }
+}
+
+impl Drop for HandleScope<'_> {
+    fn drop(&mut self) {
+        // scopes are only created by `Context::scope` so they are dropped in reverse order of creation.
+        let mut vm = self.ctx.vm();
+        vm.handle_scopes.truncate(self.index);
+    }
+}
+
+impl<'s> Local<'s> {
+    pub fn value(&self) -> JsValue {
+        self.value
+    }
+
+    /// Convert value into Rust type `T`.
+    pub fn to<T: FromJs>(&self, ctx: GcPointer<Context>) -> Result<T, JsValue> {
+        T::from_js(ctx, self.value)
+    }
+}
+
+impl Deref for Local<'_> {
+    type Target = JsValue;
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vm::{context::Context, tests::test_runtime};
+
+    #[test]
+    fn test_scope() {
+        let (mut runtime, _jobs) = test_runtime();
+        let ctx = Context::new(&mut runtime);
+        let result = ctx.scope(|scope| {
+            let add = scope.eval("(function (a, b) { return a + b; })")?;
+            let a = scope.local(1.0)?;
+            let b = scope.local("2")?;
+            let sum = scope
+                .call(add, None, &[a, b])?
+                .to::<String>(scope.context())?;
+
+            let global = scope.global();
+            scope.set(global, "answer", 42)?;
+            let answer = scope.eval("answer")?.to::<f64>(scope.context())?;
+            let object = scope.eval("({ x: [1, 2] })")?;
+            let x = scope.get(object, "x")?.to::<Vec<u8>>(scope.context())?;
+
+            let not_function = scope.call(a, None, &[]).is_err();
+            Ok::<_, crate::vm::value::JsValue>((sum, answer, x, not_function))
+        });
+        assert_eq!(result.ok(), Some(("12".to_owned(), 42.0, vec![1, 2], true)));
+    }
+
+    #[test]
+    fn test_locals_survive_gc() {
+        let (mut runtime, _jobs) = test_runtime();
+        let ctx = Context::new(&mut runtime);
+        let result = ctx.scope(|scope| {
+            let strings = (0..100)
+                .map(|i| scope.local(format!("string {}", i)))
+                .collect::<Result<Vec<_>, _>>()?;
+            let object = scope.eval("({ name: 'object' })")?;
+            let mut ctx = scope.context();
+            let inner = ctx.scope(|inner| {
+                let value = inner.local("inner")?;
+                let outer = scope.local("outer")?;
+                ctx.heap().gc();
+                Ok::<_, crate::vm::value::JsValue>((value.to::<String>(ctx)?, outer))
+            });
+            let (inner, outer) = inner?;
+            ctx.heap().gc();
+            let name = scope.get(object, "name")?.to::<String>(ctx)?;
+            let last = strings[99].to::<String>(ctx)?;
+            Ok::<_, crate::vm::value::JsValue>((name, last, inner, outer.to::<String>(ctx)?))
+        });
+        assert_eq!(
+            result.ok(),
+            Some((
+                "object".to_owned(),
+                "string 99".to_owned(),
+                "inner".to_owned(),
+                "outer".to_owned()
+            ))
+        );
+        assert!(runtime.handle_scopes.is_empty());
+    }
+}
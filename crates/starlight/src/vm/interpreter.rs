@@ -5,8 +5,10 @@ use self::{frame::CallFrame, stack::Stack};
 use super::function::*;
 use super::{
     arguments::*, array::*, code_block::CodeBlock, environment::*, error::JsTypeError, error::*,
-    native_iterator::*, object::*, slot::*, string::JsString, symbol_table::*, value::*,
+    module::ModuleRecord, native_iterator::*, object::*, slot::*, string::JsString,
+    structure::Structure, symbol_table::*, value::*,
 };
+use crate::bytecompiler::ByteCompiler;
 use crate::letroot;
 use crate::vm::class::JsClass;
 use crate::vm::context::Context;
@@ -33,6 +35,9 @@ impl GcPointer<Context> {
         args_: &Arguments,
         callee: JsValue,
     ) -> Result<JsValue, JsValue> {
+        if unlikely(func.code.is_lazy()) {
+            ByteCompiler::compile_lazy(self, func.code)?;
+        }
         let mut scope = unsafe { env.get_object().downcast::<Environment>().unwrap() };
 
         let mut nscope = Environment::new(
@@ -108,6 +113,9 @@ impl GcPointer<Context> {
         env: JsValue,
         args_: &Arguments,
     ) -> Result<(JsValue, GcPointer<Environment>), JsValue> {
+        if unlikely(func.code.is_lazy()) {
+            ByteCompiler::compile_lazy(self, func.code)?;
+        }
         let stack = self.shadowstack();
         letroot!(scope = stack, unsafe {
             env.get_object().downcast::<Environment>().unwrap()
@@ -182,6 +190,54 @@ impl GcPointer<Context> {
 
         Ok((_this, *nscope))
     }
+
+    /// Variant of [setup_for_vm_call](Self::setup_for_vm_call) used by monomorphic call sites. Arguments are copied
+    /// from interpreter stack straight into new environment without creating [Arguments].
+    ///
+    /// Must be used only when [CodeBlock::can_use_fast_call] is true.
+    pub(crate) fn setup_for_vm_call_fast(
+        mut self,
+        func: &JsVMFunction,
+        this: JsValue,
+        args: &[JsValue],
+    ) -> (JsValue, GcPointer<Environment>) {
+        debug_assert!(func.code.can_use_fast_call());
+        let mut nscope = Environment::new(self, func.code.param_count + func.code.var_count);
+        nscope.parent = Some(func.scope);
+        let values = nscope.as_slice_mut();
+        for (i, arg) in args.iter().take(func.code.param_count as usize).enumerate() {
+            values[i].value = *arg;
+        }
+        let this = if func.code.strict && !this.is_object() {
+            JsValue::encode_undefined_value()
+        } else if this.is_undefined() {
+            JsValue::encode_object_value(self.global_object())
+        } else {
+            this
+        };
+        (this, nscope)
+    }
+
+    /// Run code of module function `func` from `offset` in module environment `env`.
+    pub(crate) fn perform_module_call(
+        self,
+        func: &JsVMFunction,
+        env: GcPointer<Environment>,
+        offset: u32,
+        callee: JsValue,
+    ) -> Result<JsValue, JsValue> {
+        unsafe {
+            eval_internal(
+                self,
+                func.code,
+                &func.code.code[offset as usize] as *const u8 as *mut u8,
+                JsValue::encode_undefined_value(),
+                false,
+                env,
+                callee,
+            )
+        }
+    }
 }
 
 #[inline(never)]
@@ -626,36 +682,21 @@ pub unsafe fn eval(mut ctx: GcPointer<Context>, frame: *mut CallFrame) -> Result
                 if likely(object.is_jsobject()) {
                     letroot!(obj = gcstack, object.get_jsobject());
                     #[cfg(not(feature = "no-inline-caching"))]
-                    if let TypeFeedBack::PropertyCache {
-                        structure,
-                        offset,
-                        mode,
-                    } = unwrap_unchecked(frame.code_block)
-                        .feedback
-                        .get_unchecked(fdbk as usize)
                     {
-                        match mode {
-                            &GetByIdMode::Default => {
-                                if GcPointer::ptr_eq(structure, &obj.structure()) {
-                                    frame.push(*obj.direct(*offset as _));
-
-                                    continue;
-                                }
-                            }
-                            GetByIdMode::ProtoLoad(base) => {
-                                if false && GcPointer::ptr_eq(structure, &obj.structure()) {
-                                    frame.push(*base.direct(*offset as _));
-
-                                    continue;
-                                }
-                            }
-                            &GetByIdMode::ArrayLength => {
-                                if obj.is_class(JsArray::class()) {
-                                    frame.push(JsValue::new(obj.indexed.length()));
-                                    continue;
-                                }
+                        let mut code = unwrap_unchecked(frame.code_block);
+                        let feedback = code.feedback.get_unchecked_mut(fdbk as usize);
+                        if let Some(value) = feedback.lookup_load(ctx, &obj, name) {
+                            ctx.vm.ic_stats.get_by_id_hits += 1;
+                            frame.push(value);
+                            continue;
+                        }
+                        if feedback.is_megamorphic() {
+                            if let Some(value) = megamorphic_load(ctx, &obj, name) {
+                                frame.push(value);
+                                continue;
                             }
                         }
+                        ctx.vm.ic_stats.get_by_id_misses += 1;
                     }
 
                     #[inline(never)]
@@ -670,57 +711,31 @@ pub unsafe fn eval(mut ctx: GcPointer<Context>, frame: *mut CallFrame) -> Result
                     ) -> Result<(), JsValue> {
                         let mut slot = Slot::new();
                         if name == length_id() && obj.is_class(JsArray::class()) {
-                            *unwrap_unchecked(frame.code_block)
-                                .feedback
-                                .get_unchecked_mut(fdbk as usize) = TypeFeedBack::PropertyCache {
-                                structure: obj.structure(),
-                                mode: GetByIdMode::ArrayLength,
-                                offset: u32::MAX,
-                            };
+                            #[cfg(not(feature = "no-inline-caching"))]
+                            update_load_feedback(
+                                ctx,
+                                unwrap_unchecked(frame.code_block),
+                                fdbk,
+                                name,
+                                PropertyCacheEntry {
+                                    structure: obj.structure(),
+                                    mode: GetByIdMode::ArrayLength,
+                                    offset: u32::MAX,
+                                },
+                            );
                             frame.push(JsValue::new(obj.indexed.length()));
                             return Ok(());
                         }
                         let found = obj.get_property_slot(ctx, name, &mut slot);
                         #[cfg(not(feature = "no-inline-caching"))]
-                        if slot.is_load_cacheable() {
-                            let (structure, mode) = match slot.base() {
-                                Some(object) => {
-                                    if let Some(proto) = obj.prototype() {
-                                        if GcPointer::ptr_eq(proto, object) {
-                                            (
-                                                obj.structure(),
-                                                GetByIdMode::ProtoLoad(object.downcast_unchecked()),
-                                            )
-                                        } else {
-                                            (
-                                                slot.base()
-                                                    .unwrap()
-                                                    .downcast_unchecked::<JsObject>()
-                                                    .structure(),
-                                                GetByIdMode::Default,
-                                            )
-                                        }
-                                    } else {
-                                        (
-                                            slot.base()
-                                                .unwrap()
-                                                .downcast_unchecked::<JsObject>()
-                                                .structure(),
-                                            GetByIdMode::Default,
-                                        )
-                                    }
-                                }
-
-                                None => unreachable!(),
-                            };
-
-                            *unwrap_unchecked(frame.code_block)
-                                .feedback
-                                .get_unchecked_mut(fdbk as usize) = TypeFeedBack::PropertyCache {
-                                structure,
-                                mode,
-                                offset: slot.offset(),
-                            }
+                        if let Some(entry) = inline_cache::load_cache_entry(ctx, obj, &slot) {
+                            update_load_feedback(
+                                ctx,
+                                unwrap_unchecked(frame.code_block),
+                                fdbk,
+                                name,
+                                entry,
+                            );
                         }
                         if found {
                             frame.push(slot.get(ctx, JsValue::new(*obj))?);
@@ -761,50 +776,31 @@ pub unsafe fn eval(mut ctx: GcPointer<Context>, frame: *mut CallFrame) -> Result
                 let value = frame.pop();
                 if likely(object.is_jsobject()) {
                     let mut obj = object.get_jsobject();
-                    'exit: loop {
-                        'slowpath: loop {
-                            match unwrap_unchecked(frame.code_block).feedback[fdbk as usize] {
-                                #[cfg(not(feature = "no-inline-caching"))]
-                                TypeFeedBack::PutByIdFeedBack {
-                                    ref new_structure,
-                                    ref old_structure,
-                                    ref offset,
-                                    ref structure_chain,
-                                } => {
-                                    if Some(obj.structure()) != *old_structure {
-                                        break 'slowpath;
-                                    }
-                                    if new_structure.is_none() {
-                                        *obj.direct_mut(*offset as usize) = value;
-                                        break 'exit;
-                                    }
-
-                                    let vector = &structure_chain.unwrap().vector;
-                                    let mut i = 0;
-
-                                    let mut cur = old_structure.unwrap().prototype;
-                                    while let Some(proto) = cur {
-                                        let structure = proto.structure();
-                                        if !GcPointer::ptr_eq(&structure, &vector[i]) {
-                                            break 'slowpath;
-                                        }
-                                        i += 1;
-                                        cur = structure.prototype;
-                                    }
-
-                                    *obj.direct_mut(*offset as usize) = value;
-                                    break 'exit;
-                                }
-                                TypeFeedBack::None => {
-                                    break 'slowpath;
-                                }
-                                _ => unreachable!(),
+                    #[cfg(not(feature = "no-inline-caching"))]
+                    {
+                        let mut code = unwrap_unchecked(frame.code_block);
+                        let feedback = code.feedback.get_unchecked_mut(fdbk as usize);
+                        let structure = obj.structure();
+                        let offset = if feedback.is_megamorphic() {
+                            let offset = ctx.vm.megamorphic_cache.get_put(structure, name);
+                            if offset.is_some() {
+                                ctx.vm.ic_stats.megamorphic_hits += 1;
+                            } else {
+                                ctx.vm.ic_stats.megamorphic_misses += 1;
                             }
+                            offset
+                        } else {
+                            feedback.lookup_put(structure)
+                        };
+                        if let Some(offset) = offset {
+                            ctx.vm.ic_stats.put_by_id_hits += 1;
+                            *obj.direct_mut(offset as usize) = value;
+                            continue;
                         }
-
-                        put_by_id_slow(ctx, frame, &mut obj, name, value, fdbk)?;
-                        break 'exit;
+                        ctx.vm.ic_stats.put_by_id_misses += 1;
                     }
+
+                    put_by_id_slow(ctx, frame, &mut obj, name, value, fdbk)?;
                     continue;
                 }
             }
@@ -813,6 +809,8 @@ pub unsafe fn eval(mut ctx: GcPointer<Context>, frame: *mut CallFrame) -> Result
                 ctx.heap().collect_if_necessary();
                 let argc = ip.cast::<u32>().read();
                 ip = ip.add(4);
+                let fdbk = ip.cast::<u32>().read();
+                ip = ip.add(4);
 
                 let args_start = frame.sp.sub(argc as _);
 
@@ -829,15 +827,24 @@ pub unsafe fn eval(mut ctx: GcPointer<Context>, frame: *mut CallFrame) -> Result
                 letroot!(func_object = gcstack, func.get_jsobject());
                 letroot!(funcc = gcstack, *func_object);
                 let func = func_object.as_function_mut();
-                letroot!(args_ = gcstack, Arguments::new(this, &mut args));
+                #[cfg(not(feature = "no-inline-caching"))]
+                let hit = check_call_feedback(ctx, unwrap_unchecked(frame.code_block), fdbk, func);
+                #[cfg(feature = "no-inline-caching")]
+                let hit: Option<CallTarget> = None;
 
                 frame.ip = ip;
                 stack.cursor = frame.sp;
 
-                if func.is_vm() {
+                // Functions from other realms run on the stack of their own context through `JsFunction::call`.
+                if func.is_vm() && func.ctx == ctx {
                     let vm_fn = func.as_vm_mut();
-                    let scope = JsValue::new(vm_fn.scope);
-                    let (this, scope) = ctx.setup_for_vm_call(vm_fn, scope, &args_)?;
+                    let (this, scope) = if hit.is_some() && vm_fn.code.can_use_fast_call() {
+                        ctx.setup_for_vm_call_fast(vm_fn, this, args)
+                    } else {
+                        letroot!(args_ = gcstack, Arguments::new(this, &mut args));
+                        let scope = JsValue::new(vm_fn.scope);
+                        ctx.setup_for_vm_call(vm_fn, scope, &args_)?
+                    };
                     let mut exit = false;
                     if !frame.exit_on_return
                         && (opcode == Opcode::OP_TAILCALL
@@ -866,14 +873,150 @@ pub unsafe fn eval(mut ctx: GcPointer<Context>, frame: *mut CallFrame) -> Result
 
                     ip = (*cframe).ip;
                 } else {
-                    let result = func.call(ctx, &mut args_, JsValue::new(*funcc))?;
+                    letroot!(args_ = gcstack, Arguments::new(this, &mut args));
+                    let result = match hit {
+                        // monomorphic native call site calls cached function pointer directly
+                        Some(CallTarget::Native(native)) => native(func.ctx, &args_)?,
+                        _ => func.call(ctx, &mut args_, JsValue::new(*funcc))?,
+                    };
                     frame.push(result);
                 }
             }
+            Opcode::OP_CALL_EVAL => {
+                ctx.heap().collect_if_necessary();
+                let argc = ip.cast::<u32>().read();
+                ip = ip.add(4);
+                let site = ip.cast::<u32>().read();
+                ip = ip.add(4);
+
+                let args_start = frame.sp.sub(argc as _);
+                frame.sp = args_start;
+                let func = frame.pop();
+                let this = frame.pop();
+                let mut args = std::slice::from_raw_parts_mut(args_start, argc as _);
+                if unlikely(!func.is_callable()) {
+                    let msg = JsString::new(ctx, "not a callable object".to_string());
+                    return Err(JsValue::encode_object_value(JsTypeError::new(
+                        ctx, msg, None,
+                    )));
+                }
+                letroot!(func_object = gcstack, func.get_jsobject());
+                frame.ip = ip;
+                stack.cursor = frame.sp;
+                // eval of another realm is called as any other function.
+                let is_eval = func_object.as_function().ctx == ctx
+                    && match func_object.as_function().ty {
+                        FuncType::Native(ref x) => {
+                            x.func as usize == crate::jsrt::global::eval as usize
+                        }
+                        _ => false,
+                    };
+                let result = if is_eval {
+                    let source = if argc == 0 {
+                        JsValue::encode_undefined_value()
+                    } else {
+                        args[0]
+                    };
+                    if source.is_jsstring() {
+                        let source = source.get_jsstring().as_str().to_string();
+                        let code = ByteCompiler::compile_direct_eval(
+                            ctx,
+                            unwrap_unchecked(frame.code_block),
+                            site,
+                            &source,
+                        )?;
+                        letroot!(fun = gcstack, JsVMFunction::new(ctx, code, frame.env));
+                        letroot!(
+                            args_ = gcstack,
+                            Arguments::new(frame.this, &mut [])
+                        );
+                        let callee = JsValue::new(*fun);
+                        fun.as_function_mut().call(ctx, &mut args_, callee)?
+                    } else {
+                        source
+                    }
+                } else {
+                    letroot!(args_ = gcstack, Arguments::new(this, &mut args));
+                    let callee = JsValue::new(*func_object);
+                    func_object
+                        .as_function_mut()
+                        .call(ctx, &mut args_, callee)?
+                };
+                frame.push(result);
+            }
+            Opcode::OP_RESOLVE_VAR => {
+                let name = ip.cast::<u32>().read_unaligned();
+                ip = ip.add(4);
+                let name = unwrap_unchecked(frame.code_block).names[name as usize];
+                let mut env = Some(frame.env);
+                let mut holder = ctx.global_object();
+                while let Some(current) = env {
+                    if let Some(mut object) = current.dynamic {
+                        if object.has_own_property(ctx, name) {
+                            holder = object;
+                            break;
+                        }
+                    }
+                    env = current.parent;
+                }
+                frame.push(JsValue::new(holder));
+            }
+            Opcode::OP_DECL_VAR => {
+                let name = ip.cast::<u32>().read_unaligned();
+                ip = ip.add(4);
+                let name = unwrap_unchecked(frame.code_block).names[name as usize];
+                let mut env = unwrap_unchecked(frame.env.parent);
+                let mut object = match env.dynamic {
+                    Some(object) => object,
+                    None => {
+                        let object = JsObject::new_empty(ctx);
+                        env.dynamic = Some(object);
+                        object
+                    }
+                };
+                if !object.has_own_property(ctx, name) {
+                    object.put(ctx, name, JsValue::encode_undefined_value(), false)?;
+                }
+            }
+            Opcode::OP_GET_IMPORT => {
+                let index = ip.cast::<u32>().read_unaligned();
+                ip = ip.add(4);
+                let module = frame.pop();
+                let module = if module.is_object() {
+                    module.get_object().downcast::<ModuleRecord>()
+                } else {
+                    None
+                };
+                let module = match module {
+                    Some(module) => module,
+                    None => {
+                        return Err(JsValue::new(
+                            ctx.new_type_error("Import outside of module code"),
+                        ));
+                    }
+                };
+                frame.ip = ip;
+                let value = module.import(ctx, index)?;
+                frame.push(value);
+            }
+            Opcode::OP_SET_IMPORT => {
+                return Err(JsValue::new(
+                    ctx.new_type_error("Assignment to imported binding"),
+                ));
+            }
+            Opcode::OP_DYNAMIC_IMPORT => {
+                let specifier = frame.pop();
+                frame.ip = ip;
+                let referrer = frame.code_block.unwrap().file_name.clone();
+                let promise = crate::jsrt::import_module(ctx, specifier, &referrer)?;
+                frame.push(promise);
+            }
             Opcode::OP_NEW | Opcode::OP_TAILNEW => {
                 ctx.heap().collect_if_necessary();
                 let argc = ip.cast::<u32>().read();
                 ip = ip.add(4);
+                let fdbk = ip.cast::<u32>().read();
+                ip = ip.add(4);
 
                 let args_start = frame.sp.sub(argc as _);
                 frame.sp = args_start;
@@ -890,21 +1033,41 @@ pub unsafe fn eval(mut ctx: GcPointer<Context>, frame: *mut CallFrame) -> Result
 
                 letroot!(func_object = gcstack, func.get_jsobject());
                 letroot!(funcc = gcstack, func.get_jsobject());
+                #[cfg(not(feature = "no-inline-caching"))]
+                let hit = check_call_feedback(
+                    ctx,
+                    unwrap_unchecked(frame.code_block),
+                    fdbk,
+                    func_object.as_function(),
+                );
+                #[cfg(feature = "no-inline-caching")]
+                let hit: Option<CallTarget> = None;
+                #[cfg(not(feature = "no-inline-caching"))]
+                let map = new_site_structure(
+                    ctx,
+                    unwrap_unchecked(frame.code_block),
+                    fdbk,
+                    &mut func_object,
+                )?;
+                #[cfg(feature = "no-inline-caching")]
                 let map = func_object.func_construct_map(ctx)?;
                 let func = func_object.as_function_mut();
                 let object = JsObject::new(ctx, &map, JsObject::class(), ObjectTag::Ordinary);
-                letroot!(
-                    args_ = gcstack,
-                    Arguments::new(JsValue::new(object), &mut args)
-                );
-
-                args_.ctor_call = true;
                 frame.ip = ip;
 
-                if func.is_vm() {
+                if func.is_vm() && func.ctx == ctx {
                     let vm_fn = func.as_vm_mut();
-                    let scope = JsValue::new(vm_fn.scope);
-                    let (this, scope) = ctx.setup_for_vm_call(vm_fn, scope, &args_)?;
+                    let (this, scope) = if hit.is_some() && vm_fn.code.can_use_fast_call() {
+                        ctx.setup_for_vm_call_fast(vm_fn, JsValue::new(object), args)
+                    } else {
+                        letroot!(
+                            args_ = gcstack,
+                            Arguments::new(JsValue::new(object), &mut args)
+                        );
+                        args_.ctor_call = true;
+                        let scope = JsValue::new(vm_fn.scope);
+                        ctx.setup_for_vm_call(vm_fn, scope, &args_)?
+                    };
                     let mut exit = false;
                     if !frame.exit_on_return && (opcode == Opcode::OP_TAILNEW) {
                         // stack.pop_frame().unwrap();
@@ -927,7 +1090,15 @@ pub unsafe fn eval(mut ctx: GcPointer<Context>, frame: *mut CallFrame) -> Result
                     frame = &mut *cframe;
                     ip = (*cframe).ip;
                 } else {
-                    let result = func.call(ctx, &mut args_, JsValue::new(*funcc))?;
+                    letroot!(
+                        args_ = gcstack,
+                        Arguments::new(JsValue::new(object), &mut args)
+                    );
+                    args_.ctor_call = true;
+                    let result = match hit {
+                        Some(CallTarget::Native(native)) => native(func.ctx, &args_)?,
+                        _ => func.call(ctx, &mut args_, JsValue::new(*funcc))?,
+                    };
 
                     frame.push(result);
                 }
@@ -1061,6 +1232,8 @@ pub unsafe fn eval(mut ctx: GcPointer<Context>, frame: *mut CallFrame) -> Result
                 frame.push(value);
             }
             Opcode::OP_INSTANCEOF => {
+                let fdbk = ip.cast::<u32>().read_unaligned();
+                ip = ip.add(4);
                 let lhs = frame.pop();
                 let rhs = frame.pop();
                 if unlikely(!rhs.is_jsobject()) {
@@ -1078,6 +1251,27 @@ pub unsafe fn eval(mut ctx: GcPointer<Context>, frame: *mut CallFrame) -> Result
                         ctx, msg, None,
                     )));
                 }
+                // `prototype` load from constructor is cached the same way as `get_by_id`.
+                #[cfg(not(feature = "no-inline-caching"))]
+                if lhs.is_jsobject() {
+                    let name = "prototype".intern();
+                    let mut code = unwrap_unchecked(frame.code_block);
+                    let feedback = code.feedback.get_unchecked_mut(fdbk as usize);
+                    if let Some(prototype) = feedback.lookup_load(ctx, &robj, name) {
+                        ctx.vm.ic_stats.instanceof_hits += 1;
+                        frame.push(JsValue::encode_bool_value(
+                            JsFunction::has_instance_with_prototype(ctx, prototype, lhs)?,
+                        ));
+                        continue;
+                    }
+                    ctx.vm.ic_stats.instanceof_misses += 1;
+                    let mut slot = Slot::new();
+                    if robj.get_property_slot(ctx, name, &mut slot) {
+                        if let Some(entry) = inline_cache::load_cache_entry(ctx, &robj, &slot) {
+                            update_load_feedback(ctx, code, fdbk, name, entry);
+                        }
+                    }
+                }
 
                 frame.push(JsValue::encode_bool_value(
                     robj.as_function().has_instance(&mut robj2, ctx, lhs)?,
@@ -1093,8 +1287,27 @@ pub unsafe fn eval(mut ctx: GcPointer<Context>, frame: *mut CallFrame) -> Result
                     )));
                 }
                 let sym = lhs.to_symbol(ctx)?;
+                letroot!(object = gcstack, rhs.get_jsobject());
+                // `in` is keyed by arbitrary values so it does not have per-site feedback and uses global cache directly.
+                #[cfg(not(feature = "no-inline-caching"))]
+                {
+                    if ctx.vm.megamorphic_cache.get_in(object.structure(), sym) {
+                        ctx.vm.ic_stats.in_hits += 1;
+                        frame.push(JsValue::encode_bool_value(true));
+                        continue;
+                    }
+                    ctx.vm.ic_stats.in_misses += 1;
+                    let mut slot = Slot::new();
+                    let found = object.get_own_property_slot(ctx, sym, &mut slot);
+                    if found && slot.is_load_cacheable() {
+                        ctx.vm.megamorphic_cache.insert_in(object.structure(), sym);
+                    }
+                    frame.push(JsValue::encode_bool_value(found));
+                    continue;
+                }
+                #[allow(unreachable_code)]
                 frame.push(JsValue::encode_bool_value(
-                    rhs.get_jsobject().has_own_property(ctx, sym),
+                    object.has_own_property(ctx, sym),
                 ));
             }
 
@@ -1365,7 +1578,10 @@ pub unsafe fn eval(mut ctx: GcPointer<Context>, frame: *mut CallFrame) -> Result
                 frame.ip = ip;
                 return Ok(JsValue::encode_native_u32(FuncRet::YieldStar as u32));
             }
-            Opcode::OP_AWAIT => return Ok(JsValue::encode_native_u32(FuncRet::Await as u32)),
+            Opcode::OP_AWAIT => {
+                frame.ip = ip;
+                return Ok(JsValue::encode_native_u32(FuncRet::Await as u32));
+            }
             Opcode::OP_IS_OBJECT => {
                 let val = frame.pop();
                 frame.push(JsValue::new(val.is_jsobject()));
@@ -1433,8 +1649,121 @@ pub fn get_by_id_slow(
     val.get_slot(ctx, name, &mut slot)
 }
 
-pub(crate) unsafe fn put_by_id_slow(
+/// Check callee of `call`/`new` site against recorded feedback. First callee seen is recorded and
+/// the recorded target is returned only when the site is monomorphic and sees the same callee again. Once different
+/// callee is seen the site becomes megamorphic and always uses the generic call path.
+#[cfg(not(feature = "no-inline-caching"))]
+unsafe fn check_call_feedback(
+    mut ctx: GcPointer<Context>,
+    mut code: GcPointer<CodeBlock>,
+    fdbk: u32,
+    func: &JsFunction,
+) -> Option<CallTarget> {
+    let feedback = code.feedback.get_unchecked_mut(fdbk as usize);
+    match feedback {
+        TypeFeedBack::CallFeedBack { target, .. } => {
+            if target.matches(func) {
+                ctx.vm.ic_stats.call_hits += 1;
+                return Some(*target);
+            }
+            *feedback = TypeFeedBack::Megamorphic;
+            ctx.vm.ic_stats.to_megamorphic += 1;
+        }
+        TypeFeedBack::None => {
+            if let Some(target) = CallTarget::of(func) {
+                *feedback = TypeFeedBack::CallFeedBack {
+                    target,
+                    construct_structure: None,
+                };
+                ctx.vm.ic_stats.to_monomorphic += 1;
+            }
+        }
+        _ => (),
+    }
+    ctx.vm.ic_stats.call_misses += 1;
+    None
+}
+
+/// Structure used to allocate `this` at `new` site. Every monomorphic site owns its structure so objects
+/// allocated at different sites do not share transitions. Structure is reused for as long as its prototype is
+/// the prototype callee currently constructs with, megamorphic sites and callees with uncacheable `prototype`
+/// use structure of the callee.
+#[cfg(not(feature = "no-inline-caching"))]
+unsafe fn new_site_structure(
     ctx: GcPointer<Context>,
+    mut code: GcPointer<CodeBlock>,
+    fdbk: u32,
+    func_object: &mut GcPointer<JsObject>,
+) -> Result<GcPointer<Structure>, JsValue> {
+    if let (
+        TypeFeedBack::CallFeedBack {
+            construct_structure: Some(structure),
+            ..
+        },
+        Some(current),
+    ) = (
+        code.feedback.get_unchecked(fdbk as usize),
+        func_object.as_function().construct_struct,
+    ) {
+        let same_prototype = match (current.prototype, structure.prototype) {
+            (Some(x), Some(y)) => GcPointer::ptr_eq(&x, &y),
+            (x, y) => x.is_none() && y.is_none(),
+        };
+        if same_prototype {
+            return Ok(*structure);
+        }
+    }
+    let map = func_object.func_construct_map(ctx)?;
+    match code.feedback.get_unchecked_mut(fdbk as usize) {
+        TypeFeedBack::CallFeedBack {
+            construct_structure,
+            ..
+        } if func_object.as_function().construct_struct.is_some() => {
+            let structure = Structure::new_indexed(ctx, map.prototype, false);
+            *construct_structure = Some(structure);
+            Ok(structure)
+        }
+        _ => Ok(map),
+    }
+}
+
+/// Load property through global megamorphic cache.
+#[cfg(not(feature = "no-inline-caching"))]
+unsafe fn megamorphic_load(
+    mut ctx: GcPointer<Context>,
+    obj: &GcPointer<JsObject>,
+    name: Symbol,
+) -> Option<JsValue> {
+    if let Some(entry) = ctx.vm.megamorphic_cache.get(obj.structure(), name) {
+        if let Some(value) = inline_cache::try_cached_load(ctx, obj, name, &entry) {
+            ctx.vm.ic_stats.megamorphic_hits += 1;
+            return Some(value);
+        }
+    }
+    ctx.vm.ic_stats.megamorphic_misses += 1;
+    None
+}
+
+/// Record load `entry` in feedback slot `fdbk` of `code`, megamorphic sites record it in global cache.
+#[cfg(not(feature = "no-inline-caching"))]
+unsafe fn update_load_feedback(
+    mut ctx: GcPointer<Context>,
+    mut code: GcPointer<CodeBlock>,
+    fdbk: u32,
+    name: Symbol,
+    entry: PropertyCacheEntry,
+) {
+    let feedback = code.feedback.get_unchecked_mut(fdbk as usize);
+    if !feedback.is_megamorphic() {
+        feedback.record_load(ctx, entry);
+    }
+    if feedback.is_megamorphic() {
+        ctx.vm.megamorphic_cache.insert(name, entry);
+    }
+}
+
+pub(crate) unsafe fn put_by_id_slow(
+    mut ctx: GcPointer<Context>,
     frame: &mut CallFrame,
     obj: &mut GcPointer<JsObject>,
     name: Symbol,
@@ -1452,12 +1781,7 @@ pub(crate) unsafe fn put_by_id_slow(
     )?;
     #[cfg(not(feature = "no-inline-caching"))]
     if slot.is_put_cacheable() && slot.base.is_some() {
-        let mut base_cell = *obj;
-        let mut new_structure = base_cell.structure();
-        let mut m_old_structure;
-        let mut m_offset;
-        let mut m_new_structure = None;
-        let mut m_new_chain = None;
+        let base_cell = *obj;
 
         if GcPointer::ptr_eq(&base_cell, &slot.base.unwrap()) {
             if slot.put_result_type() == PutResultType::New {
@@ -1487,18 +1811,19 @@ pub(crate) unsafe fn put_by_id_slow(
                         }
                     }
                 }*/
-            } else {
-                m_old_structure = Some(new_structure);
-                m_offset = slot.offset();
             }
-
-            unwrap_unchecked(frame.code_block).feedback[fdbk as usize] =
-                TypeFeedBack::PutByIdFeedBack {
-                    new_structure: m_new_structure,
-                    old_structure: m_old_structure,
-                    offset: m_offset,
-                    structure_chain: m_new_chain,
-                };
+            let entry = PutByIdCacheEntry {
+                structure: base_cell.structure(),
+                offset: slot.offset(),
+            };
+            let mut code = unwrap_unchecked(frame.code_block);
+            let feedback = &mut code.feedback[fdbk as usize];
+            if !feedback.is_megamorphic() {
+                feedback.record_put(ctx, entry);
+            }
+            if feedback.is_megamorphic() {
+                ctx.vm.megamorphic_cache.insert_put(name, entry);
+            }
             debug_assert!(!matches!(
                 unwrap_unchecked(frame.code_block).feedback[fdbk as usize],
                 TypeFeedBack::None
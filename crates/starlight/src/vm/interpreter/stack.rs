@@ -38,7 +38,7 @@ impl Stack {
     pub fn new_frame(
         &mut self,
         iloc_count: u32,
-        _callee: JsValue,
+        callee: JsValue,
         env: GcPointer<Environment>,
     ) -> Option<*mut CallFrame> {
         unsafe {
@@ -57,7 +57,7 @@ impl Stack {
                 limit: self.cursor,
                 code_block: None,
 
-                callee: JsValue::encode_undefined_value(),
+                callee,
                 ip: null_mut(),
             }));
             self.current = frame;
@@ -0,0 +1,1283 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+//! ES modules.
+//!
+//! Module is compiled into a function whose [CodeBlock::module](super::code_block::CodeBlock::module) lists modules
+//! it requests, its imports and exports. Loader creates [ModuleRecord] for the module and records of all modules
+//! it requests, then the graph is linked and evaluated:
+//!
+//! - Link resolves imports of every module to [Binding]s and runs module prologue which initializes hoisted function
+//!   declarations, so functions of a module can be called by modules evaluated before it in a cycle.
+//! - Evaluate runs module bodies in post order, each module exactly once. Module that is part of a cycle can see
+//!   variables of modules that were not evaluated yet, those are `undefined` since there's no TDZ.
+//!
+//! Module body that uses top level `await` is suspended like a generator when it awaits and resumed by a promise
+//! job, the module is then [ModuleStatus::EvaluatingAsync]. Modules that request it wait with running their bodies
+//! until it completes, [ModuleRecord::promise] settles when evaluation of a module and modules it waits for is done.
+//!
+//! Every source module has its own environment created together with the record. Slot 0 of the environment holds
+//! the record so that code nested in the module can find bindings of its imports. Imports are never copied, every
+//! read goes through the binding to the environment of exporting module, so imports are live.
+use std::{
+    any::TypeId,
+    collections::HashMap,
+    mem::{size_of, ManuallyDrop},
+};
+
+use super::{
+    code_block::CodeBlock,
+    context::Context,
+    environment::Environment,
+    function::{async_func_resume, async_func_throw, AsyncFunctionState, FuncRet, HeapCallFrame},
+    promise::JsPromise,
+};
+use crate::prelude::*;
+
+/// Name imported from another module.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ImportName {
+    Name(Symbol),
+    /// `import * as ns` and `export * as ns from`.
+    Namespace,
+}
+
+pub struct ImportEntry {
+    /// Name of the import in the importing module.
+    pub local: Symbol,
+    /// Index of the requested module in [ModuleInfo::requests].
+    pub request: u32,
+    pub name: ImportName,
+}
+
+pub enum ExportEntry {
+    /// Variable of the module stored in `slot` of module environment.
+    Local { name: Symbol, slot: u32 },
+    /// Binding of another module, `export { x as y } from "mod"`, `export * as ns from "mod"` or export of imported
+    /// name.
+    Indirect {
+        name: Symbol,
+        request: u32,
+        import: ImportName,
+    },
+}
+
+/// Imports and exports of module code.
+#[derive(Default)]
+pub struct ModuleInfo {
+    /// Module specifiers in the order they appear in the source.
+    pub requests: Vec<String>,
+    pub imports: Vec<ImportEntry>,
+    pub exports: Vec<ExportEntry>,
+    /// Requests re-exported with `export * from "mod"`.
+    pub star_exports: Vec<u32>,
+    /// Offset of module body in bytecode. Code before it is the prologue that initializes hoisted functions.
+    pub body_start: u32,
+}
+
+impl ModuleInfo {
+    /// Index of request of `specifier`, request is added if module didn't request `specifier` yet.
+    pub fn request(&mut self, specifier: &str) -> u32 {
+        match self
+            .requests
+            .iter()
+            .position(|request| request == specifier)
+        {
+            Some(index) => index as u32,
+            None => {
+                self.requests.push(specifier.to_string());
+                self.requests.len() as u32 - 1
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum ModuleStatus {
+    Unlinked,
+    Linking,
+    Linked,
+    Evaluating,
+    /// Module is suspended at top level `await` or waits for modules that are.
+    EvaluatingAsync,
+    Evaluated,
+    Errored,
+}
+
+/// Resolved import or export.
+#[derive(Clone, Copy)]
+pub enum Binding {
+    /// Variable of module environment.
+    Slot(GcPointer<Environment>, u32),
+    /// Property of exports object of native module.
+    Property(GcPointer<JsObject>, Symbol),
+    /// Namespace object of the module.
+    Namespace(GcPointer<ModuleRecord>),
+}
+
+impl Binding {
+    /// Current value of the binding.
+    pub fn get(&self, ctx: GcPointer<Context>) -> Result<JsValue, JsValue> {
+        match *self {
+            Self::Slot(env, slot) => Ok(env.as_slice()[slot as usize].value),
+            Self::Property(mut object, name) => object.get(ctx, name),
+            Self::Namespace(module) => module.namespace(ctx).map(JsValue::new),
+        }
+    }
+
+    fn same(&self, other: &Self) -> bool {
+        match (*self, *other) {
+            (Self::Slot(env, slot), Self::Slot(other_env, other_slot)) => {
+                env == other_env && slot == other_slot
+            }
+            (Self::Property(object, name), Self::Property(other_object, other_name)) => {
+                object == other_object && name == other_name
+            }
+            (Self::Namespace(module), Self::Namespace(other)) => module == other,
+            _ => false,
+        }
+    }
+}
+
+enum Resolution {
+    NotFound,
+    Ambiguous,
+    Found(Binding),
+}
+
+pub struct ModuleRecord {
+    /// Key of the module in [Context::modules], absolute path of source modules.
+    pub name: String,
+    pub(crate) status: ModuleStatus,
+    /// Module function of source modules.
+    pub(crate) function: Option<GcPointer<JsObject>>,
+    pub(crate) env: Option<GcPointer<Environment>>,
+    /// Module object of native modules, `@exports` and `@default` are read from it.
+    pub(crate) object: Option<GcPointer<JsObject>>,
+    /// Records of [ModuleInfo::requests], filled by the loader.
+    pub(crate) requested: Vec<GcPointer<ModuleRecord>>,
+    /// Bindings of [ModuleInfo::imports], resolved when the module is linked.
+    pub(crate) bindings: Vec<Binding>,
+    pub(crate) namespace: Option<GcPointer<JsObject>>,
+    /// Bindings of namespace object properties.
+    pub(crate) namespace_bindings: HashMap<Symbol, Binding>,
+    /// Exception thrown when module was evaluated.
+    pub(crate) error: JsValue,
+    /// Body of the module suspended at top level `await`.
+    pub(crate) state: Option<AsyncFunctionState>,
+    /// Number of requested modules whose async evaluation the module waits for.
+    pub(crate) pending: u32,
+    /// Modules waiting for async evaluation of the module.
+    pub(crate) dependents: Vec<GcPointer<ModuleRecord>>,
+    /// Promise returned by [ModuleRecord::promise], created on first use.
+    pub(crate) promise: JsValue,
+}
+
+impl ModuleRecord {
+    /// Create record of source module `function` compiled by [Context::compile_module].
+    pub fn new(
+        mut ctx: GcPointer<Context>,
+        name: String,
+        function: GcPointer<JsObject>,
+    ) -> GcPointer<Self> {
+        let vm_function = function.as_function().as_vm();
+        let code = vm_function.code;
+        let mut env = Environment::new(ctx, code.param_count + code.var_count);
+        env.parent = Some(vm_function.scope);
+        let record = ctx.heap().allocate(Self {
+            name,
+            status: ModuleStatus::Unlinked,
+            function: Some(function),
+            env: Some(env),
+            object: None,
+            requested: vec![],
+            bindings: vec![],
+            namespace: None,
+            namespace_bindings: HashMap::new(),
+            error: JsValue::encode_undefined_value(),
+            state: None,
+            pending: 0,
+            dependents: vec![],
+            promise: JsValue::encode_undefined_value(),
+        });
+        env.as_slice_mut()[0].value = JsValue::new(record);
+        record
+    }
+
+    /// Create record of native module whose `object` has exports in `@exports` property and default export in
+    /// `@default` property. Native modules are evaluated already.
+    pub fn native(
+        mut ctx: GcPointer<Context>,
+        name: String,
+        object: GcPointer<JsObject>,
+    ) -> GcPointer<Self> {
+        ctx.heap().allocate(Self {
+            name,
+            status: ModuleStatus::Evaluated,
+            function: None,
+            env: None,
+            object: Some(object),
+            requested: vec![],
+            bindings: vec![],
+            namespace: None,
+            namespace_bindings: HashMap::new(),
+            error: JsValue::encode_undefined_value(),
+            state: None,
+            pending: 0,
+            dependents: vec![],
+            promise: JsValue::encode_undefined_value(),
+        })
+    }
+
+    pub fn status(&self) -> ModuleStatus {
+        self.status
+    }
+
+    /// Exception thrown by evaluation of the module.
+    pub fn error(&self) -> Option<JsValue> {
+        if self.status == ModuleStatus::Errored {
+            Some(self.error)
+        } else {
+            None
+        }
+    }
+
+    /// Code of source module.
+    pub fn code(&self) -> Option<GcPointer<CodeBlock>> {
+        self.function
+            .map(|function| function.as_function().as_vm().code)
+    }
+
+    /// Requested modules and exports of the module.
+    pub fn info(&self) -> Option<&ModuleInfo> {
+        let code = self.function?.as_function().as_vm().code;
+        // module info is never changed after module is compiled and code is kept alive by the record.
+        unsafe { (*(&code.module as *const Option<Box<ModuleInfo>>)).as_deref() }
+    }
+}
+
+impl GcPointer<ModuleRecord> {
+    fn native_exports(self, ctx: GcPointer<Context>) -> Option<GcPointer<JsObject>> {
+        let exports = self.object?.get(ctx, "@exports".intern()).ok()?;
+        if exports.is_jsobject() {
+            Some(exports.get_jsobject())
+        } else {
+            None
+        }
+    }
+
+    fn resolve_export(
+        self,
+        ctx: GcPointer<Context>,
+        name: Symbol,
+        resolve_set: &mut Vec<(GcPointer<ModuleRecord>, Symbol)>,
+    ) -> Resolution {
+        if resolve_set.contains(&(self, name)) {
+            // circular import request
+            return Resolution::NotFound;
+        }
+        resolve_set.push((self, name));
+        if let Some(mut object) = self.object {
+            if name == "default".intern() {
+                if object.has_own_property(ctx, "@default".intern()) {
+                    return Resolution::Found(Binding::Property(object, "@default".intern()));
+                }
+                return Resolution::NotFound;
+            }
+            return match self.native_exports(ctx) {
+                Some(mut exports) => {
+                    if exports.has_own_property(ctx, name) {
+                        Resolution::Found(Binding::Property(exports, name))
+                    } else {
+                        Resolution::NotFound
+                    }
+                }
+                None => Resolution::NotFound,
+            };
+        }
+        let info = match self.info() {
+            Some(info) => info,
+            None => return Resolution::NotFound,
+        };
+        for export in info.exports.iter() {
+            match *export {
+                ExportEntry::Local {
+                    name: export_name,
+                    slot,
+                } if export_name == name => {
+                    return Resolution::Found(Binding::Slot(self.env.unwrap(), slot));
+                }
+                ExportEntry::Indirect {
+                    name: export_name,
+                    request,
+                    import,
+                } if export_name == name => {
+                    let module = self.requested[request as usize];
+                    return match import {
+                        ImportName::Namespace => Resolution::Found(Binding::Namespace(module)),
+                        ImportName::Name(import) => module.resolve_export(ctx, import, resolve_set),
+                    };
+                }
+                _ => {}
+            }
+        }
+        if name == "default".intern() {
+            // `export *` never exports default
+            return Resolution::NotFound;
+        }
+        let mut resolution = Resolution::NotFound;
+        for request in info.star_exports.iter() {
+            let module = self.requested[*request as usize];
+            match module.resolve_export(ctx, name, resolve_set) {
+                Resolution::Ambiguous => return Resolution::Ambiguous,
+                Resolution::NotFound => {}
+                Resolution::Found(binding) => match resolution {
+                    Resolution::Found(ref found) if !found.same(&binding) => {
+                        return Resolution::Ambiguous
+                    }
+                    _ => resolution = Resolution::Found(binding),
+                },
+            }
+        }
+        resolution
+    }
+
+    fn exported_names(
+        self,
+        ctx: GcPointer<Context>,
+        star_set: &mut Vec<GcPointer<ModuleRecord>>,
+    ) -> Vec<Symbol> {
+        let mut names = vec![];
+        if star_set.contains(&self) {
+            // circular `export *`
+            return names;
+        }
+        star_set.push(self);
+        if let Some(mut object) = self.object {
+            if let Some(mut exports) = self.native_exports(ctx) {
+                exports.get_own_property_names(
+                    ctx,
+                    &mut |name, _| names.push(name),
+                    EnumerationMode::Default,
+                );
+            }
+            if object.has_own_property(ctx, "@default".intern()) {
+                names.push("default".intern());
+            }
+            return names;
+        }
+        let info = match self.info() {
+            Some(info) => info,
+            None => return names,
+        };
+        for export in info.exports.iter() {
+            match *export {
+                ExportEntry::Local { name, .. } | ExportEntry::Indirect { name, .. } => {
+                    names.push(name)
+                }
+            }
+        }
+        for request in info.star_exports.iter() {
+            let module = self.requested[*request as usize];
+            for name in module.exported_names(ctx, star_set) {
+                if name != "default".intern() && !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        names
+    }
+
+    /// Value of export `name` of the module. Returns `None` if the module doesn't export `name`.
+    pub fn get_export(
+        self,
+        ctx: GcPointer<Context>,
+        name: Symbol,
+    ) -> Result<Option<JsValue>, JsValue> {
+        match self.resolve_export(ctx, name, &mut vec![]) {
+            Resolution::Found(binding) => binding.get(ctx).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Namespace object of the module, created on first use. Names that are exported ambiguously by `export *` are
+    /// not included.
+    pub fn namespace(mut self, ctx: GcPointer<Context>) -> Result<GcPointer<JsObject>, JsValue> {
+        if let Some(namespace) = self.namespace {
+            return Ok(namespace);
+        }
+        let mut names = vec![];
+        for name in self.exported_names(ctx, &mut vec![]) {
+            if let Symbol::Key(_) = name {
+                if let Resolution::Found(binding) = self.resolve_export(ctx, name, &mut vec![]) {
+                    self.namespace_bindings.insert(name, binding);
+                    names.push(name);
+                }
+            }
+        }
+        names.sort_by_cached_key(|name| ctx.description(*name));
+        let namespace = JsModuleNamespace::new(ctx, self, &names)?;
+        self.namespace = Some(namespace);
+        Ok(namespace)
+    }
+
+    /// Value of import `index` of the module.
+    pub(crate) fn import(self, ctx: GcPointer<Context>, index: u32) -> Result<JsValue, JsValue> {
+        match self.bindings.get(index as usize) {
+            Some(binding) => binding.get(ctx),
+            None => Err(JsValue::new(ctx.new_reference_error(format!(
+                "Cannot access import of module '{}' before it is linked",
+                self.name
+            )))),
+        }
+    }
+
+    /// Resolve imports of the module and modules it requests and initialize their hoisted functions. All modules
+    /// the module requests have to be loaded. Modules that failed to link are unlinked again.
+    pub fn link(self, ctx: GcPointer<Context>) -> Result<(), JsValue> {
+        let mut stack = vec![];
+        let result = self.inner_link(ctx, &mut stack);
+        if result.is_err() {
+            for mut module in stack {
+                module.status = ModuleStatus::Unlinked;
+                module.bindings.clear();
+            }
+        }
+        result
+    }
+
+    fn inner_link(
+        mut self,
+        ctx: GcPointer<Context>,
+        stack: &mut Vec<GcPointer<ModuleRecord>>,
+    ) -> Result<(), JsValue> {
+        if self.status != ModuleStatus::Unlinked {
+            return Ok(());
+        }
+        self.status = ModuleStatus::Linking;
+        stack.push(self);
+        for i in 0..self.requested.len() {
+            self.requested[i].inner_link(ctx, stack)?;
+        }
+        let info = self.info().expect("source module without module info");
+        for export in info.exports.iter() {
+            if let ExportEntry::Indirect {
+                name,
+                request,
+                import: ImportName::Name(import),
+            } = *export
+            {
+                let module = self.requested[request as usize];
+                self.check_resolution(ctx, module, import, name)?;
+            }
+        }
+        let mut bindings = Vec::with_capacity(info.imports.len());
+        for import in info.imports.iter() {
+            let module = self.requested[import.request as usize];
+            bindings.push(match import.name {
+                ImportName::Namespace => Binding::Namespace(module),
+                ImportName::Name(name) => self.check_resolution(ctx, module, name, import.local)?,
+            });
+        }
+        self.bindings = bindings;
+        self.run(ctx, 0)?;
+        self.status = ModuleStatus::Linked;
+        Ok(())
+    }
+
+    /// Resolve export `name` of requested `module` that is imported as `local`.
+    fn check_resolution(
+        self,
+        ctx: GcPointer<Context>,
+        module: GcPointer<ModuleRecord>,
+        name: Symbol,
+        local: Symbol,
+    ) -> Result<Binding, JsValue> {
+        match module.resolve_export(ctx, name, &mut vec![]) {
+            Resolution::Found(binding) => Ok(binding),
+            Resolution::Ambiguous => Err(JsValue::new(ctx.new_syntax_error(format!(
+                "Import '{}' of module '{}' is ambiguous, '{}' exports it from several modules",
+                ctx.description(local),
+                self.name,
+                module.name
+            )))),
+            Resolution::NotFound => Err(JsValue::new(ctx.new_syntax_error(format!(
+                "Module '{}' does not provide an export named '{}' imported by '{}'",
+                module.name,
+                ctx.description(name),
+                self.name
+            )))),
+        }
+    }
+
+    /// Evaluate the module and modules it requests, linking them first if needed. Module is evaluated only once,
+    /// evaluating it again rethrows its exception if evaluation failed.
+    ///
+    /// Evaluation of module that uses top level `await` or requests such module may not be complete when this
+    /// returns, its status is [ModuleStatus::EvaluatingAsync] then and exceptions are reported by
+    /// [ModuleRecord::promise].
+    pub fn evaluate(mut self, ctx: GcPointer<Context>) -> Result<(), JsValue> {
+        match self.status {
+            ModuleStatus::Unlinked | ModuleStatus::Linking => self.link(ctx)?,
+            ModuleStatus::Linked => {}
+            // module is part of a cycle that is being evaluated
+            ModuleStatus::Evaluating | ModuleStatus::EvaluatingAsync | ModuleStatus::Evaluated => {
+                return Ok(())
+            }
+            ModuleStatus::Errored => return Err(self.error),
+        }
+        self.status = ModuleStatus::Evaluating;
+        for i in 0..self.requested.len() {
+            let mut module = self.requested[i];
+            if let Err(error) = module.evaluate(ctx) {
+                return self.finish(ctx, Err(error));
+            }
+            if module.status == ModuleStatus::EvaluatingAsync {
+                self.pending += 1;
+                module.dependents.push(self);
+            }
+        }
+        if self.pending != 0 {
+            self.status = ModuleStatus::EvaluatingAsync;
+            return Ok(());
+        }
+        self.execute(ctx)
+    }
+
+    /// Promise resolved with namespace object of the module when its evaluation completes or rejected with
+    /// exception thrown by evaluation.
+    pub fn promise(mut self, ctx: GcPointer<Context>) -> Result<JsValue, JsValue> {
+        if self.promise.is_undefined() {
+            self.promise = JsPromise::new_unresolving(ctx)?;
+            match self.status {
+                ModuleStatus::Evaluated | ModuleStatus::Errored => self.settle_promise(ctx)?,
+                _ => {}
+            }
+        }
+        Ok(self.promise)
+    }
+
+    fn settle_promise(self, ctx: GcPointer<Context>) -> Result<(), JsValue> {
+        let promise = self.promise;
+        let object = promise.get_jsobject();
+        match self.status {
+            ModuleStatus::Evaluated => {
+                let namespace = JsValue::new(self.namespace(ctx)?);
+                object.data::<JsPromise>().resolve(ctx, promise, namespace)
+            }
+            _ => object.data::<JsPromise>().reject(ctx, promise, self.error),
+        }
+    }
+
+    /// Run body of the module whose requested modules are evaluated.
+    fn execute(self, ctx: GcPointer<Context>) -> Result<(), JsValue> {
+        let body_start = self.info().unwrap().body_start;
+        let result = self.run(ctx, body_start);
+        self.suspend_or_finish(ctx, result)
+    }
+
+    /// Handle `result` of running module body, body that returned native value is suspended at top level `await`
+    /// with the awaited value on top of its stack.
+    fn suspend_or_finish(
+        mut self,
+        mut ctx: GcPointer<Context>,
+        result: Result<JsValue, JsValue>,
+    ) -> Result<(), JsValue> {
+        match result {
+            Ok(value) if value.is_native_value() => {
+                debug_assert_eq!(value.get_native_u32(), FuncRet::Await as u32);
+                let mut frame = ctx.stack.pop_frame().expect("Empty call stack");
+                // awaited value stays on the stack of the suspended body until it is replaced by the result, so it is
+                // kept alive while the module waits for it.
+                let awaited = frame.top();
+                self.state = Some(AsyncFunctionState {
+                    throw: false,
+                    frame: Box::new(unsafe { HeapCallFrame::save(&mut frame) }),
+                });
+                self.status = ModuleStatus::EvaluatingAsync;
+                match self.await_value(ctx, awaited) {
+                    Ok(()) => Ok(()),
+                    Err(error) => {
+                        self.state = None;
+                        self.finish(ctx, Err(error))
+                    }
+                }
+            }
+            Ok(_) => self.finish(ctx, Ok(())),
+            Err(error) => self.finish(ctx, Err(error)),
+        }
+    }
+
+    /// Resume suspended body of the module when `value` settles.
+    fn await_value(self, ctx: GcPointer<Context>, value: JsValue) -> Result<(), JsValue> {
+        let promise = if value.is_jsobject() && value.get_jsobject().is_class(JsPromise::class()) {
+            value
+        } else {
+            let promise = JsPromise::new_unresolving(ctx)?;
+            promise
+                .get_jsobject()
+                .data::<JsPromise>()
+                .resolve(ctx, promise, value)?;
+            promise
+        };
+        // records are kept alive by the modules of their context.
+        let module = self;
+        let on_fulfilled = JsClosureFunction::new(
+            ctx,
+            "@moduleFulfilled".intern(),
+            move |ctx, args| {
+                module.resume(ctx, Ok(args.at(0)));
+                Ok(JsValue::encode_undefined_value())
+            },
+            1,
+        );
+        let on_rejected = JsClosureFunction::new(
+            ctx,
+            "@moduleRejected".intern(),
+            move |ctx, args| {
+                module.resume(ctx, Err(args.at(0)));
+                Ok(JsValue::encode_undefined_value())
+            },
+            1,
+        );
+        promise.get_jsobject().data::<JsPromise>().then(
+            ctx,
+            promise,
+            Some(JsValue::new(on_fulfilled)),
+            Some(JsValue::new(on_rejected)),
+            None,
+        )?;
+        Ok(())
+    }
+
+    /// Continue body suspended at top level `await` with settled awaited value.
+    fn resume(mut self, ctx: GcPointer<Context>, value: Result<JsValue, JsValue>) {
+        let mut state = match self.state.take() {
+            Some(state) => state,
+            None => return,
+        };
+        let result = match value {
+            Ok(value) => {
+                *state.frame.stack.last_mut().unwrap() = value;
+                async_func_resume(ctx, &mut state)
+            }
+            Err(error) => async_func_throw(ctx, &mut state, error),
+        };
+        // exception is reported through the promise of the module.
+        let _ = self.suspend_or_finish(ctx, result);
+    }
+
+    /// Complete evaluation of the module and run bodies of modules that no longer wait for async modules. Modules
+    /// waiting for module that failed fail with the same exception.
+    fn finish(
+        mut self,
+        ctx: GcPointer<Context>,
+        result: Result<(), JsValue>,
+    ) -> Result<(), JsValue> {
+        match result {
+            Ok(()) => self.status = ModuleStatus::Evaluated,
+            Err(error) => {
+                self.status = ModuleStatus::Errored;
+                self.error = error;
+            }
+        }
+        let settled = if self.promise.is_undefined() {
+            Ok(())
+        } else {
+            self.settle_promise(ctx)
+        };
+        for mut module in std::mem::take(&mut self.dependents) {
+            if module.status != ModuleStatus::EvaluatingAsync {
+                // module failed because of another module it waits for
+                continue;
+            }
+            let _ = match result {
+                Ok(()) => {
+                    module.pending -= 1;
+                    if module.pending != 0 {
+                        continue;
+                    }
+                    module.execute(ctx)
+                }
+                Err(error) => module.finish(ctx, Err(error)),
+            };
+        }
+        result.and(settled)
+    }
+
+    /// Run code of the module from `offset` in module environment.
+    fn run(self, ctx: GcPointer<Context>, offset: u32) -> Result<JsValue, JsValue> {
+        let function = self.function.unwrap();
+        ctx.perform_module_call(
+            function.as_function().as_vm(),
+            self.env.unwrap(),
+            offset,
+            JsValue::new(function),
+        )
+    }
+}
+
+impl GcCell for ModuleRecord {
+    fn deser_pair(&self) -> (usize, usize) {
+        (Self::deserialize as _, Self::allocate as _)
+    }
+}
+
+unsafe impl Trace for Binding {
+    fn trace(&mut self, visitor: &mut dyn Tracer) {
+        match self {
+            Self::Slot(env, _) => env.trace(visitor),
+            Self::Property(object, _) => object.trace(visitor),
+            Self::Namespace(module) => module.trace(visitor),
+        }
+    }
+}
+
+unsafe impl Trace for ModuleRecord {
+    fn trace(&mut self, visitor: &mut dyn Tracer) {
+        self.function.trace(visitor);
+        self.env.trace(visitor);
+        self.object.trace(visitor);
+        self.requested.trace(visitor);
+        for binding in self.bindings.iter_mut() {
+            binding.trace(visitor);
+        }
+        self.namespace.trace(visitor);
+        for binding in self.namespace_bindings.values_mut() {
+            binding.trace(visitor);
+        }
+        self.error.trace(visitor);
+        self.state.trace(visitor);
+        self.dependents.trace(visitor);
+        self.promise.trace(visitor);
+    }
+}
+
+impl Serializable for Binding {
+    fn serialize(&self, serializer: &mut SnapshotSerializer) {
+        match self {
+            Self::Slot(env, slot) => {
+                serializer.write_u8(0x0);
+                env.serialize(serializer);
+                slot.serialize(serializer);
+            }
+            Self::Property(object, name) => {
+                serializer.write_u8(0x1);
+                object.serialize(serializer);
+                name.serialize(serializer);
+            }
+            Self::Namespace(module) => {
+                serializer.write_u8(0x2);
+                module.serialize(serializer);
+            }
+        }
+    }
+}
+
+impl Deserializable for Binding {
+    unsafe fn deserialize_inplace(deser: &mut Deserializer) -> Self {
+        match deser.get_u8() {
+            0x0 => Self::Slot(deser.read_gc(), u32::deserialize_inplace(deser)),
+            0x1 => Self::Property(deser.read_gc(), Symbol::deserialize_inplace(deser)),
+            0x2 => Self::Namespace(deser.read_gc()),
+            _ => unreachable!(),
+        }
+    }
+    unsafe fn deserialize(_at: *mut u8, _deser: &mut Deserializer) {
+        unreachable!()
+    }
+    unsafe fn allocate(_ctx: &mut VirtualMachine, _deser: &mut Deserializer) -> *mut GcPointerBase {
+        unreachable!()
+    }
+}
+
+impl Serializable for ModuleRecord {
+    fn serialize(&self, serializer: &mut SnapshotSerializer) {
+        self.name.serialize(serializer);
+        serializer.write_u8(self.status as u8);
+        self.function.serialize(serializer);
+        self.env.serialize(serializer);
+        self.object.serialize(serializer);
+        self.requested.serialize(serializer);
+        (self.bindings.len() as u32).serialize(serializer);
+        for binding in self.bindings.iter() {
+            binding.serialize(serializer);
+        }
+        self.namespace.serialize(serializer);
+        (self.namespace_bindings.len() as u32).serialize(serializer);
+        for (name, binding) in self.namespace_bindings.iter() {
+            name.serialize(serializer);
+            binding.serialize(serializer);
+        }
+        self.error.serialize(serializer);
+        assert!(
+            self.state.is_none(),
+            "Cannot serialize module suspended at top level await"
+        );
+        self.pending.serialize(serializer);
+        self.dependents.serialize(serializer);
+        self.promise.serialize(serializer);
+    }
+}
+
+impl Deserializable for ModuleRecord {
+    unsafe fn deserialize_inplace(deser: &mut Deserializer) -> Self {
+        let name = String::deserialize_inplace(deser);
+        let status = std::mem::transmute::<u8, ModuleStatus>(deser.get_u8());
+        let function = deser.read_opt_gc();
+        let env = deser.read_opt_gc();
+        let object = deser.read_opt_gc();
+        let requested = Vec::<GcPointer<ModuleRecord>>::deserialize_inplace(deser);
+        let count = u32::deserialize_inplace(deser);
+        let mut bindings = Vec::with_capacity(count as _);
+        for _ in 0..count {
+            bindings.push(Binding::deserialize_inplace(deser));
+        }
+        let namespace = deser.read_opt_gc();
+        let count = u32::deserialize_inplace(deser);
+        let mut namespace_bindings = HashMap::with_capacity(count as _);
+        for _ in 0..count {
+            let name = Symbol::deserialize_inplace(deser);
+            namespace_bindings.insert(name, Binding::deserialize_inplace(deser));
+        }
+        let error = JsValue::deserialize_inplace(deser);
+        let pending = u32::deserialize_inplace(deser);
+        let dependents = Vec::<GcPointer<ModuleRecord>>::deserialize_inplace(deser);
+        let promise = JsValue::deserialize_inplace(deser);
+        Self {
+            name,
+            status,
+            function,
+            env,
+            object,
+            requested,
+            bindings,
+            namespace,
+            namespace_bindings,
+            error,
+            state: None,
+            pending,
+            dependents,
+            promise,
+        }
+    }
+    unsafe fn deserialize(at: *mut u8, deser: &mut Deserializer) {
+        at.cast::<Self>().write(Self::deserialize_inplace(deser))
+    }
+
+    unsafe fn allocate(ctx: &mut VirtualMachine, _deser: &mut Deserializer) -> *mut GcPointerBase {
+        ctx.heap().allocate_raw(
+            vtable_of_type::<Self>() as _,
+            size_of::<Self>(),
+            TypeId::of::<Self>(),
+        )
+    }
+}
+
+/// Module namespace object. Properties are exports of the module in code unit order, they're read through the
+/// bindings of the module so their values are live. Namespace can't be extended and its properties can't be
+/// changed.
+pub struct JsModuleNamespace {
+    module: GcPointer<ModuleRecord>,
+}
+
+extern "C" fn fsz() -> usize {
+    std::mem::size_of::<JsModuleNamespace>()
+}
+
+extern "C" fn ser(object: &JsObject, serializer: &mut SnapshotSerializer) {
+    object
+        .data::<JsModuleNamespace>()
+        .module
+        .serialize(serializer);
+}
+
+extern "C" fn deser(object: &mut JsObject, deser: &mut Deserializer) {
+    *object.data::<JsModuleNamespace>() = ManuallyDrop::new(JsModuleNamespace {
+        module: unsafe { deser.read_gc() },
+    });
+}
+
+#[allow(improper_ctypes_definitions)]
+extern "C" fn trace(tracer: &mut dyn Tracer, obj: &mut JsObject) {
+    obj.data::<JsModuleNamespace>().module.trace(tracer);
+}
+
+impl JsModuleNamespace {
+    fn new(
+        ctx: GcPointer<Context>,
+        module: GcPointer<ModuleRecord>,
+        names: &[Symbol],
+    ) -> Result<GcPointer<JsObject>, JsValue> {
+        let structure = Structure::new_indexed(ctx, None, false);
+        let mut namespace = JsObject::new(ctx, &structure, Self::class(), ObjectTag::Ordinary);
+        *namespace.data::<Self>() = ManuallyDrop::new(Self { module });
+        // namespace rejects definitions, so properties are defined with the methods of ordinary objects.
+        for name in names.iter() {
+            JsObject::DefineOwnNonIndexedPropertySlotMethod(
+                &mut namespace,
+                ctx,
+                *name,
+                &*DataDescriptor::new(JsValue::encode_undefined_value(), W | E),
+                &mut Slot::new(),
+                false,
+            )?;
+        }
+        let tag = JsString::new(ctx, "Module");
+        JsObject::DefineOwnNonIndexedPropertySlotMethod(
+            &mut namespace,
+            ctx,
+            "Symbol.toStringTag".intern().private(),
+            &*DataDescriptor::new(JsValue::new(tag), NONE),
+            &mut Slot::new(),
+            false,
+        )?;
+        namespace.change_extensible(ctx, false);
+        Ok(namespace)
+    }
+}
+
+#[allow(non_snake_case)]
+impl JsClass for JsModuleNamespace {
+    fn class() -> &'static Class {
+        define_jsclass!(
+            JsModuleNamespace,
+            Module,
+            None,
+            Some(trace),
+            Some(deser),
+            Some(ser),
+            Some(fsz)
+        )
+    }
+
+    fn GetOwnNonIndexedPropertySlotMethod(
+        obj: &mut GcPointer<JsObject>,
+        ctx: GcPointer<Context>,
+        name: Symbol,
+        slot: &mut Slot,
+    ) -> bool {
+        if !JsObject::GetOwnNonIndexedPropertySlotMethod(obj, ctx, name, slot) {
+            return false;
+        }
+        let module = obj.data::<Self>().module;
+        if let Some(binding) = module.namespace_bindings.get(&name).copied() {
+            let value = binding
+                .get(ctx)
+                .unwrap_or_else(|_| JsValue::encode_undefined_value());
+            let attributes = slot.attributes();
+            // value is not stored in the object, so the load must not be cached.
+            slot.set_1(value, attributes, Some(obj.as_dyn()));
+        }
+        true
+    }
+
+    fn GetOwnPropertyNamesMethod(
+        obj: &mut GcPointer<JsObject>,
+        ctx: GcPointer<Context>,
+        collector: &mut dyn FnMut(Symbol, u32),
+        mode: EnumerationMode,
+    ) {
+        // structure enumerates in hash order, exports are sorted before `@@toStringTag`.
+        let mut names = vec![];
+        JsObject::GetOwnPropertyNamesMethod(
+            obj,
+            ctx,
+            &mut |name, offset| names.push((name, offset)),
+            mode,
+        );
+        names.sort_by_cached_key(|(name, _)| match name {
+            Symbol::Key(_) => (false, ctx.description(*name)),
+            _ => (true, String::new()),
+        });
+        for (name, offset) in names {
+            collector(name, offset);
+        }
+    }
+
+    fn PutNonIndexedSlotMethod(
+        _obj: &mut GcPointer<JsObject>,
+        ctx: GcPointer<Context>,
+        name: Symbol,
+        _val: JsValue,
+        _slot: &mut Slot,
+        throwable: bool,
+    ) -> Result<(), JsValue> {
+        if throwable {
+            return Err(JsValue::new(ctx.new_type_error(format!(
+                "Cannot assign to '{}' of module namespace",
+                ctx.description(name)
+            ))));
+        }
+        Ok(())
+    }
+
+    fn DefineOwnNonIndexedPropertySlotMethod(
+        _obj: &mut GcPointer<JsObject>,
+        ctx: GcPointer<Context>,
+        name: Symbol,
+        _desc: &PropertyDescriptor,
+        _slot: &mut Slot,
+        throwable: bool,
+    ) -> Result<bool, JsValue> {
+        if throwable {
+            return Err(JsValue::new(ctx.new_type_error(format!(
+                "Cannot redefine '{}' of module namespace",
+                ctx.description(name)
+            ))));
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::vm::{
+        context::Context,
+        module_loader::{ModuleLoader, ModuleSource},
+        tests::test_runtime,
+    };
+    use std::collections::HashMap;
+
+    /// Loader of modules kept in memory, specifiers are used as keys.
+    pub(crate) struct MemoryLoader(pub(crate) HashMap<String, String>);
+
+    impl MemoryLoader {
+        pub(crate) fn new(modules: &[(&str, &str)]) -> Self {
+            Self(
+                modules
+                    .iter()
+                    .map(|(key, source)| (key.to_string(), source.to_string()))
+                    .collect(),
+            )
+        }
+    }
+
+    impl ModuleLoader for MemoryLoader {
+        fn resolve(&mut self, specifier: &str, _referrer: &str) -> Result<String, String> {
+            if self.0.contains_key(specifier) {
+                Ok(specifier.to_string())
+            } else {
+                Err(format!("unknown module '{}'", specifier))
+            }
+        }
+
+        fn load(&mut self, key: &str) -> Result<ModuleSource, String> {
+            Ok(ModuleSource::Source(self.0[key].clone()))
+        }
+    }
+
+    /// Evaluate module `main` importing `modules`, run queued jobs and return global `result` converted to string or
+    /// message of the error module evaluation failed with.
+    pub(crate) fn run_module(main: &str, modules: &[(&str, &str)]) -> Result<String, String> {
+        let (mut runtime, jobs) = test_runtime();
+        runtime.set_module_loader(Some(Box::new(MemoryLoader::new(modules))));
+        let mut ctx = Context::new(&mut runtime);
+        let result = ctx
+            .compile_module("main", "main", main)
+            .and_then(|function| ctx.evaluate_module("main", function.get_jsobject()))
+            .and_then(|module| {
+                jobs.run(ctx);
+                module.error().map_or(Ok(()), Err)
+            })
+            .and_then(|_| ctx.eval("globalThis.result"))
+            .and_then(|result| result.to_string(ctx));
+        result.map_err(|error| error.to_string(ctx).unwrap_or_default())
+    }
+
+    const COUNTER: &str = "export let count = 0; export function inc() { count++; }";
+
+    #[test]
+    fn test_live_bindings() {
+        assert_eq!(
+            run_module(
+                "import { count, inc } from 'counter'; \
+                 import * as ns from 'counter'; \
+                 let before = count; \
+                 inc(); inc(); \
+                 let assign; \
+                 try { count = 10; } catch (e) { assign = e instanceof TypeError; } \
+                 globalThis.result = [before, count, ns.count, assign];",
+                &[("counter", COUNTER)]
+            ),
+            Ok("0,2,2,true".to_string())
+        );
+    }
+
+    #[test]
+    fn test_cyclic_imports() {
+        // `a` is requested first, so `b` is evaluated before it and sees `a`'s hoisted function but not its
+        // variables.
+        assert_eq!(
+            run_module(
+                "import 'setup'; \
+                 import { late } from 'a'; \
+                 import { hello } from 'b'; \
+                 order.push('main:' + hello() + ':' + late); \
+                 globalThis.result = order;",
+                &[
+                    ("setup", "globalThis.order = [];"),
+                    (
+                        "a",
+                        "import { hello } from 'b'; \
+                         order.push('a'); \
+                         export function early() { return 'early'; } \
+                         export let late = 'late';"
+                    ),
+                    (
+                        "b",
+                        "import { early, late } from 'a'; \
+                         order.push('b:' + early() + ':' + late); \
+                         export function hello() { return late; }"
+                    ),
+                ]
+            ),
+            Ok("b:early:undefined,a,main:late:late".to_string())
+        );
+    }
+
+    #[test]
+    fn test_star_export_conflicts() {
+        let modules = [
+            (
+                "one",
+                "export let x = 1; export let onlyOne = 'one'; export default 1;",
+            ),
+            ("two", "export let x = 2; export let onlyTwo = 'two';"),
+            ("star", "export * from 'one'; export * from 'two';"),
+            (
+                "own",
+                "export * from 'one'; export * from 'two'; export let x = 'own';",
+            ),
+        ];
+        assert_eq!(
+            run_module(
+                "import * as star from 'star'; \
+                 import { x } from 'own'; \
+                 globalThis.result = ['x' in star, 'default' in star, star.onlyOne, star.onlyTwo, x];",
+                &modules
+            ),
+            Ok("false,false,one,two,own".to_string())
+        );
+        let error = run_module("import { x } from 'star';", &modules).unwrap_err();
+        assert!(error.starts_with("SyntaxError"), "{}", error);
+        assert!(error.contains("ambiguous"), "{}", error);
+    }
+
+    #[test]
+    fn test_indirect_exports() {
+        assert_eq!(
+            run_module(
+                "import { total, inc, all } from 'reexport'; \
+                 inc(); \
+                 globalThis.result = [total, all.count, typeof all.inc, all.inc === inc];",
+                &[
+                    ("counter", COUNTER),
+                    (
+                        "reexport",
+                        "export { count as total, inc } from 'counter'; export * as all from 'counter';"
+                    ),
+                ]
+            ),
+            Ok("1,1,function,true".to_string())
+        );
+        let error = run_module(
+            "import { count } from 'reexport';",
+            &[
+                ("counter", COUNTER),
+                ("reexport", "export { count as total } from 'counter';"),
+            ],
+        )
+        .unwrap_err();
+        assert!(error.starts_with("SyntaxError"), "{}", error);
+    }
+
+    #[test]
+    fn test_namespace_object() {
+        assert_eq!(
+            run_module(
+                "import * as ns from 'counter'; \
+                 let errors = []; \
+                 try { ns.count = 1; } catch (e) { errors.push(e instanceof TypeError); } \
+                 try { ns.added = 1; } catch (e) { errors.push(e instanceof TypeError); } \
+                 try { delete ns.count; } catch (e) { errors.push(e instanceof TypeError); } \
+                 globalThis.result = [Object.isSealed(ns), Object.isFrozen(ns), Object.isExtensible(ns), \
+                     Object.getPrototypeOf(ns) === null, Object.keys(ns).join('|'), \
+                     ns[Symbol.toStringTag], Object.prototype.toString.call(ns), errors.join('|')];",
+                &[("counter", COUNTER)]
+            ),
+            Ok("true,false,false,true,count|inc,Module,[object Module],true|true|true".to_string())
+        );
+    }
+
+    #[test]
+    fn test_dynamic_import() {
+        let modules = [
+            ("counter", COUNTER),
+            (
+                "throws",
+                "export let x = 1; throw new TypeError('module failed');",
+            ),
+        ];
+        assert_eq!(
+            run_module(
+                "import * as counter from 'counter'; \
+                 globalThis.result = 'pending'; \
+                 import('counter').then(ns => { \
+                     ns.inc(); \
+                     globalThis.result = [ns === counter, ns.count, counter.count]; \
+                 });",
+                &modules
+            ),
+            Ok("true,1,1".to_string())
+        );
+        // failures reject the promise and don't fail the importer.
+        assert_eq!(
+            run_module(
+                "globalThis.result = 'pending'; \
+                 import('missing').then(() => { globalThis.result = 'loaded'; }, e1 => \
+                     import('throws').then(() => { globalThis.result = 'evaluated'; }, e2 => \
+                         import('throws').catch(e3 => { \
+                             globalThis.result = [e1 instanceof ReferenceError, e1.message, e2 instanceof TypeError, e2 === e3]; \
+                         })));",
+                &modules
+            ),
+            Ok("true,Module 'missing' not found: unknown module 'missing',true,true".to_string())
+        );
+    }
+
+    #[test]
+    fn test_top_level_await() {
+        // `b` doesn't wait for its sibling `a`, `main` runs after `a` completes.
+        assert_eq!(
+            run_module(
+                "import 'setup'; \
+                 import { value } from 'a'; \
+                 import 'b'; \
+                 order.push('main:' + value); \
+                 let ns = await import('c'); \
+                 order.push('main:' + ns.value); \
+                 globalThis.result = order;",
+                &[
+                    ("setup", "globalThis.order = [];"),
+                    (
+                        "a",
+                        "order.push('a'); \
+                         export let value = await Promise.resolve('ready'); \
+                         order.push('a:' + value);"
+                    ),
+                    ("b", "order.push('b');"),
+                    ("c", "await null; export let value = 'c';"),
+                ]
+            ),
+            Ok("a,b,a:ready,main:ready,main:c".to_string())
+        );
+        let error = run_module(
+            "import 'a'; globalThis.result = 'evaluated';",
+            &[("a", "await Promise.reject(new TypeError('rejected'));")],
+        )
+        .unwrap_err();
+        assert_eq!(error, "TypeError: rejected");
+    }
+}
@@ -0,0 +1,252 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+//! Module resolution and loading hooks.
+//!
+//! Every module is stored in [Context::modules](super::context::Context::modules) under a key. Loader resolves
+//! specifiers of `import` declarations, `import()` and `std` module loading to keys and loads source of the module
+//! stored under a key. Keys don't have to be paths, a loader can use URLs of its own scheme or names of in-memory
+//! modules. Names of modules registered with [Context::add_module](super::context::Context::add_module) are never
+//! passed to the loader.
+//!
+//! Embedders install a loader with [VirtualMachine::set_module_loader](super::VirtualMachine::set_module_loader)
+//! or for a single context with [Context::set_module_loader](super::context::Context::set_module_loader). Without one
+//! [FileModuleLoader] is used. [NodeModuleLoader](crate::jsrt::commonjs::NodeModuleLoader) resolves modules like
+//! Node and imports CommonJS modules.
+use super::{context::Context, object::JsObject, value::JsValue};
+use crate::gc::cell::GcPointer;
+use std::path::{Path, PathBuf};
+
+/// Module loaded by [ModuleLoader::load].
+pub enum ModuleSource {
+    /// Source text of ES module.
+    Source(String),
+    /// Native module. Function initializes module object like for
+    /// [ModuleKind::NativeUninit](super::ModuleKind::NativeUninit), exports are properties of its `@exports`
+    /// object.
+    Native(fn(GcPointer<Context>, GcPointer<JsObject>) -> Result<(), JsValue>),
+    /// Source text of CommonJS module or of JSON file if the key ends with `.json`. Module is run like by `require`
+    /// and shares `require.cache`, `module.exports` is its default export, see [commonjs](crate::jsrt::commonjs).
+    CommonJs(String),
+}
+
+pub trait ModuleLoader {
+    /// Key of module `specifier` imported by code of `referrer`. `referrer` is the key of importing module, file
+    /// name of importing script or empty string for code without a file. Specifiers that resolve to the same key
+    /// share a module.
+    fn resolve(&mut self, specifier: &str, referrer: &str) -> Result<String, String>;
+    /// Load module stored under `key`. Called once per context for each key.
+    fn load(&mut self, key: &str) -> Result<ModuleSource, String>;
+}
+
+/// Loader of module files. Specifiers are paths relative to directory of the referrer and keys are canonical paths.
+/// Files of standalone bundle are found before the file system.
+#[derive(Default)]
+pub struct FileModuleLoader;
+
+impl ModuleLoader for FileModuleLoader {
+    fn resolve(&mut self, specifier: &str, referrer: &str) -> Result<String, String> {
+        let mut path = match Path::new(referrer).parent() {
+            Some(dir) => dir.join(specifier),
+            None => PathBuf::from(specifier),
+        };
+        if cfg!(windows) {
+            path = PathBuf::from(path.to_string_lossy().replace("/", "\\"));
+        }
+        crate::jsrt::module_key(&path).map_err(|e| format!("'{}': {}", path.display(), e))
+    }
+
+    fn load(&mut self, key: &str) -> Result<ModuleSource, String> {
+        match crate::bundle::bundled_file(key) {
+            Some(source) => Ok(ModuleSource::Source(
+                String::from_utf8_lossy(source).into_owned(),
+            )),
+            None => std::fs::read_to_string(key)
+                .map(ModuleSource::Source)
+                .map_err(|e| e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+    use crate::vm::{context::Context, module::tests::MemoryLoader, tests::test_runtime};
+    use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+    /// Loader of `mem:` URLs that records its calls. Specifiers starting with `./` are relative to the referrer,
+    /// other specifiers are looked up in an import map.
+    struct UrlLoader {
+        sources: HashMap<&'static str, &'static str>,
+        imports: HashMap<&'static str, &'static str>,
+        log: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl ModuleLoader for UrlLoader {
+        fn resolve(&mut self, specifier: &str, referrer: &str) -> Result<String, String> {
+            self.log
+                .borrow_mut()
+                .push(format!("resolve {} from {}", specifier, referrer));
+            match specifier.strip_prefix("./") {
+                Some(path) => Ok(format!(
+                    "{}/{}",
+                    &referrer[..referrer.rfind('/').unwrap()],
+                    path
+                )),
+                None => self
+                    .imports
+                    .get(specifier)
+                    .map(|key| key.to_string())
+                    .ok_or_else(|| "not in import map".to_string()),
+            }
+        }
+
+        fn load(&mut self, key: &str) -> Result<ModuleSource, String> {
+            self.log.borrow_mut().push(format!("load {}", key));
+            if key == "mem:/native" {
+                return Ok(ModuleSource::Native(init_native));
+            }
+            self.sources
+                .get(key)
+                .map(|source| ModuleSource::Source(source.to_string()))
+                .ok_or_else(|| "no such module".to_string())
+        }
+    }
+
+    fn init_native(
+        ctx: GcPointer<Context>,
+        mut module: GcPointer<JsObject>,
+    ) -> Result<(), JsValue> {
+        let mut exports = JsObject::new_empty(ctx);
+        exports.put(ctx, "answer".intern(), JsValue::new(42), false)?;
+        module.put(ctx, "@exports".intern(), JsValue::new(exports), false)
+    }
+
+    #[test]
+    fn test_custom_loader() {
+        let (mut runtime, jobs) = test_runtime();
+        let log = Rc::new(RefCell::new(vec![]));
+        runtime.set_module_loader(Some(Box::new(UrlLoader {
+            sources: [
+                (
+                    "mem:/app/a",
+                    "import { answer } from 'native'; export let value = answer;",
+                ),
+                (
+                    "mem:/app/b",
+                    "import { value } from './a'; export let doubled = value * 2;",
+                ),
+            ]
+            .iter()
+            .copied()
+            .collect(),
+            imports: [("native", "mem:/native"), ("b", "mem:/app/b")]
+                .iter()
+                .copied()
+                .collect(),
+            log: log.clone(),
+        })));
+        let mut ctx = Context::new(&mut runtime);
+        let function = ctx
+            .compile_module(
+                "mem:/app/main",
+                "main",
+                "import { value } from './a'; \
+                 import { doubled } from 'b'; \
+                 globalThis.result = [value, doubled]; \
+                 import('./missing').catch(e => { globalThis.error = e.message; });",
+            )
+            .unwrap_or_else(|_| panic!("failed to compile main"));
+        if ctx
+            .evaluate_module("mem:/app/main", function.get_jsobject())
+            .is_err()
+        {
+            panic!("failed to evaluate main");
+        }
+        jobs.run(ctx);
+        assert_eq!(
+            ctx.eval("globalThis.result + ';' + globalThis.error")
+                .and_then(|result| result.to_string(ctx))
+                .unwrap_or_default(),
+            "42,84;Failed to read module 'mem:/app/missing': no such module"
+        );
+        // `./a` is requested twice but loaded once, modules are loaded in order they are requested.
+        assert_eq!(
+            *log.borrow(),
+            [
+                "resolve ./a from mem:/app/main",
+                "load mem:/app/a",
+                "resolve native from mem:/app/a",
+                "load mem:/native",
+                "resolve b from mem:/app/main",
+                "load mem:/app/b",
+                "resolve ./a from mem:/app/b",
+                "resolve ./missing from mem:/app/main",
+                "load mem:/app/missing",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_context_loader() {
+        let (mut runtime, jobs) = test_runtime();
+        runtime.set_module_loader(Some(Box::new(MemoryLoader::new(&[(
+            "name",
+            "export let name = 'vm';",
+        )]))));
+        let vm_ctx = Context::new(&mut runtime);
+        let mut ctx = Context::new(&mut runtime);
+        ctx.set_module_loader(Some(Box::new(MemoryLoader::new(&[(
+            "name",
+            "export let name = 'context';",
+        )]))));
+        let mut names = vec![];
+        for mut ctx in [vm_ctx, ctx] {
+            let function = ctx
+                .compile_module(
+                    "main",
+                    "main",
+                    "import { name } from 'name'; globalThis.result = name;",
+                )
+                .unwrap_or_else(|_| panic!("failed to compile main"));
+            if ctx
+                .evaluate_module("main", function.get_jsobject())
+                .is_err()
+            {
+                panic!("failed to evaluate main");
+            }
+            jobs.run(ctx);
+            names.push(
+                ctx.eval("globalThis.result")
+                    .and_then(|result| result.to_string(ctx))
+                    .unwrap_or_default(),
+            );
+        }
+        assert_eq!(names, ["vm", "context"]);
+    }
+
+    #[test]
+    fn test_file_loader() {
+        let dir = std::env::temp_dir().join(format!("starlight-loader-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("lib/util.js"), "export let x = 1;").unwrap();
+        let mut loader = FileModuleLoader;
+        let referrer = dir.join("main.js");
+        let key = loader
+            .resolve("./lib/../lib/util.js", &referrer.to_string_lossy())
+            .unwrap();
+        assert_eq!(
+            Path::new(&key),
+            dir.join("lib/util.js").canonicalize().unwrap()
+        );
+        assert!(matches!(
+            loader.load(&key),
+            Ok(ModuleSource::Source(source)) if source == "export let x = 1;"
+        ));
+        assert!(loader
+            .resolve("./missing.js", &referrer.to_string_lossy())
+            .is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
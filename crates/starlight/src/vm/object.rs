@@ -49,6 +49,8 @@ pub enum JsHint {
     Number,
     None,
 }
+/// Object has `[[IsHTMLDDA]]` internal slot. See [Annex B.3.7](https://tc39.es/ecma262/#sec-IsHTMLDDA-internal-slot).
+pub const OBJ_FLAG_HTMLDDA: u32 = 0x8;
 pub const OBJ_FLAG_TUPLE: u32 = 0x4;
 pub const OBJ_FLAG_CALLABLE: u32 = 0x2;
 pub const OBJ_FLAG_EXTENSIBLE: u32 = 0x1;
@@ -515,6 +517,20 @@ impl JsObject {
         (self.flags & OBJ_FLAG_CALLABLE) != 0
     }
 
+    /// Mark object as having `[[IsHTMLDDA]]` internal slot: `typeof` returns `"undefined"`, it is falsy and
+    /// loosely equal to `null` and `undefined`.
+    pub fn set_htmldda(&mut self, val: bool) {
+        if val {
+            self.flags |= OBJ_FLAG_HTMLDDA;
+        } else {
+            self.flags &= !OBJ_FLAG_HTMLDDA;
+        }
+    }
+
+    pub fn is_htmldda(&self) -> bool {
+        (self.flags & OBJ_FLAG_HTMLDDA) != 0
+    }
+
     // section 8.12.9 `[[DefineOwnProperty]]`
     pub fn DefineOwnNonIndexedPropertySlotMethod(
         obj: &mut GcPointer<Self>,
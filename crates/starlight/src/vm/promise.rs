@@ -198,7 +198,7 @@ impl JsPromise {
                 1,
             ));
 
-            sub_prom_jsprom.then(ctx, None, None, Some(sub_finally))?;
+            sub_prom_jsprom.then(ctx, sub_prom, None, None, Some(sub_finally))?;
         }
 
         Ok(promise_value)
@@ -315,6 +315,9 @@ impl JsPromise {
                         None,
                         prom_this,
                     ));
+                    if resolution_prom.resolution.is_some() && resolution_prom.subs.len() == 1 {
+                        Self::schedule_reactions(ctx, resolution_value)?;
+                    }
                     // exit this do_resolve()
                     return Ok(());
                 }
@@ -322,86 +325,81 @@ impl JsPromise {
 
             self.resolution = Some(resolution);
 
-            // todo everything below needs to be in async job.. need to root persistent again... later
-            // root prom_this
-            let prom_root = ctx.vm.add_persistent_root(prom_this);
-
-            ctx.schedule_async(move |ctx| {
-                let prom_val = prom_root.get_value();
-                let mut prom_js_object = prom_val.get_jsobject();
-                let prom_self: &mut JsPromise = prom_js_object.as_promise_mut();
-
-                if let Ok(ok_resolution) = prom_self.resolution.unwrap() {
-                    for sub in &prom_self.subs {
-                        // invoke 0, resolve 3
-                        if let Some(jsFunc) = sub.0 {
-                            let this = JsValue::encode_undefined_value();
-                            let mut args_vec = vec![ok_resolution];
-                            let mut args = Arguments::new(this, args_vec.as_mut_slice());
-                            let sub_res = jsFunc
-                                .get_jsobject()
-                                .as_function_mut()
-                                .call(ctx, &mut args, this);
-                            let sub_res = sub
-                                .3
-                                .get_jsobject()
-                                .as_promise_mut()
-                                .do_resolve(ctx, sub.3, sub_res);
-                            if sub_res.is_err() {
-                                println!("could not resolve sub");
-                            }
-                        }
-                    }
-                } else {
-                    let err_resolution = prom_self.resolution.unwrap().err().unwrap();
-                    for sub in &prom_self.subs {
-                        // invoke 1, resolve 3
-                        if let Some(jsFunc) = sub.1 {
-                            let this = JsValue::encode_undefined_value();
-                            let mut args_vec = vec![err_resolution];
-                            let mut args = Arguments::new(this, args_vec.as_mut_slice());
-                            let sub_res = jsFunc
-                                .get_jsobject()
-                                .as_function_mut()
-                                .call(ctx, &mut args, this);
-                            let sub_res = sub
-                                .3
-                                .get_jsobject()
-                                .as_promise_mut()
-                                .do_resolve(ctx, sub.3, sub_res);
-                            if sub_res.is_err() {
-                                println!("could not resolve sub");
-                            }
-                        }
+            Self::schedule_reactions(ctx, prom_this)?;
+            Ok(())
+        }
+    }
+
+    /// Schedule a job that runs the reactions of settled promise `prom_this` that didn't run yet. Reactions added
+    /// while the job runs are run by another job.
+    fn schedule_reactions(mut ctx: GcPointer<Context>, prom_this: JsValue) -> Result<(), JsValue> {
+        let prom_root = ctx.vm.add_persistent_root(prom_this);
+
+        ctx.schedule_async(move |ctx| {
+            let prom_val = prom_root.get_value();
+            let mut prom_js_object = prom_val.get_jsobject();
+            // subs stay in the promise until they ran so they are traced, reactions can add more of them.
+            let count = prom_js_object.as_promise_mut().subs.len();
+            let resolution = prom_js_object.as_promise_mut().resolution.unwrap();
+
+            for index in 0..count {
+                let sub = prom_js_object.as_promise_mut().subs[index];
+                // invoke 0 or 1, resolve 3
+                let (func, value) = match resolution {
+                    Ok(ok_resolution) => (sub.0, ok_resolution),
+                    Err(err_resolution) => (sub.1, err_resolution),
+                };
+                if let Some(jsFunc) = func {
+                    let this = JsValue::encode_undefined_value();
+                    let mut args_vec = vec![value];
+                    let mut args = Arguments::new(this, args_vec.as_mut_slice());
+                    let sub_res = jsFunc
+                        .get_jsobject()
+                        .as_function_mut()
+                        .call(ctx, &mut args, this);
+                    let sub_res = sub
+                        .3
+                        .get_jsobject()
+                        .as_promise_mut()
+                        .do_resolve(ctx, sub.3, sub_res);
+                    if sub_res.is_err() {
+                        println!("could not resolve sub");
                     }
                 }
-                for sub in &prom_self.subs {
-                    // invoke 2, resolve 3
-                    if let Some(jsFunc) = sub.2 {
-                        let this = JsValue::encode_undefined_value();
-                        let mut args_vec = vec![];
-                        let mut args = Arguments::new(this, args_vec.as_mut_slice());
-                        let sub_res = jsFunc
-                            .get_jsobject()
-                            .as_function_mut()
-                            .call(ctx, &mut args, this);
-                        let sub_res = sub
-                            .3
-                            .get_jsobject()
-                            .as_promise_mut()
-                            .do_resolve(ctx, sub.3, sub_res);
-                        if sub_res.is_err() {
-                            println!("could not resolve sub");
-                        }
+            }
+            for index in 0..count {
+                let sub = prom_js_object.as_promise_mut().subs[index];
+                // invoke 2, resolve 3
+                if let Some(jsFunc) = sub.2 {
+                    let this = JsValue::encode_undefined_value();
+                    let mut args_vec = vec![];
+                    let mut args = Arguments::new(this, args_vec.as_mut_slice());
+                    let sub_res = jsFunc
+                        .get_jsobject()
+                        .as_function_mut()
+                        .call(ctx, &mut args, this);
+                    let sub_res = sub
+                        .3
+                        .get_jsobject()
+                        .as_promise_mut()
+                        .do_resolve(ctx, sub.3, sub_res);
+                    if sub_res.is_err() {
+                        println!("could not resolve sub");
                     }
                 }
-            })?;
-            Ok(())
-        }
+            }
+            let prom_self = prom_js_object.as_promise_mut();
+            prom_self.subs.drain(..count);
+            if !prom_self.subs.is_empty() {
+                let _ = Self::schedule_reactions(ctx, prom_val);
+            }
+        })
     }
+
     pub fn then(
         &mut self,
         ctx: GcPointer<Context>,
+        prom_this: JsValue,
         on_resolved: Option<JsValue>,
         on_rejected: Option<JsValue>,
         on_finally: Option<JsValue>,
@@ -412,6 +410,10 @@ impl JsPromise {
 
         self.subs
             .push((on_resolved, on_rejected, on_finally, sub_prom));
+        // reactions of settled promise are run by the job scheduled when it settled, unless that job already ran.
+        if self.resolution.is_some() && self.subs.len() == 1 {
+            Self::schedule_reactions(ctx, prom_this)?;
+        }
 
         Ok(sub_prom)
     }
@@ -0,0 +1,226 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+//! Source map v3 support.
+//!
+//! Source maps are registered per script file in [VirtualMachine](super::VirtualMachine) and are used to
+//! translate locations recorded in [CodeBlock::loc](super::code_block::CodeBlock::loc) back to the original sources
+//! when printing stack traces.
+use super::code_block::FileLocation;
+use sourcemap::DecodedMap;
+use std::path::{Component, Path, PathBuf};
+
+/// Location in an original source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OriginalLocation {
+    /// Original source file.
+    pub source: String,
+    /// Zero based line.
+    pub line: u32,
+    /// Zero based column.
+    pub col: u32,
+    /// Original name of the symbol at this location.
+    pub name: Option<String>,
+}
+
+/// Parsed source map.
+pub struct SourceMap {
+    map: sourcemap::SourceMap,
+}
+
+impl SourceMap {
+    /// Parse source map from JSON. Index maps are flattened. Relative `sources` are resolved against `base_dir`.
+    /// Returns `None` if map is malformed.
+    pub fn from_slice(data: &[u8], base_dir: &Path) -> Option<Self> {
+        Self::from_decoded(sourcemap::decode_slice(data).ok()?, base_dir)
+    }
+
+    /// Load source map referenced by `url`. `url` is either inline `data:` URL or a path relative to `script_path`.
+    /// Relative `sources` of the map are resolved against the directory of the map file, inline maps are
+    /// resolved against the directory of the script.
+    pub fn from_url(url: &str, script_path: &str) -> Option<Self> {
+        let script_dir = Path::new(script_path).parent().unwrap_or(Path::new(""));
+        if url.starts_with("data:") {
+            return Self::from_decoded(sourcemap::decode_data_url(url).ok()?, script_dir);
+        }
+        let path = script_dir.join(url);
+        let map = sourcemap::decode_slice(&std::fs::read(&path).ok()?).ok()?;
+        Self::from_decoded(map, path.parent().unwrap_or(Path::new("")))
+    }
+
+    /// Load source map referenced by `//# sourceMappingURL=` comment of `script`.
+    pub fn from_script(script: &str, script_path: &str) -> Option<Self> {
+        Self::from_url(source_mapping_url(script)?, script_path)
+    }
+
+    fn from_decoded(map: DecodedMap, base_dir: &Path) -> Option<Self> {
+        let mut map = match map {
+            DecodedMap::Regular(map) => map,
+            DecodedMap::Index(index) => index.flatten().ok()?,
+            DecodedMap::Hermes(map) => (*map).clone(),
+        };
+        // `sourceRoot` is already prepended to `sources` by the decoder.
+        for idx in 0..map.get_source_count() {
+            let source = map.get_source(idx).unwrap_or("");
+            if source.is_empty() || source.contains("://") || Path::new(source).is_absolute() {
+                continue;
+            }
+            let resolved = normalize_path(&base_dir.join(source));
+            map.set_source(idx, &resolved.to_string_lossy());
+        }
+        Some(Self { map })
+    }
+
+    /// Find original location of generated `loc`.
+    pub fn lookup(&self, loc: FileLocation) -> Option<OriginalLocation> {
+        let token = self.map.lookup_token(loc.line, loc.col)?;
+        Some(OriginalLocation {
+            source: token.get_source()?.to_string(),
+            line: token.get_src_line(),
+            col: token.get_src_col(),
+            name: token.get_name().map(|name| name.to_string()),
+        })
+    }
+}
+
+/// Key of script `file` in the source map registry. Scripts are registered by canonical path so `./a.js` and
+/// `a.js` refer to the same map, names that are not files are used as they are.
+pub fn file_key(file: &str) -> String {
+    std::fs::canonicalize(file)
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| file.to_string())
+}
+
+/// Remove `.` and `..` components of `path` without touching the file system, original sources don't have to exist.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push("..");
+                }
+            }
+            component => normalized.push(component.as_os_str()),
+        }
+    }
+    normalized
+}
+
+/// Returns URL of the last `//# sourceMappingURL=` (or legacy `//@ sourceMappingURL=`) comment in `script`.
+pub fn source_mapping_url(script: &str) -> Option<&str> {
+    script.lines().rev().find_map(|line| {
+        let line = line.trim();
+        let comment = line
+            .strip_prefix("//#")
+            .or_else(|| line.strip_prefix("//@"))?;
+        let url = comment
+            .trim_start()
+            .strip_prefix("sourceMappingURL=")?
+            .trim();
+        if url.is_empty() {
+            None
+        } else {
+            Some(url)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{options::Options, Platform};
+
+    // `a.ts`:
+    // let answer = 42;
+    // function hello() { throw new Error(answer); }
+    const MAP: &str = r#"{
+        "version": 3,
+        "file": "a.js",
+        "sourceRoot": "../src",
+        "sources": ["a.ts"],
+        "names": ["answer", "hello"],
+        "mappings": "AAAA,IAAIA,MAAS;AACb,SAASC"
+    }"#;
+
+    #[test]
+    fn test_decode_and_lookup() {
+        let map = SourceMap::from_slice(MAP.as_bytes(), Path::new("/app/dist/maps")).unwrap();
+        let loc = map.lookup(FileLocation { line: 0, col: 4 }).unwrap();
+        assert_eq!(
+            loc,
+            OriginalLocation {
+                source: "/app/dist/src/a.ts".to_string(),
+                line: 0,
+                col: 4,
+                name: Some("answer".to_string()),
+            }
+        );
+        let loc = map.lookup(FileLocation { line: 1, col: 12 }).unwrap();
+        assert_eq!(loc.line, 1);
+        assert_eq!(loc.col, 9);
+        assert_eq!(loc.name.as_deref(), Some("hello"));
+    }
+
+    #[test]
+    fn test_inline_map() {
+        let url = format!(
+            "data:application/json;base64,{}",
+            base64_encode(MAP.as_bytes())
+        );
+        let script = format!("let answer = 42;\n//# sourceMappingURL={}\n", url);
+        let map = SourceMap::from_script(&script, "/app/dist/a.js").unwrap();
+        let loc = map.lookup(FileLocation { line: 0, col: 0 }).unwrap();
+        assert_eq!(loc.source, "/app/src/a.ts");
+    }
+
+    #[test]
+    fn test_registered_names() {
+        Platform::initialize();
+        let mut vm = Platform::new_runtime(Options::default(), None);
+        let map = || SourceMap::from_slice(MAP.as_bytes(), Path::new("/app")).unwrap();
+        // tests run in the crate directory
+        vm.add_source_map("./Cargo.toml", map());
+        vm.add_source_map("src/../Cargo.toml", map());
+        vm.add_source_map("<eval>", map());
+        assert_eq!(vm.source_maps.len(), 2);
+        assert!(vm.source_map("./Cargo.toml").is_some());
+        assert!(vm.source_map("src/../Cargo.toml").is_some());
+        assert!(vm.source_map("<eval>").is_some());
+        assert!(vm.source_map("<script>").is_none());
+    }
+
+    #[test]
+    fn test_source_mapping_url() {
+        assert_eq!(
+            source_mapping_url("a();\n//# sourceMappingURL=a.js.map\n"),
+            Some("a.js.map")
+        );
+        assert_eq!(
+            source_mapping_url("a();\n//@ sourceMappingURL=old.map"),
+            Some("old.map")
+        );
+        assert_eq!(source_mapping_url("a();\n//# sourceMappingURL=\n"), None);
+        assert_eq!(source_mapping_url("a();"), None);
+    }
+
+    fn base64_encode(data: &[u8]) -> String {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut out = String::new();
+        for chunk in data.chunks(3) {
+            let n = chunk
+                .iter()
+                .enumerate()
+                .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+                } else {
+                    out.push('=');
+                }
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,467 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+//! Structured stack traces attached to error objects.
+//!
+//! Frames are captured when error is constructed (or by `Error.captureStackTrace`) and stored in hidden
+//! `[[StackTrace]]` property. `stack` accessor on `Error.prototype` formats them in V8 format on first access.
+use super::{
+    arguments::Arguments,
+    attributes::*,
+    class::{Class, JsClass},
+    context::Context,
+    method_table::*,
+    object::{JsObject, ObjectTag},
+    property_descriptor::*,
+    string::JsString,
+    symbol_table::{Internable, Symbol},
+    value::JsValue,
+};
+use crate::gc::{
+    cell::GcPointer,
+    snapshot::{
+        deserializer::{Deserializable, Deserializer},
+        serializer::{Serializable, SnapshotSerializer},
+    },
+};
+use std::{fmt, mem::ManuallyDrop};
+
+/// Default value of `Error.stackTraceLimit`.
+pub const DEFAULT_STACK_TRACE_LIMIT: usize = 10;
+
+/// Single frame of captured stack trace.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StackFrame {
+    /// Function name, empty for anonymous functions.
+    pub function_name: String,
+    /// Script file name, `None` for native frames.
+    pub file_name: Option<String>,
+    /// One based line.
+    pub line: Option<u32>,
+    /// One based column.
+    pub column: Option<u32>,
+}
+
+impl StackFrame {
+    pub fn is_native(&self) -> bool {
+        self.file_name.is_none()
+    }
+}
+
+impl fmt::Display for StackFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let file = match self.file_name {
+            Some(ref file) => file,
+            None if self.function_name.is_empty() => return write!(f, "at <native code>"),
+            None => return write!(f, "at {} (native)", self.function_name),
+        };
+        let location = match (self.line, self.column) {
+            (Some(line), Some(column)) => format!("{}:{}:{}", file, line, column),
+            _ => file.clone(),
+        };
+        if self.function_name.is_empty() {
+            write!(f, "at {}", location)
+        } else {
+            write!(f, "at {} ({})", self.function_name, location)
+        }
+    }
+}
+
+/// Format `frames` in V8 format with `header` as the first line.
+pub fn format_stack_trace(header: &str, frames: &[StackFrame]) -> String {
+    let mut result = header.to_string();
+    for frame in frames {
+        result.push_str(&format!("\n    {}", frame));
+    }
+    result
+}
+
+/// Hidden object holding frames captured for an error object.
+pub struct JsStackTrace {
+    pub frames: Vec<StackFrame>,
+    /// `stack` string formatted on first access.
+    pub formatted: Option<String>,
+}
+
+extern "C" fn drop_stack_trace(obj: GcPointer<JsObject>) {
+    unsafe { ManuallyDrop::drop(obj.data::<JsStackTrace>()) }
+}
+
+fn ser_opt_string(string: &Option<String>, serializer: &mut SnapshotSerializer) {
+    match string {
+        Some(string) => {
+            serializer.write_u8(0x1);
+            string.serialize(serializer);
+        }
+        None => serializer.write_u8(0x0),
+    }
+}
+
+unsafe fn deser_opt_string(deser: &mut Deserializer) -> Option<String> {
+    match deser.get_u8() {
+        0x0 => None,
+        _ => Some(String::deserialize_inplace(deser)),
+    }
+}
+
+extern "C" fn ser(obj: &JsObject, serializer: &mut SnapshotSerializer) {
+    let trace = obj.data::<JsStackTrace>();
+    serializer.write_u32(trace.frames.len() as u32);
+    for frame in trace.frames.iter() {
+        frame.function_name.serialize(serializer);
+        ser_opt_string(&frame.file_name, serializer);
+        // lines and columns are one based so zero means no location.
+        serializer.write_u32(frame.line.unwrap_or(0));
+        serializer.write_u32(frame.column.unwrap_or(0));
+    }
+    ser_opt_string(&trace.formatted, serializer);
+}
+
+extern "C" fn deser(obj: &mut JsObject, deser: &mut Deserializer) {
+    unsafe {
+        let count = deser.get_u32();
+        let mut frames = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            frames.push(StackFrame {
+                function_name: String::deserialize_inplace(deser),
+                file_name: deser_opt_string(deser),
+                line: Some(deser.get_u32()).filter(|&line| line != 0),
+                column: Some(deser.get_u32()).filter(|&column| column != 0),
+            });
+        }
+        let formatted = deser_opt_string(deser);
+        *obj.data::<JsStackTrace>() = ManuallyDrop::new(JsStackTrace { frames, formatted });
+    }
+}
+
+extern "C" fn fsz() -> usize {
+    std::mem::size_of::<JsStackTrace>()
+}
+
+impl JsClass for JsStackTrace {
+    fn class() -> &'static Class {
+        define_jsclass!(
+            JsStackTrace,
+            StackTrace,
+            Some(drop_stack_trace),
+            None,
+            Some(deser),
+            Some(ser),
+            Some(fsz)
+        )
+    }
+}
+
+fn stack_trace_symbol() -> Symbol {
+    "[[StackTrace]]".intern().private()
+}
+
+impl JsStackTrace {
+    /// Capture current stack and attach it to `obj`. `skip_until` has the same meaning as in
+    /// [Context::capture_stack_trace](GcPointer::<Context>::capture_stack_trace). Nothing is captured when
+    /// `Error.stackTraceLimit` is not a number.
+    pub fn attach(
+        mut ctx: GcPointer<Context>,
+        mut obj: GcPointer<JsObject>,
+        skip_until: Option<JsValue>,
+    ) {
+        let limit = match stack_trace_limit(ctx) {
+            Some(limit) => limit,
+            None => return,
+        };
+        let frames = ctx.capture_stack_trace(limit, skip_until);
+        let structure = ctx.global_data().empty_object_struct.unwrap();
+        let mut trace = JsObject::new(ctx, &structure, Self::class(), ObjectTag::Ordinary);
+        *trace.data::<Self>() = ManuallyDrop::new(Self {
+            frames,
+            formatted: None,
+        });
+        let _ = obj.define_own_property(
+            ctx,
+            stack_trace_symbol(),
+            &*DataDescriptor::new(JsValue::new(trace), W | C),
+            false,
+        );
+    }
+
+    /// Frames captured for error object `obj`.
+    pub fn frames(
+        ctx: GcPointer<Context>,
+        mut obj: GcPointer<JsObject>,
+    ) -> Option<Vec<StackFrame>> {
+        let trace = Self::of(ctx, &mut obj)?;
+        Some(trace.data::<Self>().frames.clone())
+    }
+
+    fn of(ctx: GcPointer<Context>, obj: &mut GcPointer<JsObject>) -> Option<GcPointer<JsObject>> {
+        let value = obj.get_own_property(ctx, stack_trace_symbol())?.value();
+        if !value.is_jsobject() {
+            return None;
+        }
+        let trace = value.get_jsobject();
+        if std::ptr::eq(trace.class, Self::class()) {
+            Some(trace)
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns value of `Error.stackTraceLimit` or `None` if it is not a number.
+fn stack_trace_limit(ctx: GcPointer<Context>) -> Option<usize> {
+    let mut proto = ctx.global_data().error?;
+    let constructor = proto.get_own_property(ctx, "constructor".intern())?.value();
+    if !constructor.is_jsobject() {
+        return Some(DEFAULT_STACK_TRACE_LIMIT);
+    }
+    let mut constructor = constructor.get_jsobject();
+    let limit = match constructor.get_own_property(ctx, "stackTraceLimit".intern()) {
+        Some(desc) if desc.is_data() => desc.value(),
+        _ => return None,
+    };
+    if !limit.is_number() {
+        return None;
+    }
+    let limit = limit.get_number();
+    Some(if limit.is_nan() || limit <= 0.0 {
+        0
+    } else if limit.is_infinite() {
+        usize::MAX
+    } else {
+        limit as usize
+    })
+}
+
+/// `get Error.prototype.stack`
+pub fn error_stack_getter(ctx: GcPointer<Context>, args: &Arguments) -> Result<JsValue, JsValue> {
+    if !args.this.is_jsobject() {
+        return Ok(JsValue::encode_undefined_value());
+    }
+    let mut this = args.this.get_jsobject();
+    let mut trace = match JsStackTrace::of(ctx, &mut this) {
+        Some(trace) => trace,
+        None => return Ok(JsValue::encode_undefined_value()),
+    };
+    if let Some(ref formatted) = trace.data::<JsStackTrace>().formatted {
+        return Ok(JsValue::new(JsString::new(ctx, formatted)));
+    }
+    let header = crate::jsrt::error::error_to_string(ctx, args)?.to_string(ctx)?;
+    let data = trace.data::<JsStackTrace>();
+    let formatted = format_stack_trace(&header, &data.frames);
+    data.formatted = Some(formatted.clone());
+    Ok(JsValue::new(JsString::new(ctx, formatted)))
+}
+
+/// `set Error.prototype.stack`
+pub fn error_stack_setter(ctx: GcPointer<Context>, args: &Arguments) -> Result<JsValue, JsValue> {
+    if !args.this.is_jsobject() {
+        return Ok(JsValue::encode_undefined_value());
+    }
+    let mut this = args.this.get_jsobject();
+    this.define_own_property(
+        ctx,
+        "stack".intern(),
+        &*DataDescriptor::new(args.at(0), W | C),
+        false,
+    )?;
+    Ok(JsValue::encode_undefined_value())
+}
+
+/// `Error.captureStackTrace(targetObject[, constructorOpt])`
+pub fn error_capture_stack_trace(
+    mut ctx: GcPointer<Context>,
+    args: &Arguments,
+) -> Result<JsValue, JsValue> {
+    let target = args.at(0);
+    if !target.is_jsobject() {
+        return Err(JsValue::new(ctx.new_type_error(
+            "Error.captureStackTrace: target must be an object",
+        )));
+    }
+    let mut target = target.get_jsobject();
+    let skip_until = if args.at(1).is_callable() {
+        Some(args.at(1))
+    } else {
+        None
+    };
+    JsStackTrace::attach(ctx, target, skip_until);
+    let mut proto = ctx.global_data().error.unwrap();
+    if let Some(stack) = proto.get_own_property(ctx, "stack".intern()) {
+        if stack.is_accessor() {
+            target.define_own_property(
+                ctx,
+                "stack".intern(),
+                &*AccessorDescriptor::new(stack.getter(), stack.setter(), C),
+                false,
+            )?;
+        }
+    }
+    Ok(JsValue::encode_undefined_value())
+}
+
+register_native_references!(
+    error_stack_getter,
+    error_stack_setter,
+    error_capture_stack_trace,
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{function::JsNativeFunction, tests::test_runtime};
+
+    fn eval_script(ctx: GcPointer<Context>, source: &str) -> String {
+        match ctx.eval_internal(Some("trace.js"), false, source, false) {
+            Ok(value) => value.to_string(ctx).unwrap_or_default(),
+            Err(error) => panic!("{}", error.to_string(ctx).unwrap_or_default()),
+        }
+    }
+
+    #[test]
+    fn test_stack_format() {
+        let (mut runtime, _) = test_runtime();
+        let ctx = Context::new(&mut runtime);
+        // calls in tail position replace frame of the caller, so results are stored in variables first.
+        let stack = eval_script(
+            ctx,
+            "function inner() { var stack = new TypeError('boom').stack; return stack; }\n\
+             var outer = function outer() {\n  var stack = inner();\n  return stack;\n};\n\
+             var result = (function () { var stack = outer(); return stack; })(); return result;",
+        );
+        assert_eq!(
+            stack,
+            "TypeError: boom\n    \
+             at inner (trace.js:1:36)\n    \
+             at outer (trace.js:3:15)\n    \
+             at trace.js:6:41\n    \
+             at <script> (trace.js:6:14)"
+        );
+        // stack is formatted once, later changes of message are not reflected.
+        assert_eq!(
+            eval_script(
+                ctx,
+                "var e = new Error('first'); var first = e.stack; e.message = 'second'; \
+                 return [first === e.stack, e.stack.split('\\n')[0]].join();"
+            ),
+            "true,Error: first"
+        );
+    }
+
+    #[test]
+    fn test_capture_stack_trace() {
+        let (mut runtime, _) = test_runtime();
+        let ctx = Context::new(&mut runtime);
+        assert_eq!(
+            eval_script(
+                ctx,
+                "var o = { name: 'Custom', message: 'm' }; \
+                 function f() { Error.captureStackTrace(o); }\nf(); return o.stack;"
+            ),
+            "Custom: m\n    at f (trace.js:1:64)\n    at <script> (trace.js:2:1)"
+        );
+        // frames above and including constructorOpt are omitted.
+        assert_eq!(
+            eval_script(
+                ctx,
+                "var o = {}; function f() { Error.captureStackTrace(o, f); }\n\
+                 function g() { f(); }\ng(); return o.stack.split('\\n').slice(1).join();"
+            ),
+            "    at g (trace.js:2:16),    at <script> (trace.js:3:1)"
+        );
+        assert_eq!(
+            eval_script(
+                ctx,
+                "try { Error.captureStackTrace(1); } catch (e) { return e instanceof TypeError; }"
+            ),
+            "true"
+        );
+    }
+
+    #[test]
+    fn test_stack_trace_limit() {
+        let (mut runtime, _) = test_runtime();
+        let ctx = Context::new(&mut runtime);
+        let source = "function a() { return new Error('x').stack; } function b() { var s = a(); return s; } \
+                      return typeof b() === 'string' ? b().split('\\n').length - 1 : typeof b();";
+        assert_eq!(eval_script(ctx, source), "3");
+        assert_eq!(
+            eval_script(ctx, &["Error.stackTraceLimit = 1;", source].concat()),
+            "1"
+        );
+        assert_eq!(
+            eval_script(ctx, &["Error.stackTraceLimit = 0;", source].concat()),
+            "0"
+        );
+        assert_eq!(
+            eval_script(ctx, &["Error.stackTraceLimit = -5;", source].concat()),
+            "0"
+        );
+        assert_eq!(
+            eval_script(ctx, &["Error.stackTraceLimit = '2';", source].concat()),
+            "undefined"
+        );
+        assert_eq!(
+            eval_script(ctx, &["delete Error.stackTraceLimit;", source].concat()),
+            "undefined"
+        );
+    }
+
+    thread_local! {
+        static FRAMES: std::cell::RefCell<Vec<StackFrame>> = Default::default();
+    }
+
+    fn capture(mut ctx: GcPointer<Context>, args: &Arguments) -> Result<JsValue, JsValue> {
+        let skip_until = if args.at(0).is_callable() {
+            Some(args.at(0))
+        } else {
+            None
+        };
+        let frames = ctx.capture_stack_trace(2, skip_until);
+        FRAMES.with(|cell| *cell.borrow_mut() = frames);
+        Ok(JsValue::encode_undefined_value())
+    }
+
+    #[test]
+    fn test_context_capture_stack_trace() {
+        let (mut runtime, _) = test_runtime();
+        let mut ctx = Context::new(&mut runtime);
+        assert!(ctx.capture_stack_trace(10, None).is_empty());
+        let function = JsNativeFunction::new(ctx, "capture".intern(), capture, 1);
+        ctx.global_object()
+            .put(ctx, "capture".intern(), JsValue::new(function), false)
+            .unwrap_or_else(|_| panic!());
+        let frame = |function_name: &str, line: Option<u32>, column: Option<u32>| StackFrame {
+            function_name: function_name.to_string(),
+            file_name: line.map(|_| "trace.js".to_string()),
+            line,
+            column,
+        };
+
+        // native functions don't have frames.
+        eval_script(
+            ctx,
+            "function f() { capture(); }\nfunction g() { f(); }\ng();",
+        );
+        let frames = FRAMES.with(|cell| cell.take());
+        assert_eq!(
+            frames,
+            vec![frame("f", Some(1), Some(16)), frame("g", Some(2), Some(16))]
+        );
+        assert!(!frames[0].is_native());
+        assert_eq!(frames[0].to_string(), "at f (trace.js:1:16)");
+        assert_eq!(frame("map", None, None).to_string(), "at map (native)");
+        assert_eq!(frame("", None, None).to_string(), "at <native code>");
+
+        eval_script(
+            ctx,
+            "function h() { capture(h); }\nfunction i() { h(); }\ni();",
+        );
+        assert_eq!(
+            FRAMES.with(|cell| cell.take()),
+            vec![
+                frame("i", Some(2), Some(16)),
+                frame("<script>", Some(3), Some(1))
+            ]
+        );
+    }
+}
@@ -199,6 +199,7 @@ unsafe impl Trace for Structure {
         self.transitions.trace(tracer);
         self.table.trace(tracer);
         self.prototype.trace(tracer);
+        self.cached_prototype_chain.trace(tracer);
         self.deleted.entry.trace(tracer);
         match self.previous.as_mut() {
             Some(x) => {
@@ -626,11 +627,25 @@ impl GcPointer<Structure> {
 }
 
 impl GcPointer<Structure> {
+    /// Unique structure (or its table shared with successor) is about to be mutated in place, entries
+    /// keyed by it in the global megamorphic cache can't be trusted anymore.
+    fn invalidate_megamorphic_cache(&self, ctx: GcPointer<Context>) {
+        let mut vm = ctx.vm();
+        if !vm.megamorphic_cache.is_empty() {
+            vm.megamorphic_cache.invalidate();
+            vm.ic_stats.invalidations += 1;
+        }
+    }
+
     pub fn delete_property_transition(
         &mut self,
         ctx: GcPointer<Context>,
         name: Symbol,
     ) -> GcPointer<Structure> {
+        if self.is_unique() {
+            // new unique structure shares table with this one.
+            self.invalidate_megamorphic_cache(ctx);
+        }
         let mut map = Structure::new_unique(ctx, *self);
         if !map.has_table() {
             map.allocate_table(ctx);
@@ -643,6 +658,7 @@ impl GcPointer<Structure> {
             let mut map = if self.transitions.is_enabled_unique_transition() {
                 Structure::new_unique(ctx, *self)
             } else {
+                self.invalidate_megamorphic_cache(ctx);
                 *self
             };
             map.transitions.set_indexed(true);
@@ -661,6 +677,8 @@ impl GcPointer<Structure> {
             let mut map = if self.transitions.is_enabled_unique_transition() {
                 Structure::new_unique(ctx, *self)
             } else {
+                // prototype is changed in place, cached prototype chain loads can't be trusted anymore.
+                self.invalidate_megamorphic_cache(ctx);
                 *self
             };
             map.prototype = prototype;
@@ -683,6 +701,9 @@ impl GcPointer<Structure> {
         name: Symbol,
         attributes: AttrSafe,
     ) -> GcPointer<Structure> {
+        if self.is_unique() {
+            self.invalidate_megamorphic_cache(ctx);
+        }
         let mut map = Structure::new_unique(ctx, *self);
         if !map.has_table() {
             map.allocate_table(ctx);
@@ -734,6 +755,8 @@ impl GcPointer<Structure> {
             } else {
                 *self
             };
+            // table is shared with previous unique structure or mutated in place.
+            self.invalidate_megamorphic_cache(ctx);
             if !map.deleted.empty() {
                 entry.offset = map.deleted.pop();
             } else {
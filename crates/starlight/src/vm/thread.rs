@@ -22,28 +22,24 @@ impl Thread {
         let mut buf = std::mem::MaybeUninit::uninit();
         let buf_ptr = buf.as_mut_ptr();
         unsafe {
-            llvm_asm!(
-                "
-                mov [$0+0], rsp
-                mov [$0+8], rax
-                mov [$0+16], rbx
-                mov [$0+24], rcx
-                mov [$0+32], rdx
-                mov [$0+40], rbp
-                mov [$0+48], rsi
-                mov [$0+56], rdi
-                mov [$0+64], r8
-                mov [$0+72], r9
-                mov [$0+80], r10
-                mov [$0+88], r11
-                mov [$0+96], r12
-                mov [$0+104], r13
-                mov [$0+112], r14
-                mov [$0+120], r15
-                
-
-                " :: "r"(buf_ptr) :: "intel"
-            );
+            std::arch::asm!(
+"mov [{0}+0], rsp",
+"mov [{0}+8], rax",
+"mov [{0}+16], rbx",
+"mov [{0}+24], rcx",
+"mov [{0}+32], rdx",
+"mov [{0}+40], rbp",
+"mov [{0}+48], rsi",
+"mov [{0}+56], rdi",
+"mov [{0}+64], r8",
+"mov [{0}+72], r9",
+"mov [{0}+80], r10",
+"mov [{0}+88], r11",
+"mov [{0}+96], r12",
+"mov [{0}+104], r13",
+"mov [{0}+112], r14",
+"mov [{0}+120], r15",
+in(reg) buf_ptr);
         }
         unsafe { buf.assume_init() }
     }
@@ -427,7 +427,8 @@ mod tests {
             Heap::new(MiGC::new(GcParams::default())),
             None,
             |_, _| {},
-        );
+        )
+        .unwrap();
 
         let my_typed_array = vm.get_global("myTypedArray").unwrap();
         let object = my_typed_array
@@ -18,7 +18,8 @@ use std::{
     convert::TryFrom,
     hash::{Hash, Hasher},
     hint::unreachable_unchecked,
-    intrinsics::{likely, size_of, unlikely},
+    intrinsics::{likely, unlikely},
+    mem::size_of,
 };
 
 use super::{
@@ -473,6 +474,11 @@ impl JsValue {
             if (lhs.is_undefined() || lhs.is_null()) && (rhs.is_undefined() || rhs.is_null()) {
                 return Ok(true);
             }
+            if (lhs.is_undefined() || lhs.is_null()) && rhs.is_htmldda()
+                || lhs.is_htmldda() && (rhs.is_undefined() || rhs.is_null())
+            {
+                return Ok(true);
+            }
 
             if lhs.is_jsstring() && rhs.is_jsstring() {
                 return Ok(lhs.get_string().as_str() == rhs.get_string().as_str());
@@ -784,6 +790,9 @@ impl JsValue {
     }
     pub fn type_of(self) -> &'static str {
         if self.is_jsobject() {
+            if self.is_htmldda() {
+                return "undefined";
+            }
             if self.is_callable() {
                 return "function";
             }
@@ -877,9 +886,14 @@ impl JsValue {
         } else if self.is_bool() {
             return self.get_bool();
         } else {
-            true
+            !self.is_htmldda()
         }
     }
+
+    /// Returns true if value is an object with `[[IsHTMLDDA]]` internal slot.
+    pub fn is_htmldda(self) -> bool {
+        self.is_jsobject() && self.get_jsobject().is_htmldda()
+    }
     pub fn check_object_coercible(self, ctx: GcPointer<Context>) -> Result<(), Self> {
         if self.is_null() || self.is_undefined() {
             let msg = JsString::new(ctx, "null or undefined has no properties");
@@ -0,0 +1,85 @@
+//! Round trip of `starlight-bundle --standalone`: the bundle is written next to a copy of `sl` and run after the
+//! bundled sources are removed.
+use starlight::bundle::{appended_len, Bundle};
+use std::{fs, path::PathBuf, process::Command};
+
+/// Empty directory for files of one test.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("starlight-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("data")).unwrap();
+    dir
+}
+
+#[test]
+fn test_standalone_bundle() {
+    let dir = temp_dir("bundle");
+    fs::write(
+        dir.join("main.js"),
+        "import { value } from './lib.js';\n\
+         import * as std from 'std';\n\
+         let text = new std.File('data/text.txt', 'r').read();\n\
+         print(value + ':' + text);\n\
+         gc();\n",
+    )
+    .unwrap();
+    fs::write(dir.join("lib.js"), "export let value = 42;\n").unwrap();
+    fs::write(dir.join("data/text.txt"), "bundled").unwrap();
+
+    let output = dir.join("out");
+    let status = Command::new(env!("CARGO_BIN_EXE_starlight-bundle"))
+        .current_dir(&dir)
+        .args(&["--standalone", "--runner", env!("CARGO_BIN_EXE_sl")])
+        .args(&["--include", "lib.js", "--include", "data", "main.js"])
+        .arg(&output)
+        .args(&["--", "--verboseGC"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // sources are only available from the bundle now.
+    for file in ["main.js", "lib.js", "data"].iter() {
+        let path = dir.join(file);
+        if path.is_dir() {
+            fs::remove_dir_all(path).unwrap();
+        } else {
+            fs::remove_file(path).unwrap();
+        }
+    }
+    let result = Command::new(&output).current_dir("/").output().unwrap();
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(result.status.success(), "{}", stdout);
+    assert!(stdout.starts_with("42:bundled\n"), "{}", stdout);
+    // runtime flags are stored in the bundle.
+    assert!(stdout.contains("[GC] Starting GC"), "{}", stdout);
+
+    // bundle of a bundle replaces the appended bundle instead of stacking it.
+    fs::write(dir.join("second.js"), "print('second');\n").unwrap();
+    let second = dir.join("second");
+    let status = Command::new(env!("CARGO_BIN_EXE_starlight-bundle"))
+        .current_dir(&dir)
+        .arg("--standalone")
+        .arg("--runner")
+        .arg(&output)
+        .arg("second.js")
+        .arg(&second)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let result = Command::new(&second).output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&result.stdout), "second\n");
+    let executable = fs::read(&second).unwrap();
+    let runner = executable.len() - appended_len(&executable).unwrap();
+    assert_eq!(
+        runner as u64,
+        fs::metadata(env!("CARGO_BIN_EXE_sl")).unwrap().len()
+    );
+    let bundle = Bundle::decode(&executable).unwrap();
+    assert_eq!(
+        bundle.entry,
+        fs::canonicalize(dir.join("second.js")).unwrap()
+    );
+    assert_eq!(bundle.files.len(), 1);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,4 +1,4 @@
-use crate::js262;
+use crate::pool::WorkerPool;
 
 use super::{
     Harness, Outcome, Phase, SuiteResult, Test, TestFlags, TestOutcomeResult, TestResult,
@@ -6,11 +6,17 @@ use super::{
 };
 
 use colored::Colorize;
-use starlight::prelude::GcPointer;
+use starlight::prelude::*;
+use starlight::jsrt::js262;
+use starlight::vm::module_loader::FileModuleLoader;
 use starlight::vm::VirtualMachineRef;
 use starlight::vm::{context::Context, parse};
+use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::panic;
 use std::panic::AssertUnwindSafe;
+use std::path::Path;
+use std::rc::Rc;
 use std::time::{Duration, Instant};
 
 impl TestSuite {
@@ -39,55 +45,7 @@ impl TestSuite {
             .flatten()
             .collect();
 
-        if verbose != 0 {
-            println!();
-        }
-
-        // Count passed tests
-        let mut passed = 0;
-        let mut ignored = 0;
-        let mut panic = 0;
-        for test in &tests {
-            match test.result {
-                TestOutcomeResult::Passed => passed += 1,
-                TestOutcomeResult::Ignored => ignored += 1,
-                TestOutcomeResult::Panic => panic += 1,
-                TestOutcomeResult::Failed => {}
-            }
-        }
-
-        // Count total tests
-        let mut total = tests.len();
-        for suite in &suites {
-            total += suite.total;
-            passed += suite.passed;
-            ignored += suite.ignored;
-            panic += suite.panic;
-        }
-
-        if verbose != 0 {
-            println!(
-            "Suite {} results: total: {}, passed: {}, ignored: {}, failed: {} (panics: {}{}), conformance: {:.2}%",
-            self.name,
-            total,
-            passed.to_string().green(),
-            ignored.to_string().yellow(),
-            (total - passed - ignored).to_string().red(),
-            if panic == 0 {"0".normal()} else {panic.to_string().red()},
-            if panic != 0 {" ⚠"} else {""}.red(),
-            (passed as f64 / total as f64) * 100.0
-        );
-        }
-
-        SuiteResult {
-            name: self.name.clone(),
-            total,
-            passed,
-            ignored,
-            panic,
-            suites,
-            tests,
-        }
+        self.result(suites, tests, verbose)
     }
     /// Runs the test suite.
     pub(crate) fn run(&self, harness: &Harness, verbose: u8, vm: VirtualMachineRef) -> SuiteResult {
@@ -108,6 +66,51 @@ impl TestSuite {
             .flatten()
             .collect();
 
+        self.result(suites, tests, verbose)
+    }
+}
+
+impl TestSuite {
+    /// Runs the test suite on `pool`, each test in a fresh VM inside a worker process.
+    pub(crate) fn run_parallel(&self, verbose: u8, pool: &WorkerPool) -> SuiteResult {
+        let mut jobs = Vec::new();
+        self.collect_jobs(&mut jobs);
+        let mut results = pool.run(&jobs).into_iter();
+        self.collect_results(&mut results, verbose)
+    }
+
+    fn collect_jobs<'a>(&'a self, jobs: &mut Vec<(&'a Test, bool)>) {
+        for suite in self.suites.iter() {
+            suite.collect_jobs(jobs);
+        }
+        for test in self.tests.iter() {
+            for strict in test.modes() {
+                jobs.push((test, strict));
+            }
+        }
+    }
+
+    /// Builds suite results from test results in the order of [TestSuite::collect_jobs].
+    fn collect_results(
+        &self,
+        results: &mut impl Iterator<Item = TestResult>,
+        verbose: u8,
+    ) -> SuiteResult {
+        if verbose != 0 {
+            println!("Suite {}:", self.name);
+        }
+        let suites: Vec<_> = self
+            .suites
+            .iter()
+            .map(|suite| suite.collect_results(results, verbose))
+            .collect();
+        let count = self.tests.iter().map(|test| test.modes().len()).sum();
+        let tests: Vec<_> = results.take(count).collect();
+        self.result(suites, tests, verbose)
+    }
+
+    /// Counts results of `suites` and `tests` of this suite.
+    fn result(&self, suites: Vec<SuiteResult>, tests: Vec<TestResult>, verbose: u8) -> SuiteResult {
         if verbose != 0 {
             println!();
         }
@@ -116,11 +119,15 @@ impl TestSuite {
         let mut passed = 0;
         let mut ignored = 0;
         let mut panic = 0;
+        let mut timeout = 0;
+        let mut crash = 0;
         for test in &tests {
             match test.result {
                 TestOutcomeResult::Passed => passed += 1,
                 TestOutcomeResult::Ignored => ignored += 1,
                 TestOutcomeResult::Panic => panic += 1,
+                TestOutcomeResult::Timeout => timeout += 1,
+                TestOutcomeResult::Crash => crash += 1,
                 TestOutcomeResult::Failed => {}
             }
         }
@@ -132,11 +139,13 @@ impl TestSuite {
             passed += suite.passed;
             ignored += suite.ignored;
             panic += suite.panic;
+            timeout += suite.timeout;
+            crash += suite.crash;
         }
 
         if verbose != 0 {
             println!(
-            "Suite {} results: total: {}, passed: {}, ignored: {}, failed: {} (panics: {}{}), conformance: {:.2}%",
+            "Suite {} results: total: {}, passed: {}, ignored: {}, failed: {} (panics: {}{}, timeouts: {}, crashes: {}), conformance: {:.2}%",
             self.name,
             total,
             passed.to_string().green(),
@@ -144,6 +153,8 @@ impl TestSuite {
             (total - passed - ignored).to_string().red(),
             if panic == 0 {"0".normal()} else {panic.to_string().red()},
             if panic != 0 {" ⚠"} else {""}.red(),
+            if timeout == 0 {"0".normal()} else {timeout.to_string().red()},
+            if crash == 0 {"0".normal()} else {crash.to_string().red()},
             (passed as f64 / total as f64) * 100.0
         );
         }
@@ -154,6 +165,8 @@ impl TestSuite {
             passed,
             ignored,
             panic,
+            timeout,
+            crash,
             suites,
             tests,
         }
@@ -161,6 +174,29 @@ impl TestSuite {
 }
 
 impl Test {
+    /// Modes the test runs in, `true` for strict mode.
+    pub(crate) fn modes(&self) -> Vec<bool> {
+        // Module code is always strict.
+        if self.flags.contains(TestFlags::MODULE) {
+            return vec![true];
+        }
+        let mut modes = Vec::new();
+        if self.flags.contains(TestFlags::STRICT) {
+            modes.push(true);
+        }
+        if self.flags.contains(TestFlags::NO_STRICT) || self.flags.contains(TestFlags::RAW) {
+            modes.push(false);
+        }
+        modes
+    }
+
+    /// Whether the test is skipped because of the ignore list.
+    pub(crate) fn is_ignored(&self) -> bool {
+        IGNORED.contains_any_flag(self.flags)
+            || IGNORED.contains_test(&self.name)
+            || IGNORED.contains_any_feature(&self.features)
+    }
+
     /// Runs the test.
     pub(crate) fn run(
         &self,
@@ -168,15 +204,12 @@ impl Test {
         verbose: u8,
         vm: VirtualMachineRef,
     ) -> Vec<TestResult> {
-        let mut results = Vec::new();
         let start = Instant::now();
-        if self.flags.contains(TestFlags::STRICT) {
-            results.push(self.run_once(harness, true, verbose, vm));
-        }
-
-        if self.flags.contains(TestFlags::NO_STRICT) || self.flags.contains(TestFlags::RAW) {
-            results.push(self.run_once(harness, false, verbose, vm));
-        }
+        let results = self
+            .modes()
+            .into_iter()
+            .map(|strict| self.run_once(harness, strict, verbose, vm))
+            .collect();
 
         if verbose>=1 {
             if start.elapsed() > 300* Duration::MILLISECOND {
@@ -189,7 +222,7 @@ impl Test {
     }
 
     /// Runs the test once, in strict or non-strict mode
-    fn run_once(
+    pub(crate) fn run_once(
         &self,
         harness: &Harness,
         strict: bool,
@@ -205,45 +238,15 @@ impl Test {
             eprintln!("Description: {}\nesid: {:?}\n", self.description, self.esid,);
         }
         let start = Instant::now();
-        let (result, result_text) = if !IGNORED.contains_any_flag(self.flags)
-            && !IGNORED.contains_test(&self.name)
-            && !IGNORED.contains_any_feature(&self.features)
-            && (matches!(self.expected_outcome, Outcome::Positive)
-                || matches!(
-                    self.expected_outcome,
-                    Outcome::Negative {
-                        phase: Phase::Parse,
-                        error_type: _,
-                    }
-                )
-                || matches!(
-                    self.expected_outcome,
-                    Outcome::Negative {
-                        phase: Phase::Early,
-                        error_type: _,
-                    }
-                )
-                || matches!(
-                    self.expected_outcome,
-                    Outcome::Negative {
-                        phase: Phase::Runtime,
-                        error_type: _,
-                    }
-                )) {
+        let (result, result_text) = if !self.is_ignored() {
             let ctx = vm.new_context();
             let res = panic::catch_unwind(AssertUnwindSafe(|| match self.expected_outcome {
-                Outcome::Positive => {
-                    // TODO: implement async and add `harness/doneprintHandle.js` to the includes.
-
-                    match self.set_up_env(&harness, strict, ctx) {
-                        Ok(_) => {
-                            let content = if strict {
-                                format!("\"use strict\";\n {}", self.content)
-                            } else {
-                                self.content.to_string()
-                            };
-                            let res = ctx.eval_internal(None, false, &content, false);
-
+                Outcome::Positive => match self.set_up_env(&harness, strict, ctx) {
+                    Ok(output) => {
+                        let res = self.evaluate(ctx, strict);
+                        if self.flags.contains(TestFlags::ASYNC) {
+                            Self::async_result(ctx, res, &output.borrow())
+                        } else {
                             let passed = res.is_ok();
                             let text = match res {
                                 Ok(val) => val.to_string(ctx).unwrap_or_else(|_| String::new()),
@@ -255,9 +258,9 @@ impl Test {
 
                             (passed, text)
                         }
-                        Err(e) => (false, e),
                     }
-                }
+                    Err(e) => (false, e),
+                },
                 Outcome::Negative {
                     phase: Phase::Parse,
                     ref error_type,
@@ -273,51 +276,75 @@ impl Test {
                         self.name
                     );
 
-                    match parse(&self.content.as_ref(), strict) {
-                        Ok(n) => (false, format!("{:?}", n)),
-                        Err(e) => (true, format!("Uncaught {:?}", e)),
+                    if self.flags.contains(TestFlags::MODULE) {
+                        match self.compile_module(ctx) {
+                            Ok(_) => (false, "module compiled without errors".to_string()),
+                            Err(e) => Self::check_error(ctx, e, error_type),
+                        }
+                    } else {
+                        match parse(&self.content.as_ref(), strict) {
+                            Ok(n) => (false, format!("{:?}", n)),
+                            Err(e) => (true, format!("Uncaught {:?}", e)),
+                        }
                     }
                 }
                 Outcome::Negative {
                     phase: Phase::Resolution,
-                    error_type: _,
-                } => todo!("check module resolution errors"),
+                    ref error_type,
+                } => {
+                    // Imports are resolved when module is evaluated so only make sure the module itself compiles.
+                    if let Err(e) = self.compile_module(ctx) {
+                        (
+                            false,
+                            format!(
+                                "Uncaught {}",
+                                e.to_string(ctx).unwrap_or_else(|_| String::new())
+                            ),
+                        )
+                    } else {
+                        match self.set_up_env(&harness, strict, ctx) {
+                            Ok(_) => match self.evaluate(ctx, strict) {
+                                Ok(res) => (
+                                    false,
+                                    res.to_string(ctx).unwrap_or_else(|_| String::new()),
+                                ),
+                                Err(e) => Self::check_error(ctx, e, error_type),
+                            },
+                            Err(e) => (false, e),
+                        }
+                    }
+                }
                 Outcome::Negative {
                     phase: Phase::Runtime,
                     ref error_type,
                 } => {
-                    if let Err(e) = parse(&self.content.as_ref(), strict) {
-                        (false, format!("Uncaught {:?}", e))
+                    let parsed = if self.flags.contains(TestFlags::MODULE) {
+                        self.compile_module(ctx).map(|_| ()).map_err(|e| {
+                            format!("Uncaught {}", e.to_string(ctx).unwrap_or_else(|_| String::new()))
+                        })
+                    } else {
+                        parse(&self.content.as_ref(), strict)
+                            .map(|_| ())
+                            .map_err(|e| format!("Uncaught {:?}", e))
+                    };
+                    if let Err(e) = parsed {
+                        (false, e)
                     } else {
                         match self.set_up_env(&harness, strict, ctx) {
-                            Ok(_) => {
-                                match ctx.eval_internal(None, false, &self.content.as_ref(), false)
-                                {
-                                    Ok(res) => (
-                                        false,
-                                        res.to_string(ctx).unwrap_or_else(|_| String::new()),
-                                    ),
-                                    Err(e) => {
-                                        let passed = e
-                                            .to_string(ctx)
-                                            .unwrap_or_else(|_| String::new())
-                                            .contains(error_type.as_ref());
-
-                                        (
-                                            passed,
-                                            format!(
-                                                "Uncaught {}",
-                                                e.to_string(ctx).unwrap_or_else(|_| String::new())
-                                            ),
-                                        )
-                                    }
-                                }
-                            }
+                            Ok(_) => match self.evaluate(ctx, strict) {
+                                Ok(res) => (
+                                    false,
+                                    res.to_string(ctx).unwrap_or_else(|_| String::new()),
+                                ),
+                                Err(e) => Self::check_error(ctx, e, error_type),
+                            },
                             Err(e) => (false, e),
                         }
                     }
                 }
             }));
+            JOBS.with(|jobs| jobs.borrow_mut().clear());
+            js262::stop_agents();
             vm.remove_context(ctx);
             let result = res
                 .map(|(res, text)| {
@@ -369,13 +396,13 @@ impl Test {
         }
     }
 
-    /// Sets the environment up to run the test.
+    /// Sets the environment up to run the test. Returns the buffer `print` calls of the test are written to.
     fn set_up_env(
         &self,
         harness: &Harness,
         _strict: bool,
-        context: GcPointer<Context>,
-    ) -> Result<(), String> {
+        mut context: GcPointer<Context>,
+    ) -> Result<Rc<RefCell<String>>, String> {
         // TODO: in parallel.
         /*let mut context = VirtualMachine::new(
             VirtualMachineParams::default().with_dump_bytecode(false),
@@ -384,7 +411,30 @@ impl Test {
                 .with_conservative_marking(false),
             None,
         );*/
-        let _ = js262::init(context).unwrap_or_else(|_| panic!("Failed to create $262 object"));
+        let _ = js262::init(context, "$262".intern())
+            .unwrap_or_else(|_| panic!("Failed to create $262 object"));
+        let output = Rc::new(RefCell::new(String::new()));
+        let buffer = output.clone();
+        let print = JsClosureFunction::new(
+            context,
+            "print".intern(),
+            move |ctx, args| {
+                let mut buffer = buffer.borrow_mut();
+                for i in 0..args.size() {
+                    if i != 0 {
+                        buffer.push(' ');
+                    }
+                    buffer.push_str(&args.at(i).to_string(ctx)?);
+                }
+                buffer.push('\n');
+                Ok(JsValue::encode_undefined_value())
+            },
+            1,
+        );
+        context
+            .global_object()
+            .put(context, "print".intern(), JsValue::new(print), false)
+            .map_err(|_| "could not define print".to_string())?;
         context
             .eval_internal(None, false, &harness.assert.as_ref(), false)
             .map_err(|e| {
@@ -402,7 +452,12 @@ impl Test {
                 )
             })?;
 
-        for include in self.includes.iter() {
+        let done = if self.flags.contains(TestFlags::ASYNC) {
+            Some("doneprintHandle.js")
+        } else {
+            None
+        };
+        for include in self.includes.iter().map(|include| include.as_ref()).chain(done) {
             context
                 .eval_internal(
                     None,
@@ -423,6 +478,194 @@ impl Test {
                 })?;
         }
 
-        Ok(())
+        Ok(output)
+    }
+
+    /// Compiles the test as an ES module.
+    fn compile_module(&self, ctx: GcPointer<Context>) -> Result<GcPointer<JsObject>, JsValue> {
+        let name = Path::new(self.name.as_ref())
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(ctx.compile_module(&self.name, &name, &self.content)?.get_jsobject())
+    }
+
+    /// Evaluates the test as a script or, with the `module` flag, as an ES module and then drains the job queue.
+    /// Modules are loaded, linked and evaluated by the module record API with [FileModuleLoader], imports are
+    /// resolved relative to the test file so `_FIXTURE.js` files next to it are found.
+    fn evaluate(&self, mut ctx: GcPointer<Context>, strict: bool) -> Result<JsValue, JsValue> {
+        if self.flags.contains(TestFlags::MODULE) {
+            ctx.set_module_loader(Some(Box::new(FileModuleLoader)));
+            let function = self.compile_module(ctx)?;
+            let module = ctx.evaluate_module(&self.name, function)?;
+            // evaluation of module with top level await completes in jobs.
+            run_jobs(ctx);
+            return match module.error() {
+                Some(error) => Err(error),
+                None => Ok(JsValue::encode_undefined_value()),
+            };
+        }
+        let content = if strict {
+            format!("\"use strict\";\n {}", self.content)
+        } else {
+            self.content.to_string()
+        };
+        let result = ctx.eval_internal(None, false, &content, false)?;
+        run_jobs(ctx);
+        Ok(result)
+    }
+
+    /// Checks that thrown `error` is of `error_type`.
+    fn check_error(ctx: GcPointer<Context>, error: JsValue, error_type: &str) -> (bool, String) {
+        let text = error.to_string(ctx).unwrap_or_else(|_| String::new());
+        (text.contains(error_type), format!("Uncaught {}", text))
+    }
+
+    /// Result of an `async` test: `$DONE` prints `Test262:AsyncTestComplete` or `Test262:AsyncTestFailure:<reason>`.
+    fn async_result(
+        ctx: GcPointer<Context>,
+        result: Result<JsValue, JsValue>,
+        output: &str,
+    ) -> (bool, String) {
+        if let Err(e) = result {
+            return (
+                false,
+                format!(
+                    "Uncaught {}",
+                    e.to_string(ctx).unwrap_or_else(|_| String::new())
+                ),
+            );
+        }
+        for line in output.lines() {
+            if line == "Test262:AsyncTestComplete" {
+                return (true, output.to_string());
+            }
+            if line.starts_with("Test262:AsyncTestFailure:") {
+                return (false, output.to_string());
+            }
+        }
+        (false, format!("$DONE was not called\n{}", output))
+    }
+}
+
+thread_local! {
+    /// Jobs scheduled by the VM of this thread. Drained after each test is evaluated.
+    static JOBS: RefCell<VecDeque<Box<dyn FnOnce(GcPointer<Context>)>>> = RefCell::new(VecDeque::new());
+}
+
+/// Runs jobs scheduled so far, including jobs scheduled by them, in `ctx`.
+pub(crate) fn run_jobs(ctx: GcPointer<Context>) {
+    while let Some(job) = JOBS.with(|jobs| jobs.borrow_mut().pop_front()) {
+        job(ctx);
+    }
+}
+
+/// Creates a VM to run tests in. Promise jobs are queued to be run once the test script finishes.
+pub(crate) fn new_vm() -> VirtualMachineRef {
+    VirtualMachine::new(Options::default(), None).with_async_scheduler(Box::new(|job| {
+        JOBS.with(|jobs| jobs.borrow_mut().push_back(job))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fxhash::FxHashMap;
+
+    /// Minimal harness with the parts of `assert.js` and `doneprintHandle.js` the tests below use.
+    fn harness() -> Harness {
+        let mut includes = FxHashMap::default();
+        includes.insert(
+            "doneprintHandle.js".into(),
+            r#"
+            function $DONE(error) {
+                if (error) {
+                    print('Test262:AsyncTestFailure:' + error);
+                } else {
+                    print('Test262:AsyncTestComplete');
+                }
+            }
+            "#
+            .into(),
+        );
+        Harness {
+            assert: "function assert(value) { if (!value) throw new Error('assertion failed'); }"
+                .into(),
+            sta: "".into(),
+            includes,
+        }
+    }
+
+    fn run(flags: TestFlags, content: &str) -> Vec<TestResult> {
+        let test = Test {
+            name: "test/flags.js".into(),
+            flags,
+            content: content.into(),
+            ..Test::default()
+        };
+        starlight::Platform::initialize();
+        let mut vm = new_vm();
+        let results = test.run(&harness(), 0, vm);
+        unsafe {
+            vm.dispose();
+        }
+        results
+    }
+
+    fn outcomes(results: &[TestResult]) -> Vec<TestOutcomeResult> {
+        results.iter().map(|result| result.result).collect()
+    }
+
+    #[test]
+    fn test_async_flag() {
+        let flags = TestFlags::default() | TestFlags::ASYNC;
+        let results = run(
+            flags,
+            "Promise.resolve(1).then(function (v) { assert(v === 1); }).then($DONE, $DONE);",
+        );
+        assert_eq!(
+            outcomes(&results),
+            [TestOutcomeResult::Passed, TestOutcomeResult::Passed]
+        );
+        assert!(results[0].result_text.contains("Test262:AsyncTestComplete"));
+
+        let results = run(flags, "Promise.reject('boom').then($DONE, $DONE);");
+        assert_eq!(
+            outcomes(&results),
+            [TestOutcomeResult::Failed, TestOutcomeResult::Failed]
+        );
+        assert!(results[0]
+            .result_text
+            .contains("Test262:AsyncTestFailure:boom"));
+
+        let results = run(flags, "Promise.resolve(1);");
+        assert_eq!(
+            outcomes(&results),
+            [TestOutcomeResult::Failed, TestOutcomeResult::Failed]
+        );
+        assert!(results[0].result_text.starts_with("$DONE was not called"));
+    }
+
+    #[test]
+    fn test_module_flag() {
+        let test = Test {
+            flags: TestFlags::MODULE,
+            ..Test::default()
+        };
+        assert_eq!(test.modes(), [true]);
+
+        let results = run(
+            TestFlags::MODULE,
+            "var value = await Promise.resolve(1); assert(value === 1); assert(this === undefined);",
+        );
+        assert_eq!(outcomes(&results), [TestOutcomeResult::Passed]);
+        assert!(results[0].strict);
+
+        let results = run(
+            TestFlags::MODULE,
+            "await Promise.resolve(1); throw new Error('after await');",
+        );
+        assert_eq!(outcomes(&results), [TestOutcomeResult::Failed]);
+        assert!(results[0].result_text.contains("after await"));
     }
 }
@@ -5,7 +5,7 @@
 )]
 #![feature(duration_constants)]
 pub mod exec;
-pub mod js262;
+pub mod pool;
 pub mod read;
 pub mod results;
 use self::read::{read_harness, read_suite, read_test, MetaData, Negative, TestFlag};
@@ -13,12 +13,12 @@ use bitflags::bitflags;
 use colored::Colorize;
 use fxhash::{FxHashMap, FxHashSet};
 use once_cell::sync::Lazy;
+use pool::{run_worker, WorkerPool};
 use results::{analyze_results, compare_results, write_json};
 use serde::{Deserialize, Serialize};
-use starlight::prelude::Options;
-use starlight::{vm::VirtualMachine, Platform};
+use starlight::Platform;
 
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::{
     fs,
     path::{Path, PathBuf},
@@ -155,6 +155,22 @@ enum Cli {
         /// Optional output folder for the full results information.
         #[structopt(short, long, parse(from_os_str))]
         output: Option<PathBuf>,
+
+        /// Number of worker processes, 0 means one per CPU. Each test runs in a fresh VM inside a worker so
+        /// crashes and hangs do not stop the run. Tests run sequentially in this process when not set.
+        #[structopt(short, long)]
+        jobs: Option<usize>,
+
+        /// Per-test timeout in milliseconds when running with worker processes.
+        #[structopt(long, default_value = "10000")]
+        timeout: u64,
+    },
+    /// Run tests requested on stdin. Used internally by worker processes.
+    #[structopt(setting = structopt::clap::AppSettings::Hidden)]
+    Worker {
+        /// Path to the Test262 suite.
+        #[structopt(long, parse(from_os_str), default_value = "./test262")]
+        test262_path: PathBuf,
     },
     Compare {
         /// Base results of the suite.
@@ -212,6 +228,10 @@ struct SuiteResult {
     ignored: usize,
     #[serde(rename = "p")]
     panic: usize,
+    #[serde(rename = "to", default)]
+    timeout: usize,
+    #[serde(rename = "cr", default)]
+    crash: usize,
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
     #[serde(rename = "s")]
     suites: Vec<SuiteResult>,
@@ -245,6 +265,12 @@ enum TestOutcomeResult {
     Failed,
     #[serde(rename = "P")]
     Panic,
+    /// Test did not finish in time and its worker was killed.
+    #[serde(rename = "T")]
+    Timeout,
+    /// Worker process running the test died.
+    #[serde(rename = "C")]
+    Crash,
 }
 
 /// Represents a test.
@@ -403,14 +429,29 @@ fn main() {
             test262_path,
             suite,
             output,
+            jobs,
+            timeout,
         } => {
+            let pool = jobs.map(|jobs| WorkerPool {
+                jobs: if jobs == 0 {
+                    rayon::current_num_threads()
+                } else {
+                    jobs
+                },
+                timeout: Duration::from_millis(timeout),
+                test262_path: test262_path.clone(),
+                verbose,
+                program: std::env::current_exe().expect("could not find test runner executable"),
+            });
             run_test_suite(
                 verbose,
                 test262_path.as_path(),
                 suite.as_path(),
                 output.as_deref(),
+                pool.as_ref(),
             );
         }
+        Cli::Worker { test262_path } => run_worker(test262_path.as_path()),
         Cli::Compare {
             base,
             new,
@@ -429,11 +470,18 @@ fn show_result(results: &SuiteResult) {
     println!("Ignored tests: {}", results.ignored.to_string().yellow());
     println!(
         "Failed tests: {} (panics: {})",
-        (results.total - results.passed - results.ignored - results.panic)
+        (results.total
+            - results.passed
+            - results.ignored
+            - results.panic
+            - results.timeout
+            - results.crash)
             .to_string()
             .red(),
         results.panic.to_string().red()
     );
+    println!("Timed out tests: {}", results.timeout.to_string().red());
+    println!("Crashed tests: {}", results.crash.to_string().red());
     println!(
         "Conformance: {:.2}%",
         (results.passed as f64 / results.total as f64) * 100.0
@@ -441,7 +489,13 @@ fn show_result(results: &SuiteResult) {
 }
 
 /// Runs the full test suite.
-fn run_test_suite(verbose: u8, test262_path: &Path, suite: &Path, output: Option<&Path>) {
+fn run_test_suite(
+    verbose: u8,
+    test262_path: &Path,
+    suite: &Path,
+    output: Option<&Path>,
+    pool: Option<&WorkerPool>,
+) {
     if let Some(path) = output {
         if path.exists() {
             if !path.is_dir() {
@@ -460,9 +514,23 @@ fn run_test_suite(verbose: u8, test262_path: &Path, suite: &Path, output: Option
 
     let start = Instant::now();
 
-    let results = if suite.to_string_lossy().ends_with(".js") {
-        let options = Options::default();
-        let vm = VirtualMachine::new(options, None);
+    let results = if let Some(pool) = pool {
+        let suite = if suite.to_string_lossy().ends_with(".js") {
+            let test = read_test(&test262_path.join(suite)).expect("could not get the test to run");
+            TestSuite {
+                name: test.name.clone(),
+                suites: Vec::new(),
+                tests: vec![test],
+            }
+        } else {
+            read_suite(&test262_path.join(suite)).expect("could not get the list of tests to run")
+        };
+        if verbose != 0 {
+            println!("Test suite loaded, starting {} workers...", pool.jobs);
+        }
+        suite.run_parallel(verbose, pool)
+    } else if suite.to_string_lossy().ends_with(".js") {
+        let vm = exec::new_vm();
         let test = read_test(&test262_path.join(suite)).expect("could not get the test to run");
 
         if verbose != 0 {
@@ -481,8 +549,7 @@ fn run_test_suite(verbose: u8, test262_path: &Path, suite: &Path, output: Option
         if verbose != 0 {
             println!("Test suite loaded, starting tests...");
         }
-        let options = Options::default();
-        let vm = VirtualMachine::new(options, None);
+        let vm = exec::new_vm();
         suite.run_main(&harness, verbose, vm)
     };
     show_result(&results);
@@ -0,0 +1,298 @@
+//! Process isolated parallel test execution.
+//!
+//! Every worker thread of [WorkerPool] owns a child process running the hidden `worker` subcommand. Tests are
+//! sent to the child one per line on stdin and results are read back from its stdout, so a test that hangs or
+//! aborts the process only loses its own result: the child is killed and respawned and the test is recorded as
+//! [TestOutcomeResult::Timeout] or [TestOutcomeResult::Crash].
+use super::exec::new_vm;
+use super::{read_harness, read_test, Test, TestOutcomeResult, TestResult};
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Prefix of result lines printed by workers. Anything else on worker stdout (e.g. `print` calls of tests) is
+/// ignored.
+const RESULT_PREFIX: &str = "@@test262-result ";
+
+/// Result of a single test sent back by a worker.
+#[derive(Serialize, Deserialize)]
+struct WorkerResult {
+    #[serde(rename = "r")]
+    result: TestOutcomeResult,
+    #[serde(rename = "x")]
+    text: String,
+    #[serde(rename = "t")]
+    time: u32,
+}
+
+/// Entry point of worker processes. Reads `<strict>\t<test path>` lines from stdin and runs each test in a fresh
+/// VM that is disposed after the test. VM of a test that panicked may be left in an inconsistent state, it is
+/// leaked instead of disposed.
+pub(crate) fn run_worker(test262_path: &Path) {
+    let harness = read_harness(test262_path).expect("could not read initialization bindings");
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = line.expect("could not read test request");
+        let (strict, path) = match line.split_once('\t') {
+            Some((strict, path)) => (strict == "1", path),
+            None => continue,
+        };
+        let result = match read_test(Path::new(path)) {
+            Ok(test) => {
+                let mut vm = new_vm();
+                let result = test.run_once(&harness, strict, 0, vm);
+                if !matches!(result.result, TestOutcomeResult::Panic) {
+                    unsafe {
+                        vm.dispose();
+                    }
+                }
+                WorkerResult {
+                    result: result.result,
+                    text: result.result_text.into(),
+                    time: result.time,
+                }
+            }
+            Err(e) => WorkerResult {
+                result: TestOutcomeResult::Failed,
+                text: format!("could not read test: {}", e),
+                time: 0,
+            },
+        };
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        writeln!(
+            stdout,
+            "{}{}",
+            RESULT_PREFIX,
+            serde_json::to_string(&result).expect("could not serialize test result")
+        )
+        .expect("could not write test result");
+        stdout.flush().expect("could not write test result");
+    }
+}
+
+/// Handle to a worker process.
+struct Worker {
+    child: Child,
+    stdin: ChildStdin,
+    results: Receiver<String>,
+}
+
+impl Worker {
+    fn spawn(program: &Path, test262_path: &Path, verbose: u8) -> Self {
+        let mut child = Command::new(program)
+            .arg("worker")
+            .arg("--test262-path")
+            .arg(test262_path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(if verbose != 0 {
+                Stdio::inherit()
+            } else {
+                Stdio::null()
+            })
+            .spawn()
+            .expect("could not spawn worker process");
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let (sender, results) = mpsc::channel();
+        // Reader thread exits and disconnects the channel once the worker dies.
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                if let Some(result) = line.strip_prefix(RESULT_PREFIX) {
+                    if sender.send(result.to_string()).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        Self {
+            child,
+            stdin,
+            results,
+        }
+    }
+
+    /// Runs `test` in the worker. Returns the outcome to record when the worker timed out or died and has to be
+    /// replaced.
+    fn run(&mut self, test: &Test, strict: bool, timeout: Duration) -> Result<WorkerResult, TestOutcomeResult> {
+        if writeln!(self.stdin, "{}\t{}", strict as u8, test.name)
+            .and_then(|_| self.stdin.flush())
+            .is_err()
+        {
+            return Err(TestOutcomeResult::Crash);
+        }
+        match self.results.recv_timeout(timeout) {
+            Ok(result) => serde_json::from_str(&result).map_err(|_| TestOutcomeResult::Crash),
+            Err(RecvTimeoutError::Timeout) => Err(TestOutcomeResult::Timeout),
+            Err(RecvTimeoutError::Disconnected) => Err(TestOutcomeResult::Crash),
+        }
+    }
+
+    /// Kills the worker and returns its exit status.
+    fn kill(mut self) -> String {
+        let _ = self.child.kill();
+        match self.child.wait() {
+            Ok(status) => status.to_string(),
+            Err(e) => e.to_string(),
+        }
+    }
+}
+
+/// Pool of worker processes running tests in parallel.
+pub(crate) struct WorkerPool {
+    /// Number of worker processes.
+    pub(crate) jobs: usize,
+    /// Time a single test may run before its worker is killed.
+    pub(crate) timeout: Duration,
+    pub(crate) test262_path: PathBuf,
+    pub(crate) verbose: u8,
+    /// Executable started with the `worker` subcommand, normally the runner itself.
+    pub(crate) program: PathBuf,
+}
+
+impl WorkerPool {
+    /// Runs `tests` given as `(test, strict)` pairs. Results are returned in the same order.
+    pub(crate) fn run(&self, tests: &[(&Test, bool)]) -> Vec<TestResult> {
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(vec![None; tests.len()]);
+        let threads = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.jobs)
+            .build()
+            .expect("could not create worker threads");
+        threads.scope(|scope| {
+            for _ in 0..self.jobs {
+                scope.spawn(|_| {
+                    let mut worker: Option<Worker> = None;
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let (test, strict) = match tests.get(index) {
+                            Some(&job) => job,
+                            None => break,
+                        };
+                        let result = self.run_one(&mut worker, test, strict);
+                        results.lock().unwrap()[index] = Some(result);
+                    }
+                    if let Some(worker) = worker {
+                        worker.kill();
+                    }
+                });
+            }
+        });
+        results
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .map(|result| result.expect("test was not run"))
+            .collect()
+    }
+
+    fn run_one(&self, worker: &mut Option<Worker>, test: &Test, strict: bool) -> TestResult {
+        let start = Instant::now();
+        let (result, result_text, time) = if test.is_ignored() {
+            (TestOutcomeResult::Ignored, String::new(), 0)
+        } else {
+            let mut current = match worker.take() {
+                Some(worker) => worker,
+                None => Worker::spawn(&self.program, &self.test262_path, self.verbose),
+            };
+            match current.run(test, strict, self.timeout) {
+                Ok(result) => {
+                    *worker = Some(current);
+                    (result.result, result.text, result.time)
+                }
+                Err(outcome) => {
+                    let status = current.kill();
+                    let text = match outcome {
+                        TestOutcomeResult::Timeout => format!("timed out after {:?}", self.timeout),
+                        _ => format!("worker process died: {}", status),
+                    };
+                    (outcome, text, start.elapsed().as_millis() as u32)
+                }
+            }
+        };
+        if self.verbose != 0 {
+            println!(
+                "`{}`{}: {:?}",
+                test.name,
+                if strict { " (strict mode)" } else { "" },
+                result
+            );
+        }
+        TestResult {
+            name: test.name.clone(),
+            strict,
+            result_text: result_text.into_boxed_str(),
+            result,
+            time,
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// Fake worker that hangs on tests named `hang`, exits on tests named `crash` and passes everything else.
+    const FAKE_WORKER: &str = r#"#!/bin/sh
+while read -r line; do
+    case "$line" in
+        *hang) exec sleep 30 ;;
+        *crash) exit 3 ;;
+        *) echo '@@test262-result {"r":"O","x":"ok","t":1}' ;;
+    esac
+done
+"#;
+
+    #[test]
+    fn test_timeout_and_crash() {
+        let program =
+            std::env::temp_dir().join(format!("test262-fake-worker-{}", std::process::id()));
+        std::fs::write(&program, FAKE_WORKER).unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let pool = WorkerPool {
+            jobs: 1,
+            timeout: Duration::from_millis(500),
+            test262_path: PathBuf::new(),
+            verbose: 0,
+            program: program.clone(),
+        };
+        let tests = ["pass", "hang", "crash", "pass2"]
+            .iter()
+            .map(|name| Test {
+                name: (*name).into(),
+                ..Test::default()
+            })
+            .collect::<Vec<_>>();
+        let jobs = tests.iter().map(|test| (test, false)).collect::<Vec<_>>();
+        let results = pool.run(&jobs);
+        std::fs::remove_file(&program).unwrap();
+
+        let outcomes = results
+            .iter()
+            .map(|result| result.result)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            outcomes,
+            [
+                TestOutcomeResult::Passed,
+                TestOutcomeResult::Timeout,
+                TestOutcomeResult::Crash,
+                TestOutcomeResult::Passed
+            ]
+        );
+        assert_eq!(&*results[0].result_text, "ok");
+        assert!(results[1].result_text.starts_with("timed out after"));
+        assert!(results[2].result_text.starts_with("worker process died"));
+    }
+}
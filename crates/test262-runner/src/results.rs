@@ -33,6 +33,10 @@ struct ReducedResultInfo {
     ignored: usize,
     #[serde(rename = "p")]
     panic: usize,
+    #[serde(rename = "to", default)]
+    timeout: usize,
+    #[serde(rename = "cr", default)]
+    crash: usize,
 }
 
 impl From<ResultInfo> for ReducedResultInfo {
@@ -45,6 +49,8 @@ impl From<ResultInfo> for ReducedResultInfo {
             passed: info.results.passed,
             ignored: info.results.ignored,
             panic: info.results.panic,
+            timeout: info.results.timeout,
+            crash: info.results.crash,
         }
     }
 }